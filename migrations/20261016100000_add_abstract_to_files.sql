@@ -0,0 +1 @@
+ALTER TABLE files ADD COLUMN abstract_text TEXT;
@@ -0,0 +1,2 @@
+-- When a file was handed to a worker, for reclaiming files left IN_PROGRESS by an interrupted run
+ALTER TABLE files ADD COLUMN started_at DATETIME;
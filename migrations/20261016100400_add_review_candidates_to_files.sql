@@ -0,0 +1 @@
+ALTER TABLE files ADD COLUMN review_candidates TEXT; -- JSON array of rule names, for NEEDS_REVIEW files
@@ -0,0 +1,11 @@
+-- The rules in effect for each processing run, so past classifications can be explained
+CREATE TABLE rules (
+    run_id TEXT NOT NULL,
+    position INTEGER NOT NULL,   -- Order of the rule in the rules file
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    path TEXT NOT NULL,
+    PRIMARY KEY (run_id, position)
+);
+
+ALTER TABLE files ADD COLUMN run_id TEXT; -- The run that last processed the file
@@ -0,0 +1 @@
+ALTER TABLE files ADD COLUMN source_folder TEXT; -- The inbox folder the file was synced from
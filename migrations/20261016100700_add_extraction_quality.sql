@@ -0,0 +1 @@
+ALTER TABLE files ADD COLUMN extraction_quality REAL; -- From 0 (garbage) to 1 (clean text)
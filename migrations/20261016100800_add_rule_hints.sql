@@ -0,0 +1,3 @@
+-- Optional hints given to the LLM to tell similar categories apart, as JSON arrays
+ALTER TABLE rules ADD COLUMN examples TEXT;
+ALTER TABLE rules ADD COLUMN negative_keywords TEXT;
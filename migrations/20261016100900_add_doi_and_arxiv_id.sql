@@ -0,0 +1,2 @@
+ALTER TABLE files ADD COLUMN doi TEXT;
+ALTER TABLE files ADD COLUMN arxiv_id TEXT; -- Without version, e.g. 2101.00001
@@ -0,0 +1,2 @@
+-- Keywords the keyword classifier files papers by, as a JSON array
+ALTER TABLE rules ADD COLUMN keywords TEXT;
@@ -0,0 +1 @@
+ALTER TABLE files ADD COLUMN skip_reason TEXT; -- Why the file was skipped, see SkipReason
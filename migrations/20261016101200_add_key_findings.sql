@@ -0,0 +1 @@
+ALTER TABLE files ADD COLUMN key_findings TEXT; -- JSON array of strings
@@ -0,0 +1,2 @@
+ALTER TABLE files ADD COLUMN prompt_tokens INTEGER; -- LLM tokens used to process the file
+ALTER TABLE files ADD COLUMN completion_tokens INTEGER;
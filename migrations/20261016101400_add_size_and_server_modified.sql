@@ -0,0 +1,2 @@
+ALTER TABLE files ADD COLUMN size INTEGER; -- bytes, as listed by Dropbox
+ALTER TABLE files ADD COLUMN server_modified DATETIME; -- when last changed in Dropbox
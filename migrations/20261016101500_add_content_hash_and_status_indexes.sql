@@ -0,0 +1,3 @@
+-- Not unique: the same bytes may be in the inbox under several names
+CREATE INDEX idx_files_content_hash ON files(content_hash);
+CREATE INDEX idx_files_status ON files(status);
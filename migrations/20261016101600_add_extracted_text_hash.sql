@@ -0,0 +1,10 @@
+ALTER TABLE files ADD COLUMN extracted_text_hash TEXT; -- SHA-256 of the text extracted for the LLM
+
+-- The hash of the text extracted from each file in each run that processed it, to find the
+-- files whose extracted text changed since a run, e.g. after changing the extractor
+CREATE TABLE extracted_text_hashes (
+    dropbox_id TEXT NOT NULL,
+    run_id TEXT NOT NULL,
+    text_hash TEXT NOT NULL,
+    PRIMARY KEY (dropbox_id, run_id)
+);
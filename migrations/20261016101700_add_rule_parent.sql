@@ -0,0 +1,2 @@
+-- The name of the rule a rule is a subcategory of, if any
+ALTER TABLE rules ADD COLUMN parent TEXT;
@@ -0,0 +1,3 @@
+-- The targets a file is being uploaded to, until the uploads are done and they become its
+-- target_path, so a file is never listed as filed somewhere it was not uploaded to
+ALTER TABLE files ADD COLUMN pending_target_path TEXT; -- JSON array string
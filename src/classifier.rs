@@ -0,0 +1,111 @@
+use crate::clients::LlmClient;
+use crate::models::{ArticleMetadata, Rule, Rules};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Classifies papers without an LLM, by the `keywords` of the rules: a paper matches every
+/// rule with one of its keywords in the text, and none of its negative keywords. Keywords
+/// are matched case-insensitively anywhere in the text, so "compiler" also matches
+/// "Compilers". No metadata is returned, as there is no LLM to extract it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeywordClassifier;
+
+impl KeywordClassifier {
+    /// The rules the text matches by keyword.
+    pub fn matching_rules(&self, text: &str, rules: &Rules) -> Vec<Rule> {
+        let text = text.to_lowercase();
+        let mentions = |keywords: &[String]| {
+            keywords
+                .iter()
+                .map(|keyword| keyword.trim().to_lowercase())
+                .any(|keyword| !keyword.is_empty() && text.contains(&keyword))
+        };
+        rules
+            .0
+            .iter()
+            .filter(|rule| mentions(&rule.keywords) && !mentions(&rule.negative_keywords))
+            .cloned()
+            .collect()
+    }
+}
+
+#[async_trait]
+impl LlmClient for KeywordClassifier {
+    async fn query_llm(&self, text: &str, rules: &Rules) -> Result<(ArticleMetadata, Vec<Rule>)> {
+        let matching_rules = self.matching_rules(text, rules);
+        tracing::debug!(
+            "Rules matched by keyword: {:?}",
+            matching_rules
+                .iter()
+                .map(|rule| &rule.name)
+                .collect::<Vec<_>>()
+        );
+        Ok((ArticleMetadata::not_extracted(), matching_rules))
+    }
+}
+
+/// Classifies papers by keyword where it can, like [`KeywordClassifier`], and only asks the
+/// LLM about the papers that match no rule by keyword.
+pub struct HybridClassifier {
+    keywords: KeywordClassifier,
+    llm: Arc<dyn LlmClient>,
+}
+
+impl HybridClassifier {
+    pub fn new(llm: Arc<dyn LlmClient>) -> Self {
+        HybridClassifier {
+            keywords: KeywordClassifier,
+            llm,
+        }
+    }
+}
+
+#[async_trait]
+impl LlmClient for HybridClassifier {
+    async fn query_llm(&self, text: &str, rules: &Rules) -> Result<(ArticleMetadata, Vec<Rule>)> {
+        let matching_rules = self.keywords.matching_rules(text, rules);
+        if matching_rules.is_empty() {
+            return self.llm.query_llm(text, rules).await;
+        }
+        Ok((ArticleMetadata::not_extracted(), matching_rules))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::RemotePath;
+
+    fn rule(name: &str, keywords: &[&str], negative_keywords: &[&str]) -> Rule {
+        Rule {
+            name: name.to_string(),
+            path: RemotePath(format!("/sorted/{}", name)),
+            keywords: keywords.iter().map(|k| k.to_string()).collect(),
+            negative_keywords: negative_keywords.iter().map(|k| k.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_keyword_matching() {
+        let rules = Rules::from(vec![
+            rule("pl", &["compiler", "type system"], &[]),
+            rule("ai", &["neural"], &["neural compiler"]),
+            rule("misc", &[], &[]),
+        ]);
+
+        let names = |text: &str| -> Vec<String> {
+            KeywordClassifier
+                .matching_rules(text, &rules)
+                .into_iter()
+                .map(|rule| rule.name)
+                .collect()
+        };
+
+        assert_eq!(names("Optimizing Compilers for Fun"), vec!["pl"]);
+        assert_eq!(names("Training neural networks"), vec!["ai"]);
+        assert_eq!(names("A neural compiler"), vec!["pl"]);
+        assert!(names("Gardening in spring").is_empty());
+    }
+}
@@ -1,11 +1,19 @@
+use crate::http::HttpOptions;
+use crate::language::SummaryLanguage;
+use crate::metadata::{normalize_authors, normalize_tags};
 use crate::models::{
-    ArticleMetadata, DropboxId, FileHash, OneLineSummary, RemotePath, Rule, Rules,
+    ArticleMetadata, DropboxId, FileHash, OneLineSummary, RemotePath, Rule, RuleNode, Rules,
 };
 use anyhow::{Context, Result};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, BoxStream, TryStreamExt};
 use serde::Deserialize;
-use std::collections::{HashMap, HashSet};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
 use tokio::sync::Mutex;
 
 #[derive(Debug, Clone)]
@@ -14,16 +22,56 @@ pub struct DropboxEntry {
     pub name: String,
     pub path: RemotePath,
     pub content_hash: FileHash,
+    /// Size in bytes
+    pub size: u64,
+    /// When the file was last changed in Dropbox, if listed
+    pub server_modified: Option<DateTime<Utc>>,
 }
 
 #[async_trait]
 pub trait DropboxClient: Send + Sync {
+    /// The files directly in a folder.
     async fn list_folder(&self, path: &str) -> Result<Vec<DropboxEntry>>;
+    /// The files in a folder and all its subfolders.
+    async fn list_folder_recursive(&self, path: &str) -> Result<Vec<DropboxEntry>>;
+    /// The files in a folder, and in its subfolders if `recursive`, a page at a time as
+    /// Dropbox lists them, so a large folder can be handled before it is listed completely.
+    fn list_folder_stream<'a>(
+        &'a self,
+        path: &'a str,
+        recursive: bool,
+    ) -> BoxStream<'a, Result<Vec<DropboxEntry>>> {
+        Box::pin(stream::once(async move {
+            if recursive {
+                self.list_folder_recursive(path).await
+            } else {
+                self.list_folder(path).await
+            }
+        }))
+    }
+    /// The file at a path, or `None` if there is no file there.
+    async fn get_metadata(&self, path: &RemotePath) -> Result<Option<DropboxEntry>>;
+    /// Download a file by its Dropbox id (`id:...`) or by a path rooted at `/`.
     async fn download_file(&self, id: &DropboxId) -> Result<Vec<u8>>;
+    /// Download a file to a local path, returning its size. Clients that can resume an
+    /// interrupted download do so from the partial file (see [`partial_download_path`]).
+    async fn download_to(&self, id: &DropboxId, path: &Path) -> Result<u64> {
+        let content = self.download_file(id).await?;
+        tokio::fs::write(path, &content)
+            .await
+            .with_context(|| format!("Failed to save {}", path.to_string_lossy()))?;
+        Ok(content.len() as u64)
+    }
     async fn upload_file(&self, path: &RemotePath, content: Vec<u8>) -> Result<()>;
     async fn folder_exists(&self, path: &str) -> Result<bool>;
     async fn create_folder(&self, path: &str) -> Result<()>;
     async fn create_folder_if_not_exists(&self, path: &str) -> Result<()>;
+    /// Move a file within Dropbox. Like uploads, only to under the allowed upload prefix.
+    async fn move_file(&self, from: &RemotePath, to: &RemotePath) -> Result<()>;
+    async fn delete_file(&self, path: &RemotePath) -> Result<()>;
+    /// A shared link to the file at a path, anyone with which can view it. The existing
+    /// link is returned if the file is already shared.
+    async fn create_shared_link(&self, path: &RemotePath) -> Result<String>;
 }
 
 #[async_trait]
@@ -35,27 +83,133 @@ pub trait LlmClient: Send + Sync {
 pub struct DropboxHttpClient {
     token: String,
     client: reqwest::Client,
-    allowed_upload_prefix: String,
+    allowed_upload_prefixes: Vec<String>,
+    /// Namespace id of a team space root, sent as `Dropbox-API-Path-Root`
+    path_root: Option<String>,
+    /// Base URL of the RPC endpoints
+    api_url: String,
+    /// Base URL of the content upload and download endpoints
+    content_url: String,
+}
+
+/// Where a download to `path` is written until it completes, so that an interrupted download
+/// can be resumed from it, and is never mistaken for the complete file.
+pub fn partial_download_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+/// Size of the blocks hashed for a [`dropbox_content_hash`].
+const CONTENT_HASH_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+/// The content hash Dropbox lists for a file with this content: the SHA-256 of the SHA-256
+/// hashes of its 4 MB blocks, in hex. See
+/// <https://www.dropbox.com/developers/reference/content-hash>.
+pub fn dropbox_content_hash(content: &[u8]) -> FileHash {
+    let mut hasher = Sha256::new();
+    for block in content.chunks(CONTENT_HASH_BLOCK_SIZE) {
+        hasher.update(Sha256::digest(block));
+    }
+    FileHash(hex::encode(hasher.finalize()))
+}
+
+/// Check that an upload goes under one of the allowed prefixes. Dropbox paths are
+/// case-insensitive.
+pub(crate) fn check_upload_allowed(
+    path: &RemotePath,
+    allowed_upload_prefixes: &[String],
+) -> Result<()> {
+    if !path.is_under_any(allowed_upload_prefixes) {
+        return Err(anyhow::anyhow!(format!(
+            "Upload path not allowed to path: {} (allowed prefixes: {})",
+            path.0,
+            allowed_upload_prefixes.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// Check that a file reference passed to the download endpoint is a Dropbox id (`id:...`) or
+/// a rooted path (`/...`). A bare file name is ambiguous and rejected before any request.
+fn validate_download_reference(id: &DropboxId) -> Result<()> {
+    if id.0.starts_with("id:") || id.0.starts_with('/') {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "Cannot download '{}': expected a Dropbox file id (id:...) or a path starting with '/'",
+            id.0
+        ))
+    }
 }
 
+/// Whether an error from the Dropbox API says that the path does not exist.
+fn is_path_not_found(error: &str) -> bool {
+    error.contains("path") && error.contains("not_found")
+}
+
+/// Default base URL of the Dropbox RPC endpoints.
+pub const DROPBOX_API_URL: &str = "https://api.dropboxapi.com/2";
+/// Default base URL of the Dropbox content endpoints.
+pub const DROPBOX_CONTENT_URL: &str = "https://content.dropboxapi.com/2";
+
 /** Time-out for HTTP requests to the Dropbox API */
 const DROPBOX_HTTP_TIMEOUT_IN_SECONDS: u64 = 3;
 
+fn dropbox_http_client(http: &HttpOptions) -> reqwest::Client {
+    http.client_builder()
+        .timeout(std::time::Duration::from_secs(
+            DROPBOX_HTTP_TIMEOUT_IN_SECONDS,
+        ))
+        // Listings of large folders are repetitive JSON, which compresses well
+        .gzip(true)
+        .brotli(true)
+        .build()
+        .unwrap()
+}
+
 impl DropboxHttpClient {
-    /// Create a Dropbox client with an API token and allowed upload prefix as a safe-guard against
-    /// uploading files outside the allowed directory.
-    pub fn new(token: String, allowed_upload_prefix: String) -> Self {
-        let client = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_secs(
-                DROPBOX_HTTP_TIMEOUT_IN_SECONDS,
-            ))
-            .build()
-            .unwrap();
+    /// Create a Dropbox client with an API token and allowed upload prefixes as a safe-guard
+    /// against uploading files outside the allowed directories.
+    pub fn new(token: String, allowed_upload_prefixes: Vec<String>) -> Self {
         Self {
             token,
-            client,
-            allowed_upload_prefix,
+            client: dropbox_http_client(&HttpOptions::default()),
+            allowed_upload_prefixes,
+            path_root: None,
+            api_url: DROPBOX_API_URL.to_string(),
+            content_url: DROPBOX_CONTENT_URL.to_string(),
+        }
+    }
+
+    /// Send requests to other base URLs than Dropbox's, e.g. a mock server in tests.
+    pub fn with_base_urls(mut self, api_url: &str, content_url: &str) -> Self {
+        self.api_url = api_url.trim_end_matches('/').to_string();
+        self.content_url = content_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Connect as given instead of directly, e.g. through a proxy.
+    pub fn with_http_options(mut self, http: &HttpOptions) -> Self {
+        self.client = dropbox_http_client(http);
+        self
+    }
+
+    /// Resolve paths relative to the given namespace, e.g. the root of a Dropbox Business
+    /// team space, instead of the user's personal space.
+    pub fn with_path_root(mut self, path_root: Option<String>) -> Self {
+        self.path_root = path_root;
+        self
+    }
+
+    /// Start an authenticated POST request to the Dropbox API.
+    fn post(&self, url: &str) -> reqwest::RequestBuilder {
+        let mut request = self.client.post(url).bearer_auth(&self.token);
+        if let Some(root) = &self.path_root {
+            let header = serde_json::json!({ ".tag": "root", "root": root }).to_string();
+            request = request.header("Dropbox-API-Path-Root", header);
         }
+        request
     }
 
     /// Send a POST request to Dropbox API.
@@ -67,7 +221,7 @@ impl DropboxHttpClient {
         content_type: Option<&str>,
     ) -> Result<reqwest::Response> {
         tracing::debug!("Sending POST request to Dropbox API: {}", url);
-        let mut request = self.client.post(url).bearer_auth(&self.token);
+        let mut request = self.post(url);
 
         if let Some(arg) = api_arg {
             request = request.header("Dropbox-API-Arg", arg);
@@ -101,37 +255,16 @@ impl DropboxHttpClient {
 
     fn append_entries(&self, entries: &mut Vec<DropboxEntry>, res: &serde_json::Value) {
         if let Some(list) = res["entries"].as_array() {
-            for item in list {
-                if item[".tag"] == "file" {
-                    entries.push(DropboxEntry {
-                        id: DropboxId(item["id"].as_str().unwrap_or_default().to_string()),
-                        name: item["name"].as_str().unwrap_or_default().to_string(),
-                        path: RemotePath(
-                            item["path_display"]
-                                .as_str()
-                                .unwrap_or_default()
-                                .to_string(),
-                        ),
-                        content_hash: FileHash(
-                            item["content_hash"]
-                                .as_str()
-                                .unwrap_or_default()
-                                .to_string(),
-                        ),
-                    });
-                }
-            }
+            entries.extend(list.iter().filter_map(file_entry));
         }
     }
-}
 
-#[async_trait]
-impl DropboxClient for DropboxHttpClient {
-    async fn list_folder(&self, path: &str) -> Result<Vec<DropboxEntry>> {
-        let url = "https://api.dropboxapi.com/2/files/list_folder";
+    /// The first page of the listing of a folder, and of its subfolders if `recursive`.
+    async fn list_first_page(&self, path: &str, recursive: bool) -> Result<serde_json::Value> {
+        let url = &format!("{}/files/list_folder", self.api_url);
         let body = serde_json::json!({
             "path": path,
-            "recursive": false,
+            "recursive": recursive,
             "include_media_info": false,
             "include_deleted": false,
             "include_has_explicit_shared_members": false,
@@ -140,52 +273,127 @@ impl DropboxClient for DropboxHttpClient {
         });
 
         let body_bytes = serde_json::to_vec(&body)?;
-        let res_raw = self
+        let res_raw = match self
             .dropbox_post_request(url, Some(body_bytes), None, Some("application/json"))
             .await
-            .with_context(|| format!("Failed to list folder at {}", path))?;
+        {
+            Ok(res) => res,
+            Err(e) if is_path_not_found(&e.to_string()) => {
+                return Err(anyhow::anyhow!(
+                    "Inbox path {} not found — check --inbox",
+                    if path.is_empty() { "(root)" } else { path }
+                ));
+            }
+            Err(e) => return Err(e.context(format!("Failed to list folder at {}", path))),
+        };
 
-        let res: serde_json::Value = res_raw
+        res_raw
             .json()
             .await
-            .with_context(|| format!("Failed to parse JSON response from {}", url))?;
-
-        let mut all_entries = Vec::new();
-        self.append_entries(&mut all_entries, &res);
-
-        let mut current_res = res;
-        while current_res["has_more"].as_bool().unwrap_or(false) {
-            let cursor = current_res["cursor"].as_str().ok_or_else(|| {
-                anyhow::anyhow!("Missing cursor in Dropbox response despite has_more=true")
-            })?;
-
-            let continue_url = "https://api.dropboxapi.com/2/files/list_folder/continue";
-            let continue_body = serde_json::json!({ "cursor": cursor });
-            let continue_body_bytes = serde_json::to_vec(&continue_body)?;
-
-            let res_raw = self
-                .dropbox_post_request(
-                    continue_url,
-                    Some(continue_body_bytes),
-                    None,
-                    Some("application/json"),
-                )
-                .await
-                .with_context(|| format!("Failed to list folder continuation at {}", path))?;
+            .with_context(|| format!("Failed to parse JSON response from {}", url))
+    }
 
-            current_res = res_raw
-                .json()
-                .await
-                .with_context(|| format!("Failed to parse JSON response from {}", continue_url))?;
+    /// The page of the listing of a folder after the one that returned `cursor`.
+    async fn list_next_page(&self, path: &str, cursor: &str) -> Result<serde_json::Value> {
+        let continue_url = &format!("{}/files/list_folder/continue", self.api_url);
+        let continue_body = serde_json::json!({ "cursor": cursor });
+        let continue_body_bytes = serde_json::to_vec(&continue_body)?;
 
-            self.append_entries(&mut all_entries, &current_res);
-        }
+        let res_raw = self
+            .dropbox_post_request(
+                continue_url,
+                Some(continue_body_bytes),
+                None,
+                Some("application/json"),
+            )
+            .await
+            .with_context(|| format!("Failed to list folder continuation at {}", path))?;
+
+        res_raw
+            .json()
+            .await
+            .with_context(|| format!("Failed to parse JSON response from {}", continue_url))
+    }
+}
+
+/// Where a paged Dropbox listing is at.
+enum ListingPage {
+    First,
+    /// The next page follows the one that returned this cursor
+    Next(String),
+    Done,
+}
+
+/// The file described by Dropbox file metadata, or `None` for folders and deleted files.
+fn file_entry(item: &serde_json::Value) -> Option<DropboxEntry> {
+    if item[".tag"] != "file" {
+        return None;
+    }
+    Some(DropboxEntry {
+        id: DropboxId(item["id"].as_str().unwrap_or_default().to_string()),
+        name: item["name"].as_str().unwrap_or_default().to_string(),
+        path: RemotePath(
+            item["path_display"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+        ),
+        content_hash: FileHash(
+            item["content_hash"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+        ),
+        size: item["size"].as_u64().unwrap_or_default(),
+        server_modified: item["server_modified"]
+            .as_str()
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+            .map(|time| time.with_timezone(&Utc)),
+    })
+}
+
+#[async_trait]
+impl DropboxClient for DropboxHttpClient {
+    async fn list_folder(&self, path: &str) -> Result<Vec<DropboxEntry>> {
+        self.list_folder_stream(path, false).try_concat().await
+    }
+
+    async fn list_folder_recursive(&self, path: &str) -> Result<Vec<DropboxEntry>> {
+        self.list_folder_stream(path, true).try_concat().await
+    }
 
-        Ok(all_entries)
+    /// Follow the cursor until Dropbox has no more entries, yielding each page of files.
+    fn list_folder_stream<'a>(
+        &'a self,
+        path: &'a str,
+        recursive: bool,
+    ) -> BoxStream<'a, Result<Vec<DropboxEntry>>> {
+        Box::pin(stream::try_unfold(
+            ListingPage::First,
+            move |page| async move {
+                let res = match page {
+                    ListingPage::First => self.list_first_page(path, recursive).await?,
+                    ListingPage::Next(cursor) => self.list_next_page(path, &cursor).await?,
+                    ListingPage::Done => return Ok(None),
+                };
+                let mut entries = Vec::new();
+                self.append_entries(&mut entries, &res);
+                let next = if res["has_more"].as_bool().unwrap_or(false) {
+                    let cursor = res["cursor"].as_str().ok_or_else(|| {
+                        anyhow::anyhow!("Missing cursor in Dropbox response despite has_more=true")
+                    })?;
+                    ListingPage::Next(cursor.to_string())
+                } else {
+                    ListingPage::Done
+                };
+                Ok(Some((entries, next)))
+            },
+        ))
     }
 
     async fn download_file(&self, id: &DropboxId) -> Result<Vec<u8>> {
-        let url = "https://content.dropboxapi.com/2/files/download";
+        validate_download_reference(id)?;
+        let url = &format!("{}/files/download", self.content_url);
         let arg = serde_json::json!({ "path": id.0 }).to_string();
 
         let res_raw = self
@@ -196,16 +404,71 @@ impl DropboxClient for DropboxHttpClient {
         Ok(res_raw.bytes().await?.to_vec())
     }
 
+    /// Stream the file to disk, resuming from a partial file with a `Range` request. A server
+    /// that ignores the range sends the whole file, which then replaces the partial one.
+    async fn download_to(&self, id: &DropboxId, path: &Path) -> Result<u64> {
+        validate_download_reference(id)?;
+        let url = &format!("{}/files/download", self.content_url);
+        let arg = serde_json::json!({ "path": id.0 }).to_string();
+        let partial_path = partial_download_path(path);
+        let offset = tokio::fs::metadata(&partial_path)
+            .await
+            .map_or(0, |metadata| metadata.len());
+
+        let mut request = self.post(url).header("Dropbox-API-Arg", arg);
+        if offset > 0 {
+            tracing::debug!("Resuming download of {} from byte {}", id.0, offset);
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", offset));
+        }
+        let mut response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to download file {}", id.0))?;
+        let resumed = match response.status() {
+            reqwest::StatusCode::PARTIAL_CONTENT => true,
+            status if status.is_success() => false,
+            reqwest::StatusCode::RANGE_NOT_SATISFIABLE => {
+                // The partial file is not a prefix of this file after all, so start over
+                tokio::fs::remove_file(&partial_path).await?;
+                return self.download_to(id, path).await;
+            }
+            status => {
+                let error_text = response.text().await.unwrap_or_default();
+                return Err(anyhow::anyhow!(
+                    "Dropbox API error ({}): {}",
+                    status,
+                    error_text
+                ))
+                .with_context(|| format!("Failed to download file {}", id.0));
+            }
+        };
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(&partial_path)
+            .await
+            .with_context(|| format!("Failed to open {}", partial_path.to_string_lossy()))?;
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .with_context(|| format!("Failed to download file {}", id.0))?
+        {
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        drop(file);
+        tokio::fs::rename(&partial_path, path).await?;
+        Ok(tokio::fs::metadata(path).await?.len())
+    }
+
     async fn upload_file(&self, path: &RemotePath, content: Vec<u8>) -> Result<()> {
         // Check allowed paths, for extra safety
-        if !path.0.starts_with(&self.allowed_upload_prefix) {
-            return Err(anyhow::anyhow!(format!(
-                "Upload path not allowed to path: {} (allowed prefix: {})",
-                path.0, &self.allowed_upload_prefix
-            )));
-        }
+        check_upload_allowed(path, &self.allowed_upload_prefixes)?;
 
-        let url = "https://content.dropboxapi.com/2/files/upload";
+        let url = &format!("{}/files/upload", self.content_url);
         let arg = serde_json::json!({
             "path": path.0,
             "mode": "overwrite",
@@ -227,8 +490,39 @@ impl DropboxClient for DropboxHttpClient {
         Ok(())
     }
 
+    async fn get_metadata(&self, path: &RemotePath) -> Result<Option<DropboxEntry>> {
+        let url = &format!("{}/files/get_metadata", self.api_url);
+        let body = serde_json::json!({ "path": path.0 });
+
+        let body_bytes = serde_json::to_vec(&body)?;
+        let res_raw = self
+            .post(url)
+            .header("Content-Type", "application/json")
+            .body(body_bytes)
+            .send()
+            .await
+            .with_context(|| format!("Failed to get metadata for {}", path.0))?;
+
+        let status = res_raw.status();
+        if !status.is_success() {
+            let error_text = res_raw.text().await.unwrap_or_default();
+            if status == reqwest::StatusCode::NOT_FOUND || is_path_not_found(&error_text) {
+                return Ok(None);
+            }
+            return Err(anyhow::anyhow!(
+                "Dropbox API error ({}): {}",
+                status,
+                error_text
+            ))
+            .with_context(|| format!("Failed to get metadata for {}", path.0));
+        }
+
+        let res: serde_json::Value = res_raw.json().await?;
+        Ok(file_entry(&res))
+    }
+
     async fn folder_exists(&self, path: &str) -> Result<bool> {
-        let url = "https://api.dropboxapi.com/2/files/get_metadata";
+        let url = &format!("{}/files/get_metadata", self.api_url);
         let body = serde_json::json!({
             "path": path,
             "include_media_info": false,
@@ -238,9 +532,7 @@ impl DropboxClient for DropboxHttpClient {
 
         let body_bytes = serde_json::to_vec(&body)?;
         let res_raw = self
-            .client
             .post(url)
-            .bearer_auth(&self.token)
             .header("Content-Type", "application/json")
             .body(body_bytes)
             .send()
@@ -255,7 +547,7 @@ impl DropboxClient for DropboxHttpClient {
             let status = res_raw.status();
             let error_text = res_raw.text().await.unwrap_or_default();
             // Dropbox returns a 409 Conflict for "path not found" in some cases when using get_metadata
-            if error_text.contains("path") && error_text.contains("not_found") {
+            if is_path_not_found(&error_text) {
                 return Ok(false);
             }
             return Err(anyhow::anyhow!(
@@ -270,7 +562,7 @@ impl DropboxClient for DropboxHttpClient {
     }
 
     async fn create_folder(&self, path: &str) -> Result<()> {
-        let url = "https://api.dropboxapi.com/2/files/create_folder_v2";
+        let url = &format!("{}/files/create_folder_v2", self.api_url);
         let body = serde_json::json!({
             "path": path,
             "autorename": false
@@ -304,20 +596,292 @@ impl DropboxClient for DropboxHttpClient {
 
         Ok(())
     }
+
+    async fn move_file(&self, from: &RemotePath, to: &RemotePath) -> Result<()> {
+        check_upload_allowed(to, &self.allowed_upload_prefixes)?;
+        let url = &format!("{}/files/move_v2", self.api_url);
+        let body = serde_json::json!({
+            "from_path": from.0,
+            "to_path": to.0,
+            "autorename": false
+        });
+
+        let body_bytes = serde_json::to_vec(&body)?;
+        self.dropbox_post_request(url, Some(body_bytes), None, Some("application/json"))
+            .await
+            .with_context(|| format!("Failed to move {} to {}", from.0, to.0))?;
+
+        Ok(())
+    }
+
+    async fn delete_file(&self, path: &RemotePath) -> Result<()> {
+        let url = &format!("{}/files/delete_v2", self.api_url);
+        let body = serde_json::json!({ "path": path.0 });
+
+        let body_bytes = serde_json::to_vec(&body)?;
+        self.dropbox_post_request(url, Some(body_bytes), None, Some("application/json"))
+            .await
+            .with_context(|| format!("Failed to delete {}", path.0))?;
+
+        Ok(())
+    }
+
+    async fn create_shared_link(&self, path: &RemotePath) -> Result<String> {
+        let url = &format!("{}/sharing/create_shared_link_with_settings", self.api_url);
+        let body = serde_json::json!({ "path": path.0 });
+
+        let body_bytes = serde_json::to_vec(&body)?;
+        let res_raw = self
+            .post(url)
+            .header("Content-Type", "application/json")
+            .body(body_bytes)
+            .send()
+            .await
+            .with_context(|| format!("Failed to create a shared link to {}", path.0))?;
+
+        let status = res_raw.status();
+        if status.is_success() {
+            let res: serde_json::Value = res_raw.json().await?;
+            return res["url"]
+                .as_str()
+                .map(String::from)
+                .ok_or_else(|| anyhow::anyhow!("Dropbox returned no shared link for {}", path.0));
+        }
+        let error_text = res_raw.text().await.unwrap_or_default();
+        if !error_text.contains("shared_link_already_exists") {
+            return Err(anyhow::anyhow!(
+                "Dropbox API error ({}): {}",
+                status,
+                error_text
+            ))
+            .with_context(|| format!("Failed to create a shared link to {}", path.0));
+        }
+
+        // Already shared: the error usually has the existing link, otherwise look it up
+        let error: serde_json::Value = serde_json::from_str(&error_text).unwrap_or_default();
+        if let Some(link) = error["error"]["shared_link_already_exists"]["metadata"]["url"].as_str()
+        {
+            return Ok(link.to_string());
+        }
+        let url = &format!("{}/sharing/list_shared_links", self.api_url);
+        let body = serde_json::json!({ "path": path.0, "direct_only": true });
+
+        let body_bytes = serde_json::to_vec(&body)?;
+        let res: serde_json::Value = self
+            .dropbox_post_request(url, Some(body_bytes), None, Some("application/json"))
+            .await
+            .with_context(|| format!("Failed to get the shared link to {}", path.0))?
+            .json()
+            .await?;
+        res["links"][0]["url"]
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| anyhow::anyhow!("Dropbox has no shared link to {}", path.0))
+    }
 }
 
 pub struct MistralHttpClient {
     api_key: String,
     client: reqwest::Client,
+    /// How the client connects and when it gives up, kept to build it again with either changed
+    http: HttpOptions,
+    timeout: std::time::Duration,
+    /// Instructions for the LLM, with `{categories}` and `{text}` placeholders
+    prompt_template: String,
+    temperature: f32,
+    max_tokens: Option<u32>,
+    api_url: String,
+    /// The language to ask for, if any; see [`MistralHttpClient::with_summary_language`]
+    summary_language: Option<SummaryLanguage>,
 }
 
+/// Default URL of the Mistral chat completions endpoint.
+pub const MISTRAL_API_URL: &str = "https://api.mistral.ai/v1/chat/completions";
+/// Default sampling temperature. Classification should be deterministic, so none.
+pub const DEFAULT_TEMPERATURE: f32 = 0.0;
+/// Default time-out for requests to the LLM, including reading the response. Classifying a
+/// paper usually takes seconds, so a request taking longer than this has most likely hung.
+pub const DEFAULT_LLM_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// The default extraction prompt. `{categories}` is replaced by the rules and `{text}` by the
+/// text of the paper.
+pub const DEFAULT_PROMPT_TEMPLATE: &str = "Extract Title, Authors, Abstract and publication Year from the following scientific paper text. \
+Provide a 1-line summary, 3 to 5 key findings of the paper as short sentences, and up to \
+10 short keyword tags, such as \"survey\", \"reproducible\" or \"dataset\". \
+Match the abstract against these categories to select the applicable categories for the \
+text.  \n\n\
+<categories>\n\
+{categories}\
+</categories>\n\n\
+Text:\n\n\
+<text>\
+{text}\
+</text>\n\n\
+Respond ONLY with JSON in this format, where the \"categories\" key has an array of \
+strings with the exact names of the categories matched to the text, best match first, \
+\"year\" is a number or null if unknown, and \"confidence\" is a number from 0 to 1 telling how \
+sure you are of the matched categories:  \n\n\
+{\"title\": \"...\", \"authors\": [\"...\"], \"summary\": \"...\", \"key_findings\": [\"...\"], \"abstract\": \"...\", \"year\": 2024, \"tags\": [\"...\"], \"confidence\": 0.9, \"categories\": [\"...\",\"...\"]}";
+
+const CATEGORIES_PLACEHOLDER: &str = "{categories}";
+const TEXT_PLACEHOLDER: &str = "{text}";
+
 impl MistralHttpClient {
     pub fn new(api_key: String) -> Self {
         Self {
             api_key,
-            client: reqwest::Client::new(),
+            client: llm_http_client(DEFAULT_LLM_TIMEOUT, &HttpOptions::default()),
+            http: HttpOptions::default(),
+            timeout: DEFAULT_LLM_TIMEOUT,
+            prompt_template: DEFAULT_PROMPT_TEMPLATE.to_string(),
+            temperature: DEFAULT_TEMPERATURE,
+            max_tokens: None,
+            api_url: MISTRAL_API_URL.to_string(),
+            summary_language: None,
+        }
+    }
+
+    /// Have the one-line summary and key findings written in a language, or in that of each
+    /// paper. Without one, the default prompt asks for
+    /// [`DEFAULT_SUMMARY_LANGUAGE`](crate::language::DEFAULT_SUMMARY_LANGUAGE), and a custom
+    /// prompt template is left to say which language it wants.
+    pub fn with_summary_language(mut self, language: SummaryLanguage) -> Self {
+        self.summary_language = Some(language);
+        self
+    }
+
+    /// The prompt for a paper's text, with the summary language instruction if there is one.
+    fn prompt(&self, rules: &Rules, text: &str) -> String {
+        let mut prompt = render_prompt(&self.prompt_template, &render_categories(rules), text);
+        let language = match &self.summary_language {
+            Some(language) => Some(language.clone()),
+            None if self.prompt_template == DEFAULT_PROMPT_TEMPLATE => {
+                Some(SummaryLanguage::default())
+            }
+            None => None,
+        };
+        if let Some(language) = language {
+            prompt.push_str("\n\n");
+            prompt.push_str(&language.instruction(text));
+        }
+        prompt
+    }
+
+    /// Sample with another temperature than [`DEFAULT_TEMPERATURE`], and optionally bound the
+    /// length, and so the cost, of the responses.
+    pub fn with_sampling(mut self, temperature: f32, max_tokens: Option<u32>) -> Self {
+        self.temperature = temperature;
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Give up on requests taking longer than `timeout` instead of [`DEFAULT_LLM_TIMEOUT`].
+    pub fn with_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = timeout;
+        self.client = llm_http_client(timeout, &self.http);
+        self
+    }
+
+    /// Connect as given instead of directly, e.g. through a proxy.
+    pub fn with_http_options(mut self, http: &HttpOptions) -> Self {
+        self.http = http.clone();
+        self.client = llm_http_client(self.timeout, http);
+        self
+    }
+
+    /// Send requests to another URL than Mistral's, e.g. a mock server in tests.
+    pub fn with_api_url(mut self, api_url: &str) -> Self {
+        self.api_url = api_url.to_string();
+        self
+    }
+
+    /// Use other instructions than the default prompt, e.g. to summarize in another language.
+    /// The template must contain both the `{categories}` and `{text}` placeholders.
+    pub fn with_prompt_template(mut self, template: String) -> Result<Self> {
+        let missing = [CATEGORIES_PLACEHOLDER, TEXT_PLACEHOLDER]
+            .into_iter()
+            .filter(|placeholder| !template.contains(placeholder))
+            .collect::<Vec<&str>>();
+        if !missing.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Prompt template is missing the placeholders {}",
+                missing.join(" and ")
+            ));
+        }
+        self.prompt_template = template;
+        Ok(self)
+    }
+}
+
+/// Asks the LLM for the most specific categories, when some categories are subcategories
+/// of others.
+const SUBCATEGORY_INSTRUCTION: &str = "Categories with a <parent> are subcategories of that \
+category. Select the most specific categories that apply: a subcategory rather than its parent, \
+and a parent only when none of its subcategories apply.";
+
+/// The rules as categories for the prompt, one per line, with any hints for telling similar
+/// categories apart. Subcategories follow their parents, see [`Rules::tree`].
+fn render_categories(rules: &Rules) -> String {
+    let hierarchical = rules.0.iter().any(|rule| rules.parent_of(rule).is_some());
+    let ordered: Vec<&Rule> = if hierarchical {
+        rules.tree().iter().flat_map(RuleNode::rules).collect()
+    } else {
+        rules.0.iter().collect()
+    };
+    let mut categories = ordered
+        .into_iter()
+        .map(|rule| {
+            let mut category = format!(
+                "Category: <name>{}</name> <description>{}</description>",
+                rule.name, rule.description
+            );
+            if let Some(parent) = rules.parent_of(rule) {
+                category.push_str(&format!(" <parent>{}</parent>", parent.name));
+            }
+            if !rule.examples.is_empty() {
+                category.push_str(&format!(
+                    " <examples>{}</examples>",
+                    rule.examples.join("; ")
+                ));
+            }
+            if !rule.negative_keywords.is_empty() {
+                category.push_str(&format!(
+                    " <not-about>{}</not-about>",
+                    rule.negative_keywords.join("; ")
+                ));
+            }
+            category
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    if hierarchical {
+        categories.push('\n');
+        categories.push_str(SUBCATEGORY_INSTRUCTION);
+    }
+    categories
+}
+
+/// Fill in the placeholders of a prompt template in a single pass, so placeholder-like text in
+/// the rules or the paper is left alone.
+fn render_prompt(template: &str, categories: &str, text: &str) -> String {
+    let mut prompt = String::with_capacity(template.len() + categories.len() + text.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        prompt.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        if let Some(after) = tail.strip_prefix(CATEGORIES_PLACEHOLDER) {
+            prompt.push_str(categories);
+            rest = after;
+        } else if let Some(after) = tail.strip_prefix(TEXT_PLACEHOLDER) {
+            prompt.push_str(text);
+            rest = after;
+        } else {
+            prompt.push('{');
+            rest = &tail[1..];
         }
     }
+    prompt.push_str(rest);
+    prompt
 }
 
 #[derive(Debug, Deserialize)]
@@ -325,54 +889,105 @@ struct MistralQueryResponse {
     title: String,
     authors: Vec<String>,
     summary: String,
+    #[serde(default)]
+    key_findings: Vec<String>,
     #[serde(rename = "abstract")]
     abstract_text: String,
+    #[serde(default)]
+    year: Option<i32>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    confidence: Option<f64>,
     categories: Vec<String>,
 }
 
+/// How many single-character edits a category name from the LLM may be from a rule name
+/// and still be taken to mean that rule, by the shorter of the two: none for names of up to
+/// 4 characters, as any short name is a few edits from any other, e.g. "ML" from "AI", one
+/// for names of up to 8 characters, and 2 for longer ones.
+fn max_category_name_distance(name: &str, rule_name: &str) -> usize {
+    match name.chars().count().min(rule_name.chars().count()) {
+        0..=4 => 0,
+        5..=8 => 1,
+        _ => 2,
+    }
+}
+
+/// A name for comparing category names: lowercase, with runs of whitespace as one space.
+fn normalized_category_name(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// The rule a category name from the LLM means. The LLM is asked for the exact rule names,
+/// but sometimes varies them slightly, e.g. "Programming Language" for "Programming
+/// Languages", so if there is no exact match, the name is compared ignoring case and
+/// whitespace, and then allowing a few edits for longer names (see
+/// [`max_category_name_distance`]). A near-miss that is as close to several rules is
+/// ambiguous and matches none.
+fn match_rule_name<'a>(name: &str, rules: &'a Rules) -> Option<&'a Rule> {
+    if let Some(rule) = rules.0.iter().find(|rule| rule.name == name) {
+        return Some(rule);
+    }
+    let normalized = normalized_category_name(name);
+    let mut nearest: Vec<(usize, &Rule)> = rules
+        .0
+        .iter()
+        .map(|rule| {
+            let rule_name = normalized_category_name(&rule.name);
+            (strsim::levenshtein(&normalized, &rule_name), rule)
+        })
+        .filter(|(distance, rule)| *distance <= max_category_name_distance(name, &rule.name))
+        .collect();
+    nearest.sort_by_key(|(distance, _)| *distance);
+    match nearest.as_slice() {
+        [(distance, rule), rest @ ..] if rest.first().is_none_or(|(next, _)| next > distance) => {
+            tracing::warn!(
+                "LLM response category {:?} taken to mean rule {:?}",
+                name,
+                rule.name
+            );
+            Some(*rule)
+        }
+        _ => None,
+    }
+}
+
+/// The JSON object in an LLM response, without any markdown code fences or prose around it:
+/// everything from the first `{` to the last `}`. Content without braces is returned as is,
+/// and fails to parse as before.
+fn extract_json(content: &str) -> &str {
+    match (content.find('{'), content.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &content[start..=end],
+        _ => content,
+    }
+}
+
+fn llm_http_client(timeout: std::time::Duration, http: &HttpOptions) -> reqwest::Client {
+    http.client_builder().timeout(timeout).build().unwrap()
+}
+
 #[async_trait]
 impl LlmClient for MistralHttpClient {
     async fn query_llm(&self, text: &str, rules: &Rules) -> Result<(ArticleMetadata, Vec<Rule>)> {
-        let url = "https://api.mistral.ai/v1/chat/completions";
+        let url = self.api_url.as_str();
 
-        // Transform the rules to a String:
-        let rules_str = rules
-            .0
-            .iter()
-            .map(|rule| {
-                format!(
-                    "Category: <name>{}</name> <description>{}</description>",
-                    rule.name, rule.description
-                )
-            })
-            .collect::<Vec<String>>()
-            .join("\n");
-
-        let prompt = format!(
-            "Extract Title, Authors, Abstract from the following scientific paper text. \
-            Provide a 1-line summary. \
-            Match the abstract against these categories to select the applicable categories for the \
-            text.  \n\n\
-            <categories>\n\
-            {}\
-            </categories>\n\n\
-            Text:\n\n\
-            <text>\
-            {}\
-            </text>\n\n\
-            Respond ONLY with JSON in this format, where the \"categories\" key has an array of \
-            strings with the exact names of the categories matched to the text:  \n\n\
-            {{\"title\": \"...\", \"authors\": [\"...\"], \"summary\": \"...\", \"abstract\": \"...\", \"categories\": [\"...\",\"...\"]}}",
-            rules_str, text
-        );
+        let prompt = self.prompt(rules, text);
 
-        let body = serde_json::json!({
+        let mut body = serde_json::json!({
             "model": "mistral-small-latest",
             "messages": [
                 { "role": "user", "content": prompt }
             ],
-            "response_format": { "type": "json_object" }
+            "response_format": { "type": "json_object" },
+            "temperature": self.temperature
         });
+        if let Some(max_tokens) = self.max_tokens {
+            body["max_tokens"] = max_tokens.into();
+        }
 
         tracing::debug!("Mistral prompt: {}", prompt);
 
@@ -394,40 +1009,50 @@ impl LlmClient for MistralHttpClient {
         tracing::debug!("Mistral response content: {}", content);
 
         // Deserialize and validate the response shape
-        let response: MistralQueryResponse = serde_json::from_str(content)
+        let response: MistralQueryResponse = serde_json::from_str(extract_json(content))
             .context("Failed to deserialize LLM response into expected shape")?;
 
         let meta = ArticleMetadata {
             title: response.title,
-            authors: response.authors,
+            authors: normalize_authors(&response.authors),
             summary: OneLineSummary(response.summary),
+            key_findings: response
+                .key_findings
+                .into_iter()
+                .map(|finding| finding.trim().to_string())
+                .filter(|finding| !finding.is_empty())
+                .collect(),
             abstract_text: response.abstract_text,
+            year: response.year,
+            tags: normalize_tags(&response.tags),
+            confidence: response.confidence,
+            extraction_quality: None,
+            extracted_text_hash: None,
+            doi: None,
+            arxiv_id: None,
+            token_usage: serde_json::from_value(res["usage"].clone()).ok(),
+            not_extracted: false,
         };
 
-        let unique_matching_rule_names = response.categories.iter().collect::<HashSet<_>>();
-        let rules_by_name = rules
-            .0
-            .iter()
-            .map(|rule: &Rule| (rule.name.clone(), rule))
-            .collect::<HashMap<String, &Rule>>();
-        let (known_matches_rule_names, unknown_matched_rule_names): (Vec<_>, Vec<_>) =
-            unique_matching_rule_names
-                .into_iter()
-                .partition(|name| rules_by_name.contains_key(*name));
+        // Keep the rules in the order the LLM listed them, best match first
+        let mut matching_rules: Vec<Rule> = Vec::new();
+        let mut unknown_matched_rule_names = Vec::new();
+        for name in &response.categories {
+            match match_rule_name(name, rules) {
+                Some(rule) => {
+                    if !matching_rules.iter().any(|known| known.name == rule.name) {
+                        matching_rules.push(rule.clone());
+                    }
+                }
+                None => unknown_matched_rule_names.push(name),
+            }
+        }
         if !unknown_matched_rule_names.is_empty() {
             tracing::warn!(
                 "LLM response included unknown rule names: {:?}",
                 unknown_matched_rule_names
             );
         }
-        tracing::debug!(
-            "LLM response matched rules: {:?}",
-            &known_matches_rule_names
-        );
-        let matching_rules: Vec<Rule> = known_matches_rule_names
-            .into_iter()
-            .filter_map(|name| rules_by_name.get(name).map(|rule| (*rule).clone()))
-            .collect();
 
         tracing::debug!("Extracted metadata: {:#?}", meta);
         tracing::debug!("Found matching rules: {:#?}", matching_rules);
@@ -436,9 +1061,18 @@ impl LlmClient for MistralHttpClient {
     }
 }
 
+#[derive(Default)]
 pub struct FakeDropboxClient {
     pub files: Arc<Mutex<HashMap<String, Vec<u8>>>>,
     pub entries: Arc<Mutex<Vec<DropboxEntry>>>,
+    /// Every path uploaded to and the number of bytes uploaded, in order
+    pub uploads: Arc<Mutex<Vec<(RemotePath, usize)>>>,
+    /// Every move, from and to, in order
+    pub moves: Arc<Mutex<Vec<(RemotePath, RemotePath)>>>,
+    /// Every path deleted, in order
+    pub deletes: Arc<Mutex<Vec<RemotePath>>>,
+    /// Reject uploads outside these folders, like [`DropboxHttpClient`], unless empty
+    pub allowed_upload_prefixes: Vec<String>,
 }
 
 impl FakeDropboxClient {
@@ -446,9 +1080,20 @@ impl FakeDropboxClient {
         Self {
             files: Arc::new(Mutex::new(HashMap::new())),
             entries: Arc::new(Mutex::new(Vec::new())),
+            uploads: Arc::new(Mutex::new(Vec::new())),
+            moves: Arc::new(Mutex::new(Vec::new())),
+            deletes: Arc::new(Mutex::new(Vec::new())),
+            allowed_upload_prefixes: Vec::new(),
         }
     }
 
+    /// Allow uploads under a prefix, in addition to any allowed already.
+    pub fn with_allowed_upload_prefix(mut self, allowed_upload_prefix: &str) -> Self {
+        self.allowed_upload_prefixes
+            .push(allowed_upload_prefix.to_string());
+        self
+    }
+
     pub async fn add_entry(&mut self, entry: DropboxEntry, content: Vec<u8>) {
         let mut entries = self.entries.lock().await;
         entries.push(entry.clone());
@@ -459,9 +1104,50 @@ impl FakeDropboxClient {
 
 #[async_trait]
 impl DropboxClient for FakeDropboxClient {
-    async fn list_folder(&self, _path: &str) -> Result<Vec<DropboxEntry>> {
+    async fn list_folder(&self, path: &str) -> Result<Vec<DropboxEntry>> {
+        let entries = self.entries.lock().await;
+        let prefix = format!("{}/", path.to_lowercase());
+        Ok(entries
+            .iter()
+            .filter(|entry| {
+                entry
+                    .path
+                    .comparison_key()
+                    .strip_prefix(&prefix)
+                    .is_some_and(|name| !name.contains('/'))
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn list_folder_recursive(&self, path: &str) -> Result<Vec<DropboxEntry>> {
+        let entries = self.entries.lock().await;
+        let prefix = format!("{}/", path.to_lowercase());
+        Ok(entries
+            .iter()
+            .filter(|entry| entry.path.comparison_key().starts_with(&prefix))
+            .cloned()
+            .collect())
+    }
+
+    /// Finds listed entries and uploaded files alike.
+    async fn get_metadata(&self, path: &RemotePath) -> Result<Option<DropboxEntry>> {
         let entries = self.entries.lock().await;
-        Ok(entries.clone())
+        if let Some(entry) = entries
+            .iter()
+            .find(|entry| entry.path.comparison_key() == path.comparison_key())
+        {
+            return Ok(Some(entry.clone()));
+        }
+        let files = self.files.lock().await;
+        Ok(files.get(&path.0).map(|content| DropboxEntry {
+            id: DropboxId(format!("id:{}", path.0)),
+            name: path.file_name().unwrap_or_default().to_string(),
+            path: path.clone(),
+            content_hash: dropbox_content_hash(content),
+            size: content.len() as u64,
+            server_modified: None,
+        }))
     }
 
     async fn download_file(&self, id: &DropboxId) -> Result<Vec<u8>> {
@@ -473,7 +1159,14 @@ impl DropboxClient for FakeDropboxClient {
     }
 
     async fn upload_file(&self, path: &RemotePath, content: Vec<u8>) -> Result<()> {
+        if !self.allowed_upload_prefixes.is_empty() {
+            check_upload_allowed(path, &self.allowed_upload_prefixes)?;
+        }
         let mut files = self.files.lock().await;
+        self.uploads
+            .lock()
+            .await
+            .push((path.clone(), content.len()));
         files.insert(path.0.clone(), content);
         Ok(())
     }
@@ -485,12 +1178,14 @@ impl DropboxClient for FakeDropboxClient {
 
     async fn create_folder(&self, path: &str) -> Result<()> {
         let mut entries = self.entries.lock().await;
-        let name = path.split('/').last().unwrap_or_default().to_string();
+        let name = path.rsplit('/').next().unwrap_or_default().to_string();
         entries.push(DropboxEntry {
             id: DropboxId(format!("id:{}", path)),
             name,
             path: RemotePath(path.to_string()),
             content_hash: FileHash(String::new()),
+            size: 0,
+            server_modified: None,
         });
         Ok(())
     }
@@ -509,17 +1204,83 @@ impl DropboxClient for FakeDropboxClient {
         }
         Ok(())
     }
+
+    async fn move_file(&self, from: &RemotePath, to: &RemotePath) -> Result<()> {
+        if !self.allowed_upload_prefixes.is_empty() {
+            check_upload_allowed(to, &self.allowed_upload_prefixes)?;
+        }
+        let mut files = self.files.lock().await;
+        if let Some(content) = files.remove(&from.0) {
+            files.insert(to.0.clone(), content);
+        }
+        let mut entries = self.entries.lock().await;
+        for entry in entries.iter_mut() {
+            if entry.path.comparison_key() == from.comparison_key() {
+                entry.path = to.clone();
+                entry.name = to.file_name().unwrap_or_default().to_string();
+            }
+        }
+        self.moves.lock().await.push((from.clone(), to.clone()));
+        Ok(())
+    }
+
+    async fn delete_file(&self, path: &RemotePath) -> Result<()> {
+        self.files.lock().await.remove(&path.0);
+        self.entries
+            .lock()
+            .await
+            .retain(|entry| entry.path.comparison_key() != path.comparison_key());
+        self.deletes.lock().await.push(path.clone());
+        Ok(())
+    }
+
+    /// A made-up link with the path in it.
+    async fn create_shared_link(&self, path: &RemotePath) -> Result<String> {
+        Ok(format!("https://dropbox.example/s{}?dl=0", path.0))
+    }
 }
 
+/// A canned LLM response: the extracted metadata and the matching rules.
+type FakeLlmResponse = (ArticleMetadata, Vec<Rule>);
+/// An error message, and how many more times to fail with it, or `None` to always fail
+type FakeLlmError = (String, Option<usize>);
+
+/// An LLM client answering with canned responses and errors for texts containing given
+/// snippets. When several snippets are in a text, the one set first wins; setting a snippet
+/// again replaces its answer but keeps its place. Errors are checked before responses.
+#[derive(Default)]
 pub struct FakeMistralClient {
-    pub responses: Arc<Mutex<HashMap<String, (ArticleMetadata, Vec<Rule>)>>>,
+    pub responses: Arc<Mutex<Vec<(String, FakeLlmResponse)>>>,
+    pub errors: Arc<Mutex<Vec<(String, FakeLlmError)>>>,
+}
+
+/// Set the value for a snippet, in place if the snippet is already set, else last.
+fn set_for_snippet<T>(entries: &mut Vec<(String, T)>, snippet: &str, value: T) {
+    match entries.iter_mut().find(|(existing, _)| existing == snippet) {
+        Some((_, existing)) => *existing = value,
+        None => entries.push((snippet.to_string(), value)),
+    }
 }
 
 impl FakeMistralClient {
     pub fn new() -> Self {
-        Self {
-            responses: Arc::new(Mutex::new(HashMap::new())),
-        }
+        Self::default()
+    }
+
+    /// Fail every query for a text containing the snippet with the given error message.
+    pub async fn set_error(&self, text_snippet: &str, err_message: &str) {
+        let mut errors = self.errors.lock().await;
+        set_for_snippet(&mut errors, text_snippet, (err_message.to_string(), None));
+    }
+
+    /// Fail the next `n` queries for a text containing the snippet, then answer as usual.
+    pub async fn set_fail_n_times(&self, text_snippet: &str, n: usize) {
+        let mut errors = self.errors.lock().await;
+        set_for_snippet(
+            &mut errors,
+            text_snippet,
+            (String::from("Simulated transient LLM failure"), Some(n)),
+        );
     }
 
     pub async fn set_response(
@@ -529,13 +1290,29 @@ impl FakeMistralClient {
         matching_rules: Vec<Rule>,
     ) {
         let mut responses = self.responses.lock().await;
-        responses.insert(text_snippet.to_string(), (meta, matching_rules));
+        set_for_snippet(&mut responses, text_snippet, (meta, matching_rules));
     }
 }
 
 #[async_trait]
 impl LlmClient for FakeMistralClient {
     async fn query_llm(&self, text: &str, _rules: &Rules) -> Result<(ArticleMetadata, Vec<Rule>)> {
+        let mut errors = self.errors.lock().await;
+        for (snippet, (message, remaining)) in errors.iter_mut() {
+            if !text.contains(snippet.as_str()) {
+                continue;
+            }
+            match remaining {
+                None => return Err(anyhow::anyhow!(message.clone())),
+                Some(0) => {}
+                Some(n) => {
+                    *n -= 1;
+                    return Err(anyhow::anyhow!(message.clone()));
+                }
+            }
+        }
+        drop(errors);
+
         let responses = self.responses.lock().await;
         for (snippet, response) in responses.iter() {
             if text.contains(snippet) {
@@ -549,7 +1326,17 @@ impl LlmClient for FakeMistralClient {
                 title: "Unknown Paper".to_string(),
                 authors: vec!["Unknown Author".to_string()],
                 summary: OneLineSummary("A paper about something.".to_string()),
+                key_findings: vec![],
                 abstract_text: "This is a default abstract.".to_string(),
+                year: None,
+                tags: vec![],
+                confidence: None,
+                extraction_quality: None,
+                extracted_text_hash: None,
+                doi: None,
+                arxiv_id: None,
+                token_usage: None,
+                not_extracted: false,
             },
             vec![],
         ))
@@ -560,6 +1347,730 @@ impl LlmClient for FakeMistralClient {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_dropbox_content_hash() {
+        assert_eq!(
+            dropbox_content_hash(b"").0,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            dropbox_content_hash(b"abc").0,
+            "4f8b42c22dd3729b519ba6f68d2da7cc5b2d606d05daed5ad5128cc03e6c6358"
+        );
+        // Just over one block, so hashed as two
+        assert_eq!(
+            dropbox_content_hash(&vec![b'a'; CONTENT_HASH_BLOCK_SIZE + 1]).0,
+            "5f858b62ccd88447586305aec6fd53c96747cfebf527cbba129a6dfed47d9624"
+        );
+    }
+
+    #[test]
+    fn test_path_root_header_is_sent_when_configured() {
+        let client = DropboxHttpClient::new("token".to_string(), vec!["/sorted".to_string()])
+            .with_path_root(Some("12345".to_string()));
+        let request = client
+            .post("https://api.dropboxapi.com/2/files/list_folder")
+            .build()
+            .unwrap();
+        let header = request.headers()["Dropbox-API-Path-Root"].to_str().unwrap();
+        let header: serde_json::Value = serde_json::from_str(header).unwrap();
+        assert_eq!(
+            header,
+            serde_json::json!({ ".tag": "root", "root": "12345" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_folder_missing_inbox_has_friendly_error() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/files/list_folder"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(409).set_body_json(serde_json::json!({
+                    "error_summary": "path/not_found/..",
+                    "error": { ".tag": "path", "path": { ".tag": "not_found" } }
+                })),
+            )
+            .mount(&server)
+            .await;
+        let client = DropboxHttpClient::new("token".to_string(), vec!["/sorted".to_string()])
+            .with_base_urls(&server.uri(), &server.uri());
+
+        let error = client.list_folder("/0_inbox").await.unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Inbox path /0_inbox not found — check --inbox"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_folder_empty_inbox_is_empty_list() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/files/list_folder"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "entries": [],
+                    "cursor": "abc",
+                    "has_more": false
+                })),
+            )
+            .mount(&server)
+            .await;
+        let client = DropboxHttpClient::new("token".to_string(), vec!["/sorted".to_string()])
+            .with_base_urls(&server.uri(), &server.uri());
+
+        assert!(client.list_folder("/0_inbox").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_list_folder_parses_gzip_encoded_listing() {
+        use std::io::Write;
+        let listing = serde_json::json!({
+            "entries": [{
+                ".tag": "file",
+                "id": "id:abc",
+                "name": "paper.pdf",
+                "path_display": "/0_inbox/paper.pdf",
+                "content_hash": "hash"
+            }],
+            "cursor": "abc",
+            "has_more": false
+        });
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(listing.to_string().as_bytes()).unwrap();
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/files/list_folder"))
+            .and(wiremock::matchers::header_regex("accept-encoding", "gzip"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200)
+                    .insert_header("content-encoding", "gzip")
+                    .set_body_raw(encoder.finish().unwrap(), "application/json"),
+            )
+            .mount(&server)
+            .await;
+        let client = DropboxHttpClient::new("token".to_string(), vec!["/sorted".to_string()])
+            .with_base_urls(&server.uri(), &server.uri());
+
+        let entries = client.list_folder("/0_inbox").await.unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].id, DropboxId("id:abc".to_string()));
+        assert_eq!(entries[0].path, RemotePath::from("/0_inbox/paper.pdf"));
+    }
+
+    #[tokio::test]
+    async fn test_list_folder_parses_size_and_server_modified() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/files/list_folder"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "entries": [{
+                        ".tag": "file",
+                        "id": "id:abc",
+                        "name": "paper.pdf",
+                        "path_display": "/0_inbox/paper.pdf",
+                        "content_hash": "hash",
+                        "size": 1234567,
+                        "server_modified": "2026-01-02T03:04:05Z",
+                        "client_modified": "2025-12-31T00:00:00Z"
+                    }],
+                    "cursor": "abc",
+                    "has_more": false
+                })),
+            )
+            .mount(&server)
+            .await;
+        let client = DropboxHttpClient::new("token".to_string(), vec!["/sorted".to_string()])
+            .with_base_urls(&server.uri(), &server.uri());
+
+        let entries = client.list_folder("/0_inbox").await.unwrap();
+
+        assert_eq!(entries[0].size, 1234567);
+        assert_eq!(
+            entries[0].server_modified,
+            Some(
+                DateTime::parse_from_rfc3339("2026-01-02T03:04:05Z")
+                    .unwrap()
+                    .with_timezone(&Utc)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recursive_listing_follows_cursor() {
+        let file = |id: &str, path: &str| {
+            serde_json::json!({
+                ".tag": "file",
+                "id": id,
+                "name": path.rsplit('/').next().unwrap(),
+                "path_display": path,
+                "content_hash": "hash"
+            })
+        };
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/files/list_folder"))
+            .and(wiremock::matchers::body_partial_json(
+                serde_json::json!({ "recursive": true }),
+            ))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "entries": [
+                        file("id:top", "/0_inbox/top.pdf"),
+                        { ".tag": "folder", "name": "arxiv", "path_display": "/0_inbox/arxiv" }
+                    ],
+                    "cursor": "page-2",
+                    "has_more": true
+                })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        wiremock::Mock::given(wiremock::matchers::path("/files/list_folder/continue"))
+            .and(wiremock::matchers::body_json(
+                serde_json::json!({ "cursor": "page-2" }),
+            ))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "entries": [file("id:nested", "/0_inbox/arxiv/nested.pdf")],
+                    "cursor": "page-3",
+                    "has_more": false
+                })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = DropboxHttpClient::new("token".to_string(), vec!["/sorted".to_string()])
+            .with_base_urls(&server.uri(), &server.uri());
+
+        let entries = client.list_folder_recursive("/0_inbox").await.unwrap();
+
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| entry.id.0.as_str())
+                .collect::<Vec<_>>(),
+            vec!["id:top", "id:nested"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_shared_link_returns_existing_link() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path(
+            "/sharing/create_shared_link_with_settings",
+        ))
+        .respond_with(
+            wiremock::ResponseTemplate::new(409).set_body_json(serde_json::json!({
+                "error_summary": "shared_link_already_exists/..",
+                "error": { ".tag": "shared_link_already_exists" }
+            })),
+        )
+        .mount(&server)
+        .await;
+        wiremock::Mock::given(wiremock::matchers::path("/sharing/list_shared_links"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "links": [{ "url": "https://www.dropbox.com/s/abc/paper.pdf?dl=0" }],
+                    "has_more": false
+                })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = DropboxHttpClient::new("token".to_string(), vec!["/sorted".to_string()])
+            .with_base_urls(&server.uri(), &server.uri());
+
+        let link = client
+            .create_shared_link(&RemotePath::from("/sorted/paper.pdf"))
+            .await
+            .unwrap();
+
+        assert_eq!(link, "https://www.dropbox.com/s/abc/paper.pdf?dl=0");
+    }
+
+    #[tokio::test]
+    async fn test_llm_request_sets_temperature() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/v1/chat/completions"))
+            .and(wiremock::matchers::body_partial_json(serde_json::json!({
+                "temperature": 0.0,
+                "max_tokens": 500
+            })))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "choices": [{ "message": { "content": serde_json::json!({
+                        "title": "A Paper",
+                        "authors": ["A. Author"],
+                        "summary": "A summary",
+                        "abstract": "An abstract",
+                        "year": 2024,
+                        "tags": [],
+                        "confidence": 0.9,
+                        "categories": []
+                    }).to_string() } }]
+                })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = MistralHttpClient::new("key".to_string())
+            .with_sampling(DEFAULT_TEMPERATURE, Some(500))
+            .with_api_url(&format!("{}/v1/chat/completions", server.uri()));
+
+        let (meta, _) = client.query_llm("text", &Rules(vec![])).await.unwrap();
+
+        assert_eq!(meta.title, "A Paper");
+        assert_eq!(meta.token_usage, None);
+    }
+
+    #[tokio::test]
+    async fn test_llm_prompt_asks_for_summary_language() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/v1/chat/completions"))
+            .and(wiremock::matchers::body_string_contains(
+                "Write the one-line summary and the key findings in Danish.",
+            ))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "choices": [{ "message": { "content": serde_json::json!({
+                        "title": "Et papir",
+                        "authors": [],
+                        "summary": "Et resumé",
+                        "abstract": "",
+                        "categories": []
+                    }).to_string() } }]
+                })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = MistralHttpClient::new("key".to_string())
+            .with_api_url(&format!("{}/v1/chat/completions", server.uri()))
+            .with_summary_language(SummaryLanguage::Named(String::from("Danish")));
+
+        let (meta, _) = client.query_llm("text", &Rules(vec![])).await.unwrap();
+
+        assert_eq!(meta.summary.0, "Et resumé");
+    }
+
+    #[tokio::test]
+    async fn test_llm_token_usage_is_recorded() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/v1/chat/completions"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "choices": [{ "message": { "content": serde_json::json!({
+                        "title": "A Paper",
+                        "authors": [],
+                        "summary": "A summary",
+                        "abstract": "An abstract",
+                        "tags": [],
+                        "categories": []
+                    }).to_string() } }],
+                    "usage": {
+                        "prompt_tokens": 1200,
+                        "completion_tokens": 150,
+                        "total_tokens": 1350
+                    }
+                })),
+            )
+            .mount(&server)
+            .await;
+        let client = MistralHttpClient::new("key".to_string())
+            .with_api_url(&format!("{}/v1/chat/completions", server.uri()));
+
+        let (meta, _) = client.query_llm("text", &Rules(vec![])).await.unwrap();
+
+        assert_eq!(
+            meta.token_usage,
+            Some(crate::models::TokenUsage {
+                prompt_tokens: 1200,
+                completion_tokens: 150,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_llm_requests_send_the_extra_headers() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/v1/chat/completions"))
+            .and(wiremock::matchers::header("X-Gateway-Key", "secret"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "choices": [{ "message": { "content": serde_json::json!({
+                        "title": "A paper",
+                        "authors": [],
+                        "summary": "A summary",
+                        "abstract": "",
+                        "categories": []
+                    }).to_string() } }]
+                })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        let http = HttpOptions::default()
+            .with_header("X-Gateway-Key: secret")
+            .unwrap();
+        let client = MistralHttpClient::new("key".to_string())
+            .with_http_options(&http)
+            .with_timeout(std::time::Duration::from_secs(5))
+            .with_api_url(&format!("{}/v1/chat/completions", server.uri()));
+
+        let (meta, _) = client.query_llm("text", &Rules(vec![])).await.unwrap();
+
+        assert_eq!(meta.title, "A paper");
+    }
+
+    #[tokio::test]
+    async fn test_llm_request_times_out() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/v1/chat/completions"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "choices": [] }))
+                    .set_delay(std::time::Duration::from_secs(5)),
+            )
+            .mount(&server)
+            .await;
+        let client = MistralHttpClient::new("key".to_string())
+            .with_timeout(std::time::Duration::from_millis(100))
+            .with_api_url(&format!("{}/v1/chat/completions", server.uri()));
+
+        let error = client.query_llm("text", &Rules(vec![])).await.unwrap_err();
+
+        assert!(
+            error
+                .chain()
+                .filter_map(|cause| cause.downcast_ref::<reqwest::Error>())
+                .any(|cause| cause.is_timeout()),
+            "expected a time-out, got {:#}",
+            error
+        );
+    }
+
+    fn rules_named(names: &[&str]) -> Rules {
+        Rules(
+            names
+                .iter()
+                .map(|name| Rule {
+                    name: name.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_match_rule_name_exact() {
+        let rules = rules_named(&["Programming Languages", "Physics"]);
+        assert_eq!(
+            match_rule_name("Physics", &rules).map(|rule| rule.name.as_str()),
+            Some("Physics")
+        );
+    }
+
+    #[test]
+    fn test_match_rule_name_ignores_case() {
+        let rules = rules_named(&["Programming Languages", "Physics"]);
+        assert_eq!(
+            match_rule_name("programming languages", &rules).map(|rule| rule.name.as_str()),
+            Some("Programming Languages")
+        );
+    }
+
+    #[test]
+    fn test_match_rule_name_near_miss() {
+        let rules = rules_named(&["Programming Languages", "Physics"]);
+        assert_eq!(
+            match_rule_name("Programming Language", &rules).map(|rule| rule.name.as_str()),
+            Some("Programming Languages")
+        );
+        assert!(match_rule_name("Programming", &rules).is_none());
+        // Equally close to both rules
+        assert!(match_rule_name("Physic", &rules_named(&["Physics", "Physik"])).is_none());
+    }
+
+    #[test]
+    fn test_match_rule_name_short_names_only_ignore_case_and_whitespace() {
+        let rules = rules_named(&["AI", "Databases"]);
+        for other in ["ML", "HCI", "CV", "DB"] {
+            assert!(match_rule_name(other, &rules).is_none(), "{}", other);
+        }
+        assert_eq!(
+            match_rule_name(" ai ", &rules).map(|rule| rule.name.as_str()),
+            Some("AI")
+        );
+        assert_eq!(
+            match_rule_name("Database", &rules).map(|rule| rule.name.as_str()),
+            Some("Databases")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_resumes_from_partial_file() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/files/download"))
+            .and(wiremock::matchers::header("range", "bytes=5-"))
+            .respond_with(wiremock::ResponseTemplate::new(206).set_body_bytes(" world"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = DropboxHttpClient::new("token".to_string(), vec!["/sorted".to_string()])
+            .with_base_urls(&server.uri(), &server.uri());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("paper.pdf");
+        std::fs::write(partial_download_path(&path), "hello").unwrap();
+
+        let size = client
+            .download_to(&DropboxId("id:abc".to_string()), &path)
+            .await
+            .unwrap();
+
+        assert_eq!(size, 11);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello world");
+        assert!(!partial_download_path(&path).exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_starts_over_without_range_support() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/files/download"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_bytes("hello world"))
+            .mount(&server)
+            .await;
+        let client = DropboxHttpClient::new("token".to_string(), vec!["/sorted".to_string()])
+            .with_base_urls(&server.uri(), &server.uri());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("paper.pdf");
+        std::fs::write(partial_download_path(&path), "hello").unwrap();
+
+        client
+            .download_to(&DropboxId("id:abc".to_string()), &path)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello world");
+    }
+
+    #[tokio::test]
+    async fn test_fake_rejects_upload_outside_prefix() {
+        let dropbox = FakeDropboxClient::new().with_allowed_upload_prefix("/sorted");
+
+        let rejected = dropbox
+            .upload_file(&RemotePath::from("/elsewhere/paper.pdf"), vec![1])
+            .await;
+        dropbox
+            .upload_file(&RemotePath::from("/Sorted/pl/paper.pdf"), vec![1])
+            .await
+            .unwrap();
+
+        assert!(
+            rejected
+                .unwrap_err()
+                .to_string()
+                .contains("Upload path not allowed")
+        );
+        assert_eq!(
+            *dropbox.uploads.lock().await,
+            vec![(RemotePath::from("/Sorted/pl/paper.pdf"), 1)]
+        );
+    }
+
+    #[test]
+    fn test_upload_allowed_under_any_prefix() {
+        let prefixes = vec![String::from("/research"), String::from("/shared")];
+
+        for allowed in ["/research/pl/paper.pdf", "/Shared/ai/paper.pdf"] {
+            check_upload_allowed(&RemotePath::from(allowed), &prefixes).unwrap();
+        }
+        let error = check_upload_allowed(&RemotePath::from("/private/paper.pdf"), &prefixes)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("allowed prefixes: /research, /shared"),
+            "{}",
+            error
+        );
+        // A sibling folder whose name starts with an allowed prefix is outside it
+        assert!(
+            check_upload_allowed(&RemotePath::from("/research-private/paper.pdf"), &prefixes)
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fake_logs_move_and_not_upload() {
+        let dropbox = FakeDropboxClient::new();
+        dropbox
+            .upload_file(&RemotePath::from("/0_inbox/paper.pdf"), vec![1, 2, 3])
+            .await
+            .unwrap();
+        dropbox.uploads.lock().await.clear();
+
+        dropbox
+            .move_file(
+                &RemotePath::from("/0_inbox/paper.pdf"),
+                &RemotePath::from("/sorted/paper.pdf"),
+            )
+            .await
+            .unwrap();
+
+        assert!(dropbox.uploads.lock().await.is_empty());
+        assert_eq!(
+            *dropbox.moves.lock().await,
+            vec![(
+                RemotePath::from("/0_inbox/paper.pdf"),
+                RemotePath::from("/sorted/paper.pdf")
+            )]
+        );
+        let files = dropbox.files.lock().await;
+        assert_eq!(files["/sorted/paper.pdf"], vec![1, 2, 3]);
+        assert!(!files.contains_key("/0_inbox/paper.pdf"));
+    }
+
+    #[test]
+    fn test_extract_json_strips_markdown_fences() {
+        assert_eq!(
+            extract_json("```json\n{\"title\": \"A\"}\n```"),
+            r#"{"title": "A"}"#
+        );
+    }
+
+    #[test]
+    fn test_extract_json_strips_surrounding_prose() {
+        assert_eq!(
+            extract_json("Here is the metadata:\n{\"a\": {\"b\": 1}}\nHope this helps!"),
+            r#"{"a": {"b": 1}}"#
+        );
+    }
+
+    #[test]
+    fn test_extract_json_keeps_clean_json() {
+        assert_eq!(extract_json(r#"{"a": 1}"#), r#"{"a": 1}"#);
+        assert_eq!(extract_json("no json"), "no json");
+    }
+
+    #[test]
+    fn test_custom_prompt_template_is_rendered() {
+        let client = MistralHttpClient::new("key".to_string())
+            .with_prompt_template(
+                "Fasse auf Deutsch zusammen. Kategorien: {categories}. Text: {text}. JSON: {\"title\": \"...\"}"
+                    .to_string(),
+            )
+            .unwrap();
+        assert_eq!(
+            render_prompt(&client.prompt_template, "PL", "A paper about {text}"),
+            "Fasse auf Deutsch zusammen. Kategorien: PL. Text: A paper about {text}. JSON: {\"title\": \"...\"}"
+        );
+    }
+
+    #[test]
+    fn test_summary_language_is_only_asked_for_when_given_or_by_default_prompt() {
+        let rules = Rules(vec![]);
+        let default = MistralHttpClient::new("key".to_string());
+        assert!(
+            default
+                .prompt(&rules, "text")
+                .ends_with("Write the one-line summary and the key findings in English.")
+        );
+        // A custom template says which language it wants
+        let custom = default
+            .with_prompt_template("Fasse auf Deutsch zusammen: {categories} {text}".to_string())
+            .unwrap();
+        assert_eq!(
+            custom.prompt(&rules, "text"),
+            "Fasse auf Deutsch zusammen:  text"
+        );
+        let custom = custom.with_summary_language(SummaryLanguage::Named(String::from("Danish")));
+        assert!(
+            custom
+                .prompt(&rules, "text")
+                .ends_with("Write the one-line summary and the key findings in Danish.")
+        );
+    }
+
+    #[test]
+    fn test_prompt_includes_rule_hints() {
+        let rules = Rules::from(vec![
+            Rule {
+                name: String::from("Programming Languages"),
+                description: String::from("Type systems and compilers"),
+                path: RemotePath::from("/sorted/pl"),
+                examples: vec![String::from("Gradual typing for Python")],
+                negative_keywords: vec![String::from("agile processes")],
+                ..Default::default()
+            },
+            Rule {
+                name: String::from("Software Engineering"),
+                description: String::from("Building software"),
+                path: RemotePath::from("/sorted/se"),
+                ..Default::default()
+            },
+        ]);
+
+        let prompt = render_prompt(DEFAULT_PROMPT_TEMPLATE, &render_categories(&rules), "text");
+
+        assert!(prompt.contains(
+            "<name>Programming Languages</name> <description>Type systems and compilers</description> \
+             <examples>Gradual typing for Python</examples> <not-about>agile processes</not-about>\n"
+        ));
+        assert!(prompt.contains(
+            "<name>Software Engineering</name> <description>Building software</description></categories>"
+        ));
+    }
+
+    #[test]
+    fn test_prompt_lists_subcategories_after_their_parents() {
+        let rules = Rules::from(vec![
+            Rule {
+                name: String::from("NLP"),
+                description: String::from("Language models"),
+                path: RemotePath::from("/sorted/cs/ai/nlp"),
+                parent: Some(String::from("AI")),
+                ..Default::default()
+            },
+            Rule {
+                name: String::from("AI"),
+                description: String::from("Machine learning"),
+                path: RemotePath::from("/sorted/cs/ai"),
+                ..Default::default()
+            },
+        ]);
+
+        assert_eq!(
+            render_categories(&rules),
+            format!(
+                "Category: <name>AI</name> <description>Machine learning</description>\n\
+                 Category: <name>NLP</name> <description>Language models</description> \
+                 <parent>AI</parent>\n{}",
+                SUBCATEGORY_INSTRUCTION
+            )
+        );
+    }
+
+    #[test]
+    fn test_prompt_template_without_placeholders_is_rejected() {
+        let error = MistralHttpClient::new("key".to_string())
+            .with_prompt_template("Summarize {text}".to_string())
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Prompt template is missing the placeholders {categories}"
+        );
+    }
+
+    #[test]
+    fn test_path_root_header_is_absent_by_default() {
+        let client = DropboxHttpClient::new("token".to_string(), vec!["/sorted".to_string()]);
+        let request = client
+            .post("https://api.dropboxapi.com/2/files/list_folder")
+            .build()
+            .unwrap();
+        assert!(!request.headers().contains_key("Dropbox-API-Path-Root"));
+    }
+
     #[tokio::test]
     async fn test_fake_dropbox_client_create_folder_if_not_exists() {
         let client = FakeDropboxClient::new();
@@ -571,7 +2082,67 @@ mod tests {
         assert!(client.folder_exists("/a/b").await.unwrap());
         assert!(client.folder_exists("/a/b/c").await.unwrap());
 
-        let entries = client.list_folder("").await.unwrap();
+        let entries = client.list_folder_recursive("").await.unwrap();
         assert_eq!(entries.len(), 3);
+        assert_eq!(client.list_folder("").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fake_llm_picks_first_set_of_overlapping_snippets() {
+        let client = FakeMistralClient::new();
+        let answer = |title: &str| ArticleMetadata {
+            title: title.to_string(),
+            ..Default::default()
+        };
+        client
+            .set_response("Gradual", answer("First"), vec![])
+            .await;
+        client
+            .set_response("Gradual Typing", answer("Second"), vec![])
+            .await;
+        let rules = Rules::from(vec![]);
+
+        for _ in 0..20 {
+            let (meta, _) = client
+                .query_llm("On Gradual Typing for Objects", &rules)
+                .await
+                .unwrap();
+            assert_eq!(meta.title, "First");
+        }
+
+        // Setting a snippet again replaces its answer in place
+        client
+            .set_response("Gradual", answer("Third"), vec![])
+            .await;
+        let (meta, _) = client
+            .query_llm("On Gradual Typing for Objects", &rules)
+            .await
+            .unwrap();
+        assert_eq!(meta.title, "Third");
+    }
+
+    #[tokio::test]
+    async fn test_dropbox_download_rejects_bare_file_name() {
+        let client = DropboxHttpClient::new(String::from("token"), vec![String::from("/sorted")]);
+
+        let err = client
+            .download_file(&DropboxId(String::from("paper.pdf")))
+            .await
+            .unwrap_err();
+
+        assert!(
+            err.to_string()
+                .contains("expected a Dropbox file id (id:...) or a path starting with '/'"),
+            "unexpected error: {:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_validate_download_reference_accepts_ids_and_rooted_paths() {
+        assert!(validate_download_reference(&DropboxId(String::from("id:abc123"))).is_ok());
+        assert!(
+            validate_download_reference(&DropboxId(String::from("/0_inbox/paper.pdf"))).is_ok()
+        );
     }
 }
@@ -0,0 +1,287 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_CONFIG_FILE: &str = "sci-librarian.toml";
+pub const DEFAULT_WORK_DIRECTORY: &str = "working";
+pub const DEFAULT_INBOX: &str = "";
+pub const DEFAULT_ALLOWED_UPLOAD_PREFIX: &str = "/sorted";
+/// Our own generated files: indexes, sidecars and citation exports
+pub const DEFAULT_SKIPPED_SUFFIXES: &[&str] = &[".md", ".jsonld", ".bib", ".ris"];
+/// Environment variables holding secrets, which are never shown
+pub const SECRET_ENV_VARS: &[&str] = &["DROPBOX_TOKEN", "MISTRAL_API_KEY"];
+/// How a secret that is set is shown
+pub const REDACTED: &str = "***";
+
+/// The config file: named profiles, each given as a `[profile.<name>]` table.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub profile: BTreeMap<String, Profile>,
+}
+
+/// Settings for one library. Anything not given falls back to the default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// One inbox, a comma-separated list of inboxes or a list of inboxes
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub inbox: Option<Vec<String>>,
+    pub rules: Option<PathBuf>,
+    /// One folder uploads are allowed under, a comma-separated list or a list of them
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub allowed_upload_prefix: Option<Vec<String>>,
+    pub work_directory: Option<PathBuf>,
+    pub dropbox_path_root: Option<String>,
+    /// File name endings of files that are never processed, e.g. `.md`
+    pub skip_suffixes: Option<Vec<String>>,
+    /// Whether to sync the files in the subfolders of the inboxes too
+    pub inbox_recursive: Option<bool>,
+}
+
+impl Config {
+    /// Parse a config file from TOML.
+    pub fn from_toml(toml: &str) -> Result<Config> {
+        toml::from_str(toml).context("Invalid config file")
+    }
+
+    /// Load a config file from TOML.
+    pub fn load(path: &Path) -> Result<Config> {
+        let toml = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.to_string_lossy()))?;
+        Self::from_toml(&toml)
+            .with_context(|| format!("Failed to parse config file {}", path.to_string_lossy()))
+    }
+
+    /// Get a profile by name.
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        self.profile.get(name).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Unknown profile '{}'. Available profiles: {}",
+                name,
+                self.profile.keys().cloned().collect::<Vec<_>>().join(", ")
+            )
+        })
+    }
+}
+
+/// The settings for a run, after applying command line flags, the selected profile and
+/// the defaults, in that order of precedence.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Settings {
+    pub inboxes: Vec<String>,
+    pub rules: Option<PathBuf>,
+    pub allowed_upload_prefixes: Vec<String>,
+    pub work_directory: PathBuf,
+    pub dropbox_path_root: Option<String>,
+    pub skip_suffixes: Vec<String>,
+    pub inbox_recursive: bool,
+}
+
+impl Settings {
+    /// Resolve the settings from the values given on the command line and the selected
+    /// profile, if any.
+    pub fn resolve(cli: Profile, profile: Option<&Profile>) -> Settings {
+        let profile = profile.cloned().unwrap_or_default();
+        Settings {
+            inboxes: cli
+                .inbox
+                .or(profile.inbox)
+                .unwrap_or_else(|| vec![String::from(DEFAULT_INBOX)]),
+            rules: cli.rules.or(profile.rules),
+            allowed_upload_prefixes: cli
+                .allowed_upload_prefix
+                .or(profile.allowed_upload_prefix)
+                .unwrap_or_else(|| vec![String::from(DEFAULT_ALLOWED_UPLOAD_PREFIX)]),
+            work_directory: cli
+                .work_directory
+                .or(profile.work_directory)
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_WORK_DIRECTORY)),
+            dropbox_path_root: cli.dropbox_path_root.or(profile.dropbox_path_root),
+            skip_suffixes: cli
+                .skip_suffixes
+                .or(profile.skip_suffixes)
+                .unwrap_or_else(|| {
+                    DEFAULT_SKIPPED_SUFFIXES
+                        .iter()
+                        .map(|suffix| suffix.to_string())
+                        .collect()
+                }),
+            inbox_recursive: cli
+                .inbox_recursive
+                .or(profile.inbox_recursive)
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// The settings in effect and where they came from, for showing, with the secrets in the
+/// environment redacted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EffectiveConfig {
+    /// The config file read, if any
+    pub config_file: Option<PathBuf>,
+    /// The profile selected, if any
+    pub profile: Option<String>,
+    #[serde(flatten)]
+    pub settings: Settings,
+    /// Each of [`SECRET_ENV_VARS`], as [`REDACTED`] if set
+    pub secrets: BTreeMap<String, Option<String>>,
+}
+
+impl EffectiveConfig {
+    /// Describe the settings, looking up the secrets with `env`, e.g. `std::env::var`.
+    pub fn new(
+        settings: Settings,
+        config_file: Option<PathBuf>,
+        profile: Option<String>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Self {
+        let secrets = SECRET_ENV_VARS
+            .iter()
+            .map(|name| {
+                let value = env(name)
+                    .filter(|value| !value.is_empty())
+                    .map(|_| REDACTED.to_string());
+                (name.to_string(), value)
+            })
+            .collect();
+        EffectiveConfig {
+            config_file,
+            profile,
+            settings,
+            secrets,
+        }
+    }
+}
+
+/// Read a string of comma-separated values, or a list, as a list.
+fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<String>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(Some(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => value.split(',').map(|s| s.trim().to_string()).collect(),
+        OneOrMany::Many(values) => values,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+[profile.work]
+inbox = "/work/inbox"
+rules = "work-rules.yaml"
+allowed_upload_prefix = "/work/sorted"
+work_directory = "working-work"
+
+[profile.personal]
+inbox = "/personal/inbox"
+"#;
+
+    #[test]
+    fn test_profile_inbox_is_used_when_selected() {
+        let config = Config::from_toml(CONFIG).unwrap();
+        let settings = Settings::resolve(Profile::default(), Some(config.profile("work").unwrap()));
+        assert_eq!(
+            settings,
+            Settings {
+                inboxes: vec![String::from("/work/inbox")],
+                rules: Some(PathBuf::from("work-rules.yaml")),
+                allowed_upload_prefixes: vec![String::from("/work/sorted")],
+                work_directory: PathBuf::from("working-work"),
+                dropbox_path_root: None,
+                skip_suffixes: vec![
+                    String::from(".md"),
+                    String::from(".jsonld"),
+                    String::from(".bib"),
+                    String::from(".ris")
+                ],
+                inbox_recursive: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_effective_config_shows_flag_over_profile_and_redacts_token() {
+        let config = Config::from_toml(CONFIG).unwrap();
+        let flags = Profile {
+            allowed_upload_prefix: Some(vec![String::from("/from/flag")]),
+            ..Default::default()
+        };
+        let settings = Settings::resolve(flags, Some(config.profile("work").unwrap()));
+
+        let effective = EffectiveConfig::new(
+            settings,
+            Some(PathBuf::from("sci-librarian.toml")),
+            Some(String::from("work")),
+            |name| (name == "DROPBOX_TOKEN").then(|| String::from("sl.secret-token")),
+        );
+        let json = serde_json::to_value(&effective).unwrap();
+
+        assert_eq!(
+            json["allowed_upload_prefixes"],
+            serde_json::json!(["/from/flag"])
+        );
+        assert_eq!(json["work_directory"], "working-work");
+        assert_eq!(json["profile"], "work");
+        assert_eq!(json["secrets"]["DROPBOX_TOKEN"], REDACTED);
+        assert!(json["secrets"]["MISTRAL_API_KEY"].is_null());
+        assert!(!json.to_string().contains("sl.secret-token"));
+    }
+
+    #[test]
+    fn test_explicit_inbox_overrides_profile() {
+        let config = Config::from_toml(CONFIG).unwrap();
+        let cli = Profile {
+            inbox: Some(vec![String::from("/elsewhere")]),
+            ..Default::default()
+        };
+        let settings = Settings::resolve(cli, Some(config.profile("personal").unwrap()));
+        assert_eq!(settings.inboxes, vec!["/elsewhere"]);
+        assert_eq!(
+            settings.allowed_upload_prefixes,
+            vec![DEFAULT_ALLOWED_UPLOAD_PREFIX]
+        );
+        assert_eq!(
+            settings.work_directory,
+            PathBuf::from(DEFAULT_WORK_DIRECTORY)
+        );
+    }
+
+    #[test]
+    fn test_profile_inbox_may_list_several_inboxes() {
+        let config = Config::from_toml(
+            r#"
+[profile.list]
+inbox = ["/0_inbox", "/shared/incoming"]
+
+[profile.commas]
+inbox = "/0_inbox, /shared/incoming"
+"#,
+        )
+        .unwrap();
+        for name in ["list", "commas"] {
+            let settings =
+                Settings::resolve(Profile::default(), Some(config.profile(name).unwrap()));
+            assert_eq!(settings.inboxes, vec!["/0_inbox", "/shared/incoming"]);
+        }
+    }
+
+    #[test]
+    fn test_unknown_profile_lists_available_profiles() {
+        let config = Config::from_toml(CONFIG).unwrap();
+        let error = config.profile("home").unwrap_err().to_string();
+        assert!(error.contains("'home'"));
+        assert!(error.contains("personal, work"));
+    }
+}
@@ -0,0 +1,195 @@
+use crate::clients::{DropboxClient, LlmClient};
+use crate::models::{Rules, WorkDirectory};
+use anyhow::Result;
+use std::fs;
+
+/// The outcome of a single preflight check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    pub passed: bool,
+    pub detail: Option<String>,
+}
+
+impl Check {
+    fn from_result(name: impl Into<String>, result: Result<()>) -> Self {
+        Self {
+            name: name.into(),
+            passed: result.is_ok(),
+            detail: result.err().map(|e| format!("{:#}", e)),
+        }
+    }
+}
+
+/// Everything the preflight checks look at. The Dropbox client is absent when its token is.
+pub struct Preflight<'a> {
+    /// Required environment variables and whether they are set
+    pub env_vars: Vec<(&'a str, bool)>,
+    pub dropbox: Option<&'a dyn DropboxClient>,
+    /// The LLM client, or why it could not be built, e.g. a missing API key or an invalid
+    /// prompt template
+    pub llm: Result<&'a dyn LlmClient>,
+    pub inboxes: &'a [String],
+    pub work_dir: &'a WorkDirectory,
+    pub rules: Result<Rules>,
+    pub allowed_upload_prefixes: &'a [String],
+}
+
+/// Text sent to the LLM to check that it responds.
+const LLM_CHECK_TEXT: &str = "Sci-Librarian Health Check\nby Jane Doe\n\nThis is a test.";
+
+/// Run all preflight checks, in order, without stopping at the first failure.
+pub async fn run_checks(preflight: Preflight<'_>) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    for (name, is_set) in &preflight.env_vars {
+        let result = if *is_set {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("{} is not set", name))
+        };
+        checks.push(Check::from_result(
+            format!("Environment variable {}", name),
+            result,
+        ));
+    }
+
+    for inbox in preflight.inboxes {
+        let dropbox_result = match preflight.dropbox {
+            Some(dropbox) => dropbox.list_folder(inbox).await.map(|_| ()),
+            None => Err(anyhow::anyhow!("No Dropbox client (missing token)")),
+        };
+        checks.push(Check::from_result(
+            format!("Dropbox can list inbox '{}'", inbox),
+            dropbox_result,
+        ));
+    }
+
+    let llm_result = match preflight.llm {
+        Ok(llm) => llm
+            .query_llm(LLM_CHECK_TEXT, &Rules(vec![]))
+            .await
+            .map(|_| ()),
+        Err(e) => Err(e.context("No LLM client")),
+    };
+    checks.push(Check::from_result("LLM responds", llm_result));
+
+    checks.push(Check::from_result(
+        format!(
+            "Work directory {} is writable",
+            preflight.work_dir.0.to_string_lossy()
+        ),
+        check_writable(preflight.work_dir),
+    ));
+
+    let rules_result = preflight
+        .rules
+        .and_then(|rules| rules.validate_targets(preflight.allowed_upload_prefixes));
+    checks.push(Check::from_result(
+        format!(
+            "Rules parse and targets are under {}",
+            preflight.allowed_upload_prefixes.join(", ")
+        ),
+        rules_result,
+    ));
+
+    checks
+}
+
+fn check_writable(work_dir: &WorkDirectory) -> Result<()> {
+    let probe = work_dir.0.join(".sci-librarian-write-check");
+    fs::write(&probe, b"ok")?;
+    fs::remove_file(&probe)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::{FakeDropboxClient, FakeMistralClient};
+    use crate::models::{RemotePath, Rule};
+
+    fn rules(path: &str) -> Rules {
+        Rules::from(vec![Rule {
+            name: String::from("AI"),
+            description: String::from("Artificial intelligence"),
+            path: RemotePath::from(path),
+            ..Default::default()
+        }])
+    }
+
+    #[tokio::test]
+    async fn test_run_checks_all_pass_with_fakes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let work_dir = WorkDirectory(temp_dir.path().to_path_buf());
+        let dropbox = FakeDropboxClient::new();
+        let llm = FakeMistralClient::new();
+
+        let checks = run_checks(Preflight {
+            env_vars: vec![("DROPBOX_TOKEN", true), ("MISTRAL_API_KEY", true)],
+            dropbox: Some(&dropbox),
+            llm: Ok(&llm),
+            inboxes: &[String::from("/0_inbox")],
+            work_dir: &work_dir,
+            rules: Ok(rules("/sorted/ai")),
+            allowed_upload_prefixes: &[String::from("/sorted")],
+        })
+        .await;
+
+        assert_eq!(checks.len(), 6);
+        assert!(checks.iter().all(|c| c.passed), "{:#?}", checks);
+    }
+
+    #[tokio::test]
+    async fn test_run_checks_fails_on_missing_rule_target() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let work_dir = WorkDirectory(temp_dir.path().to_path_buf());
+        let dropbox = FakeDropboxClient::new();
+        let llm = FakeMistralClient::new();
+
+        let checks = run_checks(Preflight {
+            env_vars: vec![("DROPBOX_TOKEN", true), ("MISTRAL_API_KEY", true)],
+            dropbox: Some(&dropbox),
+            llm: Ok(&llm),
+            inboxes: &[String::from("/0_inbox")],
+            work_dir: &work_dir,
+            rules: Ok(rules("")),
+            allowed_upload_prefixes: &[String::from("/sorted")],
+        })
+        .await;
+
+        let failed: Vec<&Check> = checks.iter().filter(|c| !c.passed).collect();
+        assert_eq!(failed.len(), 1);
+        assert!(failed[0].name.starts_with("Rules parse"));
+        assert!(failed[0].detail.as_deref().unwrap().contains("'AI'"));
+    }
+
+    #[tokio::test]
+    async fn test_run_checks_reports_why_there_is_no_llm_client() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let work_dir = WorkDirectory(temp_dir.path().to_path_buf());
+        let dropbox = FakeDropboxClient::new();
+
+        let checks = run_checks(Preflight {
+            env_vars: vec![("DROPBOX_TOKEN", true), ("MISTRAL_API_KEY", true)],
+            dropbox: Some(&dropbox),
+            llm: Err(anyhow::anyhow!("Invalid prompt template prompt.txt")),
+            inboxes: &[String::from("/0_inbox")],
+            work_dir: &work_dir,
+            rules: Ok(rules("/sorted/ai")),
+            allowed_upload_prefixes: &[String::from("/sorted")],
+        })
+        .await;
+
+        let failed: Vec<&Check> = checks.iter().filter(|c| !c.passed).collect();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].name, "LLM responds");
+        assert!(
+            failed[0]
+                .detail
+                .as_deref()
+                .unwrap()
+                .contains("Invalid prompt template prompt.txt")
+        );
+    }
+}
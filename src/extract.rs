@@ -0,0 +1,437 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::io::{Cursor, Read};
+
+/// Only the start of a document is needed to classify it: the first pages of a PDF, the
+/// first chapters of an EPUB, or about as much plain text.
+const MAX_PAGES: usize = 5;
+
+/// About the length of [`MAX_PAGES`] pages of a paper.
+const MAX_TEXT_CHARS: usize = MAX_PAGES * 4000;
+
+/// A first page with less text than this, e.g. a cover page or a scanned page, is not
+/// enough to classify a paper by, see [`extract_text_fast`].
+const MIN_FIRST_PAGE_CHARS: usize = 1000;
+
+/// Gets the text to classify out of the content of a file.
+pub trait TextExtractor: Send + Sync {
+    fn extract(&self, content: &[u8]) -> Result<String>;
+}
+
+/// Extracts the text of the first pages of a PDF with lopdf.
+pub struct PdfExtractor;
+
+/// Extracts the text of the first chapters of an EPUB, in reading order, stripped of markup.
+pub struct EpubExtractor;
+
+/// Passes UTF-8 text through as it is.
+pub struct PlainTextExtractor;
+
+/// The SHA-256 of extracted text, in hex, to tell when a file's text changed between runs,
+/// e.g. after switching between OCR and the text embedded in a PDF.
+pub fn text_hash(text: &str) -> String {
+    hex::encode(Sha256::digest(text.as_bytes()))
+}
+
+/// The extractor for content, chosen by sniffing it rather than trusting its file name.
+pub fn extractor_for(content: &[u8]) -> Option<&'static dyn TextExtractor> {
+    if content.starts_with(b"%PDF-") {
+        Some(&PdfExtractor)
+    } else if is_epub(content) {
+        Some(&EpubExtractor)
+    } else if std::str::from_utf8(content).is_ok() {
+        Some(&PlainTextExtractor)
+    } else {
+        None
+    }
+}
+
+/// Extract the text of a PDF, EPUB or plain text file.
+pub fn extract_text(content: &[u8]) -> Result<String> {
+    let extractor = extractor_for(content)
+        .ok_or_else(|| anyhow::anyhow!("Unsupported file type, not a PDF, EPUB or text"))?;
+    let text = extractor.extract(content)?;
+    if text.trim().is_empty() {
+        return Err(anyhow::anyhow!("No text extracted from file"));
+    }
+    Ok(text)
+}
+
+/// Like [`extract_text`], but reading the first `max_pages` pages of a PDF instead of the
+/// usual [`MAX_PAGES`], e.g. to see what text the later pages of a paper yield.
+pub fn extract_text_pages(content: &[u8], max_pages: usize) -> Result<String> {
+    if !content.starts_with(b"%PDF-") {
+        return extract_text(content);
+    }
+    let text = pdf_text(&load_pdf(content)?, max_pages);
+    if text.trim().is_empty() {
+        return Err(anyhow::anyhow!("No text extracted from PDF"));
+    }
+    Ok(text)
+}
+
+/// Like [`extract_text`], but reading only the first page of a PDF, where the title,
+/// authors and abstract are, unless it has less than [`MIN_FIRST_PAGE_CHARS`] characters of
+/// text, when the first [`MAX_PAGES`] pages are read as usual. Saves time and LLM tokens
+/// on bulk runs of long PDFs.
+pub fn extract_text_fast(content: &[u8]) -> Result<String> {
+    if !content.starts_with(b"%PDF-") {
+        return extract_text(content);
+    }
+    let doc = load_pdf(content)?;
+    let first_page = pdf_text(&doc, 1);
+    if first_page.trim().chars().count() >= MIN_FIRST_PAGE_CHARS {
+        return Ok(first_page);
+    }
+    tracing::debug!(
+        "First page has little text, extracting the first {} pages",
+        MAX_PAGES
+    );
+    let text = pdf_text(&doc, MAX_PAGES);
+    if text.trim().is_empty() {
+        return Err(anyhow::anyhow!("No text extracted from PDF"));
+    }
+    Ok(text)
+}
+
+/// An EPUB is a ZIP archive starting with an uncompressed `mimetype` entry, whose content
+/// follows its name in the first local file header.
+pub(crate) fn is_epub(content: &[u8]) -> bool {
+    const MIMETYPE: &[u8] = b"mimetypeapplication/epub+zip";
+    content.starts_with(b"PK\x03\x04")
+        && content[..content.len().min(128)]
+            .windows(MIMETYPE.len())
+            .any(|window| window == MIMETYPE)
+}
+
+impl TextExtractor for PdfExtractor {
+    fn extract(&self, content: &[u8]) -> Result<String> {
+        let doc = load_pdf(content)?;
+        // Extract from first 5 pages as per PRD
+        let text = pdf_text(&doc, MAX_PAGES);
+
+        if text.trim().is_empty() {
+            return Err(anyhow::anyhow!("No text extracted from PDF"));
+        }
+
+        Ok(text)
+    }
+}
+
+fn load_pdf(content: &[u8]) -> Result<lopdf::Document> {
+    // lopdf can panic on malformed input, which must not take down the worker
+    let doc = std::panic::catch_unwind(|| lopdf::Document::load_mem(content))
+        .map_err(|_| anyhow::anyhow!("PDF parser panicked on malformed input"))??;
+    Ok(doc)
+}
+
+/// Whether content is a PDF without a text layer, as a scan is: none of its first
+/// [`MAX_PAGES`] pages has text, and some are only images.
+pub fn is_scanned_pdf(content: &[u8]) -> bool {
+    if !content.starts_with(b"%PDF-") {
+        return false;
+    }
+    let Ok(doc) = load_pdf(content) else {
+        return false;
+    };
+    pdf_text(&doc, MAX_PAGES).trim().is_empty()
+        && doc
+            .get_pages()
+            .into_iter()
+            .take(MAX_PAGES)
+            .any(|(number, page_id)| is_image_only_page(&doc, number, page_id))
+}
+
+/// Whether a page has images but no text, like a scanned page.
+fn is_image_only_page(doc: &lopdf::Document, number: u32, page_id: lopdf::ObjectId) -> bool {
+    let has_text = doc
+        .extract_text(&[number])
+        .is_ok_and(|text| !text.trim().is_empty());
+    !has_text && page_has_images(doc, page_id)
+}
+
+/// Whether a page, or the page tree it inherits resources from, has image XObjects.
+fn page_has_images(doc: &lopdf::Document, page_id: lopdf::ObjectId) -> bool {
+    let Ok((resources, inherited)) = doc.get_page_resources(page_id) else {
+        return false;
+    };
+    resources
+        .into_iter()
+        .chain(
+            inherited
+                .iter()
+                .filter_map(|id| doc.get_dictionary(*id).ok()),
+        )
+        .filter_map(|resources| resources.get(b"XObject").ok())
+        .filter_map(|xobjects| doc.dereference(xobjects).ok()?.1.as_dict().ok())
+        .flat_map(|xobjects| xobjects.iter())
+        .filter_map(|(_, xobject)| doc.dereference(xobject).ok()?.1.as_stream().ok())
+        .any(|stream| {
+            stream
+                .dict
+                .get(b"Subtype")
+                .and_then(|subtype| subtype.as_name())
+                .is_ok_and(|subtype| subtype == b"Image")
+        })
+}
+
+/// The text of the first `max_pages` pages of a PDF, skipping pages without text.
+fn pdf_text(doc: &lopdf::Document, max_pages: usize) -> String {
+    let mut text = String::new();
+    let max_pages = std::cmp::min(doc.get_pages().len(), max_pages);
+
+    for i in 1..=max_pages {
+        if let Ok(page_text) = doc.extract_text(&[i as u32]) {
+            text.push_str(&page_text);
+            text.push('\n');
+        }
+    }
+    text
+}
+
+impl TextExtractor for EpubExtractor {
+    fn extract(&self, content: &[u8]) -> Result<String> {
+        let mut archive =
+            zip::ZipArchive::new(Cursor::new(content)).context("Failed to open EPUB archive")?;
+
+        let container = read_entry(&mut archive, "META-INF/container.xml")?;
+        let container =
+            roxmltree::Document::parse(&container).context("Failed to parse EPUB container.xml")?;
+        let package_path = container
+            .descendants()
+            .find(|node| node.has_tag_name("rootfile"))
+            .and_then(|node| node.attribute("full-path"))
+            .ok_or_else(|| anyhow::anyhow!("EPUB container.xml names no package document"))?
+            .to_string();
+
+        let package = read_entry(&mut archive, &package_path)?;
+        let package = roxmltree::Document::parse(&package)
+            .with_context(|| format!("Failed to parse EPUB package document {}", package_path))?;
+        // Chapter paths in the package document are relative to it
+        let base = match package_path.rsplit_once('/') {
+            Some((folder, _)) => format!("{}/", folder),
+            None => String::new(),
+        };
+        let chapters: Vec<String> = package
+            .descendants()
+            .filter(|node| node.has_tag_name("itemref"))
+            .filter_map(|itemref| itemref.attribute("idref"))
+            .filter_map(|idref| {
+                package
+                    .descendants()
+                    .find(|node| node.has_tag_name("item") && node.attribute("id") == Some(idref))
+                    .and_then(|item| item.attribute("href"))
+            })
+            .map(|href| format!("{}{}", base, href))
+            .collect();
+
+        let mut text = String::new();
+        for chapter in chapters.iter().take(MAX_PAGES) {
+            text.push_str(&strip_markup(&read_entry(&mut archive, chapter)?));
+            text.push('\n');
+        }
+        Ok(truncate_chars(text, MAX_TEXT_CHARS))
+    }
+}
+
+impl TextExtractor for PlainTextExtractor {
+    fn extract(&self, content: &[u8]) -> Result<String> {
+        let text = std::str::from_utf8(content).context("Text is not valid UTF-8")?;
+        Ok(truncate_chars(text.to_string(), MAX_TEXT_CHARS))
+    }
+}
+
+fn read_entry(archive: &mut zip::ZipArchive<Cursor<&[u8]>>, name: &str) -> Result<String> {
+    let mut entry = archive
+        .by_name(name)
+        .with_context(|| format!("EPUB has no {}", name))?;
+    let mut text = String::new();
+    entry
+        .read_to_string(&mut text)
+        .with_context(|| format!("Failed to read {} from EPUB", name))?;
+    Ok(text)
+}
+
+/// The text of an (X)HTML document: its tags dropped, common entities decoded and runs of
+/// whitespace collapsed. Tags are not parsed as XML, as EPUBs often use HTML entities like
+/// `&nbsp;` that XML parsers reject.
+fn strip_markup(html: &str) -> String {
+    let body = match html.find("<body") {
+        Some(start) => &html[start..],
+        None => html,
+    };
+    let mut text = String::with_capacity(body.len());
+    let mut in_tag = false;
+    for c in body.chars() {
+        match c {
+            '<' => {
+                in_tag = true;
+                // Tags such as </p> and <br/> separate words
+                text.push(' ');
+            }
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn truncate_chars(mut text: String, max_chars: usize) -> String {
+    if let Some((index, _)) = text.char_indices().nth(max_chars) {
+        text.truncate(index);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{pdf_bytes, scanned_pdf_bytes};
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn epub(chapters: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let stored =
+            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        writer.start_file("mimetype", stored).unwrap();
+        writer.write_all(b"application/epub+zip").unwrap();
+
+        let options = SimpleFileOptions::default();
+        writer
+            .start_file("META-INF/container.xml", options)
+            .unwrap();
+        writer
+            .write_all(
+                br#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>"#,
+            )
+            .unwrap();
+
+        let items: String = chapters
+            .iter()
+            .map(|(name, _)| {
+                format!(
+                    r#"<item id="{0}" href="{0}.xhtml" media-type="application/xhtml+xml"/>"#,
+                    name
+                )
+            })
+            .collect();
+        // The spine lists chapters in reverse, to check reading order is followed
+        let spine: String = chapters
+            .iter()
+            .rev()
+            .map(|(name, _)| format!(r#"<itemref idref="{}"/>"#, name))
+            .collect();
+        writer.start_file("OEBPS/content.opf", options).unwrap();
+        writer
+            .write_all(
+                format!(
+                    r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+  <manifest>{}</manifest>
+  <spine>{}</spine>
+</package>"#,
+                    items, spine
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+
+        for (name, body) in chapters {
+            writer
+                .start_file(format!("OEBPS/{}.xhtml", name), options)
+                .unwrap();
+            writer
+                .write_all(
+                    format!(
+                        "<html><head><title>Ignored</title></head><body>{}</body></html>",
+                        body
+                    )
+                    .as_bytes(),
+                )
+                .unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_scanned_pdf_is_detected() {
+        let scan = scanned_pdf_bytes();
+
+        assert!(extract_text(&scan).is_err());
+        assert!(is_scanned_pdf(&scan));
+        assert!(!is_scanned_pdf(&pdf_bytes(&["Gradual types"])));
+        assert!(!is_scanned_pdf(&pdf_bytes(&[""])));
+        assert!(!is_scanned_pdf(b"Plain text"));
+    }
+
+    #[test]
+    fn test_fast_extraction_reads_only_a_rich_first_page() {
+        let rich = "Gradual types. ".repeat(MIN_FIRST_PAGE_CHARS / 10);
+        let content = pdf_bytes(&[&rich, "Second page"]);
+
+        let text = extract_text_fast(&content).unwrap();
+
+        assert!(text.contains("Gradual types."));
+        assert!(!text.contains("Second page"));
+        assert!(extract_text(&content).unwrap().contains("Second page"));
+    }
+
+    #[test]
+    fn test_extract_text_pages() {
+        let content = pdf_bytes(&["One", "Two", "Three"]);
+
+        let text = extract_text_pages(&content, 2).unwrap();
+
+        assert!(text.contains("One") && text.contains("Two"));
+        assert!(!text.contains("Three"));
+    }
+
+    #[test]
+    fn test_fast_extraction_falls_back_to_more_pages() {
+        let content = pdf_bytes(&["Cover", "Abstract on the second page"]);
+
+        let text = extract_text_fast(&content).unwrap();
+
+        assert!(text.contains("Cover"));
+        assert!(text.contains("Abstract on the second page"));
+    }
+
+    #[test]
+    fn test_extract_plain_text() {
+        let text = extract_text("A Gradual Type System\nJane Roe\n".as_bytes()).unwrap();
+
+        assert_eq!(text, "A Gradual Type System\nJane Roe\n");
+    }
+
+    #[test]
+    fn test_extract_epub_in_reading_order() {
+        let content = epub(&[
+            ("two", "<p>Chapter&nbsp;two.</p>"),
+            ("one", "<h1>Types &amp; Effects</h1><p>By Jane<br/>Roe</p>"),
+        ]);
+
+        let text = extract_text(&content).unwrap();
+
+        assert_eq!(text, "Types & Effects By Jane Roe\nChapter two.\n");
+    }
+
+    #[test]
+    fn test_extract_rejects_binary_content() {
+        let error = extract_text(&[0x89, b'P', b'N', b'G', 0xff, 0xfe]).unwrap_err();
+
+        assert!(error.to_string().contains("Unsupported file type"));
+    }
+}
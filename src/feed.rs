@@ -0,0 +1,98 @@
+use crate::models::FileRecord;
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+
+/// Default base URL for item links: the Dropbox web interface, followed by the target path.
+pub const DEFAULT_LINK_BASE: &str = "https://www.dropbox.com/home";
+
+/// Render an RSS 2.0 feed with one item per filed paper, linking to its first target.
+pub fn render_rss(files: &[FileRecord], link_base: &str) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <rss version=\"2.0\">\n\
+         <channel>\n\
+         <title>Sci-Librarian: newly filed papers</title>\n",
+    );
+    xml.push_str(&format!("<link>{}</link>\n", escape_xml(link_base)));
+    xml.push_str("<description>Papers recently filed by sci-librarian</description>\n");
+
+    for file in files {
+        let title = file.title.as_deref().unwrap_or("Unknown");
+        let link = file
+            .target_paths()
+            .first()
+            .map(|target| format!("{}{}", link_base, target.0))
+            .unwrap_or_else(|| link_base.to_string());
+        xml.push_str("<item>\n");
+        xml.push_str(&format!("<title>{}</title>\n", escape_xml(title)));
+        xml.push_str(&format!("<link>{}</link>\n", escape_xml(&link)));
+        xml.push_str(&format!(
+            "<description>{}</description>\n",
+            escape_xml(file.summary.as_deref().unwrap_or_default())
+        ));
+        xml.push_str(&format!(
+            "<guid isPermaLink=\"false\">{}</guid>\n",
+            escape_xml(&file.dropbox_id.0)
+        ));
+        if let Some(processed_at) = file.processed_at {
+            xml.push_str(&format!(
+                "<pubDate>{}</pubDate>\n",
+                processed_at.to_rfc2822()
+            ));
+        }
+        xml.push_str("</item>\n");
+    }
+
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+/// Parse a `--since` value: an RFC 3339 timestamp or a date, meaning midnight UTC.
+pub fn parse_since(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").with_context(|| {
+        format!(
+            "Invalid date '{}', expected e.g. 2026-01-31 or 2026-01-31T12:00:00Z",
+            value
+        )
+    })?;
+    Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(
+            escape_xml(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn test_parse_since_accepts_date_and_timestamp() {
+        assert_eq!(
+            parse_since("2026-01-31").unwrap().to_rfc3339(),
+            "2026-01-31T00:00:00+00:00"
+        );
+        assert_eq!(
+            parse_since("2026-01-31T12:30:00+01:00")
+                .unwrap()
+                .to_rfc3339(),
+            "2026-01-31T11:30:00+00:00"
+        );
+        assert!(parse_since("last week").is_err());
+    }
+}
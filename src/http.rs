@@ -0,0 +1,95 @@
+use anyhow::{Context, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::fs;
+use std::path::Path;
+
+/// How the HTTP clients for Dropbox and the LLM connect, e.g. from behind a corporate proxy.
+/// By default, they connect directly, or through the proxy in the `HTTPS_PROXY` or
+/// `HTTP_PROXY` environment variables if set.
+#[derive(Debug, Clone, Default)]
+pub struct HttpOptions {
+    proxy: Option<reqwest::Proxy>,
+    /// Trusted in addition to the built-in CA certificates
+    ca_certificates: Vec<reqwest::Certificate>,
+    /// Sent with every request
+    headers: HeaderMap,
+}
+
+impl HttpOptions {
+    /// Send all requests through the proxy at `url`, e.g. `http://proxy.example.com:8080`,
+    /// except those to the hosts in the `NO_PROXY` environment variable.
+    pub fn with_proxy(mut self, url: &str) -> Result<Self> {
+        let proxy = reqwest::Proxy::all(url)
+            .with_context(|| format!("Invalid proxy URL {}", url))?
+            .no_proxy(reqwest::NoProxy::from_env());
+        self.proxy = Some(proxy);
+        Ok(self)
+    }
+
+    /// Also trust the CA certificate in a PEM file, e.g. that of a proxy inspecting TLS.
+    pub fn with_ca_certificate(mut self, path: &Path) -> Result<Self> {
+        let pem = fs::read(path)
+            .with_context(|| format!("Failed to read CA certificate {}", path.to_string_lossy()))?;
+        let certificate = reqwest::Certificate::from_pem(&pem)
+            .with_context(|| format!("Invalid CA certificate {}", path.to_string_lossy()))?;
+        self.ca_certificates.push(certificate);
+        Ok(self)
+    }
+
+    /// Send a header with every request, given as `Name: value`, e.g. the key of an API
+    /// gateway.
+    pub fn with_header(mut self, header: &str) -> Result<Self> {
+        let (name, value) = header.split_once(':').ok_or_else(|| {
+            anyhow::anyhow!("Expected a header as 'Name: value', got '{}'", header)
+        })?;
+        let name = HeaderName::try_from(name.trim())
+            .with_context(|| format!("Invalid header name in '{}'", header))?;
+        let value = HeaderValue::try_from(value.trim())
+            .with_context(|| format!("Invalid header value in '{}'", header))?;
+        self.headers.append(name, value);
+        Ok(self)
+    }
+
+    /// A client builder connecting as configured.
+    pub(crate) fn client_builder(&self) -> reqwest::ClientBuilder {
+        let builder = reqwest::Client::builder()
+            .default_headers(self.headers.clone())
+            .tls_certs_merge(self.ca_certificates.clone());
+        match &self.proxy {
+            Some(proxy) => builder.proxy(proxy.clone()),
+            None => builder,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::{DropboxHttpClient, MistralHttpClient};
+
+    #[test]
+    fn test_clients_build_with_a_proxy() {
+        let http = HttpOptions::default()
+            .with_proxy("http://proxy.example.com:8080")
+            .unwrap()
+            .with_header("X-Gateway-Key: secret")
+            .unwrap();
+        assert!(http.client_builder().build().is_ok());
+        // Building the clients panics if their reqwest clients fail to build
+        DropboxHttpClient::new(String::from("token"), vec![String::from("/sorted")])
+            .with_http_options(&http);
+        MistralHttpClient::new(String::from("key")).with_http_options(&http);
+    }
+
+    #[test]
+    fn test_invalid_http_options_are_rejected() {
+        assert!(HttpOptions::default().with_proxy("not a url").is_err());
+        assert!(HttpOptions::default().with_header("X-Gateway-Key").is_err());
+        assert!(HttpOptions::default().with_header("Bad Name: x").is_err());
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("ca.pem");
+        assert!(HttpOptions::default().with_ca_certificate(&path).is_err());
+        fs::write(&path, "not a certificate").unwrap();
+        assert!(HttpOptions::default().with_ca_certificate(&path).is_err());
+    }
+}
@@ -1,46 +1,443 @@
 use crate::clients::DropboxClient;
-use crate::models::RemotePath;
+use crate::metadata::canonical_author_key;
+use crate::models::{DropboxId, FileRecord, RemotePath};
 use crate::storage::Storage;
 use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::{StreamExt, TryStreamExt};
+use std::collections::{BTreeMap, HashMap};
+
+/// Options for what goes into a folder index.
+#[derive(Debug, Clone, Default)]
+pub struct IndexOptions {
+    /// Add a column with each paper's abstract
+    pub include_abstract: bool,
+    /// Also write an `AUTHORS.md` listing the papers in the folder by author
+    pub by_author: bool,
+    /// Only update the rows of papers processed after this time, keeping the other rows of
+    /// the existing index instead of rebuilding it
+    pub since: Option<DateTime<Utc>>,
+    /// What the titles in the index link to
+    pub links: IndexLinks,
+    /// Start the index with a line counting the papers and naming the author with the most
+    pub summary_line: bool,
+    /// List this many of the most recently processed papers above the table; none if 0
+    pub recently_added: usize,
+}
+
+/// The number of papers in the "Recently added" section of an index by default.
+pub const DEFAULT_RECENTLY_ADDED: usize = 5;
+
+/// What the titles in a folder index link to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum IndexLinks {
+    /// The file name of the PDF, which works when browsing the folder in Dropbox
+    #[default]
+    Relative,
+    /// A Dropbox shared link to the PDF, which works for collaborators the index is sent to
+    Shared,
+}
 
 pub async fn generate_index(
     storage: &Storage,
     dropbox: &dyn DropboxClient,
     folder: &str,
+    options: &IndexOptions,
 ) -> Result<()> {
-    let files = storage.get_files_in_folder(folder).await?;
-    if files.is_empty() {
+    // Stream the files, keeping only their rendered rows, as folders may hold thousands
+    let mut files = storage.stream_files_in_folder(folder);
+    let mut found = false;
+    let mut authors = AuthorIndex::default();
+    let mut rows = Vec::new();
+    // The links of the rows by file, so a shared link is only created once per paper
+    let mut links = HashMap::new();
+    while let Some(file) = files.try_next().await? {
+        found = true;
+        if options.by_author || options.summary_line {
+            authors.add(&file, folder);
+        }
+        let recent = match options.since {
+            Some(since) => file.processed_at.is_some_and(|at| at > since),
+            None => true,
+        };
+        if recent {
+            let link = index_link(dropbox, &file, folder, options.links).await?;
+            rows.push(IndexRow::new(
+                &file,
+                folder,
+                &link,
+                options.include_abstract,
+            ));
+            links.insert(file.dropbox_id, link);
+        }
+    }
+    if !found {
         return Ok(());
     }
 
-    let mut markdown = String::from("| Title | Authors | Summary |\n| :--- | :--- | :--- |\n");
+    let top_author = authors.top_author();
+    if options.by_author {
+        let authors_path = RemotePath::from(folder).join("AUTHORS.md");
+        dropbox
+            .upload_file(
+                &authors_path,
+                render_author_index(authors.into_facets()).into_bytes(),
+            )
+            .await?;
+    }
 
-    for file in files {
-        let title = file.title.unwrap_or_else(|| "Unknown".to_string());
-        let authors = file.authors.unwrap_or_else(|| "[]".to_string());
-        let authors_list: Vec<String> = serde_json::from_str(&authors).unwrap_or_default();
-        let summary = file.summary.unwrap_or_default();
-
-        // Extract filename from target_path for relative link
-        let filename = if let Some(path) = file.target_path {
-            path.split('/').last().unwrap_or("").to_string()
-        } else {
-            "".to_string()
-        };
+    let readme_path = RemotePath::from(folder).join("README.md");
+    if rows.is_empty() {
+        // Nothing processed since the given time, so the index is up to date
+        return Ok(());
+    }
+    let existing = match options.since {
+        Some(_) => match dropbox
+            .download_file(&DropboxId(readme_path.0.clone()))
+            .await
+        {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(e) => {
+                tracing::debug!(
+                    "No existing index in {}, writing a new one: {:#}",
+                    folder,
+                    e
+                );
+                String::new()
+            }
+        },
+        None => String::new(),
+    };
+    let (existing_header, mut lines) = parse_index_rows(&existing, folder);
+
+    // Only folders with tagged papers get a Tags column
+    let include_tags =
+        rows.iter().any(|row| !row.tags.is_empty()) || existing_header.contains("| Tags |");
+    let mut header = vec!["Title", "Authors", "Summary"];
+    if options.include_abstract {
+        header.push("Abstract");
+    }
+    if include_tags {
+        header.push("Tags");
+    }
+
+    for row in rows {
+        let line = row.render(include_tags);
+        // A paper already in the index keeps its place, with its row updated
+        match lines.iter_mut().find(|(key, _)| *key == row.key) {
+            Some((_, existing_line)) => *existing_line = line,
+            None => lines.push((row.key, line)),
+        }
+    }
 
-        markdown.push_str(&format!(
-            "| [{}]({}) | {} | {} |\n",
-            title,
-            filename,
-            authors_list.join(", "),
-            summary
-        ));
+    let header = format!(
+        "| {} |\n|{}\n",
+        header.join(" | "),
+        " :--- |".repeat(header.len())
+    );
+    let mut markdown = String::with_capacity(
+        header.len() + lines.iter().map(|(_, line)| line.len() + 1).sum::<usize>(),
+    );
+    if options.summary_line {
+        markdown.push_str(&summary_line(lines.len(), top_author));
+        markdown.push_str("\n\n");
+    }
+    if options.recently_added > 0 {
+        let mut recently_added = String::from("## Recently added\n\n");
+        for file in storage
+            .get_recent_files_in_folder(folder, options.recently_added)
+            .await?
+        {
+            let link = match links.remove(&file.dropbox_id) {
+                Some(link) => link,
+                None => index_link(dropbox, &file, folder, options.links).await?,
+            };
+            recently_added.push_str(&recently_added_line(&file, &link));
+            recently_added.push('\n');
+        }
+        markdown.push_str(&recently_added);
+        markdown.push_str("\n## All papers\n\n");
+    }
+    markdown.push_str(&header);
+    for (_, line) in lines {
+        markdown.push_str(&line);
+        markdown.push('\n');
     }
 
-    let readme_path = RemotePath(format!("{}/README.md", folder));
     dropbox
         .upload_file(&readme_path, markdown.into_bytes())
         .await?;
 
     Ok(())
 }
+
+/// A paper's row in a folder index, rendered but for the Tags cell, which only folders
+/// with tagged papers have.
+struct IndexRow {
+    /// The folder and the link of the row, compared case-insensitively
+    key: String,
+    cells: String,
+    tags: String,
+}
+
+impl IndexRow {
+    fn new(file: &FileRecord, folder: &str, link: &str, include_abstract: bool) -> Self {
+        let title = file.title.as_deref().unwrap_or("Unknown");
+        let authors_list: Vec<String> = file
+            .authors
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default();
+        let summary = file.summary.as_deref().unwrap_or_default();
+
+        let mut cells = format!(
+            "| [{}]({}) | {} | {} |",
+            table_cell(title),
+            link,
+            table_cell(&authors_list.join(", ")),
+            table_cell(summary)
+        );
+        if include_abstract {
+            let abstract_text = file.abstract_text.as_deref().unwrap_or_default();
+            cells.push_str(&format!(" {} |", table_cell(abstract_text)));
+        }
+        IndexRow {
+            key: RemotePath::from(folder).join(link).comparison_key(),
+            cells,
+            tags: table_cell(&file.tag_list().join(", ")),
+        }
+    }
+
+    fn render(&self, include_tags: bool) -> String {
+        if include_tags {
+            format!("{} {} |", self.cells, self.tags)
+        } else {
+            self.cells.clone()
+        }
+    }
+}
+
+/// What the title of a paper in a folder index links to.
+async fn index_link(
+    dropbox: &dyn DropboxClient,
+    file: &FileRecord,
+    folder: &str,
+    links: IndexLinks,
+) -> Result<String> {
+    let filename = file_name_in_folder(file, folder);
+    Ok(match links {
+        IndexLinks::Relative => filename,
+        IndexLinks::Shared => {
+            let path = RemotePath::from(folder).join(&filename);
+            dropbox.create_shared_link(&path).await?
+        }
+    })
+}
+
+/// A paper's line in the "Recently added" section of an index, e.g.
+/// `- [Gradual Typing](gradual.pdf) by Jeremy Siek, 2026-10-16`.
+fn recently_added_line(file: &FileRecord, link: &str) -> String {
+    let title = file.title.as_deref().unwrap_or("Unknown");
+    let authors: Vec<String> = file
+        .authors
+        .as_deref()
+        .and_then(|json| serde_json::from_str(json).ok())
+        .unwrap_or_default();
+    let mut line = format!("- [{}]({})", table_cell(title), link);
+    if !authors.is_empty() {
+        line.push_str(&format!(" by {}", table_cell(&authors.join(", "))));
+    }
+    if let Some(processed_at) = file.processed_at {
+        line.push_str(&format!(", {}", processed_at.format("%Y-%m-%d")));
+    }
+    line
+}
+
+/// The name of the file filed into a folder, for linking to it from the folder.
+fn file_name_in_folder(file: &FileRecord, folder: &str) -> String {
+    file.target_in_folder(folder)
+        .and_then(|path| path.file_name().map(String::from))
+        .unwrap_or_default()
+}
+
+/// The line above the table of an index, e.g. `42 papers, most by Jeremy Siek (5)`.
+fn summary_line(papers: usize, top_author: Option<(String, usize)>) -> String {
+    let papers = match papers {
+        1 => String::from("1 paper"),
+        n => format!("{} papers", n),
+    };
+    match top_author {
+        Some((name, count)) => format!("{}, most by {} ({})", papers, name, count),
+        None => papers,
+    }
+}
+
+/// Split an existing index into its header and its rows, keyed by what each row links to
+/// (compared case-insensitively, like Dropbox paths). Any summary line above the table is
+/// left out.
+fn parse_index_rows(markdown: &str, folder: &str) -> (String, Vec<(String, String)>) {
+    let mut lines = markdown.lines().skip_while(|line| !line.starts_with('|'));
+    let header = lines.next().unwrap_or_default().to_string();
+    let rows = lines
+        .skip(1)
+        .filter_map(|line| {
+            let (_, rest) = line.split_once("](")?;
+            let (filename, _) = rest.split_once(") |")?;
+            let key = RemotePath::from(folder).join(filename).comparison_key();
+            Some((key, line.to_string()))
+        })
+        .collect();
+    (header, rows)
+}
+
+/// Regenerate the index of every folder files have been filed into, with at most
+/// `concurrency` indexes being generated at a time. Returns the folders indexed.
+pub async fn generate_all_indexes(
+    storage: &Storage,
+    dropbox: &dyn DropboxClient,
+    options: &IndexOptions,
+    concurrency: usize,
+) -> Result<Vec<String>> {
+    let folders = storage.get_target_folders().await?;
+    futures::stream::iter(&folders)
+        .map(|folder| async move {
+            tracing::debug!("Generating index for {}", folder);
+            generate_index(storage, dropbox, folder, options).await
+        })
+        .buffer_unordered(concurrency.max(1))
+        .try_collect::<Vec<()>>()
+        .await?;
+    Ok(folders)
+}
+
+/// An author and the titles and file names of their papers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthorFacet {
+    /// The most complete spelling of the author's name seen
+    pub name: String,
+    pub papers: Vec<(String, String)>,
+}
+
+/// Group the papers in a folder by author, merging different spellings of the same author
+/// (see [`canonical_author_key`]). Authors are sorted by key, i.e. by surname.
+pub fn group_by_author(files: &[FileRecord], folder: &str) -> Vec<AuthorFacet> {
+    let mut index = AuthorIndex::default();
+    for file in files {
+        index.add(file, folder);
+    }
+    index.into_facets()
+}
+
+/// The papers in a folder by author, built up one paper at a time.
+#[derive(Debug, Default)]
+struct AuthorIndex {
+    facets: BTreeMap<String, AuthorFacet>,
+}
+
+impl AuthorIndex {
+    fn add(&mut self, file: &FileRecord, folder: &str) {
+        let filename = file_name_in_folder(file, folder);
+        let title = file.title.clone().unwrap_or_else(|| "Unknown".to_string());
+        let authors: Vec<String> = file
+            .authors
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default();
+        for author in authors {
+            let key = canonical_author_key(&author);
+            if key.is_empty() {
+                continue;
+            }
+            let facet = self.facets.entry(key).or_insert_with(|| AuthorFacet {
+                name: author.clone(),
+                papers: Vec::new(),
+            });
+            if author.len() > facet.name.len() {
+                facet.name = author;
+            }
+            facet.papers.push((title.clone(), filename.clone()));
+        }
+    }
+
+    /// The author with the most papers and their number of papers, the first by surname
+    /// of those with as many.
+    fn top_author(&self) -> Option<(String, usize)> {
+        let mut top: Option<&AuthorFacet> = None;
+        for facet in self.facets.values() {
+            if top.is_none_or(|top| facet.papers.len() > top.papers.len()) {
+                top = Some(facet);
+            }
+        }
+        top.map(|facet| (facet.name.clone(), facet.papers.len()))
+    }
+
+    fn into_facets(self) -> Vec<AuthorFacet> {
+        self.facets.into_values().collect()
+    }
+}
+
+fn render_author_index(facets: Vec<AuthorFacet>) -> String {
+    let mut markdown = String::from("| Author | Papers |\n| :--- | :--- |\n");
+    for facet in facets {
+        let papers = facet
+            .papers
+            .iter()
+            .map(|(title, filename)| format!("[{}]({})", table_cell(title), filename))
+            .collect::<Vec<String>>()
+            .join(", ");
+        markdown.push_str(&format!("| {} | {} |\n", table_cell(&facet.name), papers));
+    }
+    markdown
+}
+
+/// Make text safe for a Markdown table cell: no line breaks or unescaped pipes.
+fn table_cell(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .replace('|', "\\|")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{DropboxId, FileHash, FileStatus};
+    use chrono::Utc;
+
+    fn record(id: &str, title: &str, authors: &[&str]) -> FileRecord {
+        FileRecord {
+            dropbox_id: DropboxId(id.to_string()),
+            file_name: Some(format!("{}.pdf", id)),
+            content_hash: FileHash(id.to_string()),
+            status: FileStatus::Archived,
+            title: Some(title.to_string()),
+            authors: Some(serde_json::to_string(authors).unwrap()),
+            target_path: Some(serde_json::to_string(&[format!("/out/{}.pdf", id)]).unwrap()),
+            updated_at: Utc::now(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_group_by_author_merges_spellings() {
+        let files = vec![
+            record("a", "Alpha", &["J. Smith"]),
+            record("b", "Beta", &["John Smith", "Jane Roe"]),
+            record("c", "Gamma", &["Smith, J."]),
+        ];
+
+        let facets = group_by_author(&files, "/out");
+
+        assert_eq!(facets.len(), 2);
+        assert_eq!(facets[0].name, "Jane Roe");
+        assert_eq!(facets[1].name, "John Smith");
+        assert_eq!(
+            facets[1].papers,
+            vec![
+                ("Alpha".to_string(), "a.pdf".to_string()),
+                ("Beta".to_string(), "b.pdf".to_string()),
+                ("Gamma".to_string(), "c.pdf".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::str::FromStr;
+
+/// The language the LLM writes the one-line summary and key findings in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SummaryLanguage {
+    /// A language by name, e.g. `German`
+    Named(String),
+    /// The language of the paper, as detected from its text (see [`detect_language`])
+    Auto,
+}
+
+impl Default for SummaryLanguage {
+    fn default() -> Self {
+        SummaryLanguage::Named(String::from(DEFAULT_SUMMARY_LANGUAGE))
+    }
+}
+
+impl FromStr for SummaryLanguage {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "" => Err(String::from("the language must not be empty")),
+            "auto" => Ok(SummaryLanguage::Auto),
+            name => Ok(SummaryLanguage::Named(name.to_string())),
+        }
+    }
+}
+
+impl SummaryLanguage {
+    /// The instruction added to the prompt for a paper with the given text. A language that
+    /// cannot be detected is left to the LLM.
+    pub fn instruction(&self, text: &str) -> String {
+        let language = match self {
+            SummaryLanguage::Named(name) => name.as_str(),
+            SummaryLanguage::Auto => detect_language(text).unwrap_or("the language of the paper"),
+        };
+        format!(
+            "Write the one-line summary and the key findings in {}.",
+            language
+        )
+    }
+}
+
+/// The summary language when none is given.
+pub const DEFAULT_SUMMARY_LANGUAGE: &str = "English";
+
+/// The language of a text, by its English name, e.g. `German`, if it can be told reliably.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    whatlang::detect(text)
+        .filter(|info| info.is_reliable())
+        .map(|info| info.lang().eng_name())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_language_of_sample_texts() {
+        let samples = [
+            (
+                "English",
+                "We present a type system for the gradual migration of programs, and show that \
+                 it is sound with respect to the dynamic semantics.",
+            ),
+            (
+                "German",
+                "Wir stellen ein Typsystem für die schrittweise Migration von Programmen vor \
+                 und zeigen, dass es mit der dynamischen Semantik verträglich ist.",
+            ),
+            (
+                "French",
+                "Nous présentons un système de types pour la migration progressive des \
+                 programmes et montrons que le système est correct pour la sémantique.",
+            ),
+            (
+                "Spanish",
+                "Presentamos un sistema de tipos para la migración gradual de programas y \
+                 demostramos que es correcto con respecto a la semántica dinámica.",
+            ),
+            (
+                "Portuguese",
+                "Apresentamos um sistema de tipos para a migração gradual de programas e \
+                 mostramos que ele é correto em relação à semântica dinâmica.",
+            ),
+            (
+                "Italian",
+                "Presentiamo un sistema di tipi per la migrazione graduale dei programmi e \
+                 dimostriamo che è corretto rispetto alla semantica dinamica.",
+            ),
+        ];
+        for (language, text) in samples {
+            assert_eq!(detect_language(text), Some(language), "{}", text);
+        }
+        assert_eq!(detect_language("Gradual Typing"), None);
+    }
+
+    #[test]
+    fn test_summary_language_instruction() {
+        assert_eq!(
+            SummaryLanguage::default().instruction("Der Text"),
+            "Write the one-line summary and the key findings in English."
+        );
+        let german = "Wir zeigen, dass die Methode mit der Zeit für den Nutzer nicht \
+                      schlechter ist als das Original.";
+        assert_eq!(
+            "auto"
+                .parse::<SummaryLanguage>()
+                .unwrap()
+                .instruction(german),
+            "Write the one-line summary and the key findings in German."
+        );
+        assert_eq!(
+            SummaryLanguage::Auto.instruction("Gradual Typing"),
+            "Write the one-line summary and the key findings in the language of the paper."
+        );
+        assert!("".parse::<SummaryLanguage>().is_err());
+    }
+}
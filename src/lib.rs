@@ -1,17 +1,194 @@
+pub mod classifier;
 pub mod clients;
+pub mod config;
+pub mod doctor;
+pub mod extract;
+pub mod feed;
+pub mod http;
 pub mod indexing;
+pub mod language;
+pub mod local;
+pub mod metadata;
 pub mod models;
+pub mod outcome;
 pub mod pipeline;
+pub mod rate_limit;
+pub mod retry;
+pub mod scaffold;
+#[cfg(feature = "serve")]
+pub mod server;
+pub mod sidecar;
 pub mod storage;
+pub mod targets;
+pub mod terminal;
+// Document builders for the tests, which the integration tests get through `test-support`
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+pub mod verify;
+pub mod watch;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use sqlx::SqlitePool;
+use sqlx::migrate::{MigrateError, Migrator};
 use sqlx::sqlite::SqliteConnectOptions;
+use std::collections::HashSet;
+use std::path::Path;
 use std::str::FromStr;
 
+/// The migrations of the state database, built into the binary.
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Open the state database, creating it if missing, and apply any pending migrations.
 pub async fn setup_db(url: &str) -> Result<SqlitePool> {
-    let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
-    let pool = SqlitePool::connect_with(options).await?;
-    sqlx::migrate!("./migrations").run(&pool).await?;
+    let pool = open_db(url).await?;
+    apply_migrations(&pool).await?;
     Ok(pool)
 }
+
+/// The URL of the SQLite database at `path`, which may be absolute or relative to the current
+/// directory. Backslashes become slashes on Windows, where they separate folders, and the
+/// characters that would end the file name in a URL are escaped.
+pub fn sqlite_url(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    let path = if cfg!(windows) {
+        path.replace('\\', "/")
+    } else {
+        path.into_owned()
+    };
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            '%' => escaped.push_str("%25"),
+            '?' => escaped.push_str("%3F"),
+            '#' => escaped.push_str("%23"),
+            c => escaped.push(c),
+        }
+    }
+    // Everything after the `//` is the file name: `sqlite:///abs`, `sqlite://C:/abs`, `sqlite://rel`
+    format!("sqlite://{}", escaped)
+}
+
+/// Open the state database, creating it if missing, without migrating it.
+pub async fn open_db(url: &str) -> Result<SqlitePool> {
+    let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+    Ok(SqlitePool::connect_with(options).await?)
+}
+
+/// A migration of the state database and whether it has been applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+}
+
+impl MigrationStatus {
+    /// The name of the migration, as in its file name.
+    pub fn name(&self) -> String {
+        migration_name(self.version, &self.description)
+    }
+}
+
+/// Apply the pending migrations. An error names the migration that failed.
+pub async fn apply_migrations(pool: &SqlitePool) -> Result<()> {
+    MIGRATOR.run(pool).await.map_err(|error| {
+        let name = failed_migration_name(&error);
+        anyhow::Error::new(error).context(match name {
+            Some(name) => format!("Failed to apply database migration {}", name),
+            None => "Failed to apply database migrations".to_string(),
+        })
+    })
+}
+
+/// Every migration, in order, with whether it has been applied to the database.
+pub async fn migration_status(pool: &SqlitePool) -> Result<Vec<MigrationStatus>> {
+    // A database never migrated has no table of applied migrations
+    let migrated: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations')",
+    )
+    .fetch_one(pool)
+    .await?;
+    let applied: HashSet<i64> = if migrated {
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success = 1")
+            .fetch_all(pool)
+            .await
+            .context("Failed to read the applied database migrations")?
+            .into_iter()
+            .collect()
+    } else {
+        HashSet::new()
+    };
+    Ok(MIGRATOR
+        .iter()
+        .map(|migration| MigrationStatus {
+            version: migration.version,
+            description: migration.description.to_string(),
+            applied: applied.contains(&migration.version),
+        })
+        .collect())
+}
+
+/// sqlx takes the description of a migration from its file name, with spaces for underscores.
+fn migration_name(version: i64, description: &str) -> String {
+    format!("{}_{}", version, description.replace(' ', "_"))
+}
+
+/// The name of the migration a migration error is about, if it is about one.
+fn failed_migration_name(error: &MigrateError) -> Option<String> {
+    let version = match error {
+        MigrateError::ExecuteMigration(_, version)
+        | MigrateError::VersionMissing(version)
+        | MigrateError::VersionMismatch(version)
+        | MigrateError::VersionNotPresent(version)
+        | MigrateError::Dirty(version) => *version,
+        _ => return None,
+    };
+    let name = MIGRATOR
+        .iter()
+        .find(|migration| migration.version == version)
+        .map(|migration| migration_name(version, &migration.description))
+        .unwrap_or_else(|| version.to_string());
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqlite_url_of_path_shapes() {
+        let cases = [
+            ("/home/me/work/state.db", "sqlite:///home/me/work/state.db"),
+            ("work/state.db", "sqlite://work/state.db"),
+            ("state.db", "sqlite://state.db"),
+            ("/tmp/what?#100%.db", "sqlite:///tmp/what%3F%23100%25.db"),
+        ];
+        for (path, url) in cases {
+            assert_eq!(sqlite_url(Path::new(path)), url, "{}", path);
+        }
+        let options =
+            SqliteConnectOptions::from_str(&sqlite_url(Path::new("/tmp/what?#100%.db"))).unwrap();
+        assert_eq!(options.get_filename(), Path::new("/tmp/what?#100%.db"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_sqlite_url_of_windows_paths() {
+        let cases = [
+            (r"C:\Users\me\state.db", "sqlite://C:/Users/me/state.db"),
+            (r"..\shared\state.db", "sqlite://../shared/state.db"),
+        ];
+        for (path, url) in cases {
+            assert_eq!(sqlite_url(Path::new(path)), url, "{}", path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sqlite_url_keeps_backslash_in_unix_file_name() {
+        let path = Path::new(r"/tmp/back\slash.db");
+        assert_eq!(sqlite_url(path), r"sqlite:///tmp/back\slash.db");
+        let options = SqliteConnectOptions::from_str(&sqlite_url(path)).unwrap();
+        assert_eq!(options.get_filename(), path);
+    }
+}
@@ -0,0 +1,227 @@
+use crate::clients::{DropboxClient, DropboxEntry, check_upload_allowed, dropbox_content_hash};
+use crate::models::{DropboxId, RemotePath};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::path::{Component, Path, PathBuf};
+
+/// A local directory tree standing in for Dropbox, e.g. for archives that must stay offline.
+/// Dropbox paths are paths under the root, and the id of a file is its path, so the pipeline
+/// runs unchanged. Listed content hashes are computed like Dropbox's, so duplicates and raw
+/// copies are recognized as with Dropbox.
+pub struct LocalFsClient {
+    root: PathBuf,
+    allowed_upload_prefixes: Vec<String>,
+}
+
+impl LocalFsClient {
+    /// A client for the tree under `root`, uploading only under the allowed prefixes, like
+    /// [`DropboxHttpClient`](crate::clients::DropboxHttpClient).
+    pub fn new(root: PathBuf, allowed_upload_prefixes: Vec<String>) -> Self {
+        Self {
+            root,
+            allowed_upload_prefixes,
+        }
+    }
+
+    /// The local path of a Dropbox path, which may not leave the root.
+    fn local_path(&self, path: &str) -> Result<PathBuf> {
+        let relative = Path::new(path.trim_start_matches('/'));
+        if relative
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_)))
+        {
+            return Err(anyhow::anyhow!("Path not allowed: {}", path));
+        }
+        Ok(self.root.join(relative))
+    }
+
+    /// The Dropbox path of a local path under the root.
+    fn remote_path(&self, local: &Path) -> RemotePath {
+        let relative = local.strip_prefix(&self.root).unwrap_or(local);
+        let segments = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>();
+        RemotePath(format!("/{}", segments.join("/")))
+    }
+
+    async fn entry(&self, local: &Path) -> Result<DropboxEntry> {
+        let content = tokio::fs::read(local)
+            .await
+            .with_context(|| format!("Failed to read {}", local.to_string_lossy()))?;
+        let modified = tokio::fs::metadata(local).await?.modified().ok();
+        let path = self.remote_path(local);
+        Ok(DropboxEntry {
+            id: DropboxId(path.0.clone()),
+            name: local
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            path,
+            content_hash: dropbox_content_hash(&content),
+            size: content.len() as u64,
+            server_modified: modified.map(DateTime::<Utc>::from),
+        })
+    }
+
+    /// The files in a folder, and in its subfolders if `recursive`, in name order.
+    async fn list(&self, path: &str, recursive: bool) -> Result<Vec<DropboxEntry>> {
+        let mut folders = vec![self.local_path(path)?];
+        if !folders[0].is_dir() {
+            return Err(anyhow::anyhow!(
+                "Inbox path {} not found — check --inbox",
+                if path.is_empty() { "(root)" } else { path }
+            ));
+        }
+        let mut files = Vec::new();
+        while let Some(folder) = folders.pop() {
+            let mut listing = tokio::fs::read_dir(&folder)
+                .await
+                .with_context(|| format!("Failed to list folder {}", folder.to_string_lossy()))?;
+            while let Some(item) = listing.next_entry().await? {
+                let file_type = item.file_type().await?;
+                if file_type.is_file() {
+                    files.push(item.path());
+                } else if file_type.is_dir() && recursive {
+                    folders.push(item.path());
+                }
+            }
+        }
+        files.sort();
+        let mut entries = Vec::with_capacity(files.len());
+        for file in files {
+            entries.push(self.entry(&file).await?);
+        }
+        Ok(entries)
+    }
+
+    async fn create_parent(&self, local: &Path) -> Result<()> {
+        if let Some(parent) = local.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DropboxClient for LocalFsClient {
+    async fn list_folder(&self, path: &str) -> Result<Vec<DropboxEntry>> {
+        self.list(path, false).await
+    }
+
+    async fn list_folder_recursive(&self, path: &str) -> Result<Vec<DropboxEntry>> {
+        self.list(path, true).await
+    }
+
+    async fn get_metadata(&self, path: &RemotePath) -> Result<Option<DropboxEntry>> {
+        let local = self.local_path(&path.0)?;
+        if !local.is_file() {
+            return Ok(None);
+        }
+        self.entry(&local).await.map(Some)
+    }
+
+    /// Read a file by its id, which is its path.
+    async fn download_file(&self, id: &DropboxId) -> Result<Vec<u8>> {
+        let local = self.local_path(&id.0)?;
+        tokio::fs::read(&local)
+            .await
+            .with_context(|| format!("Failed to read {}", local.to_string_lossy()))
+    }
+
+    async fn upload_file(&self, path: &RemotePath, content: Vec<u8>) -> Result<()> {
+        check_upload_allowed(path, &self.allowed_upload_prefixes)?;
+        let local = self.local_path(&path.0)?;
+        self.create_parent(&local).await?;
+        tokio::fs::write(&local, content)
+            .await
+            .with_context(|| format!("Failed to write {}", local.to_string_lossy()))
+    }
+
+    async fn folder_exists(&self, path: &str) -> Result<bool> {
+        Ok(self.local_path(path)?.is_dir())
+    }
+
+    async fn create_folder(&self, path: &str) -> Result<()> {
+        let local = self.local_path(path)?;
+        tokio::fs::create_dir(&local)
+            .await
+            .with_context(|| format!("Failed to create folder {}", local.to_string_lossy()))
+    }
+
+    async fn create_folder_if_not_exists(&self, path: &str) -> Result<()> {
+        let local = self.local_path(path)?;
+        tokio::fs::create_dir_all(&local)
+            .await
+            .with_context(|| format!("Failed to create folder {}", local.to_string_lossy()))
+    }
+
+    async fn move_file(&self, from: &RemotePath, to: &RemotePath) -> Result<()> {
+        check_upload_allowed(to, &self.allowed_upload_prefixes)?;
+        let (from, to) = (self.local_path(&from.0)?, self.local_path(&to.0)?);
+        self.create_parent(&to).await?;
+        tokio::fs::rename(&from, &to).await.with_context(|| {
+            format!(
+                "Failed to move {} to {}",
+                from.to_string_lossy(),
+                to.to_string_lossy()
+            )
+        })
+    }
+
+    async fn delete_file(&self, path: &RemotePath) -> Result<()> {
+        let local = self.local_path(&path.0)?;
+        tokio::fs::remove_file(&local)
+            .await
+            .with_context(|| format!("Failed to delete {}", local.to_string_lossy()))
+    }
+
+    /// A `file://` URL of the file, as there is nothing to share it with.
+    async fn create_shared_link(&self, path: &RemotePath) -> Result<String> {
+        let local = std::path::absolute(self.local_path(&path.0)?)?;
+        Ok(format!(
+            "file://{}",
+            local.to_string_lossy().replace('\\', "/")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_paths_stay_under_the_root() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let client = LocalFsClient::new(temp_dir.path().to_path_buf(), vec!["/".to_string()]);
+
+        let escape = client
+            .upload_file(&RemotePath::from("/../outside.pdf"), vec![1])
+            .await;
+        client
+            .upload_file(&RemotePath::from("/sorted/inside.pdf"), vec![1])
+            .await
+            .unwrap();
+
+        assert!(escape.is_err());
+        assert!(
+            !temp_dir
+                .path()
+                .parent()
+                .unwrap()
+                .join("outside.pdf")
+                .exists()
+        );
+        assert!(temp_dir.path().join("sorted/inside.pdf").is_file());
+        assert_eq!(
+            client
+                .get_metadata(&RemotePath::from("/sorted/inside.pdf"))
+                .await
+                .unwrap()
+                .unwrap()
+                .id,
+            DropboxId("/sorted/inside.pdf".to_string())
+        );
+    }
+}
@@ -1,16 +1,47 @@
-use anyhow::{Error, Result};
-use clap::{Parser, Subcommand};
+use anyhow::{Context, Error, Result};
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use colored::*;
-use sci_librarian::clients::{DropboxClient, DropboxHttpClient, LlmClient, MistralHttpClient};
-use sci_librarian::indexing::generate_index;
-use sci_librarian::models::{DropboxInbox, RemotePath, Rule, Rules, WorkDirectory};
-use sci_librarian::pipeline::Pipeline;
-use sci_librarian::setup_db;
-use sci_librarian::storage::Storage;
+use sci_librarian::classifier::{HybridClassifier, KeywordClassifier};
+use sci_librarian::clients::{
+    DEFAULT_LLM_TIMEOUT, DEFAULT_TEMPERATURE, DropboxClient, DropboxHttpClient, LlmClient,
+    MistralHttpClient,
+};
+use sci_librarian::config::{Config, DEFAULT_CONFIG_FILE, EffectiveConfig, Profile, Settings};
+use sci_librarian::doctor::{Preflight, run_checks};
+use sci_librarian::feed::{DEFAULT_LINK_BASE, parse_since, render_rss};
+use sci_librarian::http::HttpOptions;
+use sci_librarian::indexing::{
+    DEFAULT_RECENTLY_ADDED, IndexLinks, IndexOptions, generate_all_indexes, generate_index,
+};
+use sci_librarian::language::SummaryLanguage;
+use sci_librarian::local::LocalFsClient;
+use sci_librarian::models::{
+    DatabaseDump, DropboxId, DropboxInbox, FileStatus, RemotePath, Rule, Rules, RunId,
+    WorkDirectory,
+};
+use sci_librarian::outcome::CommandOutcome;
+use sci_librarian::pipeline::{
+    BatchSummary, DEFAULT_JOB_QUEUE_CAPACITY, DEFAULT_MAX_INFLIGHT_UPLOADS, DEFAULT_MAX_PDF_BYTES,
+    DEFAULT_MIN_CONFIDENCE, DEFAULT_RECLAIM_AFTER, DEFAULT_RESULT_QUEUE_CAPACITY,
+    LOW_EXTRACTION_QUALITY, Pipeline, PipelineOptions, TooManyCategories, analyze_local_file,
+    dropbox_file_text, file_text, sync_inbox,
+};
+use sci_librarian::retry::retry_async;
+use sci_librarian::scaffold::{Scaffold, scaffold_work_directory};
+use sci_librarian::sidecar::{SidecarFormat, regenerate_sidecars};
+use sci_librarian::storage::{ListFilter, OnContentChange, PendingOrder, Storage};
+use sci_librarian::targets::{SidecarPlacement, static_prefix};
+use sci_librarian::terminal::{ProgressAwareStderr, can_draw_progress, configure_colors, progress};
+use sci_librarian::verify::verify_library;
+use sci_librarian::watch::{WatchOptions, watch};
+use sci_librarian::{open_db, setup_db, sqlite_url};
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use tracing::info;
 use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 
@@ -18,71 +49,623 @@ use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 #[command(name = "sci-librarian")]
 #[command(about = "Organize scientific articles in Dropbox", long_about = None)]
 struct Cli {
-    /// Path to the application working directory with state database and temporary files.
-    #[arg(short, long, global = true, default_value = "working")]
-    work_directory: PathBuf,
+    /// Path to the application working directory with state database and temporary files [default: working]
+    #[arg(short, long, global = true)]
+    work_directory: Option<PathBuf>,
 
-    /// Path to application inbox. This is where files are picked up for processing.
+    /// Path of the state database, e.g. to keep it on a faster drive than the working
+    /// directory [default: state.db in the working directory]
+    #[arg(long, global = true)]
+    db_path: Option<PathBuf>,
+
+    /// Path to application inbox. This is where files are picked up for processing. Repeat, or
+    /// separate with commas, for several inboxes [default: ""]
     #[arg(
         short,
         long,
         global = true,
-        default_value = "",
+        value_delimiter = ',',
         long_help = "If your app is restricted to just its own folder under Apps, the path to that folder is the empty string. If you bravely gave it access to your whole Dropbox account, the root folder is the empty string, all other folders start with a '/'."
     )]
-    inbox: String,
+    inbox: Vec<String>,
+
+    /// Path to a YAML rules file. Uses the built-in rules if not given.
+    #[arg(short, long, global = true)]
+    rules: Option<PathBuf>,
+
+    /// Dropbox folder that files may be uploaded under. Repeat, or separate with commas, to
+    /// allow several [default: /sorted]
+    #[arg(long, global = true, value_delimiter = ',')]
+    allowed_upload_prefix: Vec<String>,
+
+    /// Path to a TOML config file with profiles [default: sci-librarian.toml, if present]
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Name of the config file profile to use. Command line flags override its settings.
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// Namespace id of a Dropbox team space to resolve paths in, instead of the personal space
+    #[arg(long, global = true)]
+    dropbox_path_root: Option<String>,
+
+    /// What to do with a known file whose content has changed since it was synced
+    #[arg(long, global = true, value_enum, default_value_t = OnContentChange::Reprocess)]
+    on_content_change: OnContentChange,
+
+    /// Also sync the files in the subfolders of the inboxes, except those under the allowed
+    /// upload prefixes, where papers are filed
+    #[arg(long, global = true)]
+    inbox_recursive: bool,
+
+    /// File name ending of files never to process, e.g. .md; repeat for more [default: .md .jsonld .bib .ris]
+    #[arg(long = "skip-suffix", global = true)]
+    skip_suffixes: Vec<String>,
+
+    /// Only use the rules with these names, for this run; repeat, or separate with commas
+    #[arg(long, global = true, value_delimiter = ',')]
+    only_rules: Vec<String>,
+
+    /// Leave out the rules with these names, for this run; repeat, or separate with commas
+    #[arg(long, global = true, value_delimiter = ',')]
+    skip_rules: Vec<String>,
+
+    #[command(flatten)]
+    llm: LlmArgs,
+
+    #[command(flatten)]
+    backend: BackendArgs,
+
+    #[command(flatten)]
+    http: HttpArgs,
+
+    /// Print the result of the command as human-readable text or as a JSON object for scripts,
+    /// with the progress messages on standard error
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Human)]
+    output: OutputFormat,
+
+    /// Never connect to Dropbox, e.g. to analyze local files only
+    #[arg(long, global = true)]
+    offline: bool,
+
+    /// Disable colored output. Also disabled by setting NO_COLOR or when not on a terminal.
+    #[arg(long, global = true)]
+    no_color: bool,
 
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Human,
+    Json,
+}
+
+/// Whether the command's result is printed as JSON, see [`say!`]
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+fn json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Print a progress message: on standard output, or on standard error with `--output json`,
+/// leaving standard output to the JSON result. Progress bars are hidden meanwhile, so they are
+/// not drawn over the message.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        progress().suspend(|| {
+            if json_output() {
+                eprintln!($($arg)*);
+            } else {
+                println!($($arg)*);
+            }
+        })
+    };
+}
+
+/// A command that ran to the end, but failed, e.g. a doctor run with failed checks, with the
+/// outcome to report.
+#[derive(Debug)]
+struct FailedCommand {
+    outcome: CommandOutcome,
+    message: String,
+}
+
+impl std::fmt::Display for FailedCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for FailedCommand {}
+
 const DEFAULT_JOBS: usize = 4;
 const DEFAULT_BATCH_SIZE: i64 = 10;
+const DEFAULT_MAX_FILE_RETRIES: u32 = 1;
+/// Attempts at syncing an inbox before giving up on connection errors
+const SYNC_ATTEMPTS: u32 = 3;
+const DEFAULT_WATCH_INTERVAL_SECS: u64 = 300;
+#[cfg(feature = "serve")]
+const DEFAULT_PORT: u16 = 8080;
+
+/// Where the inboxes and the library are
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Backend {
+    Dropbox,
+    /// A local directory tree, see `--root`
+    Local,
+}
+
+/// Options for choosing the backend
+#[derive(Args, Clone)]
+struct BackendArgs {
+    /// Where the inboxes and the library are: in Dropbox, or in a local directory tree with
+    /// Dropbox paths under `--root`, e.g. for archives that must stay offline
+    #[arg(long, global = true, value_enum, default_value_t = Backend::Dropbox)]
+    backend: Backend,
+    /// Root directory of the local backend
+    #[arg(long, global = true, required_if_eq("backend", "local"))]
+    root: Option<PathBuf>,
+}
+
+/// Options for querying the LLM
+#[derive(Args, Clone)]
+struct LlmArgs {
+    /// Path to a file with the instructions for the LLM, with {categories} and {text}
+    /// placeholders. Uses the built-in prompt if not given.
+    #[arg(long, global = true)]
+    prompt_template: Option<PathBuf>,
+    /// Sampling temperature of the LLM; 0 for reproducible classifications
+    #[arg(long, global = true, default_value_t = DEFAULT_TEMPERATURE)]
+    model_temperature: f32,
+    /// Maximum number of tokens in an LLM response [default: the model's]
+    #[arg(long, global = true)]
+    max_tokens: Option<u32>,
+    /// Give up on an LLM request after this many seconds
+    #[arg(long, global = true, default_value_t = DEFAULT_LLM_TIMEOUT.as_secs())]
+    llm_timeout_secs: u64,
+    /// Language to write the summaries in, or `auto` for the language of each paper [default:
+    /// English, unless a --prompt-template is given, which then says which language it wants]
+    #[arg(long, global = true)]
+    summary_language: Option<SummaryLanguage>,
+    /// How to classify papers: by the LLM, by the keywords of the rules, or by keywords
+    /// first and the LLM for papers matching no keywords
+    #[arg(long, global = true, value_enum, default_value_t = Classifier::Llm)]
+    classifier: Classifier,
+}
+
+/// Options for connecting to Dropbox and the LLM, e.g. from behind a corporate proxy
+#[derive(Args, Clone)]
+struct HttpArgs {
+    /// Proxy to send all requests through, e.g. http://proxy.example.com:8080. Without it,
+    /// the proxy in the HTTPS_PROXY or HTTP_PROXY environment variable is used, if set
+    #[arg(long, global = true)]
+    proxy: Option<String>,
+    /// PEM file with a CA certificate to trust besides the built-in ones, e.g. that of a
+    /// proxy inspecting TLS
+    #[arg(long, global = true)]
+    ca_certificate: Option<PathBuf>,
+    /// Header to send with every request, as 'Name: value', e.g. for an API gateway; repeat
+    /// for more
+    #[arg(long = "header", global = true)]
+    headers: Vec<String>,
+}
+
+impl HttpArgs {
+    fn options(&self) -> Result<HttpOptions> {
+        let mut options = HttpOptions::default();
+        if let Some(proxy) = &self.proxy {
+            options = options.with_proxy(proxy)?;
+        }
+        if let Some(path) = &self.ca_certificate {
+            options = options.with_ca_certificate(path)?;
+        }
+        for header in &self.headers {
+            options = options.with_header(header)?;
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Classifier {
+    Llm,
+    Keyword,
+    Hybrid,
+}
+
+/// Options for processing a batch of pending files
+#[derive(Args)]
+struct ProcessArgs {
+    #[arg(short, long, default_value_t = DEFAULT_JOBS)]
+    jobs: usize,
+    #[arg(short, long, default_value_t = DEFAULT_BATCH_SIZE)]
+    batch_size: i64,
+    /// How many times to retry a file after a transient (network) failure
+    #[arg(long, default_value_t = DEFAULT_MAX_FILE_RETRIES)]
+    max_file_retries: u32,
+    /// Skip files larger than this many bytes instead of parsing them
+    #[arg(long, default_value_t = DEFAULT_MAX_PDF_BYTES)]
+    max_pdf_bytes: u64,
+    /// Maximum number of concurrent downloads [default: one per job]
+    #[arg(long)]
+    download_jobs: Option<usize>,
+    /// Maximum number of concurrent LLM queries [default: one per job]
+    #[arg(long)]
+    llm_jobs: Option<usize>,
+    /// Maximum number of LLM queries started per minute, e.g. the provider's rate limit
+    #[arg(long)]
+    llm_rpm: Option<u32>,
+    /// Maximum number of uploads to Dropbox at once across all jobs, to stay within its
+    /// limits on concurrent writes
+    #[arg(
+        long,
+        default_value_t = DEFAULT_MAX_INFLIGHT_UPLOADS,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    max_inflight_uploads: usize,
+    /// Process files left in progress by an interrupted run after this many minutes
+    #[arg(long, default_value_t = DEFAULT_RECLAIM_AFTER.as_secs() / 60)]
+    reclaim_after_minutes: u64,
+    /// Leave files for review when the LLM's confidence is below this (0 to 1)
+    #[arg(long, default_value_t = DEFAULT_MIN_CONFIDENCE)]
+    min_confidence: f64,
+    /// File papers under at most this many categories [default: unlimited]
+    #[arg(long)]
+    max_categories: Option<usize>,
+    /// What to do with papers matching more than --max-categories: leave them for review,
+    /// or file them under the categories the LLM matched best
+    #[arg(long, value_enum, default_value_t = TooManyCategories::Review)]
+    too_many_categories: TooManyCategories,
+    /// Upload files as first-author-year-title.pdf instead of under their original name
+    #[arg(long)]
+    rename_from_metadata: bool,
+    /// Format of the sidecar uploaded for each filed PDF
+    #[arg(long, value_enum, default_value_t = SidecarFormat::Markdown)]
+    sidecar_format: SidecarFormat,
+    /// Upload sidecars next to their PDFs, or in a `_meta` subfolder of their folders
+    #[arg(long, value_enum, default_value_t = SidecarPlacement::Alongside)]
+    sidecar_placement: SidecarPlacement,
+    /// Print one line per finished file, in the order the files were queued, instead of
+    /// drawing progress bars, e.g. for logs to compare between runs
+    #[arg(long)]
+    no_progress: bool,
+    /// Read only the first page of each PDF, unless it has too little text, to save time and
+    /// LLM tokens on bulk runs
+    #[arg(long)]
+    fast: bool,
+    /// Maximum number of finished files waiting to be recorded in the database; workers wait
+    /// when it is reached
+    #[arg(long, default_value_t = DEFAULT_RESULT_QUEUE_CAPACITY)]
+    result_queue_capacity: usize,
+    /// Price per thousand LLM tokens, to print the estimated cost of the run
+    #[arg(long, value_name = "DOLLARS")]
+    cost_per_1k: Option<f64>,
+    /// The order to take pending files into the batch in: as synced, largest first, or most
+    /// recently changed first
+    #[arg(long, value_enum, default_value_t = PendingOrder::Synced)]
+    order_by: PendingOrder,
+    /// When processing files again, e.g. with another model, only upload the sidecars whose
+    /// metadata changed, and report how many changed
+    #[arg(long)]
+    only_changed: bool,
+    /// Keep the raw copies of downloaded files in the work directory compressed with gzip
+    #[arg(long)]
+    compress_raw: bool,
+}
+
+impl ProcessArgs {
+    fn pipeline_options(&self, allowed_upload_prefixes: &[String]) -> PipelineOptions {
+        PipelineOptions {
+            max_file_retries: self.max_file_retries,
+            max_pdf_bytes: self.max_pdf_bytes,
+            download_jobs: self.download_jobs,
+            llm_jobs: self.llm_jobs,
+            llm_rpm: self.llm_rpm,
+            max_inflight_uploads: self.max_inflight_uploads,
+            reclaim_after: Duration::from_secs(self.reclaim_after_minutes * 60),
+            min_confidence: self.min_confidence,
+            max_categories: self.max_categories,
+            too_many_categories: self.too_many_categories,
+            rename_from_metadata: self.rename_from_metadata,
+            allowed_upload_prefixes: allowed_upload_prefixes.to_vec(),
+            job_queue_capacity: DEFAULT_JOB_QUEUE_CAPACITY,
+            result_queue_capacity: self.result_queue_capacity,
+            sidecar_format: self.sidecar_format,
+            sidecar_placement: self.sidecar_placement,
+            ordered_results: self.no_progress,
+            fast_extraction: self.fast,
+            order_by: self.order_by,
+            only_changed: self.only_changed,
+            compress_raw: self.compress_raw,
+        }
+    }
+}
+
+/// Options for regenerating folder indexes
+#[derive(Args)]
+struct IndexArgs {
+    #[arg(short, long, required_unless_present = "all")]
+    path: Option<String>,
+    /// Regenerate the index of every folder files have been filed into
+    #[arg(short, long, conflicts_with = "path")]
+    all: bool,
+    /// Maximum number of indexes to generate concurrently with --all
+    #[arg(short, long, default_value_t = DEFAULT_JOBS)]
+    jobs: usize,
+    /// Add a column with each paper's abstract
+    #[arg(long)]
+    with_abstract: bool,
+    /// Also write an AUTHORS.md listing the papers by author
+    #[arg(long)]
+    by_author: bool,
+    /// Only update the rows of papers processed since this date or RFC 3339 timestamp,
+    /// keeping the rest of the existing index
+    #[arg(long, value_parser = parse_since)]
+    since: Option<DateTime<Utc>>,
+    /// Link each title to the PDF by its file name, or by a Dropbox shared link for sharing
+    /// the index with collaborators
+    #[arg(long, value_enum, default_value_t = IndexLinks::Relative)]
+    links: IndexLinks,
+    /// Start each index with a line counting its papers and naming the most frequent author
+    #[arg(long)]
+    with_summary: bool,
+    /// List this many of the most recently processed papers in a "Recently added" section
+    /// above the table; 0 for none
+    #[arg(long, default_value_t = DEFAULT_RECENTLY_ADDED)]
+    recently_added: usize,
+}
+
+impl IndexArgs {
+    fn index_options(&self) -> IndexOptions {
+        IndexOptions {
+            include_abstract: self.with_abstract,
+            by_author: self.by_author,
+            since: self.since,
+            links: self.links,
+            summary_line: self.with_summary,
+            recently_added: self.recently_added,
+        }
+    }
+}
 
 #[derive(Subcommand)]
 enum Commands {
     /// Sync, process, and index
     Run {
-        #[arg(short, long, default_value_t = DEFAULT_JOBS)]
-        jobs: usize,
-        #[arg(short, long, default_value_t = DEFAULT_BATCH_SIZE)]
-        batch_size: i64,
+        #[command(flatten)]
+        process: ProcessArgs,
+    },
+    /// Sync and process new files every few minutes until stopped with Ctrl-C
+    Watch {
+        /// Seconds to wait after a cycle before syncing again
+        #[arg(long, default_value_t = DEFAULT_WATCH_INTERVAL_SECS)]
+        interval_secs: u64,
+        #[command(flatten)]
+        process: ProcessArgs,
     },
     /// Only sync new files from Dropbox
     Sync,
+    /// Show how many files there are with each status
+    Status,
     /// Only process downloaded files
     Process {
-        #[arg(short, long, default_value_t = DEFAULT_JOBS)]
-        jobs: usize,
-        #[arg(short, long, default_value_t = DEFAULT_BATCH_SIZE)]
-        batch_size: i64,
+        #[command(flatten)]
+        process: ProcessArgs,
     },
-    /// Force regeneration of index for a path
+    /// Force regeneration of index for a path, or for all folders with --all
     Index {
+        #[command(flatten)]
+        index: IndexArgs,
+    },
+    /// Initialize working directory and Dropbox folders, writing a sample rules file and
+    /// `.env` file to the working directory
+    Init {
+        /// Overwrite the sample files if they exist
+        #[arg(long)]
+        force: bool,
+    },
+    /// Write all file records in the database to standard output as JSON
+    #[command(alias = "export-db")]
+    Dump,
+    /// Check credentials, connectivity, work directory and rules before a run
+    Doctor,
+    /// Print the settings in effect after applying the flags, profile and defaults, with
+    /// secrets redacted
+    Config,
+    /// Compact the state database and report its size before and after
+    Maintenance,
+    /// Apply the pending migrations of the state database, which other commands also do
+    /// when they start
+    Migrate {
+        /// Only list the migrations applied and pending, without applying any
+        #[arg(long)]
+        status: bool,
+    },
+    /// Restore file records from a JSON dump into the database
+    Import {
+        /// Path to a JSON file written by the dump command
+        file: PathBuf,
+    },
+    /// List the files in the database
+    List {
+        /// Only list files with this tag
+        #[arg(long)]
+        tag: Option<String>,
+        /// Only list files whose extracted text was poor, e.g. to find PDFs needing OCR
+        #[arg(long)]
+        low_quality: bool,
+        /// Only list files whose extracted text changed since this run processed them, e.g.
+        /// after switching extractors. The run id is recorded with each file, see `dump`
+        #[arg(long, value_name = "RUN")]
+        text_changed_since: Option<String>,
+        /// The page to show, starting from 1
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        page: u32,
+        /// Files per page; all files are listed when not given
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        page_size: Option<u32>,
+    },
+    /// Regenerate the sidecars of processed files without uploading their PDFs again
+    Sidecars {
+        /// Format of the sidecars to write
+        #[arg(long, value_enum, default_value_t = SidecarFormat::Markdown)]
+        format: SidecarFormat,
+        /// Write the sidecars next to their PDFs, or in a `_meta` subfolder of their folders
+        #[arg(long, value_enum, default_value_t = SidecarPlacement::Alongside)]
+        placement: SidecarPlacement,
+    },
+    /// Check that the PDFs and sidecars of filed papers are still in Dropbox where the
+    /// database says they are, e.g. after moving or deleting files by hand
+    Verify {
+        /// Reset the papers with a PDF missing to pending, to be processed and filed again
+        #[arg(long)]
+        fix: bool,
+        /// Format of the sidecars to look for
+        #[arg(long, value_enum, default_value_t = SidecarFormat::Markdown)]
+        format: SidecarFormat,
+        /// Look for the sidecars next to their PDFs, or in a `_meta` subfolder of their folders
+        #[arg(long, value_enum, default_value_t = SidecarPlacement::Alongside)]
+        placement: SidecarPlacement,
+    },
+    /// List the papers waiting for review, with the categories they may be filed under
+    Review,
+    /// List the files that failed most recently, with their errors
+    Errors {
+        /// Number of files to list
+        #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(i64).range(1..))]
+        limit: i64,
+    },
+    /// Write an RSS feed of newly filed papers
+    Feed {
+        /// Path of the RSS file to write
         #[arg(short, long)]
-        path: String,
+        out: PathBuf,
+        /// Only include papers processed since this date or RFC 3339 timestamp
+        #[arg(long, value_parser = parse_since)]
+        since: Option<DateTime<Utc>>,
+        /// Base URL that item links are formed from by appending the paper's target path
+        #[arg(long, default_value = DEFAULT_LINK_BASE)]
+        link_base: String,
+    },
+    /// Analyze a local PDF and print its metadata and matching rules as JSON, without
+    /// uploading anything
+    Analyze {
+        /// Path of the PDF file
+        path: PathBuf,
+    },
+    /// Print the text extracted from a PDF in Dropbox or a local file, as the LLM gets it,
+    /// e.g. to see why a paper was misclassified
+    DumpText {
+        /// Dropbox id (id:...) or path of the file
+        #[arg(required_unless_present = "path", conflicts_with = "path")]
+        id: Option<String>,
+        /// Path of a local file instead
+        #[arg(long)]
+        path: Option<PathBuf>,
+        /// Number of pages of a PDF to read [default: as many as when processing]
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        pages: Option<u32>,
+    },
+    /// Serve an HTTP API to browse the files and trigger syncs and processing
+    #[cfg(feature = "serve")]
+    Serve {
+        /// Port to listen on, on localhost
+        #[arg(long, default_value_t = DEFAULT_PORT)]
+        port: u16,
+        #[command(flatten)]
+        process: ProcessArgs,
     },
-    /// Initialize working directory and Dropbox folders
-    Init,
 }
 
-// TODO: Get this as a parameter
-const DROPBOX_ALLOWED_UPLOAD_PREFIX: &'static str = "/sorted";
+impl Commands {
+    /// The name of the command, as in its JSON result.
+    fn name(&self) -> &'static str {
+        match self {
+            Commands::Run { .. } => "run",
+            Commands::Watch { .. } => "watch",
+            Commands::Sync => "sync",
+            Commands::Status => "status",
+            Commands::Process { .. } => "process",
+            Commands::Index { .. } => "index",
+            Commands::Init { .. } => "init",
+            Commands::Dump => "dump",
+            Commands::Doctor => "doctor",
+            Commands::Config => "config",
+            Commands::Maintenance => "maintenance",
+            Commands::Migrate { .. } => "migrate",
+            Commands::Import { .. } => "import",
+            Commands::List { .. } => "list",
+            Commands::Sidecars { .. } => "sidecars",
+            Commands::Verify { .. } => "verify",
+            Commands::Review => "review",
+            Commands::Errors { .. } => "errors",
+            Commands::Feed { .. } => "feed",
+            Commands::Analyze { .. } => "analyze",
+            Commands::DumpText { .. } => "dump-text",
+            #[cfg(feature = "serve")]
+            Commands::Serve { .. } => "serve",
+        }
+    }
+
+    /// Whether the command talks to Dropbox, and so cannot run with --offline.
+    fn needs_dropbox(&self) -> bool {
+        !matches!(
+            self,
+            Commands::Dump
+                | Commands::Maintenance
+                | Commands::Migrate { .. }
+                | Commands::Config
+                | Commands::Status
+                | Commands::Import { .. }
+                | Commands::Init { .. }
+                | Commands::List { .. }
+                | Commands::Review
+                | Commands::Errors { .. }
+                | Commands::Feed { .. }
+                | Commands::Analyze { .. }
+                | Commands::DumpText { id: None, .. }
+        )
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenvy::dotenv().ok();
 
+    let cli = Cli::parse();
+    JSON_OUTPUT.store(cli.output == OutputFormat::Json, Ordering::Relaxed);
+    let colorize = configure_colors(cli.no_color);
+
     tracing_subscriber::registry()
-        .with(fmt::layer())
+        .with(
+            fmt::layer()
+                .with_writer(|| ProgressAwareStderr)
+                .with_ansi(colorize),
+        )
         .with(EnvFilter::from_default_env())
         .init();
 
-    let cli = Cli::parse();
+    let settings = resolve_settings(&cli)?;
+    let config_path = config_file(&cli);
+    let profile = cli.profile.clone();
 
-    let work_dir_buf = Cli::parse().work_directory;
-    let work_dir = absolute_work_directory(&work_dir_buf)?;
-    let files = init_work_directory_and_db(work_dir).await?;
+    let work_dir = absolute_work_directory(&settings.work_directory)?;
+    // The migrate command reports the migrations before they are applied
+    let migrate = !matches!(cli.command, Commands::Migrate { .. });
+    let database_path = cli
+        .db_path
+        .clone()
+        .unwrap_or_else(|| work_dir.0.join("state.db"));
+    let files = init_work_directory_and_db(
+        work_dir,
+        database_path.clone(),
+        migrate,
+        cli.on_content_change,
+    )
+    .await?;
     info!(
         "{}: {}",
         "Using working directory".cyan().bold(),
@@ -96,60 +679,331 @@ async fn main() -> Result<()> {
     let work_dir = files.work_directory;
     let storage = files.storage;
 
-    let inbox = DropboxInbox(cli.inbox.clone());
-    info!("{}: {}", "Using Dropbox inbox".cyan().bold(), inbox.0);
-
-    let dropbox_token = get_env_var("DROPBOX_TOKEN")?;
-    let mistral_key = get_env_var("MISTRAL_API_KEY")?;
+    let inboxes = settings
+        .inboxes
+        .iter()
+        .map(|inbox| DropboxInbox(inbox.clone()))
+        .collect::<Vec<DropboxInbox>>();
+    for inbox in &inboxes {
+        info!("{}: {}", "Using Dropbox inbox".cyan().bold(), inbox.0);
+    }
 
-    let dropbox: Arc<dyn DropboxClient> = Arc::new(DropboxHttpClient::new(
-        dropbox_token,
-        String::from(DROPBOX_ALLOWED_UPLOAD_PREFIX),
-    ));
-    let llm: Arc<dyn LlmClient> = Arc::new(MistralHttpClient::new(mistral_key));
+    let rules = load_rules(settings.rules.as_deref())
+        .and_then(|rules| rules.select(&cli.only_rules, &cli.skip_rules));
+    let llm_args = cli.llm.clone();
+    let backend = cli.backend.clone();
+    let http = cli.http.clone();
+    if cli.offline && backend.backend == Backend::Dropbox && cli.command.needs_dropbox() {
+        return Err(anyhow::anyhow!(
+            "This command needs Dropbox, which --offline rules out"
+        ));
+    }
 
-    let rules = Arc::new(get_rules());
+    let offline = cli.offline;
+    let command = cli.command.name();
+    let outcome = async {
+        let outcome = match cli.command {
+            Commands::Run { process } => {
+                let rules = Arc::new(rules?);
+                let dropbox = dropbox_client(&settings, &backend, &http)?;
+                let llm = llm_client(&llm_args, &http)?;
+                say!("{}", "Starting full run...".cyan().bold());
+                let synced = execute_sync(&inboxes, &storage, &dropbox, &settings).await?;
+                let summary = execute_process(
+                    rules,
+                    work_dir,
+                    &storage,
+                    &dropbox,
+                    llm,
+                    &process,
+                    &settings.allowed_upload_prefixes,
+                )
+                .await?;
+                say!("{}", "Run complete.".green());
+                Some(CommandOutcome::run(synced, &summary))
+            }
+            Commands::Watch {
+                interval_secs,
+                process,
+            } => {
+                let rules = Arc::new(rules?);
+                let dropbox = dropbox_client(&settings, &backend, &http)?;
+                let llm = llm_client(&llm_args, &http)?;
+                let pipeline = process_pipeline(
+                    rules,
+                    work_dir,
+                    &storage,
+                    &dropbox,
+                    llm,
+                    &process,
+                    &settings.allowed_upload_prefixes,
+                );
+                let options = WatchOptions {
+                    inboxes: settings.inboxes.clone(),
+                    inbox_recursive: settings.inbox_recursive,
+                    skip_suffixes: settings.skip_suffixes.clone(),
+                    allowed_upload_prefixes: settings.allowed_upload_prefixes.clone(),
+                    interval: Duration::from_secs(interval_secs),
+                    batch_size: process.batch_size,
+                    jobs: process.jobs,
+                };
+                Some(execute_watch(&pipeline, &storage, &dropbox, &options).await?)
+            }
+            Commands::Sync => {
+                let dropbox = dropbox_client(&settings, &backend, &http)?;
+                let synced = execute_sync(&inboxes, &storage, &dropbox, &settings).await?;
+                Some(CommandOutcome::sync(synced))
+            }
+            Commands::Process { process } => {
+                let rules = Arc::new(rules?);
+                let dropbox = dropbox_client(&settings, &backend, &http)?;
+                let llm = llm_client(&llm_args, &http)?;
+                let summary = execute_process(
+                    rules,
+                    work_dir,
+                    &storage,
+                    &dropbox,
+                    llm,
+                    &process,
+                    &settings.allowed_upload_prefixes,
+                )
+                .await?;
+                Some(CommandOutcome::batch("process", &summary))
+            }
+            Commands::Status => Some(execute_status(&storage).await?),
+            Commands::Index { index } => {
+                let dropbox = dropbox_client(&settings, &backend, &http)?;
+                let folders = if index.all {
+                    execute_index_all(&storage, dropbox, &index).await?
+                } else if let Some(path) = &index.path {
+                    execute_index(&storage, dropbox, path, &index).await?
+                } else {
+                    vec![]
+                };
+                Some(CommandOutcome::index(&folders))
+            }
+            Commands::Init { force } => {
+                // The rules and client are only built once the sample rules and `.env` files
+                // are written, since a new user has neither yet
+                let connect = || {
+                    if offline && backend.backend == Backend::Dropbox {
+                        return Err(anyhow::anyhow!("--offline rules out Dropbox"));
+                    }
+                    let rules = load_rules(settings.rules.as_deref())
+                        .and_then(|rules| rules.select(&cli.only_rules, &cli.skip_rules))?;
+                    Ok((Arc::new(rules), dropbox_client(&settings, &backend, &http)?))
+                };
+                Some(execute_init(work_dir, database_path, force, connect).await?)
+            }
+            Commands::Doctor => {
+                Some(execute_doctor(&work_dir, rules, &settings, &backend, &llm_args, &http).await?)
+            }
+            Commands::Config => {
+                let effective =
+                    EffectiveConfig::new(settings.clone(), config_path, profile, |name| {
+                        env::var(name).ok()
+                    });
+                print_config(&effective)?;
+                None
+            }
+            Commands::Dump => {
+                execute_dump(&storage).await?;
+                None
+            }
+            Commands::Maintenance => Some(execute_maintenance(&storage).await?),
+            Commands::Migrate { status } => Some(execute_migrate(&storage, status).await?),
+            Commands::Import { file } => Some(execute_import(&storage, &file).await?),
+            Commands::List {
+                tag,
+                low_quality,
+                text_changed_since,
+                page,
+                page_size,
+            } => {
+                let run_id = text_changed_since.map(RunId);
+                let filter = ListFilter {
+                    tag: tag.as_deref(),
+                    below_extraction_quality: low_quality.then_some(LOW_EXTRACTION_QUALITY),
+                    text_changed_since: run_id.as_ref(),
+                };
+                Some(execute_list(&storage, &filter, page, page_size).await?)
+            }
+            Commands::Sidecars { format, placement } => {
+                let dropbox = dropbox_client(&settings, &backend, &http)?;
+                Some(execute_sidecars(&storage, dropbox, format, placement).await?)
+            }
+            Commands::Verify {
+                fix,
+                format,
+                placement,
+            } => {
+                let dropbox = dropbox_client(&settings, &backend, &http)?;
+                Some(execute_verify(&storage, dropbox, fix, format, placement).await?)
+            }
+            Commands::Review => Some(execute_review(&storage).await?),
+            Commands::Errors { limit } => Some(execute_errors(&storage, limit).await?),
+            Commands::Feed {
+                out,
+                since,
+                link_base,
+            } => Some(execute_feed(&storage, &out, since, &link_base).await?),
+            Commands::Analyze { path } => {
+                let llm = llm_client(&llm_args, &http)?;
+                let analysis = analyze_local_file(&path, llm.as_ref(), &rules?).await?;
+                println!("{}", serde_json::to_string_pretty(&analysis)?);
+                None
+            }
+            Commands::DumpText { id, path, pages } => {
+                let pages = pages.map(|pages| pages as usize);
+                let text = match (id, path) {
+                    (Some(id), _) => {
+                        let dropbox = dropbox_client(&settings, &backend, &http)?;
+                        dropbox_file_text(&*dropbox, &DropboxId(id), pages).await?
+                    }
+                    (None, Some(path)) => {
+                        let content = fs::read(&path).with_context(|| {
+                            format!("Failed to read {}", path.to_string_lossy())
+                        })?;
+                        file_text(&content, pages).with_context(|| {
+                            format!("Failed to extract text from {}", path.to_string_lossy())
+                        })?
+                    }
+                    (None, None) => unreachable!("clap requires an id or a path"),
+                };
+                print!("{}", text);
+                None
+            }
+            #[cfg(feature = "serve")]
+            Commands::Serve { port, process } => {
+                let state = sci_librarian::server::AppState {
+                    storage: storage.clone(),
+                    dropbox: dropbox_client(&settings, &backend, &http)?,
+                    llm: llm_client(&llm_args, &http)?,
+                    work_dir,
+                    rules: Arc::new(rules?),
+                    inboxes: settings.inboxes.clone(),
+                    inbox_recursive: settings.inbox_recursive,
+                    skip_suffixes: settings.skip_suffixes.clone(),
+                    options: process.pipeline_options(&settings.allowed_upload_prefixes),
+                    batch_size: process.batch_size,
+                    jobs: process.jobs,
+                    run_lock: Arc::default(),
+                };
+                sci_librarian::server::serve(state, port).await?;
+                None
+            }
+        };
+        Ok::<_, Error>(outcome)
+    }
+    .await;
 
-    match cli.command {
-        Commands::Run { jobs, batch_size } => {
-            info!("{}", "Starting full run...".cyan().bold());
-            execute_sync(&inbox, &storage, &dropbox).await?;
-            execute_process(rules, work_dir, &storage, &dropbox, llm, jobs, batch_size).await?;
-            info!("{}", "Run complete.".green());
+    match (cli.output, outcome) {
+        (OutputFormat::Json, Ok(Some(outcome))) => {
+            println!("{}", serde_json::to_string(&outcome)?);
         }
-        Commands::Sync => {
-            execute_sync(&inbox, &storage, &dropbox).await?;
+        (OutputFormat::Json, Err(e)) => {
+            let outcome = match e.downcast_ref::<FailedCommand>() {
+                Some(failed) => failed.outcome.clone(),
+                None => CommandOutcome::failed(command, &e),
+            };
+            println!("{}", serde_json::to_string(&outcome)?);
+            return Err(e);
         }
-        Commands::Process { jobs, batch_size } => {
-            execute_process(rules, work_dir, &storage, &dropbox, llm, jobs, batch_size).await?;
-        }
-        Commands::Index { path } => {
-            execute_index(&storage, dropbox, &path).await?;
-        }
-        Commands::Init => {
-            execute_init(rules, work_dir, dropbox).await?;
+        (_, outcome) => {
+            outcome?;
         }
     }
 
     Ok(())
 }
 
+/// Print the settings as `name: value` lines, or as a JSON object with `--output json`.
+fn print_config(effective: &EffectiveConfig) -> Result<()> {
+    let json = serde_json::to_value(effective)?;
+    if json_output() {
+        println!("{}", serde_json::to_string_pretty(&json)?);
+        return Ok(());
+    }
+    let show = |value: &serde_json::Value| match value {
+        serde_json::Value::Null => "not set".dimmed().to_string(),
+        serde_json::Value::String(text) if text.is_empty() => "\"\"".to_string(),
+        serde_json::Value::String(text) => text.clone(),
+        serde_json::Value::Array(values) => values
+            .iter()
+            .map(|value| {
+                value
+                    .as_str()
+                    .map_or_else(|| value.to_string(), |text| format!("\"{}\"", text))
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+        other => other.to_string(),
+    };
+    // The settings, then the secrets they are nested under
+    let fields = json.as_object().into_iter().flatten();
+    let (secrets, settings): (Vec<_>, Vec<_>) = fields.partition(|(_, value)| value.is_object());
+    let secrets = secrets
+        .into_iter()
+        .flat_map(|(_, secrets)| secrets.as_object().into_iter().flatten());
+    for (name, value) in settings.into_iter().chain(secrets) {
+        println!("{}: {}", name.cyan(), show(value));
+    }
+    Ok(())
+}
+
+/// The config file to read: the one given, or the default one if present.
+fn config_file(cli: &Cli) -> Option<PathBuf> {
+    match &cli.config {
+        Some(path) => Some(path.clone()),
+        None if Path::new(DEFAULT_CONFIG_FILE).exists() => Some(PathBuf::from(DEFAULT_CONFIG_FILE)),
+        None => None,
+    }
+}
+
+/// Combine the command line flags with the selected profile from the config file.
+fn resolve_settings(cli: &Cli) -> Result<Settings> {
+    let config = match config_file(cli) {
+        Some(path) => Config::load(&path)?,
+        None => Config::default(),
+    };
+    let profile = match &cli.profile {
+        Some(name) => Some(config.profile(name)?),
+        None => None,
+    };
+    let flags = Profile {
+        inbox: (!cli.inbox.is_empty()).then(|| cli.inbox.clone()),
+        rules: cli.rules.clone(),
+        allowed_upload_prefix: (!cli.allowed_upload_prefix.is_empty())
+            .then(|| cli.allowed_upload_prefix.clone()),
+        work_directory: cli.work_directory.clone(),
+        dropbox_path_root: cli.dropbox_path_root.clone(),
+        skip_suffixes: (!cli.skip_suffixes.is_empty()).then(|| cli.skip_suffixes.clone()),
+        inbox_recursive: cli.inbox_recursive.then_some(true),
+    };
+    Ok(Settings::resolve(flags, profile))
+}
+
 struct LocalFiles {
     work_directory: WorkDirectory,
     database_path: PathBuf,
     storage: Arc<Storage>,
 }
 
-fn absolute_work_directory(work_dir_path: &PathBuf) -> Result<WorkDirectory, Error> {
+fn absolute_work_directory(work_dir_path: &Path) -> Result<WorkDirectory, Error> {
     let work_dir_abs = if work_dir_path.is_absolute() {
-        work_dir_path.clone()
+        work_dir_path.to_path_buf()
     } else {
-        env::current_dir()?.join(&work_dir_path)
+        env::current_dir()?.join(work_dir_path)
     };
     Ok(WorkDirectory(work_dir_abs.clone()))
 }
 
-async fn init_work_directory_and_db(work_directory: WorkDirectory) -> Result<LocalFiles, Error> {
+async fn init_work_directory_and_db(
+    work_directory: WorkDirectory,
+    database_path: PathBuf,
+    migrate: bool,
+    on_content_change: OnContentChange,
+) -> Result<LocalFiles, Error> {
     let WorkDirectory(work_dir_path) = &work_directory;
     // Initialize work directory
     fs::create_dir_all(work_dir_path)?;
@@ -157,17 +1011,32 @@ async fn init_work_directory_and_db(work_directory: WorkDirectory) -> Result<Loc
     // Ensure raw directory exists
     fs::create_dir_all(work_dir_path.join("raw"))?;
 
-    let db_path = work_dir_path.join("state.db");
-    let db_url = format!("sqlite:///{}", db_path.to_string_lossy().replace('\\', "/"));
-    let pool = setup_db(&db_url).await?;
-    let storage = Arc::new(Storage::new(pool));
+    if let Some(parent) = database_path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent)?;
+    }
+    let db_url = sqlite_url(&database_path);
+    let pool = if migrate {
+        setup_db(&db_url).await?
+    } else {
+        open_db(&db_url).await?
+    };
+    let storage = Arc::new(Storage::new(pool).with_on_content_change(on_content_change));
     Ok(LocalFiles {
         work_directory,
-        database_path: db_path,
+        database_path,
         storage,
     })
 }
 
+fn load_rules(path: Option<&Path>) -> Result<Rules> {
+    match path {
+        Some(path) => Rules::load(path),
+        None => Ok(get_rules()),
+    }
+}
+
 fn get_rules() -> Rules {
     Rules::from(vec![
         Rule {
@@ -176,6 +1045,7 @@ fn get_rules() -> Rules {
                 "Neural Networks, Deep Learning, Large Language Models (LLMs), Reinforcement Learning and other large-scale text, image and video processing tasks using function approximators",
             ),
             path: RemotePath::from("/sorted/ai"),
+            ..Default::default()
         },
         Rule {
             name: String::from("Programming Language Theory"),
@@ -183,11 +1053,13 @@ fn get_rules() -> Rules {
                 "Programming language theory, parsers, compilers, partial evaluation, type systems etc.",
             ),
             path: RemotePath::from("/sorted/programming-languages"),
+            ..Default::default()
         },
         Rule {
             name: String::from("DSLs"),
             description: String::from("Domain specific languages and their implementation."),
             path: RemotePath::from("/sorted/domain-specific-languages"),
+            ..Default::default()
         },
         Rule {
             name: String::from("LegalTech"),
@@ -195,6 +1067,7 @@ fn get_rules() -> Rules {
                 "Legal technology in various forms: drafting, management, review, reporting and auditing; legal research; compliance; law practice management systems and more.",
             ),
             path: RemotePath::from("/sorted/legal-tech"),
+            ..Default::default()
         },
     ])
 }
@@ -203,27 +1076,89 @@ async fn execute_index(
     storage: &Arc<Storage>,
     dropbox: Arc<dyn DropboxClient>,
     path: &String,
-) -> Result<(), Error> {
-    println!("Indexing {}...", path);
-    generate_index(&storage, &*dropbox, &path).await?;
-    println!("{}", "Indexing complete.".green());
-    Ok(())
+    args: &IndexArgs,
+) -> Result<Vec<String>, Error> {
+    say!("Indexing {}...", path);
+    generate_index(storage, &*dropbox, path, &args.index_options()).await?;
+    say!("{}", "Indexing complete.".green());
+    Ok(vec![path.clone()])
+}
+
+async fn execute_index_all(
+    storage: &Arc<Storage>,
+    dropbox: Arc<dyn DropboxClient>,
+    args: &IndexArgs,
+) -> Result<Vec<String>, Error> {
+    say!("Indexing all folders...");
+    let folders =
+        generate_all_indexes(storage, &*dropbox, &args.index_options(), args.jobs).await?;
+    say!(
+        "{}: {} indexes written.",
+        "Indexing complete".green(),
+        folders.len()
+    );
+    Ok(folders)
 }
 
 async fn execute_init(
-    rules: Arc<Rules>,
     work_directory: WorkDirectory,
-    dropbox: Arc<dyn DropboxClient>,
-) -> Result<(), Error> {
-    println!("Initializing working directory...");
-    init_work_directory_and_db(work_directory).await?;
-    println!("Initializing Dropbox folders...");
+    database_path: PathBuf,
+    force: bool,
+    connect: impl FnOnce() -> Result<(Arc<Rules>, Arc<dyn DropboxClient>)>,
+) -> Result<CommandOutcome, Error> {
+    say!("Initializing working directory...");
+    let scaffold = scaffold_work_directory(&work_directory.0, force)?;
+    for path in &scaffold.written {
+        say!("Wrote {}", path.to_string_lossy());
+    }
+    for path in &scaffold.kept {
+        say!(
+            "Kept existing {}; use --force to overwrite it",
+            path.to_string_lossy()
+        );
+    }
+    init_work_directory_and_db(
+        work_directory,
+        database_path,
+        true,
+        OnContentChange::default(),
+    )
+    .await?;
+    let mut folders = Vec::new();
+    let (rules, dropbox) = match connect() {
+        Ok(connection) => connection,
+        Err(e) => {
+            say!(
+                "{} Skipped the Dropbox folders; fill in the .env file and run init again to create them.",
+                "?".yellow()
+            );
+            say!("{}", e);
+            return Ok(init_outcome(folders, &scaffold));
+        }
+    };
+    say!("Initializing Dropbox folders...");
     for rule in &rules.0 {
-        println!("Ensuring folder exists: {}", rule.path.0);
-        dropbox.create_folder_if_not_exists(&rule.path.0).await?;
+        // Templated targets are resolved per paper, so only their static part can be created
+        let folder = static_prefix(&rule.path);
+        say!("Ensuring folder exists: {}", folder.0);
+        dropbox.create_folder_if_not_exists(&folder.0).await?;
+        folders.push(folder.0);
     }
-    println!("{}", "Initialization complete.".green());
-    Ok(())
+    say!("{}", "Initialization complete.".green());
+    Ok(init_outcome(folders, &scaffold))
+}
+
+fn init_outcome(folders: Vec<String>, scaffold: &Scaffold) -> CommandOutcome {
+    CommandOutcome::new("init")
+        .with_count("folders", folders.len() as u64)
+        .with_count("files", scaffold.written.len() as u64)
+        .with_paths(folders)
+        .with_paths(
+            scaffold
+                .written
+                .iter()
+                .map(|path| path.to_string_lossy().into_owned()),
+        )
 }
 
 async fn execute_process(
@@ -232,39 +1167,536 @@ async fn execute_process(
     storage: &Arc<Storage>,
     dropbox: &Arc<dyn DropboxClient>,
     llm: Arc<dyn LlmClient>,
-    jobs: usize,
-    batch_size: i64,
-) -> Result<(), Error> {
-    println!("Processing pending files...");
-    let pipeline = Pipeline::new(
+    args: &ProcessArgs,
+    allowed_upload_prefixes: &[String],
+) -> Result<BatchSummary, Error> {
+    say!("Processing pending files...");
+    let pipeline = process_pipeline(
+        rules,
+        work_dir,
+        storage,
+        dropbox,
+        llm,
+        args,
+        allowed_upload_prefixes,
+    );
+    let summary = pipeline.run_batch(args.batch_size, args.jobs).await?;
+    say!("Processing completed.");
+    if args.only_changed {
+        say!(
+            "Metadata changed: {}, unchanged: {}",
+            summary.processed - summary.unchanged,
+            summary.unchanged
+        );
+    }
+    if let Some(timings) = summary.average_timings() {
+        say!("Average time per file: {}", timings);
+    }
+    let usage = summary.token_usage;
+    if usage.total() > 0 {
+        say!(
+            "LLM tokens used: {} prompt, {} completion",
+            usage.prompt_tokens,
+            usage.completion_tokens
+        );
+        if let Some(cost_per_1k) = args.cost_per_1k {
+            say!(
+                "Estimated LLM cost: ${:.4}",
+                usage.estimated_cost(cost_per_1k)
+            );
+        }
+    }
+    Ok(summary)
+}
+
+/// A pipeline with the given options, printing its progress the way the output allows.
+fn process_pipeline(
+    rules: Arc<Rules>,
+    work_dir: WorkDirectory,
+    storage: &Arc<Storage>,
+    dropbox: &Arc<dyn DropboxClient>,
+    llm: Arc<dyn LlmClient>,
+    args: &ProcessArgs,
+    allowed_upload_prefixes: &[String],
+) -> Pipeline {
+    let mut pipeline = Pipeline::new(
         storage.clone(),
         dropbox.clone(),
         llm.clone(),
         work_dir.clone(),
         rules.clone(),
+    )
+    .with_options(args.pipeline_options(allowed_upload_prefixes));
+    if json_output() {
+        pipeline = pipeline.with_plain_output_on_stderr();
+    } else if args.no_progress || !can_draw_progress() {
+        pipeline = pipeline.with_plain_output();
+    } else {
+        pipeline = pipeline.with_multi_progress(progress());
+    }
+    pipeline
+}
+
+async fn execute_watch(
+    pipeline: &Pipeline,
+    storage: &Arc<Storage>,
+    dropbox: &Arc<dyn DropboxClient>,
+    options: &WatchOptions,
+) -> Result<CommandOutcome, Error> {
+    say!(
+        "{} every {}s, press Ctrl-C to stop...",
+        "Watching inboxes".cyan().bold(),
+        options.interval.as_secs()
     );
-    pipeline.run_batch(batch_size, jobs).await?;
-    println!("Processing completed.");
-    Ok(())
+    let shutdown = async {
+        // Without a Ctrl-C handler, watch until killed
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+        say!("Stopping after the current cycle...");
+    };
+    let mut synced = 0;
+    let mut totals = BatchSummary::default();
+    let cycles = watch(
+        pipeline,
+        storage,
+        dropbox.as_ref(),
+        options,
+        shutdown,
+        |cycle, summary| match summary {
+            Ok(summary) => {
+                say!(
+                    "Cycle {}: synced {}, processed {}, needs review {}, skipped {}, failed {}",
+                    cycle,
+                    summary.synced,
+                    summary.batch.processed,
+                    summary.batch.needs_review,
+                    summary.batch.skipped,
+                    summary.batch.failed.len()
+                );
+                synced += summary.synced;
+                totals.processed += summary.batch.processed;
+                totals.unchanged += summary.batch.unchanged;
+                totals.needs_review += summary.batch.needs_review;
+                totals.skipped += summary.batch.skipped;
+                totals.failed.extend(summary.batch.failed.iter().cloned());
+                totals.timings += summary.batch.timings;
+                totals
+                    .target_paths
+                    .extend(summary.batch.target_paths.iter().cloned());
+            }
+            Err(e) => say!("{}: {:#}", format!("Cycle {} failed", cycle).red(), e),
+        },
+    )
+    .await?;
+    say!("{}", "Stopped watching.".green());
+    Ok(CommandOutcome::batch("watch", &totals)
+        .with_count("synced", synced as u64)
+        .with_count("cycles", cycles as u64))
 }
 
 async fn execute_sync(
-    inbox: &DropboxInbox,
+    inboxes: &[DropboxInbox],
     storage: &Arc<Storage>,
     dropbox: &Arc<dyn DropboxClient>,
-) -> Result<(), Error> {
-    println!("Syncing from Dropbox folder: '{}'...", inbox.0);
-    let entries = dropbox.list_folder(&inbox.0).await?;
-    let count = entries.len();
-    for entry in entries {
-        storage
-            .upsert_file(&entry.id, &entry.name, &entry.content_hash)
-            .await?;
-    }
-    println!("{}: Found {} files.", "Sync complete".green(), count);
+    settings: &Settings,
+) -> Result<usize, Error> {
+    let mut count = 0;
+    for inbox in inboxes {
+        say!("Syncing from Dropbox folder: '{}'...", inbox.0);
+        count += retry_async(SYNC_ATTEMPTS, || {
+            sync_inbox(
+                storage,
+                dropbox.as_ref(),
+                &inbox.0,
+                settings.inbox_recursive,
+                &settings.skip_suffixes,
+                &settings.allowed_upload_prefixes,
+            )
+        })
+        .await?;
+    }
+    say!("{}: Found {} files.", "Sync complete".green(), count);
+    Ok(count)
+}
+
+async fn execute_status(storage: &Arc<Storage>) -> Result<CommandOutcome, Error> {
+    let counts = storage.count_by_status().await?;
+    for (status, count) in &counts {
+        say!("{:<12} {}", format!("{:?}", status).cyan(), count);
+        if *status == FileStatus::Skipped {
+            for (reason, count) in storage.count_skipped_by_reason().await? {
+                say!("  {:<10} {}", reason, count);
+            }
+        }
+    }
+    say!(
+        "{} files.",
+        counts.iter().map(|(_, count)| count).sum::<u64>()
+    );
+    Ok(CommandOutcome::status(&counts))
+}
+
+async fn execute_doctor(
+    work_dir: &WorkDirectory,
+    rules: Result<Rules>,
+    settings: &Settings,
+    backend: &BackendArgs,
+    llm_args: &LlmArgs,
+    http: &HttpArgs,
+) -> Result<CommandOutcome, Error> {
+    say!("Running preflight checks...");
+    let dropbox = dropbox_client(settings, backend, http).ok();
+    let llm = llm_client(llm_args, http);
+    let checks = run_checks(Preflight {
+        env_vars: [
+            ("DROPBOX_TOKEN", env::var("DROPBOX_TOKEN").is_ok()),
+            ("MISTRAL_API_KEY", env::var("MISTRAL_API_KEY").is_ok()),
+        ]
+        .into_iter()
+        .filter(|(name, _)| backend.backend == Backend::Dropbox || *name != "DROPBOX_TOKEN")
+        .collect(),
+        dropbox: dropbox.as_deref(),
+        llm: llm.as_deref().map_err(|e| anyhow::anyhow!("{:#}", e)),
+        inboxes: &settings.inboxes,
+        work_dir,
+        rules,
+        allowed_upload_prefixes: &settings.allowed_upload_prefixes,
+    })
+    .await;
+
+    for check in &checks {
+        match (&check.passed, &check.detail) {
+            (true, _) => say!("{} {}", "✔".green(), check.name),
+            (false, Some(detail)) => say!("{} {}: {}", "✘".red(), check.name, detail),
+            (false, None) => say!("{} {}", "✘".red(), check.name),
+        }
+    }
+    let failed = checks.iter().filter(|c| !c.passed).count();
+    if failed > 0 {
+        return Err(FailedCommand {
+            outcome: CommandOutcome::doctor(&checks),
+            message: format!("{} of {} checks failed", failed, checks.len()),
+        }
+        .into());
+    }
+    say!("{}", "All checks passed.".green());
+    Ok(CommandOutcome::doctor(&checks))
+}
+
+async fn execute_dump(storage: &Arc<Storage>) -> Result<(), Error> {
+    let dump = DatabaseDump {
+        files: storage.export_all().await?,
+        rules: storage.export_rules_snapshots().await?,
+        extracted_text_hashes: storage.export_extracted_text_hashes().await?,
+    };
+    println!("{}", serde_json::to_string_pretty(&dump)?);
     Ok(())
 }
 
+async fn execute_import(storage: &Arc<Storage>, file: &PathBuf) -> Result<CommandOutcome, Error> {
+    let json = fs::read_to_string(file)
+        .with_context(|| format!("Failed to read dump file {}", file.to_string_lossy()))?;
+    let dump: DatabaseDump = serde_json::from_str(&json)
+        .with_context(|| format!("Invalid dump file {}", file.to_string_lossy()))?;
+    let count = storage.import_all(&dump.files).await?;
+    storage.import_rules_snapshots(&dump.rules).await?;
+    storage
+        .import_extracted_text_hashes(&dump.extracted_text_hashes)
+        .await?;
+    say!(
+        "{}: {} file records restored.",
+        "Import complete".green(),
+        count
+    );
+    Ok(CommandOutcome::new("import").with_count("imported", count as u64))
+}
+
+async fn execute_list(
+    storage: &Arc<Storage>,
+    filter: &ListFilter<'_>,
+    page: u32,
+    page_size: Option<u32>,
+) -> Result<CommandOutcome, Error> {
+    let offset = page_size.map_or(0, |size| (page - 1).saturating_mul(size));
+    let files = storage.list_files(filter, page_size, offset).await?;
+    for file in &files {
+        let title = file
+            .title
+            .as_deref()
+            .or(file.file_name.as_deref())
+            .unwrap_or(&file.dropbox_id.0);
+        let tags = file.tag_list();
+        let targets = file
+            .target_paths()
+            .into_iter()
+            .map(|path| path.0)
+            .collect::<Vec<String>>();
+        say!(
+            "{:<12} {}{}{}{}",
+            format!("{:?}", file.status).cyan(),
+            title,
+            match &file.skip_reason {
+                Some(reason) if file.status == FileStatus::Skipped => {
+                    format!(" ({})", reason).dimmed().to_string()
+                }
+                _ => String::new(),
+            },
+            if tags.is_empty() {
+                String::new()
+            } else {
+                format!(" [{}]", tags.join(", ")).yellow().to_string()
+            },
+            if targets.is_empty() {
+                String::new()
+            } else {
+                format!(" -> {}", targets.join(", "))
+            }
+        );
+    }
+    let outcome = CommandOutcome::new("list")
+        .with_count("listed", files.len() as u64)
+        .with_paths(files.iter().map(|file| file.dropbox_id.0.clone()));
+    match page_size {
+        Some(size) => {
+            let total = storage.count_files(filter).await?;
+            let pages = total.div_ceil(u64::from(size)).max(1);
+            say!("page {} of {} ({} total)", page, pages, total);
+            Ok(outcome.with_count("total", total))
+        }
+        None => {
+            say!("{} files.", files.len());
+            Ok(outcome)
+        }
+    }
+}
+
+async fn execute_sidecars(
+    storage: &Arc<Storage>,
+    dropbox: Arc<dyn DropboxClient>,
+    format: SidecarFormat,
+    placement: SidecarPlacement,
+) -> Result<CommandOutcome, Error> {
+    say!("Regenerating sidecars...");
+    let count = regenerate_sidecars(storage, &*dropbox, format, placement).await?;
+    say!(
+        "{}: {} sidecars written.",
+        "Sidecars complete".green(),
+        count
+    );
+    Ok(CommandOutcome::new("sidecars").with_count("sidecars", count as u64))
+}
+
+async fn execute_verify(
+    storage: &Arc<Storage>,
+    dropbox: Arc<dyn DropboxClient>,
+    fix: bool,
+    format: SidecarFormat,
+    placement: SidecarPlacement,
+) -> Result<CommandOutcome, Error> {
+    say!("Verifying filed papers...");
+    let report = verify_library(storage, &*dropbox, format, placement, fix).await?;
+    for path in &report.missing {
+        say!("{} Missing {}", "✘".red(), path.0);
+    }
+    for id in &report.orphaned {
+        say!("{} No PDF left for {}", "?".yellow(), id.0);
+    }
+    say!(
+        "{}: {} papers checked, {} files missing, {} orphaned records.",
+        "Verify complete".green(),
+        report.checked,
+        report.missing.len(),
+        report.orphaned.len()
+    );
+    if fix {
+        say!("{} papers reset to pending.", report.reset.len());
+    }
+    Ok(CommandOutcome::new("verify")
+        .with_count("checked", report.checked as u64)
+        .with_count("missing", report.missing.len() as u64)
+        .with_count("orphaned", report.orphaned.len() as u64)
+        .with_count("reset", report.reset.len() as u64)
+        .with_paths(report.missing.iter().map(|path| path.0.clone())))
+}
+
+async fn execute_maintenance(storage: &Arc<Storage>) -> Result<CommandOutcome, Error> {
+    say!("Compacting the database...");
+    let before = storage.database_size().await?;
+    storage.vacuum().await?;
+    let after = storage.database_size().await?;
+    say!(
+        "{}: {} KiB before, {} KiB after.",
+        "Maintenance complete".green(),
+        before / 1024,
+        after / 1024
+    );
+    Ok(CommandOutcome::new("maintenance")
+        .with_count("bytes_before", before)
+        .with_count("bytes_after", after))
+}
+
+async fn execute_migrate(
+    storage: &Arc<Storage>,
+    status_only: bool,
+) -> Result<CommandOutcome, Error> {
+    let pending = storage
+        .migration_status()
+        .await?
+        .into_iter()
+        .filter(|migration| !migration.applied)
+        .count();
+    if !status_only && pending > 0 {
+        say!("Applying {} migrations...", pending);
+        storage.apply_migrations().await?;
+    }
+    let migrations = storage.migration_status().await?;
+    for migration in &migrations {
+        if migration.applied {
+            say!("{} {}", "✔".green(), migration.name());
+        } else {
+            say!("{} {} (pending)", "·".yellow(), migration.name());
+        }
+    }
+    let applied = migrations
+        .iter()
+        .filter(|migration| migration.applied)
+        .count();
+    say!(
+        "{}: {} applied, {} pending.",
+        "Migrations".green(),
+        applied,
+        migrations.len() - applied
+    );
+    Ok(CommandOutcome::new("migrate")
+        .with_count("applied", applied as u64)
+        .with_count("pending", (migrations.len() - applied) as u64))
+}
+
+async fn execute_review(storage: &Arc<Storage>) -> Result<CommandOutcome, Error> {
+    let files = storage
+        .get_files_with_status(FileStatus::NeedsReview)
+        .await?;
+    for file in &files {
+        let title = file
+            .title
+            .as_deref()
+            .or(file.file_name.as_deref())
+            .unwrap_or(&file.dropbox_id.0);
+        say!("{} ({})", title.bold(), file.dropbox_id.0);
+        if let Some(reason) = &file.last_error {
+            say!("  {}", reason);
+        }
+        let candidates = file.review_candidate_list();
+        if candidates.is_empty() {
+            say!("  Candidates: none");
+        } else {
+            say!("  Candidates: {}", candidates.join(", "));
+        }
+    }
+    say!("{} papers need review.", files.len());
+    Ok(CommandOutcome::new("review")
+        .with_count("needs_review", files.len() as u64)
+        .with_paths(files.iter().map(|file| file.dropbox_id.0.clone())))
+}
+
+async fn execute_errors(storage: &Arc<Storage>, limit: i64) -> Result<CommandOutcome, Error> {
+    let files = storage.recent_errors(limit).await?;
+    for file in &files {
+        say!(
+            "{} ({})",
+            file.file_name.as_deref().unwrap_or("unknown").bold(),
+            file.dropbox_id.0
+        );
+        say!(
+            "  {}",
+            file.last_error
+                .as_deref()
+                .unwrap_or("no error recorded")
+                .red()
+        );
+    }
+    say!("{} failed files listed.", files.len());
+    Ok(CommandOutcome::new("errors")
+        .with_count("errors", files.len() as u64)
+        .with_paths(files.iter().map(|file| file.dropbox_id.0.clone())))
+}
+
+async fn execute_feed(
+    storage: &Arc<Storage>,
+    out: &Path,
+    since: Option<DateTime<Utc>>,
+    link_base: &str,
+) -> Result<CommandOutcome, Error> {
+    let files = storage.get_processed_since(since).await?;
+    fs::write(out, render_rss(&files, link_base))
+        .with_context(|| format!("Failed to write feed {}", out.to_string_lossy()))?;
+    say!(
+        "{}: {} papers written to {}.",
+        "Feed complete".green(),
+        files.len(),
+        out.to_string_lossy()
+    );
+    Ok(CommandOutcome::new("feed")
+        .with_count("papers", files.len() as u64)
+        .with_paths([out.to_string_lossy().into_owned()]))
+}
+
+fn dropbox_client(
+    settings: &Settings,
+    backend: &BackendArgs,
+    http: &HttpArgs,
+) -> Result<Arc<dyn DropboxClient>> {
+    if backend.backend == Backend::Local {
+        let root = backend
+            .root
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("The local backend needs --root"))?;
+        return Ok(Arc::new(LocalFsClient::new(
+            root,
+            settings.allowed_upload_prefixes.clone(),
+        )));
+    }
+    let dropbox_token = get_env_var("DROPBOX_TOKEN")?;
+    Ok(Arc::new(
+        DropboxHttpClient::new(dropbox_token, settings.allowed_upload_prefixes.clone())
+            .with_path_root(settings.dropbox_path_root.clone())
+            .with_http_options(&http.options()?),
+    ))
+}
+
+fn llm_client(args: &LlmArgs, http: &HttpArgs) -> Result<Arc<dyn LlmClient>> {
+    match args.classifier {
+        Classifier::Llm => mistral_client(args, http),
+        Classifier::Keyword => Ok(Arc::new(KeywordClassifier)),
+        Classifier::Hybrid => Ok(Arc::new(HybridClassifier::new(mistral_client(args, http)?))),
+    }
+}
+
+fn mistral_client(args: &LlmArgs, http: &HttpArgs) -> Result<Arc<dyn LlmClient>> {
+    let mistral_key = get_env_var("MISTRAL_API_KEY")?;
+    let client = MistralHttpClient::new(mistral_key)
+        .with_http_options(&http.options()?)
+        .with_sampling(args.model_temperature, args.max_tokens)
+        .with_timeout(Duration::from_secs(args.llm_timeout_secs));
+    let client = match &args.summary_language {
+        Some(language) => client.with_summary_language(language.clone()),
+        None => client,
+    };
+    let client = match args.prompt_template.as_deref() {
+        Some(path) => {
+            let template = fs::read_to_string(path).with_context(|| {
+                format!("Failed to read prompt template {}", path.to_string_lossy())
+            })?;
+            client
+                .with_prompt_template(template)
+                .with_context(|| format!("Invalid prompt template {}", path.to_string_lossy()))?
+        }
+        None => client,
+    };
+    Ok(Arc::new(client))
+}
+
 fn get_env_var(name: &str) -> Result<String> {
     env::var(name).map_err(|_| {
         anyhow::anyhow!(
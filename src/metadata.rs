@@ -0,0 +1,351 @@
+use crate::models::ArticleMetadata;
+
+/// Normalize author names returned by the LLM into a list of `First Last` names.
+///
+/// Each raw entry may hold several authors separated by " and ", "&" or ";". Names in
+/// `Last, First` form are flipped, and affiliations in parentheses are removed.
+pub fn normalize_authors(raw: &[String]) -> Vec<String> {
+    raw.iter()
+        .map(|entry| strip_parenthesized(entry))
+        .flat_map(|entry| {
+            entry
+                .split(" and ")
+                .flat_map(|s| s.split('&'))
+                .flat_map(|s| s.split(';'))
+                .map(String::from)
+                .collect::<Vec<String>>()
+        })
+        .map(|name| flip_last_first(&name))
+        .map(|name| name.split_whitespace().collect::<Vec<&str>>().join(" "))
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// The maximum number of tags kept per paper.
+pub const MAX_TAGS: usize = 10;
+
+/// Normalize tags returned by the LLM: trimmed, lowercase, without empty or repeated tags,
+/// and at most [`MAX_TAGS`] of them.
+pub fn normalize_tags(raw: &[String]) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in raw {
+        let tag = tag
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ")
+            .to_lowercase();
+        if !tag.is_empty() && !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags.truncate(MAX_TAGS);
+    tags
+}
+
+/// Maximum length of a slug from [`make_slug`], in characters.
+pub const MAX_SLUG_LENGTH: usize = 80;
+
+/// Make a stable file name stem from the metadata of a paper: the first author's surname, the
+/// year and the title, as lowercase words joined by hyphens, e.g. `doe-2021-attention-is-all-
+/// you-need`. Punctuation is removed and letters outside ASCII are kept. The slug is cut at a
+/// word boundary to at most [`MAX_SLUG_LENGTH`] characters. It is empty if there is no title.
+pub fn make_slug(meta: &ArticleMetadata, year: Option<i32>) -> String {
+    if meta.title.trim().is_empty() {
+        return String::new();
+    }
+    let surname = meta
+        .authors
+        .first()
+        .and_then(|name| name.split_whitespace().last())
+        .unwrap_or_default();
+    let year = year.map(|y| y.to_string()).unwrap_or_default();
+    let text = format!("{} {} {}", surname, year, meta.title).to_lowercase();
+    let words = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty());
+
+    let mut slug = String::new();
+    for word in words {
+        let extra = if slug.is_empty() { 0 } else { 1 };
+        if slug.chars().count() + extra + word.chars().count() > MAX_SLUG_LENGTH {
+            if slug.is_empty() {
+                slug = word.chars().take(MAX_SLUG_LENGTH).collect();
+            }
+            break;
+        }
+        if !slug.is_empty() {
+            slug.push('-');
+        }
+        slug.push_str(word);
+    }
+    slug
+}
+
+/// Remove any text in parentheses, e.g. affiliations like "John Doe (MIT)".
+fn strip_parenthesized(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut depth = 0usize;
+    for c in s.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            _ if depth == 0 => result.push(c),
+            _ => {}
+        }
+    }
+    result
+}
+
+/// Flip a `Last, First` name to `First Last`. Other names are returned unchanged.
+fn flip_last_first(name: &str) -> String {
+    match name.split(',').collect::<Vec<&str>>().as_slice() {
+        [last, first] if !last.trim().is_empty() && !first.trim().is_empty() => {
+            format!("{} {}", first.trim(), last.trim())
+        }
+        _ => name.trim().to_string(),
+    }
+}
+
+/// A key under which different spellings of the same author compare equal: the lowercase
+/// surname followed by the first initial, e.g. "smith j" for "J. Smith", "John Smith" and
+/// "Smith, J.". Authors sharing a surname and first initial get the same key.
+pub fn canonical_author_key(name: &str) -> String {
+    let name = flip_last_first(&strip_parenthesized(name)).replace('.', " ");
+    let mut parts: Vec<&str> = name.split_whitespace().collect();
+    let Some(surname) = parts.pop() else {
+        return String::new();
+    };
+    match parts.first().and_then(|given| given.chars().next()) {
+        Some(initial) => format!("{} {}", surname, initial).to_lowercase(),
+        None => surname.to_lowercase(),
+    }
+}
+
+/// The first DOI in a text, e.g. `10.1145/3290355` in "doi:10.1145/3290355.". Punctuation
+/// at the end, e.g. ending a sentence, is not taken to be part of the DOI.
+pub fn extract_doi(text: &str) -> Option<String> {
+    text.match_indices("10.").find_map(|(start, _)| {
+        // Not the end of a longer number, e.g. 110.5
+        if text[..start]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '.')
+        {
+            return None;
+        }
+        let candidate = text[start..]
+            .split(|c: char| c.is_whitespace() || matches!(c, '"' | '<' | '>'))
+            .next()?;
+        let (prefix, suffix) = candidate.split_once('/')?;
+        let registrant = &prefix[3..];
+        let suffix = suffix.trim_end_matches(['.', ',', ';', ':', ')', ']', '}', '\'']);
+        let valid = registrant.len() >= 4
+            && registrant[..4].chars().all(|c| c.is_ascii_digit())
+            && registrant.chars().all(|c| c.is_ascii_digit() || c == '.')
+            && !suffix.is_empty();
+        valid.then(|| format!("{}/{}", prefix, suffix))
+    })
+}
+
+/// The first arXiv identifier in a text, without its version, e.g. `2101.00001` in
+/// "arXiv:2101.00001v2 [cs.PL]" or "arxiv.org/abs/2101.00001", or `hep-th/9901001` in
+/// "arXiv:hep-th/9901001".
+pub fn extract_arxiv_id(text: &str) -> Option<String> {
+    // ASCII lowercase keeps the byte offsets of the original text
+    let lower = text.to_ascii_lowercase();
+    lower.match_indices("arxiv").find_map(|(start, _)| {
+        let rest = &lower[start + "arxiv".len()..];
+        let rest = [":", ".org/abs/", ".org/pdf/"]
+            .iter()
+            .find_map(|separator| rest.strip_prefix(separator))?;
+        let offset = text.len() - rest.len();
+        parse_arxiv_id(text[offset..].trim_start())
+    })
+}
+
+/// An arXiv identifier at the start of `s`: new style, `YYMM.NNNNN`, or old style,
+/// `archive/YYMMNNN`.
+fn parse_arxiv_id(s: &str) -> Option<String> {
+    let digits = |s: &str| s.chars().take_while(char::is_ascii_digit).count();
+    if digits(s) == 4 && s[4..].starts_with('.') {
+        let number = digits(&s[5..]);
+        return (4..=5)
+            .contains(&number)
+            .then(|| s[..5 + number].to_string());
+    }
+    let (archive, rest) = s.split_once('/')?;
+    let valid_archive = !archive.is_empty()
+        && archive
+            .chars()
+            .all(|c| c.is_ascii_alphabetic() || matches!(c, '-' | '.'));
+    (valid_archive && digits(rest) == 7).then(|| format!("{}/{}", archive, &rest[..7]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paper(title: &str, authors: &[&str]) -> ArticleMetadata {
+        ArticleMetadata {
+            title: title.to_string(),
+            authors: strings(authors),
+            ..Default::default()
+        }
+    }
+
+    fn strings(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_normalize_authors_keeps_clean_names() {
+        let authors = normalize_authors(&strings(&["John Doe", "Jane Roe"]));
+        assert_eq!(authors, strings(&["John Doe", "Jane Roe"]));
+    }
+
+    #[test]
+    fn test_normalize_authors_splits_on_and() {
+        let authors = normalize_authors(&strings(&["John Doe and Jane Roe"]));
+        assert_eq!(authors, strings(&["John Doe", "Jane Roe"]));
+    }
+
+    #[test]
+    fn test_normalize_authors_splits_on_ampersand() {
+        let authors = normalize_authors(&strings(&["John Doe & Jane Roe"]));
+        assert_eq!(authors, strings(&["John Doe", "Jane Roe"]));
+    }
+
+    #[test]
+    fn test_normalize_authors_splits_on_semicolon() {
+        let authors = normalize_authors(&strings(&["John Doe; Jane Roe;"]));
+        assert_eq!(authors, strings(&["John Doe", "Jane Roe"]));
+    }
+
+    #[test]
+    fn test_normalize_authors_flips_last_first() {
+        let authors = normalize_authors(&strings(&["Doe, John", "Roe, Jane A."]));
+        assert_eq!(authors, strings(&["John Doe", "Jane A. Roe"]));
+    }
+
+    #[test]
+    fn test_normalize_authors_trims_affiliations() {
+        let authors = normalize_authors(&strings(&["John Doe (MIT)", "Roe, Jane (ETH Zürich)"]));
+        assert_eq!(authors, strings(&["John Doe", "Jane Roe"]));
+    }
+
+    #[test]
+    fn test_normalize_authors_mixed_shapes() {
+        let authors = normalize_authors(&strings(&["Doe, John (MIT) and Jane Roe; Smith, J."]));
+        assert_eq!(authors, strings(&["John Doe", "Jane Roe", "J. Smith"]));
+    }
+
+    #[test]
+    fn test_normalize_tags_dedups_and_limits() {
+        let tags = normalize_tags(&strings(&[" Survey ", "survey", "", "Data  Set"]));
+        assert_eq!(tags, strings(&["survey", "data set"]));
+
+        let many: Vec<String> = (0..15).map(|i| format!("tag{}", i)).collect();
+        assert_eq!(normalize_tags(&many).len(), MAX_TAGS);
+    }
+
+    #[test]
+    fn test_canonical_author_key_merges_spellings() {
+        assert_eq!(canonical_author_key("J. Smith"), "smith j");
+        assert_eq!(canonical_author_key("John Smith"), "smith j");
+        assert_eq!(canonical_author_key("Smith, J."), "smith j");
+    }
+
+    #[test]
+    fn test_canonical_author_key_keeps_different_initials_apart() {
+        assert_ne!(
+            canonical_author_key("John Smith"),
+            canonical_author_key("Adam Smith")
+        );
+        assert_eq!(canonical_author_key("Plato"), "plato");
+        assert_eq!(canonical_author_key("  "), "");
+    }
+
+    #[test]
+    fn test_make_slug() {
+        let meta = paper(
+            "Attention Is All You Need!",
+            &["Ashish Vaswani", "Noam Shazeer"],
+        );
+        assert_eq!(
+            make_slug(&meta, Some(2017)),
+            "vaswani-2017-attention-is-all-you-need"
+        );
+        assert_eq!(make_slug(&meta, None), "vaswani-attention-is-all-you-need");
+        assert_eq!(
+            make_slug(&paper("Types: A Survey", &[]), None),
+            "types-a-survey"
+        );
+    }
+
+    #[test]
+    fn test_make_slug_keeps_unicode_letters() {
+        let meta = paper(
+            "Über Gödels Unvollständigkeitssätze — eine Einführung",
+            &["Kurt Gödel"],
+        );
+        assert_eq!(
+            make_slug(&meta, Some(1931)),
+            "gödel-1931-über-gödels-unvollständigkeitssätze-eine-einführung"
+        );
+        assert_eq!(make_slug(&paper("深度学习", &[]), None), "深度学习");
+    }
+
+    #[test]
+    fn test_make_slug_truncates_at_word_boundary() {
+        let title = "word ".repeat(40);
+        let slug = make_slug(&paper(&title, &["Jane Roe"]), Some(2020));
+        assert!(slug.chars().count() <= MAX_SLUG_LENGTH);
+        assert!(slug.starts_with("roe-2020-word-word"));
+        assert!(slug.ends_with("-word"));
+
+        let long_word = "ä".repeat(100);
+        assert_eq!(
+            make_slug(&paper(&long_word, &[]), None).chars().count(),
+            MAX_SLUG_LENGTH
+        );
+    }
+
+    #[test]
+    fn test_make_slug_empty_without_title() {
+        assert_eq!(make_slug(&paper("  ", &["Jane Roe"]), Some(2020)), "");
+    }
+
+    #[test]
+    fn test_extract_doi() {
+        assert_eq!(
+            extract_doi("Published at POPL. doi:10.1145/3290355.\nAbstract"),
+            Some("10.1145/3290355".to_string())
+        );
+        assert_eq!(
+            extract_doi("(https://doi.org/10.1007/978-3-030-72019-3_1)"),
+            Some("10.1007/978-3-030-72019-3_1".to_string())
+        );
+        assert_eq!(extract_doi("Version 110.5/2 of 10.5 pages"), None);
+    }
+
+    #[test]
+    fn test_extract_arxiv_id() {
+        assert_eq!(
+            extract_arxiv_id("arXiv:2101.00001v2 [cs.PL] 4 Jan 2021"),
+            Some("2101.00001".to_string())
+        );
+        assert_eq!(
+            extract_arxiv_id("See https://arxiv.org/abs/1706.03762."),
+            Some("1706.03762".to_string())
+        );
+        assert_eq!(
+            extract_arxiv_id("ArXiv: hep-th/9901001v1"),
+            Some("hep-th/9901001".to_string())
+        );
+        assert_eq!(
+            extract_arxiv_id("arxiv.org/pdf/0704.0001.pdf"),
+            Some("0704.0001".to_string())
+        );
+        assert_eq!(extract_arxiv_id("We posted it to arXiv in 2021."), None);
+    }
+}
@@ -1,15 +1,83 @@
+use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, sqlx::Type)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, Hash, sqlx::Type)]
 #[sqlx(transparent)]
 pub struct DropboxId(pub String);
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
 #[sqlx(transparent)]
 pub struct RemotePath(pub String);
 
+impl RemotePath {
+    /// Key for comparing paths the way Dropbox does, i.e. case-insensitively.
+    /// The path itself keeps its display case.
+    pub fn comparison_key(&self) -> String {
+        self.0.to_lowercase()
+    }
+
+    /// Whether two paths refer to the same Dropbox location.
+    pub fn same_location(&self, other: &RemotePath) -> bool {
+        self.comparison_key() == other.comparison_key()
+    }
+
+    /// Whether the path is the `prefix` folder or inside it, compared the way Dropbox does.
+    /// A sibling folder whose name merely starts the same, like `/research-private` for
+    /// `/research`, is not inside it.
+    pub fn is_under(&self, prefix: &str) -> bool {
+        let path = self.comparison_key();
+        let prefix = prefix.trim_end_matches('/').to_lowercase();
+        path == prefix || path.starts_with(&format!("{}/", prefix))
+    }
+
+    /// Whether the path is under any of the prefixes, see [`RemotePath::is_under`].
+    pub fn is_under_any(&self, prefixes: &[String]) -> bool {
+        prefixes.iter().any(|prefix| self.is_under(prefix))
+    }
+
+    /// The path of `segment` inside this folder, with exactly one slash between them, e.g.
+    /// `/sorted/` and `/ai` make `/sorted/ai`.
+    pub fn join(&self, segment: &str) -> RemotePath {
+        RemotePath(format!(
+            "{}/{}",
+            self.0.trim_end_matches('/'),
+            segment.trim_start_matches('/')
+        ))
+    }
+
+    /// The folder the path is in, if it is in one. Files at the top level are in the
+    /// root folder, the empty path, as Dropbox has it.
+    pub fn parent(&self) -> Option<RemotePath> {
+        self.0
+            .trim_end_matches('/')
+            .rsplit_once('/')
+            .map(|(parent, _)| RemotePath::from(parent))
+    }
+
+    /// The last segment of the path, if it is not the root.
+    pub fn file_name(&self) -> Option<&str> {
+        self.0
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .filter(|name| !name.is_empty())
+    }
+
+    /// The path with the extension of its file name replaced by `extension`, or added if it
+    /// has none, like [`Path::with_extension`](std::path::Path::with_extension).
+    pub fn with_extension(&self, extension: &str) -> RemotePath {
+        let path = self.0.trim_end_matches('/');
+        let name = self.file_name().unwrap_or_default();
+        let stem = match crate::targets::extension(name) {
+            Some(ext) => &path[..path.len() - ext.len() - 1],
+            None => path,
+        };
+        RemotePath(format!("{}.{}", stem, extension))
+    }
+}
+
 impl From<&str> for RemotePath {
     fn from(s: &str) -> Self {
         RemotePath(s.to_string())
@@ -25,34 +93,163 @@ pub struct WorkDirectory(pub PathBuf);
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct DropboxInbox(pub String);
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
 #[sqlx(transparent)]
 pub struct FileHash(pub String);
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+/// Identifies one processing run, i.e. one batch, e.g. `20261016T101500.123Z`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, sqlx::Type)]
+#[sqlx(transparent)]
+pub struct RunId(pub String);
+
+impl RunId {
+    /// A new run id from the current time.
+    pub fn now() -> Self {
+        RunId(Utc::now().format("%Y%m%dT%H%M%S%.3fZ").to_string())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
 #[sqlx(transparent)]
 pub struct OneLineSummary(pub String);
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ArticleMetadata {
     pub title: String,
     pub authors: Vec<String>,
     pub summary: OneLineSummary,
+    /// The main results of the paper, a few sentences each, to go with the one-line summary
+    #[serde(default)]
+    pub key_findings: Vec<String>,
     pub abstract_text: String,
+    /// Publication year, if the LLM could determine it
+    pub year: Option<i32>,
+    /// Free-form keywords, e.g. "survey" or "dataset"
+    pub tags: Vec<String>,
+    /// How sure the LLM is of the categories it matched, from 0 to 1, if it said
+    pub confidence: Option<f64>,
+    /// How clean the text extracted from the PDF was, from 0 to 1, set by the pipeline
+    /// rather than the LLM (see [`extraction_quality`](crate::pipeline::extraction_quality))
+    pub extraction_quality: Option<f32>,
+    /// Found in the extracted text by the pipeline, see [`extract_doi`](crate::metadata::extract_doi)
+    pub doi: Option<String>,
+    /// Found in the extracted text by the pipeline, see
+    /// [`extract_arxiv_id`](crate::metadata::extract_arxiv_id)
+    pub arxiv_id: Option<String>,
+    /// Set by the pipeline, see [`text_hash`](crate::extract::text_hash)
+    #[serde(default)]
+    pub extracted_text_hash: Option<String>,
+    /// The tokens the LLM used for the paper, if it said
+    #[serde(default)]
+    pub token_usage: Option<TokenUsage>,
+    /// Set by classifiers that extract no metadata at all, like
+    /// [`KeywordClassifier`](crate::classifier::KeywordClassifier), so the empty metadata is
+    /// not taken for a poor answer that needs review
+    #[serde(default)]
+    pub not_extracted: bool,
+}
+
+impl ArticleMetadata {
+    /// Metadata from a classifier that extracts none, see [`ArticleMetadata::not_extracted`].
+    pub fn not_extracted() -> Self {
+        ArticleMetadata {
+            not_extracted: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// The tokens an LLM request used, as reported by the LLM.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    /// The estimated cost of the tokens, at `cost_per_1k` per thousand tokens.
+    pub fn estimated_cost(&self, cost_per_1k: f64) -> f64 {
+        self.total() as f64 / 1000.0 * cost_per_1k
+    }
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+/// How long the stages of processing a file took, including any wait for a turn to download
+/// or query the LLM.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StageTimings {
+    pub download: std::time::Duration,
+    pub extract: std::time::Duration,
+    pub llm: std::time::Duration,
+    pub upload: std::time::Duration,
+}
+
+impl StageTimings {
+    /// The timings divided by `count`, e.g. the average of the totals of `count` files.
+    pub fn divided_by(&self, count: u32) -> StageTimings {
+        let count = count.max(1);
+        StageTimings {
+            download: self.download / count,
+            extract: self.extract / count,
+            llm: self.llm / count,
+            upload: self.upload / count,
+        }
+    }
+}
+
+impl std::ops::AddAssign for StageTimings {
+    fn add_assign(&mut self, other: StageTimings) {
+        self.download += other.download;
+        self.extract += other.extract;
+        self.llm += other.llm;
+        self.upload += other.upload;
+    }
+}
+
+impl std::fmt::Display for StageTimings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "download {:.1}s, extract {:.1}s, LLM {:.1}s, upload {:.1}s",
+            self.download.as_secs_f64(),
+            self.extract.as_secs_f64(),
+            self.llm.as_secs_f64(),
+            self.upload.as_secs_f64()
+        )
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
 #[sqlx(rename_all = "UPPERCASE")]
 pub enum FileStatus {
+    #[default]
     Pending,
     Downloaded,
     Processed,
     Archived,
     Error,
     Skipped,
+    /// Handed to a worker in a batch that has not finished with it yet
+    #[sqlx(rename = "IN_PROGRESS")]
+    InProgress,
+    /// Not filed because the classification was uncertain; waiting for a human decision
+    #[sqlx(rename = "NEEDS_REVIEW")]
+    NeedsReview,
+    /// Metadata and targets recorded, with the uploads to the targets not yet confirmed
+    Uploading,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, sqlx::FromRow)]
 pub struct FileRecord {
     pub dropbox_id: DropboxId,
     pub file_name: Option<String>,
@@ -61,15 +258,131 @@ pub struct FileRecord {
     pub title: Option<String>,
     pub authors: Option<String>, // JSON array string
     pub summary: Option<String>,
-    pub target_path: Option<String>,
+    pub abstract_text: Option<String>,
+    pub target_path: Option<String>,  // JSON array string
+    pub tags: Option<String>,         // JSON array string
+    pub key_findings: Option<String>, // JSON array string
+    /// The targets the file is being uploaded to while it is [`FileStatus::Uploading`], as a
+    /// JSON array string
+    pub pending_target_path: Option<String>,
+    /// LLM tokens used to process the file, see [`TokenUsage`]
+    pub prompt_tokens: Option<i64>,
+    pub completion_tokens: Option<i64>,
+    /// Size in bytes, as listed by Dropbox
+    pub size: Option<i64>,
+    /// When the file was last changed in Dropbox, as listed
+    pub server_modified: Option<DateTime<Utc>>,
+    pub review_candidates: Option<String>, // JSON array of rule names
+    /// The inbox folder the file was synced from
+    pub source_folder: Option<String>,
+    /// How clean the extracted text was, from 0 to 1
+    pub extraction_quality: Option<f32>,
+    /// The hash of the extracted text, see [`text_hash`](crate::extract::text_hash)
+    pub extracted_text_hash: Option<String>,
+    pub doi: Option<String>,
+    pub arxiv_id: Option<String>,
+    /// Why the file was skipped, if it was, see [`SkipReason`]
+    pub skip_reason: Option<String>,
+    /// The run that last processed the file, see [`Storage::rules_for_run`](crate::storage::Storage::rules_for_run)
+    pub run_id: Option<RunId>,
     pub last_error: Option<String>,
     pub updated_at: DateTime<Utc>,
+    /// When the file was last processed, i.e. filed into its targets
+    pub processed_at: Option<DateTime<Utc>>,
+    /// When the file was last handed to a worker
+    pub started_at: Option<DateTime<Utc>>,
 }
 
+impl FileRecord {
+    /// The paths the file was filed to.
+    pub fn target_paths(&self) -> Vec<RemotePath> {
+        self.target_path
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default()
+    }
+
+    /// The paths the file is being uploaded to, see [`FileRecord::pending_target_path`].
+    pub fn pending_target_paths(&self) -> Vec<RemotePath> {
+        self.pending_target_path
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default()
+    }
+
+    /// The tags of the file.
+    pub fn tag_list(&self) -> Vec<String> {
+        self.tags
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default()
+    }
+
+    /// The key findings of the paper, see [`ArticleMetadata::key_findings`].
+    pub fn key_finding_list(&self) -> Vec<String> {
+        self.key_findings
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default()
+    }
+
+    /// The names of the rules the LLM matched for a file that needs review.
+    pub fn review_candidate_list(&self) -> Vec<String> {
+        self.review_candidates
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default()
+    }
+
+    /// The target path of the file directly in the given folder, if any.
+    pub fn target_in_folder(&self, folder: &str) -> Option<RemotePath> {
+        self.target_paths().into_iter().find(|path| {
+            path.parent()
+                .is_some_and(|parent| parent.same_location(&folder.into()))
+        })
+    }
+}
+
+/// A JSON-serializable snapshot of the database, for backup and migration.
+/// Sync does not keep a listing cursor, so the file records, the rules of the runs that
+/// processed them and the text hashes of each run are the complete state.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DatabaseDump {
+    pub files: Vec<FileRecord>,
+    /// The rules in effect for each run; missing from dumps made before they were recorded
+    #[serde(default)]
+    pub rules: Vec<RulesSnapshot>,
+    /// The hash of the text extracted from each file in each run that processed it
+    #[serde(default)]
+    pub extracted_text_hashes: Vec<ExtractedTextHash>,
+}
+
+/// The rules that were in effect for one processing run.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RulesSnapshot {
+    pub run_id: RunId,
+    pub rules: Rules,
+}
+
+/// The hash of the text extracted from a file in a run that processed it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, sqlx::FromRow)]
+pub struct ExtractedTextHash {
+    pub dropbox_id: DropboxId,
+    pub run_id: RunId,
+    pub text_hash: String,
+}
+
+#[derive(Debug, Clone)]
 pub struct Job {
     pub id: DropboxId,
     pub file_name: Option<String>,
     pub path: RemotePath,
+    /// The content hash Dropbox listed for the file when it was synced
+    pub content_hash: FileHash,
+    /// The size Dropbox listed for the file when it was synced, if known
+    pub size: Option<u64>,
+    /// The record of the file as filed before, to upload only what changed since, if asked to
+    pub previous: Option<FileRecord>,
 }
 
 pub enum JobResult {
@@ -78,12 +391,129 @@ pub enum JobResult {
         file_name: Option<String>,
         meta: ArticleMetadata,
         target_paths: Vec<RemotePath>,
+        timings: StageTimings,
+    },
+    /// The file was processed again, but its metadata and targets are as stored, and the
+    /// PDFs at the targets are the same, so nothing was uploaded
+    Unchanged {
+        id: DropboxId,
+        file_name: Option<String>,
+        target_paths: Vec<RemotePath>,
+        /// The hash of the text extracted this time, which may differ from the stored one
+        text_hash: Option<String>,
+        /// The tokens the LLM used to analyze the file again
+        token_usage: Option<TokenUsage>,
+        timings: StageTimings,
     },
     Failure {
         id: DropboxId,
         file_name: Option<String>,
-        error: String,
+        error: ProcessError,
+    },
+    /// The file was analyzed, but the classification is too uncertain to file it automatically
+    NeedsReview {
+        id: DropboxId,
+        file_name: Option<String>,
+        meta: ArticleMetadata,
+        /// Names of the rules the LLM matched
+        candidates: Vec<String>,
+        reason: String,
     },
+    /// The file was deliberately not processed, e.g. because it is too large to parse safely
+    Skipped {
+        id: DropboxId,
+        file_name: Option<String>,
+        reason: String,
+    },
+}
+
+/// Why a file was skipped rather than processed, recorded as its `skip_reason`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The file name ends in a suffix of files never to process, e.g. our own sidecars
+    NotAPaper { suffix: String },
+    /// The content is none of the formats text can be extracted from
+    NotAPdf,
+    /// Another file with the same content is known
+    Duplicate { of: DropboxId },
+    /// The file is too large to parse safely
+    TooLarge { size: u64, max: u64 },
+    /// The file is a PDF of scanned pages without a text layer, to be run through OCR
+    Scanned,
+}
+
+/// The kinds of [`SkipReason`], as recorded reasons start.
+const SKIP_REASON_KINDS: [&str; 5] = [
+    "not a paper",
+    "not a PDF",
+    "duplicate",
+    "too large",
+    "scanned",
+];
+
+impl SkipReason {
+    /// The kind of a recorded reason, e.g. "duplicate" for "duplicate of id:a", to count
+    /// skipped files by. Reasons of no known kind are their own kind.
+    pub fn kind_of(reason: &str) -> &str {
+        SKIP_REASON_KINDS
+            .iter()
+            .find(|kind| {
+                reason
+                    .get(..kind.len())
+                    .is_some_and(|start| start.eq_ignore_ascii_case(kind))
+            })
+            .copied()
+            .unwrap_or(reason)
+    }
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::NotAPaper { suffix } => write!(f, "not a paper: {} file", suffix),
+            SkipReason::NotAPdf => write!(f, "not a PDF"),
+            SkipReason::Duplicate { of } => write!(f, "duplicate of {}", of.0),
+            SkipReason::TooLarge { size, max } => write!(
+                f,
+                "too large: {} bytes, more than the maximum of {} bytes",
+                size, max
+            ),
+            SkipReason::Scanned => write!(f, "scanned (no text layer)"),
+        }
+    }
+}
+
+/// Why processing a file failed, by the stage that failed.
+#[derive(Debug, thiserror::Error)]
+pub enum ProcessError {
+    #[error("Download failed: {0:#}")]
+    Download(anyhow::Error),
+    #[error("Saving local copy failed: {0:#}")]
+    Io(anyhow::Error),
+    #[error("Parsing failed: {0:#}")]
+    Parse(anyhow::Error),
+    #[error("LLM query failed: {0:#}")]
+    Llm(anyhow::Error),
+    #[error("Upload failed: {0:#}")]
+    Upload(anyhow::Error),
+    #[error("Timed out: {0:#}")]
+    Timeout(anyhow::Error),
+    #[error("Recording the upload failed: {0:#}")]
+    Database(anyhow::Error),
+}
+
+impl ProcessError {
+    /// Whether retrying the file may succeed, i.e. the failure was in talking to a service
+    /// rather than in the file itself.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ProcessError::Download(_)
+                | ProcessError::Llm(_)
+                | ProcessError::Upload(_)
+                | ProcessError::Timeout(_)
+        )
+    }
 }
 impl JobResult {
     /// Create a successful job result
@@ -92,35 +522,78 @@ impl JobResult {
         file_name: Option<String>,
         meta: ArticleMetadata,
         target_paths: Vec<RemotePath>,
+        timings: StageTimings,
     ) -> Self {
         Self::Success {
             id,
             file_name,
             meta,
             target_paths,
+            timings,
         }
     }
     /// Create a failed job result
-    pub fn failure(id: DropboxId, file_name: Option<String>, error: anyhow::Error) -> Self {
+    pub fn failure(id: DropboxId, file_name: Option<String>, error: ProcessError) -> Self {
         Self::Failure {
             id,
             file_name,
-            error: format!("{:#}", error),
+            error,
+        }
+    }
+    /// Create a skipped job result
+    pub fn skipped(id: DropboxId, file_name: Option<String>, reason: impl Into<String>) -> Self {
+        Self::Skipped {
+            id,
+            file_name,
+            reason: reason.into(),
         }
     }
 }
 
 /// A file categorization rule
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Rule {
     /// Unique name for the rule
     pub name: String,
     pub description: String,
     pub path: RemotePath,
+    /// Titles or topics of papers that belong in the category, to tell it apart from
+    /// similar ones
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub examples: Vec<String>,
+    /// Topics that do not belong in the category, even if they seem to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub negative_keywords: Vec<String>,
+    /// Words that file a paper into the category without asking the LLM, with
+    /// `--classifier keyword` or `hybrid`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    /// Name of the broader rule this is a subcategory of. Papers matching both are filed
+    /// under the subcategory only, see [`Rules::most_specific`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+}
+
+/// A rule with its subcategories, see [`Rules::tree`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleNode<'a> {
+    pub rule: &'a Rule,
+    pub children: Vec<RuleNode<'a>>,
+}
+
+impl<'a> RuleNode<'a> {
+    /// The rules of the node and of its subcategories, each before its subcategories.
+    pub fn rules(&self) -> Vec<&'a Rule> {
+        let mut rules = vec![self.rule];
+        for child in &self.children {
+            rules.extend(child.rules());
+        }
+        rules
+    }
 }
 
 /** This is a struct representing all the rules for categorizing files. */
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Rules(pub Vec<Rule>);
 
 impl From<Vec<Rule>> for Rules {
@@ -128,3 +601,324 @@ impl From<Vec<Rule>> for Rules {
         Rules(rules)
     }
 }
+
+impl Rules {
+    /// Parse rules from YAML: a list of rules with `name`, `description` and `path`.
+    pub fn from_yaml(yaml: &str) -> Result<Rules> {
+        let rules: Rules = serde_yaml::from_str(yaml).context("Invalid rules file")?;
+        rules.validate_tree().context("Invalid rules file")?;
+        Ok(rules)
+    }
+
+    /// Load rules from a YAML file.
+    pub fn load(path: &Path) -> Result<Rules> {
+        let yaml = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read rules file {}", path.to_string_lossy()))?;
+        Self::from_yaml(&yaml)
+            .with_context(|| format!("Failed to parse rules file {}", path.to_string_lossy()))
+    }
+
+    /// The rules named in `only`, or all rules if it is empty, except those named in `skip`,
+    /// e.g. to try out classifying without some rules. Names are compared ignoring case,
+    /// and must all be names of rules.
+    pub fn select(&self, only: &[String], skip: &[String]) -> Result<Rules> {
+        let named = |names: &[String], rule: &Rule| {
+            names
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(&rule.name))
+        };
+        let unknown: Vec<&str> = only
+            .iter()
+            .chain(skip)
+            .filter(|name| {
+                !self
+                    .0
+                    .iter()
+                    .any(|rule| rule.name.eq_ignore_ascii_case(name))
+            })
+            .map(String::as_str)
+            .collect();
+        if !unknown.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Unknown rules {}. Available rules: {}",
+                unknown.join(", "),
+                self.0
+                    .iter()
+                    .map(|rule| rule.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        Ok(Rules(
+            self.0
+                .iter()
+                .filter(|rule| (only.is_empty() || named(only, rule)) && !named(skip, rule))
+                .cloned()
+                .collect(),
+        ))
+    }
+
+    /// The rule a rule is a subcategory of, if it is one of these rules. Names are compared
+    /// ignoring case.
+    pub fn parent_of(&self, rule: &Rule) -> Option<&Rule> {
+        self.named(rule.parent.as_deref()?)
+    }
+
+    /// The rule with a name, compared ignoring case.
+    fn named(&self, name: &str) -> Option<&Rule> {
+        self.0
+            .iter()
+            .find(|rule| rule.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Whether `ancestor` is the parent of `rule`, or the parent of its parent, and so on,
+    /// going by the parents of these rules with the same names.
+    pub fn is_ancestor(&self, ancestor: &Rule, rule: &Rule) -> bool {
+        let mut parent = self.named(&rule.name).and_then(|rule| self.parent_of(rule));
+        // Stop after as many steps as there are rules, in case the parents make a cycle
+        for _ in 0..self.0.len() {
+            match parent {
+                Some(candidate) if candidate.name.eq_ignore_ascii_case(&ancestor.name) => {
+                    return true;
+                }
+                Some(candidate) => parent = self.parent_of(candidate),
+                None => break,
+            }
+        }
+        false
+    }
+
+    /// The rules as a tree of categories and their subcategories, in the order of the rules.
+    /// Rules without a parent among these rules are at the top. Rules whose parents make a
+    /// cycle are left out, see [`Rules::validate_tree`].
+    pub fn tree(&self) -> Vec<RuleNode<'_>> {
+        fn node<'a>(rules: &'a Rules, rule: &'a Rule) -> RuleNode<'a> {
+            let children = rules
+                .0
+                .iter()
+                .filter(|child| {
+                    rules
+                        .parent_of(child)
+                        .is_some_and(|parent| std::ptr::eq(parent, rule))
+                })
+                .map(|child| node(rules, child))
+                .collect();
+            RuleNode { rule, children }
+        }
+        self.0
+            .iter()
+            .filter(|rule| self.parent_of(rule).is_none())
+            .map(|rule| node(self, rule))
+            .collect()
+    }
+
+    /// Keep the most specific of the matched rules: a rule is left out if one of its
+    /// subcategories matched too, so the paper is filed under the subcategory only. A rule
+    /// matched without any of its subcategories is kept.
+    pub fn most_specific(&self, matching: Vec<Rule>) -> Vec<Rule> {
+        let keep: Vec<bool> = matching
+            .iter()
+            .map(|rule| !matching.iter().any(|other| self.is_ancestor(rule, other)))
+            .collect();
+        matching
+            .into_iter()
+            .zip(keep)
+            .filter_map(|(rule, keep)| keep.then_some(rule))
+            .collect()
+    }
+
+    /// Check that the parents of the rules make a tree: each parent is the name of a rule,
+    /// and no rule is a subcategory of itself, directly or through other rules.
+    pub fn validate_tree(&self) -> Result<()> {
+        let missing: Vec<String> = self
+            .0
+            .iter()
+            .filter_map(|rule| {
+                let parent = rule.parent.as_deref()?;
+                self.parent_of(rule)
+                    .is_none()
+                    .then(|| format!("'{}' (parent '{}')", rule.name, parent))
+            })
+            .collect();
+        if !missing.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Rules with a parent that is not a rule: {}",
+                missing.join(", ")
+            ));
+        }
+        let in_tree: Vec<&Rule> = self.tree().iter().flat_map(RuleNode::rules).collect();
+        let cycle: Vec<&str> = self
+            .0
+            .iter()
+            .filter(|rule| !in_tree.iter().any(|node| std::ptr::eq(*node, *rule)))
+            .map(|rule| rule.name.as_str())
+            .collect();
+        if !cycle.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Rules that are subcategories of themselves: {}",
+                cycle.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check that every rule has a target path under one of the allowed upload prefixes.
+    pub fn validate_targets(&self, allowed_upload_prefixes: &[String]) -> Result<()> {
+        let invalid = self
+            .0
+            .iter()
+            .filter(|rule| {
+                rule.path.0.trim().is_empty() || !rule.path.is_under_any(allowed_upload_prefixes)
+            })
+            .map(|rule| format!("'{}' ({})", rule.name, rule.path.0))
+            .collect::<Vec<String>>();
+        if invalid.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "Rule targets missing or outside the allowed upload prefixes {}: {}",
+                allowed_upload_prefixes.join(", "),
+                invalid.join(", ")
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_path_join_normalizes_slashes() {
+        let joined = |folder: &str, segment: &str| RemotePath::from(folder).join(segment).0;
+        assert_eq!(joined("/sorted", "ai"), "/sorted/ai");
+        assert_eq!(joined("/sorted/", "ai"), "/sorted/ai");
+        assert_eq!(joined("/sorted", "/ai"), "/sorted/ai");
+        assert_eq!(joined("/sorted//", "//ai"), "/sorted/ai");
+        assert_eq!(joined("/sorted", "ai/paper.pdf"), "/sorted/ai/paper.pdf");
+        assert_eq!(joined("", "sorted"), "/sorted");
+        assert_eq!(joined("/", "sorted"), "/sorted");
+    }
+
+    #[test]
+    fn test_remote_path_is_under_respects_folder_boundaries() {
+        let path = RemotePath::from("/Research/pl/paper.pdf");
+        assert!(path.is_under("/research"));
+        assert!(path.is_under("/research/"));
+        assert!(path.is_under("/research/pl/paper.pdf"));
+        assert!(!path.is_under("/res"));
+        assert!(!RemotePath::from("/research-private/paper.pdf").is_under("/research"));
+        assert!(!RemotePath::from("/sortedness").is_under("/sorted"));
+    }
+
+    #[test]
+    fn test_remote_path_parent_and_file_name() {
+        let path = RemotePath::from("/sorted/ai/paper.pdf");
+        assert_eq!(path.parent(), Some(RemotePath::from("/sorted/ai")));
+        assert_eq!(path.file_name(), Some("paper.pdf"));
+
+        let folder = RemotePath::from("/sorted/ai/");
+        assert_eq!(folder.parent(), Some(RemotePath::from("/sorted")));
+        assert_eq!(folder.file_name(), Some("ai"));
+
+        let top = RemotePath::from("/paper.pdf");
+        assert_eq!(top.parent(), Some(RemotePath::from("")));
+        assert_eq!(top.file_name(), Some("paper.pdf"));
+
+        assert_eq!(RemotePath::from("paper.pdf").parent(), None);
+        assert_eq!(RemotePath::from("paper.pdf").file_name(), Some("paper.pdf"));
+        assert_eq!(RemotePath::from("/").file_name(), None);
+        assert_eq!(RemotePath::from("").file_name(), None);
+        assert_eq!(RemotePath::from("").parent(), None);
+    }
+
+    #[test]
+    fn test_remote_path_with_extension() {
+        let with_extension = |path: &str, ext: &str| RemotePath::from(path).with_extension(ext).0;
+        assert_eq!(
+            with_extension("/sorted/paper.pdf", "md"),
+            "/sorted/paper.md"
+        );
+        assert_eq!(with_extension("/sorted/paper", "pdf"), "/sorted/paper.pdf");
+        assert_eq!(
+            with_extension("/sorted.v2/paper", "pdf"),
+            "/sorted.v2/paper.pdf"
+        );
+        assert_eq!(
+            with_extension("/sorted/.hidden", "md"),
+            "/sorted/.hidden.md"
+        );
+        assert_eq!(
+            with_extension("/sorted/paper.pdf", "pdf.md"),
+            "/sorted/paper.pdf.md"
+        );
+    }
+
+    fn rule(name: &str, parent: Option<&str>) -> Rule {
+        Rule {
+            name: name.to_string(),
+            description: format!("Papers about {}", name),
+            path: RemotePath(format!("/sorted/{}", name.to_lowercase())),
+            parent: parent.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rules_tree_keeps_most_specific_matches() {
+        let rules = Rules(vec![
+            rule("CS", None),
+            rule("NLP", Some("AI")),
+            rule("AI", Some("cs")),
+            rule("Biology", None),
+        ]);
+        rules.validate_tree().unwrap();
+
+        let tree = rules.tree();
+        assert_eq!(tree.len(), 2);
+        let names = |node: &RuleNode| {
+            node.rules()
+                .iter()
+                .map(|rule| rule.name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&tree[0]), ["CS", "AI", "NLP"]);
+        assert_eq!(names(&tree[1]), ["Biology"]);
+        assert!(rules.is_ancestor(&rules.0[0], &rules.0[1]));
+        assert!(!rules.is_ancestor(&rules.0[1], &rules.0[0]));
+
+        let matched = |names: &[&str]| {
+            rules
+                .most_specific(names.iter().map(|name| rule(name, None)).collect())
+                .into_iter()
+                .map(|rule| rule.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(matched(&["CS", "AI", "Biology"]), ["AI", "Biology"]);
+        assert_eq!(matched(&["NLP", "CS"]), ["NLP"]);
+        assert_eq!(matched(&["CS"]), ["CS"]);
+    }
+
+    #[test]
+    fn test_rules_tree_validation() {
+        let missing = Rules(vec![rule("AI", Some("CS"))]);
+        assert_eq!(
+            missing.validate_tree().unwrap_err().to_string(),
+            "Rules with a parent that is not a rule: 'AI' (parent 'CS')"
+        );
+        let cycle = Rules(vec![
+            rule("CS", None),
+            rule("AI", Some("NLP")),
+            rule("NLP", Some("AI")),
+        ]);
+        assert_eq!(
+            cycle.validate_tree().unwrap_err().to_string(),
+            "Rules that are subcategories of themselves: AI, NLP"
+        );
+        assert!(Rules(vec![rule("AI", Some("AI"))]).validate_tree().is_err());
+        assert!(
+            Rules::from_yaml("- name: AI\n  description: AI\n  path: /sorted/ai\n  parent: CS\n")
+                .is_err()
+        );
+    }
+}
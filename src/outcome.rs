@@ -0,0 +1,238 @@
+use crate::doctor::Check;
+use crate::models::{FileStatus, RemotePath};
+use crate::pipeline::BatchSummary;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The result of a command, printed as JSON with `--output json` for scripts to read:
+///
+/// ```json
+/// {"command": "process", "counts": {"processed": 2, "failed": 1}, "paths": ["/sorted/a.pdf"], "errors": ["id:x: ..."]}
+/// ```
+///
+/// Every outcome has all four keys, so scripts need not check for them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CommandOutcome {
+    pub command: String,
+    pub counts: BTreeMap<String, u64>,
+    /// Paths written or filed to
+    pub paths: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+impl CommandOutcome {
+    pub fn new(command: &str) -> Self {
+        CommandOutcome {
+            command: command.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_count(mut self, name: &str, count: u64) -> Self {
+        self.counts.insert(name.to_string(), count);
+        self
+    }
+
+    pub fn with_paths(mut self, paths: impl IntoIterator<Item = String>) -> Self {
+        self.paths.extend(paths);
+        self
+    }
+
+    /// A command that failed before it could finish.
+    pub fn failed(command: &str, error: &anyhow::Error) -> Self {
+        let mut outcome = CommandOutcome::new(command);
+        outcome.errors.push(format!("{:#}", error));
+        outcome
+    }
+
+    pub fn sync(synced: usize) -> Self {
+        CommandOutcome::new("sync").with_count("synced", synced as u64)
+    }
+
+    pub fn batch(command: &str, summary: &BatchSummary) -> Self {
+        let mut outcome = CommandOutcome::new(command)
+            .with_count("processed", summary.processed as u64)
+            .with_count("needs_review", summary.needs_review as u64)
+            .with_count("skipped", summary.skipped as u64)
+            .with_count("failed", summary.failed.len() as u64)
+            .with_paths(summary.target_paths.iter().map(|path| path.0.clone()));
+        if summary.token_usage.total() > 0 {
+            outcome = outcome
+                .with_count("prompt_tokens", summary.token_usage.prompt_tokens)
+                .with_count("completion_tokens", summary.token_usage.completion_tokens);
+        }
+        outcome.errors = summary
+            .failed
+            .iter()
+            .map(|(id, error)| format!("{}: {}", id.0, error))
+            .collect();
+        outcome
+    }
+
+    /// A full run: the files synced and what became of the batch processed after.
+    pub fn run(synced: usize, summary: &BatchSummary) -> Self {
+        CommandOutcome::batch("run", summary).with_count("synced", synced as u64)
+    }
+
+    /// The number of files with each status, with every status present.
+    pub fn status(counts: &[(FileStatus, u64)]) -> Self {
+        STATUSES
+            .iter()
+            .fold(CommandOutcome::new("status"), |outcome, status| {
+                let count = counts
+                    .iter()
+                    .find(|(counted, _)| counted == status)
+                    .map_or(0, |(_, count)| *count);
+                outcome.with_count(status_key(status), count)
+            })
+    }
+
+    pub fn index(folders: &[String]) -> Self {
+        CommandOutcome::new("index")
+            .with_count("indexes", folders.len() as u64)
+            .with_paths(
+                folders
+                    .iter()
+                    .map(|folder| RemotePath::from(folder.as_str()).join("README.md").0),
+            )
+    }
+
+    pub fn doctor(checks: &[Check]) -> Self {
+        let failed = checks.iter().filter(|check| !check.passed);
+        let mut outcome = CommandOutcome::new("doctor")
+            .with_count(
+                "passed",
+                checks.iter().filter(|check| check.passed).count() as u64,
+            )
+            .with_count("failed", failed.clone().count() as u64);
+        outcome.errors = failed
+            .map(|check| match &check.detail {
+                Some(detail) => format!("{}: {}", check.name, detail),
+                None => check.name.clone(),
+            })
+            .collect();
+        outcome
+    }
+}
+
+const STATUSES: [FileStatus; 9] = [
+    FileStatus::Pending,
+    FileStatus::Downloaded,
+    FileStatus::InProgress,
+    FileStatus::Uploading,
+    FileStatus::Processed,
+    FileStatus::NeedsReview,
+    FileStatus::Archived,
+    FileStatus::Skipped,
+    FileStatus::Error,
+];
+
+fn status_key(status: &FileStatus) -> &'static str {
+    match status {
+        FileStatus::Pending => "pending",
+        FileStatus::Downloaded => "downloaded",
+        FileStatus::Processed => "processed",
+        FileStatus::Archived => "archived",
+        FileStatus::Error => "error",
+        FileStatus::Skipped => "skipped",
+        FileStatus::InProgress => "in_progress",
+        FileStatus::NeedsReview => "needs_review",
+        FileStatus::Uploading => "uploading",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{DropboxId, RemotePath};
+    use serde_json::json;
+
+    fn summary() -> BatchSummary {
+        BatchSummary {
+            processed: 1,
+            needs_review: 1,
+            skipped: 0,
+            failed: vec![(DropboxId("id:b".to_string()), "Download failed".to_string())],
+            target_paths: vec![RemotePath::from("/sorted/a.pdf")],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sync_outcome() {
+        assert_eq!(
+            serde_json::to_value(CommandOutcome::sync(3)).unwrap(),
+            json!({"command": "sync", "counts": {"synced": 3}, "paths": [], "errors": []})
+        );
+    }
+
+    #[test]
+    fn test_process_outcome() {
+        assert_eq!(
+            serde_json::to_value(CommandOutcome::batch("process", &summary())).unwrap(),
+            json!({
+                "command": "process",
+                "counts": {"processed": 1, "needs_review": 1, "skipped": 0, "failed": 1},
+                "paths": ["/sorted/a.pdf"],
+                "errors": ["id:b: Download failed"]
+            })
+        );
+    }
+
+    #[test]
+    fn test_run_outcome() {
+        assert_eq!(
+            serde_json::to_value(CommandOutcome::run(2, &summary())).unwrap(),
+            json!({
+                "command": "run",
+                "counts": {"synced": 2, "processed": 1, "needs_review": 1, "skipped": 0, "failed": 1},
+                "paths": ["/sorted/a.pdf"],
+                "errors": ["id:b: Download failed"]
+            })
+        );
+    }
+
+    #[test]
+    fn test_status_outcome_has_every_status() {
+        let outcome = CommandOutcome::status(&[(FileStatus::Processed, 4), (FileStatus::Error, 1)]);
+        assert_eq!(
+            serde_json::to_value(outcome).unwrap(),
+            json!({
+                "command": "status",
+                "counts": {
+                    "pending": 0, "downloaded": 0, "in_progress": 0, "uploading": 0, "processed": 4,
+                    "needs_review": 0, "archived": 0, "skipped": 0, "error": 1
+                },
+                "paths": [],
+                "errors": []
+            })
+        );
+    }
+
+    #[test]
+    fn test_index_outcome() {
+        assert_eq!(
+            serde_json::to_value(CommandOutcome::index(&["/sorted/pl".to_string()])).unwrap(),
+            json!({
+                "command": "index",
+                "counts": {"indexes": 1},
+                "paths": ["/sorted/pl/README.md"],
+                "errors": []
+            })
+        );
+    }
+
+    #[test]
+    fn test_failed_outcome() {
+        let error = anyhow::anyhow!("connection refused").context("Failed to list folder");
+        assert_eq!(
+            serde_json::to_value(CommandOutcome::failed("sync", &error)).unwrap(),
+            json!({
+                "command": "sync",
+                "counts": {},
+                "paths": [],
+                "errors": ["Failed to list folder: connection refused"]
+            })
+        );
+    }
+}
@@ -1,12 +1,259 @@
-use crate::clients::{DropboxClient, LlmClient};
-use crate::models::{FileStatus, Job, JobResult, RemotePath, Rules, WorkDirectory};
-use crate::storage::Storage;
+use crate::clients::{DropboxClient, LlmClient, dropbox_content_hash};
+use crate::extract::{
+    TextExtractor, extract_text, extract_text_fast, extract_text_pages, extractor_for,
+    is_scanned_pdf, text_hash,
+};
+use crate::metadata::{extract_arxiv_id, extract_doi, make_slug};
+use crate::models::{
+    ArticleMetadata, DropboxId, FileHash, FileStatus, Job, JobResult, ProcessError, RemotePath,
+    Rule, Rules, RunId, SkipReason, StageTimings, TokenUsage, WorkDirectory,
+};
+use crate::rate_limit::RateLimiter;
+use crate::sidecar::{SidecarFormat, stored_metadata};
+use crate::storage::{PendingOrder, Storage};
+use crate::targets::{
+    SidecarPlacement, dedup_targets, extension, remote_file_name, resolve_target_folder,
+    sniff_extension, target_file_path,
+};
 use anyhow::{Context, Result};
+use chrono::Utc;
 use colored::*;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use futures::TryStreamExt;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{Semaphore, mpsc, oneshot};
+
+/// The stages a file passes through while being processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ProcessingStage {
+    Download,
+    Extract,
+    Analyze,
+    Upload,
+}
+
+/// Machine-readable progress of a batch, for embedding the pipeline in other applications.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum ProgressEvent {
+    /// A worker picked up the file
+    Started { id: DropboxId },
+    /// The file entered a processing stage
+    Progress {
+        id: DropboxId,
+        stage: ProcessingStage,
+    },
+    /// The file was processed and its result recorded
+    Completed {
+        id: DropboxId,
+        target_paths: Vec<RemotePath>,
+    },
+    /// Processing the file failed and the failure was recorded
+    Failed { id: DropboxId, error: String },
+    /// The file was skipped and the reason recorded
+    Skipped { id: DropboxId, reason: String },
+    /// The file was not filed, but recorded for review with the rules it may be filed under
+    NeedsReview {
+        id: DropboxId,
+        candidates: Vec<String>,
+    },
+}
+
+/// Optional destination for progress events.
+#[derive(Clone, Default)]
+struct EventSink(Option<mpsc::Sender<ProgressEvent>>);
+
+impl EventSink {
+    async fn emit(&self, event: ProgressEvent) {
+        if let Some(tx) = &self.0 {
+            // A receiver that has gone away is not a reason to stop processing
+            let _ = tx.send(event).await;
+        }
+    }
+}
+
+/// Settings for how a batch is processed.
+#[derive(Debug, Clone)]
+pub struct PipelineOptions {
+    /// How many times a file is processed again after a transient failure before it is
+    /// marked as failed
+    pub max_file_retries: u32,
+    /// Files larger than this are skipped rather than parsed, as malformed PDFs can make
+    /// the parser allocate huge amounts of memory
+    pub max_pdf_bytes: u64,
+    /// Maximum number of concurrent downloads, or one per worker if not given
+    pub download_jobs: Option<usize>,
+    /// Maximum number of concurrent LLM queries, or one per worker if not given
+    pub llm_jobs: Option<usize>,
+    /// Maximum number of LLM queries started per minute across all workers, if limited
+    pub llm_rpm: Option<u32>,
+    /// Maximum number of uploads to Dropbox in flight at once across all workers, however
+    /// many workers there are, as many concurrent writes make Dropbox reject some
+    pub max_inflight_uploads: usize,
+    /// Files still in progress this long after being started are assumed to be left over
+    /// from an interrupted run, and are processed again
+    pub reclaim_after: Duration,
+    /// Files the LLM is less confident about than this are left for review instead of filed
+    pub min_confidence: f64,
+    /// Most rules a file is filed under, if limited. What happens to files matching more is
+    /// up to [`PipelineOptions::too_many_categories`].
+    pub max_categories: Option<usize>,
+    /// What to do with a file matching more rules than [`PipelineOptions::max_categories`]
+    pub too_many_categories: TooManyCategories,
+    /// Upload files under a name made from their metadata (see [`make_slug`]) instead of
+    /// their original name
+    pub rename_from_metadata: bool,
+    /// Targets outside these folders are never uploaded to, whatever the LLM answers, unless
+    /// there are none. The Dropbox client enforces the same limit; this rejects the targets
+    /// before any upload.
+    pub allowed_upload_prefixes: Vec<String>,
+    /// Maximum number of jobs queued for the workers at a time. Jobs beyond it wait in the
+    /// batch until the workers catch up, so a batch of any size never blocks on the queue.
+    pub job_queue_capacity: usize,
+    /// Maximum number of results waiting for the collector to record them. When it is full,
+    /// workers wait to hand over their result before taking another job, so with slow
+    /// database writes at most this many results, plus one per worker, are ahead of it.
+    pub result_queue_capacity: usize,
+    /// The format of the sidecar uploaded for each filed PDF
+    pub sidecar_format: SidecarFormat,
+    /// Where the sidecars are uploaded: next to the PDFs or in a subfolder
+    pub sidecar_placement: SidecarPlacement,
+    /// Report the results of a batch in the order its files were queued, rather than as the
+    /// workers finish them, so the output of a run can be compared with that of another
+    pub ordered_results: bool,
+    /// Read only the first page of PDFs unless it has too little text, see
+    /// [`extract_text_fast`]
+    pub fast_extraction: bool,
+    /// The order pending files are taken into a batch in
+    pub order_by: PendingOrder,
+    /// Compare the metadata and targets of files processed again with those stored, and
+    /// upload only the sidecars whose metadata changed, leaving unchanged files as they are
+    pub only_changed: bool,
+    /// Keep the raw copies of downloaded files compressed with gzip, to save disk space
+    pub compress_raw: bool,
+}
+
+/// What to do with a file matching more rules than it may be filed under.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TooManyCategories {
+    /// Leave the file for review, with the rules it matched as candidates
+    #[default]
+    Review,
+    /// File the file under the rules the LLM matched best, as it lists them best first
+    Top,
+}
+
+/// Default for [`PipelineOptions::max_inflight_uploads`].
+pub const DEFAULT_MAX_INFLIGHT_UPLOADS: usize = 4;
+
+/// Default for [`PipelineOptions::max_pdf_bytes`]: 100 MB.
+pub const DEFAULT_MAX_PDF_BYTES: u64 = 100 * 1024 * 1024;
+
+/// Default for [`PipelineOptions::reclaim_after`]: one hour.
+pub const DEFAULT_RECLAIM_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// Default for [`PipelineOptions::min_confidence`].
+pub const DEFAULT_MIN_CONFIDENCE: f64 = 0.5;
+
+/// Titles longer than this are taken to be something else, like the abstract, see
+/// [`metadata_problem`].
+pub const MAX_TITLE_CHARS: usize = 300;
+
+/// Workers beyond this many share one progress bar, to keep the bars on one screen.
+pub const MAX_WORKER_BARS: usize = 8;
+
+/// Default for [`PipelineOptions::job_queue_capacity`].
+pub const DEFAULT_JOB_QUEUE_CAPACITY: usize = 64;
+
+/// Default for [`PipelineOptions::result_queue_capacity`].
+pub const DEFAULT_RESULT_QUEUE_CAPACITY: usize = 64;
+
+impl Default for PipelineOptions {
+    fn default() -> Self {
+        Self {
+            max_file_retries: 1,
+            max_pdf_bytes: DEFAULT_MAX_PDF_BYTES,
+            download_jobs: None,
+            llm_jobs: None,
+            llm_rpm: None,
+            max_inflight_uploads: DEFAULT_MAX_INFLIGHT_UPLOADS,
+            reclaim_after: DEFAULT_RECLAIM_AFTER,
+            min_confidence: DEFAULT_MIN_CONFIDENCE,
+            max_categories: None,
+            too_many_categories: TooManyCategories::default(),
+            rename_from_metadata: false,
+            allowed_upload_prefixes: Vec::new(),
+            job_queue_capacity: DEFAULT_JOB_QUEUE_CAPACITY,
+            result_queue_capacity: DEFAULT_RESULT_QUEUE_CAPACITY,
+            sidecar_format: SidecarFormat::default(),
+            sidecar_placement: SidecarPlacement::default(),
+            ordered_results: false,
+            fast_extraction: false,
+            order_by: PendingOrder::default(),
+            only_changed: false,
+            compress_raw: false,
+        }
+    }
+}
+
+/// Record the files in the inbox as pending, unless already known with the same content,
+/// noting the inbox they came from. With `recursive`, the files in the subfolders of the
+/// inbox are recorded too, except those under the `allowed_upload_prefixes` papers are filed
+/// into, as those are the library, not the inbox, unless the inbox is itself inside one.
+/// Files whose names end in one of `skip_suffixes`, e.g. our own indexes and sidecars, are
+/// recorded as skipped instead, as are new files with the same content as a filed file.
+/// Returns the number of files found.
+pub async fn sync_inbox(
+    storage: &Storage,
+    dropbox: &dyn DropboxClient,
+    inbox: &str,
+    recursive: bool,
+    skip_suffixes: &[String],
+    allowed_upload_prefixes: &[String],
+) -> Result<usize> {
+    // The folders papers are filed into, which a recursive listing of the inbox may include
+    let library: Vec<&String> = allowed_upload_prefixes
+        .iter()
+        .filter(|prefix| !RemotePath::from(inbox).is_under(prefix))
+        .collect();
+    // Record each page as it is listed, so a sync that is interrupted keeps what it found
+    let mut pages = dropbox.list_folder_stream(inbox, recursive);
+    let mut count = 0;
+    while let Some(entries) = pages.try_next().await? {
+        for entry in entries {
+            if library.iter().any(|prefix| entry.path.is_under(prefix)) {
+                continue;
+            }
+            count += 1;
+            storage
+                .upsert_inbox_file(&entry.id, &entry.name, &entry.content_hash, Some(inbox))
+                .await?;
+            storage
+                .set_listing_details(&entry.id, entry.size, entry.server_modified)
+                .await?;
+            let name = entry.name.to_lowercase();
+            if let Some(suffix) = skip_suffixes
+                .iter()
+                .find(|suffix| name.ends_with(&suffix.to_lowercase()))
+            {
+                let reason = SkipReason::NotAPaper {
+                    suffix: suffix.clone(),
+                };
+                storage.mark_skipped(&entry.id, &reason.to_string()).await?;
+            }
+        }
+    }
+    Ok(count)
+}
 
 pub struct Pipeline {
     storage: Arc<Storage>,
@@ -15,6 +262,48 @@ pub struct Pipeline {
     multi_progress: MultiProgress,
     work_dir: WorkDirectory,
     rules: Arc<Rules>,
+    events: EventSink,
+    options: PipelineOptions,
+    /// Extracts the text of every file instead of the extractor sniffed from its content
+    extractor: Option<Arc<dyn TextExtractor>>,
+    /// Print plain progress lines instead of drawing progress bars, e.g. when not on a terminal
+    plain_output: bool,
+    /// Where progress lines go
+    output: LineOutput,
+}
+
+/// Where a [`Pipeline`] prints its progress lines.
+#[derive(Clone, Default)]
+enum LineOutput {
+    #[default]
+    Stdout,
+    Stderr,
+    Channel(mpsc::UnboundedSender<String>),
+}
+
+/// What became of the files of a batch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchSummary {
+    pub processed: usize,
+    /// Of the processed files, those left as they were, see [`PipelineOptions::only_changed`]
+    pub unchanged: usize,
+    pub needs_review: usize,
+    pub skipped: usize,
+    /// The files that failed, with their errors
+    pub failed: Vec<(DropboxId, String)>,
+    /// Every path the processed files were filed under
+    pub target_paths: Vec<RemotePath>,
+    /// The LLM tokens used for the processed, including unchanged, and reviewed files
+    pub token_usage: TokenUsage,
+    /// The total time the stages of processing the processed files took
+    pub timings: StageTimings,
+}
+
+impl BatchSummary {
+    /// How long the stages took per processed file on average, if any file was processed.
+    pub fn average_timings(&self) -> Option<StageTimings> {
+        (self.processed > 0).then(|| self.timings.divided_by(self.processed as u32))
+    }
 }
 
 impl Pipeline {
@@ -32,241 +321,1415 @@ impl Pipeline {
             multi_progress: MultiProgress::new(),
             work_dir,
             rules,
+            events: EventSink::default(),
+            options: PipelineOptions::default(),
+            extractor: None,
+            plain_output: false,
+            output: LineOutput::default(),
         }
     }
 
-    pub async fn run_batch(&self, batch_size: i64, num_workers: usize) -> Result<()> {
-        let pending = self.storage.get_pending_files(batch_size).await?;
+    /// Print a plain line with the batch progress for each result instead of drawing progress
+    /// bars, for output that is not a terminal.
+    pub fn with_plain_output(mut self) -> Self {
+        self.multi_progress = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        self.plain_output = true;
+        self
+    }
+
+    /// Like [`Pipeline::with_plain_output`], but printing to standard error, to keep standard
+    /// output for a machine-readable result.
+    pub fn with_plain_output_on_stderr(mut self) -> Self {
+        self.output = LineOutput::Stderr;
+        self.with_plain_output()
+    }
+
+    /// Like [`Pipeline::with_plain_output`], but sending the lines to a channel instead of
+    /// printing them, e.g. to show them elsewhere.
+    pub fn with_plain_output_to(mut self, lines: mpsc::UnboundedSender<String>) -> Self {
+        self.output = LineOutput::Channel(lines);
+        self.with_plain_output()
+    }
+
+    /// Draw the progress bars with the given [`MultiProgress`], e.g. one that log lines are also
+    /// printed through, so they do not garble each other.
+    pub fn with_multi_progress(mut self, multi_progress: MultiProgress) -> Self {
+        self.multi_progress = multi_progress;
+        self
+    }
+
+    pub fn with_options(mut self, options: PipelineOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Extract the text of every file with the given extractor, e.g. an OCR engine, instead
+    /// of the one for its file type.
+    pub fn with_extractor(mut self, extractor: Arc<dyn TextExtractor>) -> Self {
+        self.extractor = Some(extractor);
+        self
+    }
+
+    /// Send progress events to the given channel instead of drawing terminal progress bars.
+    /// The receiver must be drained while a batch runs, as workers wait for room in the channel.
+    pub fn with_progress_events(mut self, events: mpsc::Sender<ProgressEvent>) -> Self {
+        self.multi_progress = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        self.events = EventSink(Some(events));
+        self
+    }
+
+    pub async fn run_batch(&self, batch_size: i64, num_workers: usize) -> Result<BatchSummary> {
+        let mut summary = BatchSummary::default();
+        self.reconcile_uploads().await?;
+        let started_before = Utc::now() - self.options.reclaim_after;
+        let reclaimed = self.storage.reclaim_in_progress(started_before).await?;
+        if reclaimed > 0 {
+            self.print(
+                format!(
+                    "Resuming {} files left in progress by an earlier run.",
+                    reclaimed
+                )
+                .yellow()
+                .to_string(),
+            );
+        }
+
+        // Claim the files before working on them, as another batch on the same database may
+        // have claimed some of them since they were listed
+        let mut pending = Vec::new();
+        for file in self
+            .storage
+            .get_pending_files_in_order(batch_size, self.options.order_by)
+            .await?
+        {
+            if self.storage.mark_in_progress(&file.dropbox_id).await? {
+                pending.push(file);
+            }
+        }
         if pending.is_empty() {
-            println!("{}", "No pending files to process.".yellow());
-            return Ok(());
+            self.print("No pending files to process.".yellow().to_string());
+            return Ok(summary);
         }
 
-        let (job_tx, job_rx) = mpsc::channel(batch_size as usize);
-        let (result_tx, mut result_rx) = mpsc::channel(batch_size as usize);
+        // Keep the rules of this run, to explain later where its files were filed and why
+        let run_id = RunId::now();
+        self.storage.snapshot_rules(&self.rules, &run_id).await?;
+
+        let queue_capacity = self.options.job_queue_capacity.max(1);
+        let (job_tx, job_rx) = mpsc::channel(queue_capacity);
+        let (result_tx, mut result_rx) = mpsc::channel(self.options.result_queue_capacity.max(1));
+        let (intent_tx, mut intent_rx) = mpsc::channel(num_workers.max(1));
 
-        // 1. Scanner: Push jobs to queue
+        // The scanner and the collector hand jobs to a producer, which feeds them to the
+        // bounded job queue as the workers take them, so neither blocks on a full queue
+        let (producer_tx, mut producer_rx) = mpsc::unbounded_channel::<Job>();
+        let producer = tokio::spawn(async move {
+            while let Some(job) = producer_rx.recv().await {
+                if job_tx.send(job).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        // 1. Scanner: Push jobs to queue, keeping them for retries along with their attempt count
+        let mut jobs: HashMap<DropboxId, (Job, u32)> = HashMap::new();
+        let mut order = self
+            .options
+            .ordered_results
+            .then(|| ResultOrder::new(pending.iter().map(|file| file.dropbox_id.clone())));
         for file in pending {
+            let previous = (self.options.only_changed && !file.target_paths().is_empty())
+                .then(|| file.clone());
             let job = Job {
                 id: file.dropbox_id,
                 file_name: file.file_name,
                 path: RemotePath("".to_string()), // We might need the path from DB if we store it
+                content_hash: file.content_hash,
+                size: file.size.map(|size| size as u64),
+                previous,
             };
-            job_tx.send(job).await?;
+            jobs.insert(job.id.clone(), (job.clone(), 1));
+            producer_tx.send(job)?;
         }
-        drop(job_tx);
+        // The collector holds on to the sender for retries until all jobs are done
+        let mut job_tx = Some(producer_tx);
+        let mut remaining = jobs.len();
 
-        // 2. Workers: Spawn worker threads
+        // 2. Workers: Spawn worker threads, sharing the limits on concurrent external calls
         let mut worker_handles = Vec::new();
         let job_rx = Arc::new(tokio::sync::Mutex::new(job_rx));
+        let worker = Worker {
+            dropbox: Arc::clone(&self.dropbox),
+            llm: Arc::clone(&self.llm),
+            work_dir: self.work_dir.clone(),
+            rules: Arc::clone(&self.rules),
+            options: self.options.clone(),
+            extractor: self.extractor.clone(),
+            events: self.events.clone(),
+            download_permits: Arc::new(Semaphore::new(
+                self.options.download_jobs.unwrap_or(num_workers).max(1),
+            )),
+            llm_permits: Arc::new(Semaphore::new(
+                self.options.llm_jobs.unwrap_or(num_workers).max(1),
+            )),
+            upload_permits: Arc::new(Semaphore::new(self.options.max_inflight_uploads.max(1))),
+            llm_rate: self
+                .options
+                .llm_rpm
+                .map(|rpm| Arc::new(RateLimiter::per_minute(rpm))),
+            upload_intents: intent_tx,
+        };
 
+        let worker_style = ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] {msg}")?;
+        let shared_bar = (num_workers > MAX_WORKER_BARS).then(|| {
+            let pb = self.multi_progress.add(ProgressBar::new_spinner());
+            pb.set_style(worker_style.clone());
+            let bar = Arc::new(SharedWorkerBar {
+                pb,
+                workers: num_workers - MAX_WORKER_BARS,
+                busy: AtomicUsize::new(0),
+                running: AtomicUsize::new(num_workers - MAX_WORKER_BARS),
+            });
+            bar.show(0);
+            bar
+        });
         for i in 0..num_workers {
             let job_rx = Arc::clone(&job_rx);
             let result_tx = result_tx.clone();
-            let dropbox = Arc::clone(&self.dropbox);
-            let llm = Arc::clone(&self.llm);
-            let work_dir = self.work_dir.clone();
-            let rules = Arc::clone(&self.rules);
+            let worker = worker.clone();
 
-            let pb = self.multi_progress.add(ProgressBar::new_spinner());
-            pb.set_style(
-                ProgressStyle::default_spinner()
-                    .template("{spinner:.green} [{elapsed_precise}] {msg}")?,
-            );
-            pb.set_message(format!("Worker {}", i));
+            let bar = match &shared_bar {
+                Some(shared) if i >= MAX_WORKER_BARS => WorkerBar::Shared(Arc::clone(shared)),
+                _ => {
+                    let pb = self.multi_progress.add(ProgressBar::new_spinner());
+                    pb.set_style(worker_style.clone());
+                    pb.set_message(format!("Worker {}", i));
+                    WorkerBar::Own(pb, i)
+                }
+            };
 
             let handle = tokio::spawn(async move {
                 while let Some(job) = {
                     let mut rx = job_rx.lock().await;
                     rx.recv().await
                 } {
-                    let display_name = job.file_name.as_deref().unwrap_or("unknown");
-                    pb.set_message(format!("Processing {} ({})", display_name, job.id.0));
-                    let result = process_file(job, &*dropbox, &*llm, &work_dir, &rules).await;
+                    bar.start(&job);
+                    worker
+                        .events
+                        .emit(ProgressEvent::Started { id: job.id.clone() })
+                        .await;
+                    let result = worker.process_file(job).await;
+                    bar.done();
                     let _ = result_tx.send(result).await;
                 }
-                pb.finish_with_message(format!("Worker {} idle", i));
+                bar.idle();
             });
             worker_handles.push(handle);
         }
         drop(result_tx);
 
         // 3. Collector: Listen for results and update DB/UI
-        let main_pb = self.multi_progress.add(ProgressBar::new(batch_size as u64));
-        main_pb.set_style(
-            ProgressStyle::default_bar().template(
-                "{span:.green} [{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} {msg}",
-            )?,
-        );
+        let main_pb = self.multi_progress.add(ProgressBar::new(jobs.len() as u64));
+        main_pb.set_style(overall_progress_style()?);
         main_pb.set_message("Overall Progress");
 
-        while let Some(result) = result_rx.recv().await {
+        loop {
+            let result = tokio::select! {
+                Some(intent) = intent_rx.recv() => {
+                    // Record the targets before anything is uploaded to them
+                    let recorded = self
+                        .storage
+                        .record_upload_intent(&intent.id, intent.meta, &intent.target_paths)
+                        .await;
+                    let _ = intent.recorded.send(recorded);
+                    continue;
+                }
+                result = result_rx.recv() => match result {
+                    Some(result) => result,
+                    None => break,
+                },
+            };
             match result {
                 JobResult::Success {
                     id,
                     file_name,
                     meta,
-                    target_paths: _,
+                    target_paths,
+                    timings,
+                } => {
+                    summary.token_usage += meta.token_usage.unwrap_or_default();
+                    summary.timings += timings;
+                    // Update DB with metadata, targets and status
+                    self.storage
+                        .update_metadata(&id, meta, &target_paths, FileStatus::Processed)
+                        .await?;
+                    self.storage.set_run_id(&id, &run_id).await?;
+                    summary.processed += 1;
+                    summary.target_paths.extend(target_paths.iter().cloned());
+                    self.events
+                        .emit(ProgressEvent::Completed {
+                            id: id.clone(),
+                            target_paths,
+                        })
+                        .await;
+                    let display_name = file_name.as_deref().unwrap_or("unknown");
+                    self.report_result(
+                        &main_pb,
+                        order.as_mut(),
+                        &id,
+                        format!("{} Processed {} ({})", "✔".green(), display_name, id.0),
+                    );
+                }
+                JobResult::Unchanged {
+                    id,
+                    file_name,
+                    target_paths,
+                    text_hash,
+                    token_usage,
+                    timings,
                 } => {
-                    // Update DB with metadata and status
+                    summary.token_usage += token_usage.unwrap_or_default();
+                    summary.timings += timings;
+                    // Keep the metadata and the time it was last changed
+                    let updated_at = jobs
+                        .get(&id)
+                        .and_then(|(job, _)| job.previous.as_ref())
+                        .map(|previous| previous.updated_at)
+                        .unwrap_or_else(Utc::now);
                     self.storage
-                        .update_metadata(&id, meta, FileStatus::Processed)
+                        .mark_unchanged(&id, updated_at, text_hash.as_deref(), token_usage)
                         .await?;
+                    self.storage.set_run_id(&id, &run_id).await?;
+                    summary.processed += 1;
+                    summary.unchanged += 1;
+                    summary.target_paths.extend(target_paths.iter().cloned());
+                    self.events
+                        .emit(ProgressEvent::Completed {
+                            id: id.clone(),
+                            target_paths,
+                        })
+                        .await;
                     let display_name = file_name.as_deref().unwrap_or("unknown");
-                    main_pb.println(format!(
-                        "{} Processed {} ({})",
-                        "✔".green(),
-                        display_name,
-                        id.0
-                    ));
+                    self.report_result(
+                        &main_pb,
+                        order.as_mut(),
+                        &id,
+                        format!("{} Unchanged {} ({})", "=".green(), display_name, id.0),
+                    );
                 }
                 JobResult::Failure {
                     id,
                     file_name,
                     error,
                 } => {
-                    self.storage.update_status(&id, FileStatus::Error).await?;
                     let display_name = file_name.as_deref().unwrap_or("unknown");
-                    main_pb.println(format!(
-                        "{} Failed {} ({}): {}",
-                        "✘".red(),
-                        display_name,
-                        id.0,
-                        error
-                    ));
+                    if let Some((job, attempts)) = jobs.get_mut(&id)
+                        && error.is_transient()
+                        && *attempts <= self.options.max_file_retries
+                        && let Some(tx) = &job_tx
+                    {
+                        *attempts += 1;
+                        let line = format!(
+                            "{} Retrying {} ({}) after: {}",
+                            "↻".yellow(),
+                            display_name,
+                            id.0,
+                            error
+                        );
+                        if order.is_some() {
+                            // Retries happen in no particular order, so only log them
+                            tracing::info!("{}", line);
+                        } else {
+                            self.report(&main_pb, line);
+                        }
+                        tx.send(job.clone())?;
+                        continue;
+                    }
+                    self.storage.mark_error(&id, &error.to_string()).await?;
+                    summary.failed.push((id.clone(), error.to_string()));
+                    self.events
+                        .emit(ProgressEvent::Failed {
+                            id: id.clone(),
+                            error: error.to_string(),
+                        })
+                        .await;
+                    self.report_result(
+                        &main_pb,
+                        order.as_mut(),
+                        &id,
+                        format!(
+                            "{} Failed {} ({}): {}",
+                            "✘".red(),
+                            display_name,
+                            id.0,
+                            error
+                        ),
+                    );
+                }
+                JobResult::NeedsReview {
+                    id,
+                    file_name,
+                    meta,
+                    candidates,
+                    reason,
+                } => {
+                    summary.token_usage += meta.token_usage.unwrap_or_default();
+                    self.storage
+                        .mark_needs_review(&id, meta, &candidates, &reason)
+                        .await?;
+                    self.storage.set_run_id(&id, &run_id).await?;
+                    summary.needs_review += 1;
+                    self.events
+                        .emit(ProgressEvent::NeedsReview {
+                            id: id.clone(),
+                            candidates,
+                        })
+                        .await;
+                    let display_name = file_name.as_deref().unwrap_or("unknown");
+                    self.report_result(
+                        &main_pb,
+                        order.as_mut(),
+                        &id,
+                        format!(
+                            "{} Needs review {} ({}): {}",
+                            "?".yellow(),
+                            display_name,
+                            id.0,
+                            reason
+                        ),
+                    );
+                }
+                JobResult::Skipped {
+                    id,
+                    file_name,
+                    reason,
+                } => {
+                    self.storage.mark_skipped(&id, &reason).await?;
+                    summary.skipped += 1;
+                    self.events
+                        .emit(ProgressEvent::Skipped {
+                            id: id.clone(),
+                            reason: reason.clone(),
+                        })
+                        .await;
+                    let display_name = file_name.as_deref().unwrap_or("unknown");
+                    self.report_result(
+                        &main_pb,
+                        order.as_mut(),
+                        &id,
+                        format!(
+                            "{} Skipped {} ({}): {}",
+                            "⊘".yellow(),
+                            display_name,
+                            id.0,
+                            reason
+                        ),
+                    );
                 }
             }
             main_pb.inc(1);
+            remaining -= 1;
+            if remaining == 0 {
+                // No more retries can come, so let the workers run out of jobs
+                job_tx = None;
+            }
         }
 
+        let _ = producer.await;
         for handle in worker_handles {
             let _ = handle.await;
         }
 
         main_pb.finish_with_message("Batch complete");
 
+        Ok(summary)
+    }
+
+    /// Settle the files left uploading by a run that stopped between recording their targets
+    /// and recording the result, e.g. by crashing. Files found at all their targets are
+    /// processed; the others are processed again.
+    async fn reconcile_uploads(&self) -> Result<()> {
+        for file in self
+            .storage
+            .get_files_with_status(FileStatus::Uploading)
+            .await?
+        {
+            let targets = file.pending_target_paths();
+            let mut uploaded = !targets.is_empty();
+            for target in &targets {
+                uploaded = uploaded
+                    && self.dropbox.get_metadata(target).await?.is_some()
+                    && self
+                        .dropbox
+                        .get_metadata(
+                            &self
+                                .options
+                                .sidecar_format
+                                .path(target, self.options.sidecar_placement),
+                        )
+                        .await?
+                        .is_some();
+            }
+            tracing::info!(
+                "File {} was left uploading, now {}",
+                file.dropbox_id.0,
+                if uploaded { "processed" } else { "pending" }
+            );
+            self.storage
+                .settle_upload(&file.dropbox_id, uploaded)
+                .await?;
+        }
         Ok(())
     }
+
+    /// Print a line, hiding the progress bars meanwhile so they are not drawn over it.
+    fn print(&self, line: String) {
+        match &self.output {
+            LineOutput::Stdout => self.multi_progress.suspend(|| println!("{}", line)),
+            LineOutput::Stderr => self.multi_progress.suspend(|| eprintln!("{}", line)),
+            LineOutput::Channel(lines) => {
+                let _ = lines.send(line);
+            }
+        }
+    }
+
+    /// Report a result line above the progress bars, or as a plain line with the batch progress.
+    fn report(&self, main_pb: &ProgressBar, line: String) {
+        self.report_at(main_pb, main_pb.position() + 1, line);
+    }
+
+    /// Report the final result of a file, in the order of `order` if given.
+    fn report_result(
+        &self,
+        main_pb: &ProgressBar,
+        order: Option<&mut ResultOrder>,
+        id: &DropboxId,
+        line: String,
+    ) {
+        match order {
+            Some(order) => {
+                for (position, line) in order.release(id, line) {
+                    self.report_at(main_pb, position as u64 + 1, line);
+                }
+            }
+            None => self.report(main_pb, line),
+        }
+    }
+
+    fn report_at(&self, main_pb: &ProgressBar, position: u64, line: String) {
+        if self.plain_output {
+            self.print(format!(
+                "[{}/{}] {}",
+                position,
+                main_pb.length().unwrap_or_default(),
+                line
+            ));
+        } else {
+            main_pb.println(line);
+        }
+    }
 }
 
-async fn process_file(
-    job: Job,
-    dropbox: &dyn DropboxClient,
-    llm: &dyn LlmClient,
-    work_dir: &WorkDirectory,
-    rules: &Rules,
-) -> JobResult {
-    // 1. Download
-    tracing::debug!(
-        "Downloading file {} ({})",
-        &job.file_name.clone().unwrap_or_else(|| String::from("")),
-        &job.id.0
-    );
-    let content = match dropbox.download_file(&job.id).await {
-        Ok(c) => c,
-        Err(e) => {
-            return JobResult::failure(job.id.clone(), job.file_name, e);
+/// The progress bar of a worker: its own, or one shared with the other workers beyond the
+/// first [`MAX_WORKER_BARS`].
+enum WorkerBar {
+    Own(ProgressBar, usize),
+    Shared(Arc<SharedWorkerBar>),
+}
+
+impl WorkerBar {
+    fn start(&self, job: &Job) {
+        match self {
+            WorkerBar::Own(pb, _) => {
+                let display_name = job.file_name.as_deref().unwrap_or("unknown");
+                pb.set_message(format!("Processing {} ({})", display_name, job.id.0));
+            }
+            WorkerBar::Shared(shared) => {
+                shared.show(shared.busy.fetch_add(1, Ordering::SeqCst) + 1)
+            }
         }
-    };
+    }
 
-    // 2. Save to local raw directory
-    tracing::debug!(
-        "Saving file {} ({}) to local raw directory",
-        &job.file_name.clone().unwrap_or_else(|| String::from("")),
-        &job.id.0
-    );
-    let sanitized_id = job.id.0.replace([':', '/', '\\', ' '], "_");
-    let local_path = work_dir.0.join("raw").join(format!("{}.pdf", sanitized_id));
-    if let Err(e) = fs::write(&local_path, &content)
-        .with_context(|| format!("Failed to save local copy to: {}", &local_path.to_string_lossy()))
-    {
-        return JobResult::failure(job.id, job.file_name, e);
+    fn done(&self) {
+        if let WorkerBar::Shared(shared) = self {
+            shared.show(shared.busy.fetch_sub(1, Ordering::SeqCst) - 1);
+        }
     }
 
-    // 3. Extract Text (lopdf)
-    tracing::debug!(
-        "Extracting text from file {} ({})",
-        &job.file_name.clone().unwrap_or_else(|| String::from("")),
-        &job.id.0
-    );
-    let text = match extract_text(&content) {
-        Ok(t) => t,
-        Err(e) => {
-            return JobResult::failure(job.id.clone(), job.file_name, e);
+    fn idle(&self) {
+        match self {
+            WorkerBar::Own(pb, i) => pb.finish_with_message(format!("Worker {} idle", i)),
+            WorkerBar::Shared(shared) => {
+                if shared.running.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    shared
+                        .pb
+                        .finish_with_message(format!("{} more workers idle", shared.workers));
+                }
+            }
         }
-    };
+    }
+}
+
+/// The progress bar standing for the workers without one of their own.
+struct SharedWorkerBar {
+    pb: ProgressBar,
+    workers: usize,
+    busy: AtomicUsize,
+    running: AtomicUsize,
+}
 
-    // 4. LLM Analysis
-    tracing::debug!(
-        "Querying LLM for file {} ({})",
-        &job.file_name.clone().unwrap_or_else(|| String::from("")),
-        &job.id.0
-    );
-    let (meta, matching_rules) = match llm.query_llm(&text, &rules).await {
-        Ok(r) => r,
-        Err(e) => {
-            tracing::warn!("LLM query failed: {}", e);
-            return JobResult::failure(job.id.clone(), job.file_name, e);
+impl SharedWorkerBar {
+    fn show(&self, busy: usize) {
+        self.pb
+            .set_message(format!("{} more workers, {} busy", self.workers, busy));
+    }
+}
+
+/// Holds back the result lines of a batch until the results of all the files queued before
+/// them are reported, see [`PipelineOptions::ordered_results`].
+struct ResultOrder {
+    positions: HashMap<DropboxId, usize>,
+    held: BTreeMap<usize, String>,
+    next: usize,
+}
+
+impl ResultOrder {
+    fn new(queued: impl IntoIterator<Item = DropboxId>) -> Self {
+        Self {
+            positions: queued
+                .into_iter()
+                .enumerate()
+                .map(|(position, id)| (id, position))
+                .collect(),
+            held: BTreeMap::new(),
+            next: 0,
         }
-    };
+    }
 
-    // 5. Upload
-    let remote_file_name = job
-        .file_name
-        .clone()
-        .unwrap_or_else(|| format!("{}.pdf", sanitized_id));
-    tracing::debug!(
-        "Uploading file {} ({}) to Dropbox",
-        &job.file_name.clone().unwrap_or_else(|| String::from("")),
-        &job.id.0
-    );
-    let targets = matching_rules
-        .iter()
-        .map(|x| RemotePath(format!("{}/{}", x.path.0, remote_file_name)))
-        .collect::<Vec<RemotePath>>();
-    for target in &targets {
-        if let Err(e) = dropbox.upload_file(&target, content.clone()).await {
-            tracing::warn!("Failed to upload file {} to Dropbox: {:?}", &target.0, e);
-            return JobResult::failure(job.id.clone(), job.file_name, e);
-        }
-        let sidecar_path = RemotePath(format!("{}.md", &target.0));
-        let sidecar_content = format!(
-            "# {}\n\n## Authors\n{}\n\n## Summary\n{}\n\n## Abstract\n{}",
-            meta.title,
-            meta.authors.join(", "),
-            meta.summary.0,
-            meta.abstract_text
+    /// The lines that can be reported now that the result of `id` is in, with their
+    /// positions in the queue.
+    fn release(&mut self, id: &DropboxId, line: String) -> Vec<(usize, String)> {
+        if let Some(&position) = self.positions.get(id) {
+            self.held.insert(position, line);
+        }
+        let mut ready = Vec::new();
+        while let Some(line) = self.held.remove(&self.next) {
+            ready.push((self.next, line));
+            self.next += 1;
+        }
+        ready
+    }
+}
+
+/// Everything a worker needs to process files, shared between the workers of a batch.
+#[derive(Clone)]
+struct Worker {
+    dropbox: Arc<dyn DropboxClient>,
+    llm: Arc<dyn LlmClient>,
+    work_dir: WorkDirectory,
+    rules: Arc<Rules>,
+    options: PipelineOptions,
+    extractor: Option<Arc<dyn TextExtractor>>,
+    events: EventSink,
+    /// Limits the number of concurrent downloads across workers
+    download_permits: Arc<Semaphore>,
+    /// Limits the number of concurrent LLM queries across workers
+    llm_permits: Arc<Semaphore>,
+    /// Limits the number of concurrent uploads across workers
+    upload_permits: Arc<Semaphore>,
+    /// Limits the rate of LLM queries across workers
+    llm_rate: Option<Arc<RateLimiter>>,
+    /// Sends the targets of each file to the collector to record before uploading
+    upload_intents: mpsc::Sender<UploadIntent>,
+}
+
+/// A file about to be uploaded, for the collector to record before the uploads start, so
+/// that a run stopped during the uploads can tell what was being uploaded where.
+struct UploadIntent {
+    id: DropboxId,
+    meta: ArticleMetadata,
+    target_paths: Vec<RemotePath>,
+    /// Whether recording succeeded, for the worker to wait for
+    recorded: oneshot::Sender<Result<()>>,
+}
+
+impl Worker {
+    async fn process_file(&self, job: Job) -> JobResult {
+        let dropbox = &*self.dropbox;
+        let llm = &*self.llm;
+        let work_dir = &self.work_dir;
+        let rules = &*self.rules;
+        let options = &self.options;
+        let events = &self.events;
+        let stage = |stage| ProgressEvent::Progress {
+            id: job.id.clone(),
+            stage,
+        };
+
+        let mut timings = StageTimings::default();
+
+        // 1. Download
+        events.emit(stage(ProcessingStage::Download)).await;
+        let started = Instant::now();
+        tracing::debug!(
+            "Downloading file {} ({})",
+            &job.file_name.clone().unwrap_or_else(|| String::from("")),
+            &job.id.0
+        );
+
+        // Don't download a file listed as too large to parse
+        if let Some(size) = job.size
+            && size > options.max_pdf_bytes
+        {
+            let reason = SkipReason::TooLarge {
+                size,
+                max: options.max_pdf_bytes,
+            };
+            return JobResult::skipped(job.id, job.file_name, reason.to_string());
+        }
+
+        // 2. Save to local raw directory as it downloads, so a retry can resume it. Raw copies
+        // are named by content hash, so a copy from an earlier run can be reused instead. The
+        // download only gets the extension of its format once it is known to be one we read.
+        let sanitized_id = job.id.0.replace([':', '/', '\\', ' '], "_");
+        let raw_copy = raw_copy_path(work_dir, &job);
+        let mut local_path = raw_copy.clone();
+        'reuse: for extension in RAW_COPY_EXTENSIONS {
+            for compressed in [false, true] {
+                let kept = raw_copy.with_extension(raw_copy_file_extension(extension, compressed));
+                if verified_raw_copy(&kept, &job.content_hash).is_some() {
+                    local_path = kept;
+                    break 'reuse;
+                }
+            }
+        }
+        let download = async {
+            if let Some(size) = verified_raw_copy(&local_path, &job.content_hash) {
+                tracing::debug!("Reusing raw copy of {} at {:?}", &job.id.0, &local_path);
+                return Ok(size);
+            }
+            let _permit = self.download_permits.acquire().await?;
+            dropbox.download_to(&job.id, &local_path).await
+        };
+        let size = match download.await {
+            Ok(size) => size,
+            Err(e) => {
+                let error = network_error(ProcessError::Download, e);
+                return JobResult::failure(job.id.clone(), job.file_name, error);
+            }
+        };
+
+        if size > options.max_pdf_bytes {
+            let _ = fs::remove_file(&local_path);
+            let reason = SkipReason::TooLarge {
+                size,
+                max: options.max_pdf_bytes,
+            };
+            return JobResult::skipped(job.id, job.file_name, reason.to_string());
+        }
+
+        let content = match read_raw_copy(&local_path).with_context(|| {
+            format!(
+                "Failed to read local copy at: {}",
+                &local_path.to_string_lossy()
+            )
+        }) {
+            Ok(content) => content,
+            Err(e) => return JobResult::failure(job.id, job.file_name, ProcessError::Io(e)),
+        };
+
+        let Some(raw_extension) = raw_copy_extension(&content) else {
+            let _ = fs::remove_file(&local_path);
+            return JobResult::skipped(job.id, job.file_name, SkipReason::NotAPdf.to_string());
+        };
+        let kept =
+            raw_copy.with_extension(raw_copy_file_extension(raw_extension, options.compress_raw));
+        // A reused copy is kept as it is, compressed or not
+        let keep = || {
+            if options.compress_raw {
+                write_compressed_raw_copy(&kept, &content)?;
+                fs::remove_file(&local_path)
+            } else {
+                fs::rename(&local_path, &kept)
+            }
+        };
+        if local_path == raw_copy
+            && let Err(e) = keep()
+        {
+            let error = anyhow::Error::from(e).context(format!(
+                "Failed to keep raw copy at: {}",
+                kept.to_string_lossy()
+            ));
+            return JobResult::failure(job.id, job.file_name, ProcessError::Io(error));
+        }
+
+        timings.download = started.elapsed();
+
+        // 3. Extract Text
+        events.emit(stage(ProcessingStage::Extract)).await;
+        let started = Instant::now();
+        tracing::debug!(
+            "Extracting text from file {} ({})",
+            &job.file_name.clone().unwrap_or_else(|| String::from("")),
+            &job.id.0
         );
-        if let Err(e) = dropbox
-            .upload_file(&sidecar_path, sidecar_content.into_bytes())
-            .await
+        let extracted = if let Some(extractor) = &self.extractor {
+            extractor.extract(&content)
+        } else if options.fast_extraction {
+            extract_text_fast(&content)
+        } else {
+            extract_text(&content)
+        };
+        let text = match extracted {
+            Ok(t) => t,
+            Err(_) if is_scanned_pdf(&content) => {
+                return JobResult::skipped(job.id, job.file_name, SkipReason::Scanned.to_string());
+            }
+            Err(e) => {
+                return JobResult::failure(job.id.clone(), job.file_name, ProcessError::Parse(e));
+            }
+        };
+
+        timings.extract = started.elapsed();
+
+        // 4. LLM Analysis
+        events.emit(stage(ProcessingStage::Analyze)).await;
+        let started = Instant::now();
+        tracing::debug!(
+            "Querying LLM for file {} ({})",
+            &job.file_name.clone().unwrap_or_else(|| String::from("")),
+            &job.id.0
+        );
+        let query = async {
+            let _permit = self.llm_permits.acquire().await?;
+            if let Some(rate) = &self.llm_rate {
+                rate.acquire().await;
+            }
+            llm.query_llm(&text, rules).await
+        };
+        let (mut meta, matching_rules) = match query.await {
+            Ok(r) => r,
+            Err(e) => {
+                tracing::warn!("LLM query failed: {}", e);
+                let error = network_error(ProcessError::Llm, e);
+                return JobResult::failure(job.id.clone(), job.file_name, error);
+            }
+        };
+        timings.llm = started.elapsed();
+        meta.extraction_quality = Some(extraction_quality(&text));
+        meta.extracted_text_hash = Some(text_hash(&text));
+        meta.doi = extract_doi(&text);
+        meta.arxiv_id = extract_arxiv_id(&text);
+        let (matching_rules, rejected) = guard_rules(
+            matching_rules,
+            rules,
+            &meta,
+            &options.allowed_upload_prefixes,
+        );
+        for rejection in &rejected {
+            tracing::warn!("Rejected target for file {}: {}", &job.id.0, rejection);
+        }
+        if matching_rules.is_empty() && !rejected.is_empty() {
+            return JobResult::NeedsReview {
+                id: job.id,
+                file_name: job.file_name,
+                meta,
+                candidates: Vec::new(),
+                reason: format!("All targets were rejected: {}", rejected.join("; ")),
+            };
+        }
+        // A paper in a subcategory is filed there, not also under its parent categories
+        let mut matching_rules = rules.most_specific(matching_rules);
+
+        if let Some(max) = options.max_categories
+            && options.too_many_categories == TooManyCategories::Top
+            && matching_rules.len() > max
         {
-            tracing::warn!("Failed to upload file {} to Dropbox: {:?}", target.0, e);
-            return JobResult::failure(job.id.clone(), job.file_name, e);
+            tracing::info!(
+                "File {} matched {} categories, filing it under the first {}",
+                &job.id.0,
+                matching_rules.len(),
+                max
+            );
+            matching_rules.truncate(max);
+        }
+
+        if let Some(reason) = review_reason(&meta, &matching_rules, options) {
+            let candidates = matching_rules
+                .iter()
+                .map(|rule| rule.name.clone())
+                .collect();
+            return JobResult::NeedsReview {
+                id: job.id,
+                file_name: job.file_name,
+                meta,
+                candidates,
+                reason,
+            };
         }
+
+        // 5. Upload
+        events.emit(stage(ProcessingStage::Upload)).await;
+        let started = Instant::now();
+        let slug = make_slug(&meta, meta.year);
+        let file_name = if options.rename_from_metadata && !slug.is_empty() {
+            // Keep the original extension, if any
+            match job.file_name.as_deref().and_then(extension) {
+                Some(ext) => Some(format!("{}.{}", slug, ext)),
+                None => Some(slug),
+            }
+        } else {
+            job.file_name.clone()
+        };
+        let remote_file_name = remote_file_name(file_name.as_deref(), &sanitized_id, &content);
+        tracing::debug!(
+            "Uploading file {} ({}) to Dropbox",
+            &job.file_name.clone().unwrap_or_else(|| String::from("")),
+            &job.id.0
+        );
+        let targets = dedup_targets(
+            matching_rules
+                .iter()
+                .map(|x| {
+                    let folder = resolve_target_folder(&x.path, &meta);
+                    target_file_path(&folder, &remote_file_name)
+                })
+                .collect::<Vec<RemotePath>>(),
+        );
+        // When processing a file again, the PDFs may already be at the targets, and the
+        // sidecars may already have the metadata
+        let (upload_pdfs, upload_sidecars) = match job.previous.as_ref() {
+            Some(previous) if options.only_changed && previous.target_paths() == targets => {
+                let mut filed = true;
+                for target in &targets {
+                    match dropbox.get_metadata(target).await {
+                        Ok(entry) => {
+                            filed &= entry.is_some_and(|entry| {
+                                entry.content_hash == dropbox_content_hash(&content)
+                            })
+                        }
+                        Err(e) => {
+                            let error = network_error(ProcessError::Upload, e);
+                            return JobResult::failure(job.id, job.file_name, error);
+                        }
+                    }
+                }
+                (
+                    !filed,
+                    !filed || metadata_changed(&stored_metadata(previous), &meta),
+                )
+            }
+            _ => (true, true),
+        };
+        if !upload_pdfs && !upload_sidecars {
+            timings.upload = started.elapsed();
+            return JobResult::Unchanged {
+                id: job.id,
+                file_name: job.file_name,
+                target_paths: targets,
+                text_hash: meta.extracted_text_hash,
+                token_usage: meta.token_usage,
+                timings,
+            };
+        }
+
+        let (recorded_tx, recorded_rx) = oneshot::channel();
+        let intent = UploadIntent {
+            id: job.id.clone(),
+            meta: meta.clone(),
+            target_paths: targets.clone(),
+            recorded: recorded_tx,
+        };
+        let recorded = match self.upload_intents.send(intent).await {
+            Ok(()) => recorded_rx
+                .await
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("The batch stopped"))),
+            Err(_) => Err(anyhow::anyhow!("The batch stopped")),
+        };
+        if let Err(e) = recorded {
+            return JobResult::failure(job.id, job.file_name, ProcessError::Database(e));
+        }
+        for target in &targets {
+            if upload_pdfs && let Err(e) = self.upload(target, content.clone()).await {
+                tracing::warn!("Failed to upload file {} to Dropbox: {:?}", &target.0, e);
+                let error = network_error(ProcessError::Upload, e);
+                return JobResult::failure(job.id.clone(), job.file_name, error);
+            }
+            let sidecar_path = options
+                .sidecar_format
+                .path(target, options.sidecar_placement);
+            let sidecar_content = options.sidecar_format.render(&meta);
+            if let Err(e) = self
+                .upload(&sidecar_path, sidecar_content.into_bytes())
+                .await
+            {
+                tracing::warn!("Failed to upload file {} to Dropbox: {:?}", target.0, e);
+                let error = network_error(ProcessError::Upload, e);
+                return JobResult::failure(job.id.clone(), job.file_name, error);
+            }
+        }
+
+        timings.upload = started.elapsed();
+
+        JobResult::success(job.id, job.file_name, meta, targets, timings)
     }
 
-    JobResult::success(job.id, job.file_name, meta, targets)
+    /// Upload a file to Dropbox once one of the upload permits shared by the workers is free.
+    async fn upload(&self, path: &RemotePath, content: Vec<u8>) -> Result<()> {
+        let _permit = self.upload_permits.acquire().await?;
+        self.dropbox.upload_file(path, content).await
+    }
 }
 
-fn extract_text(content: &[u8]) -> Result<String> {
-    let doc = lopdf::Document::load_mem(content)?;
-    let mut text = String::new();
+/// Style of the overall progress bar of a batch, with the ETA and the throughput so far.
+fn overall_progress_style() -> Result<ProgressStyle> {
+    Ok(ProgressStyle::default_bar()
+        .template(
+            "{spinner:.green} [{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} \
+             ETA {eta} ({throughput}) {msg}",
+        )?
+        .with_key(
+            "throughput",
+            |state: &ProgressState, w: &mut dyn std::fmt::Write| {
+                let _ = w.write_str(&format_throughput(state.pos(), state.elapsed()));
+            },
+        ))
+}
 
-    // Extract from first 5 pages as per PRD
-    let pages = doc.get_pages();
-    let max_pages = std::cmp::min(pages.len(), 5);
+/// Format the number of files completed per minute.
+fn format_throughput(completed: u64, elapsed: Duration) -> String {
+    let minutes = elapsed.as_secs_f64() / 60.0;
+    if minutes <= 0.0 {
+        return String::from("- files/min");
+    }
+    format!("{:.1} files/min", completed as f64 / minutes)
+}
 
-    for i in 1..=max_pages {
-        if let Ok(page_text) = doc.extract_text(&[i as u32]) {
-            text.push_str(&page_text);
-            text.push('\n');
+/// Why a classification is too uncertain to file the paper automatically, if it is, or its
+/// metadata too implausible (see [`metadata_problem`]).
+fn review_reason(
+    meta: &ArticleMetadata,
+    matching_rules: &[Rule],
+    options: &PipelineOptions,
+) -> Option<String> {
+    if let Some(problem) = metadata_problem(meta) {
+        return Some(problem);
+    }
+    if let Some(confidence) = meta.confidence
+        && confidence < options.min_confidence
+    {
+        return Some(format!(
+            "Confidence {:.2} is below {:.2}",
+            confidence, options.min_confidence
+        ));
+    }
+    if let Some(max) = options.max_categories
+        && matching_rules.len() > max
+    {
+        return Some(format!(
+            "Matched {} categories, more than {}",
+            matching_rules.len(),
+            max
+        ));
+    }
+    None
+}
+
+/// What is wrong with the metadata the LLM extracted, if anything is: an empty title, a title
+/// so long it is likely the abstract, or no authors. A classifier that extracts no metadata
+/// says so (see [`ArticleMetadata::not_extracted`]), and its metadata is fine.
+fn metadata_problem(meta: &ArticleMetadata) -> Option<String> {
+    if meta.not_extracted {
+        return None;
+    }
+    let title = meta.title.trim();
+    if title.is_empty() {
+        return Some(String::from("The title is empty"));
+    }
+    let title_chars = title.chars().count();
+    if title_chars > MAX_TITLE_CHARS {
+        return Some(format!(
+            "The title is {} characters, more than {}",
+            title_chars, MAX_TITLE_CHARS
+        ));
+    }
+    if meta.authors.iter().all(|author| author.trim().is_empty()) {
+        return Some(String::from("No authors were found"));
+    }
+    None
+}
+
+/// Whether metadata differs from the metadata stored for a file in anything that is stored,
+/// and so in its sidecar.
+fn metadata_changed(stored: &ArticleMetadata, meta: &ArticleMetadata) -> bool {
+    stored.title != meta.title
+        || stored.authors != meta.authors
+        || stored.summary.0 != meta.summary.0
+        || stored.key_findings != meta.key_findings
+        || stored.abstract_text != meta.abstract_text
+        || stored.tags != meta.tags
+        || stored.doi != meta.doi
+        || stored.arxiv_id != meta.arxiv_id
+}
+
+/// Keep only the matched rules that are currently loaded, with the same target, and whose
+/// target folder is under the allowed upload prefix. This guards against a model answering
+/// with a rule of its own or one that has since been edited. Returns the accepted rules and
+/// descriptions of the rejected ones.
+fn guard_rules(
+    matching_rules: Vec<Rule>,
+    rules: &Rules,
+    meta: &ArticleMetadata,
+    allowed_upload_prefixes: &[String],
+) -> (Vec<Rule>, Vec<String>) {
+    let mut accepted = Vec::new();
+    let mut rejected = Vec::new();
+    for rule in matching_rules {
+        let loaded = rules
+            .0
+            .iter()
+            .any(|loaded| loaded.name == rule.name && loaded.path == rule.path);
+        let folder = resolve_target_folder(&rule.path, meta);
+        if !loaded {
+            rejected.push(format!(
+                "'{}' ({}) is not a loaded rule",
+                rule.name, rule.path.0
+            ));
+        } else if !allowed_upload_prefixes.is_empty()
+            && !allowed_upload_prefixes
+                .iter()
+                .any(|prefix| folder.is_under(prefix))
+        {
+            rejected.push(format!(
+                "'{}' ({}) is outside the allowed upload prefixes {}",
+                rule.name,
+                folder.0,
+                allowed_upload_prefixes.join(", ")
+            ));
+        } else {
+            accepted.push(rule);
         }
     }
+    (accepted, rejected)
+}
+
+/// Classify an error from a network call made in the given stage, distinguishing time-outs.
+fn network_error(stage: fn(anyhow::Error) -> ProcessError, error: anyhow::Error) -> ProcessError {
+    let timed_out = error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<reqwest::Error>())
+        .any(|e| e.is_timeout());
+    if timed_out {
+        ProcessError::Timeout(error)
+    } else {
+        stage(error)
+    }
+}
+
+/// What the LLM made of a paper, without filing it anywhere.
+#[derive(Debug, Clone, Serialize)]
+pub struct Analysis {
+    pub metadata: ArticleMetadata,
+    pub rules: Vec<Rule>,
+}
+
+/// Where the raw copy of a file is kept: named by the file's content hash, or by its id if it
+/// has none.
+fn raw_copy_path(work_dir: &WorkDirectory, job: &Job) -> PathBuf {
+    let stem = match job.content_hash.0.as_str() {
+        "" => job.id.0.as_str(),
+        hash => hash,
+    };
+    let sanitized_stem = stem.replace([':', '/', '\\', ' '], "_");
+    work_dir.0.join("raw").join(sanitized_stem)
+}
+
+/// Extensions of the raw copies kept of the formats we read, see [`raw_copy_extension`].
+const RAW_COPY_EXTENSIONS: &[&str] = &["pdf", "epub", "ps", "txt"];
+
+/// The extension to keep a raw copy with, by its sniffed format, or `None` if it is not a
+/// format text can be extracted from, when it is not kept at all.
+fn raw_copy_extension(content: &[u8]) -> Option<&'static str> {
+    extractor_for(content)?;
+    Some(sniff_extension(content).unwrap_or("txt"))
+}
+
+/// Extension added to raw copies compressed with gzip, see [`PipelineOptions::compress_raw`].
+const RAW_COPY_COMPRESSED_EXTENSION: &str = "gz";
+
+/// The file extension of a raw copy in the format with the given extension, e.g. `pdf.gz`
+/// when compressed.
+fn raw_copy_file_extension(extension: &str, compressed: bool) -> String {
+    if compressed {
+        format!("{}.{}", extension, RAW_COPY_COMPRESSED_EXTENSION)
+    } else {
+        extension.to_string()
+    }
+}
+
+/// The content of the raw copy at `path`, decompressed if it was kept compressed.
+fn read_raw_copy(path: &Path) -> std::io::Result<Vec<u8>> {
+    if path
+        .extension()
+        .is_some_and(|ext| ext == RAW_COPY_COMPRESSED_EXTENSION)
+    {
+        let mut content = Vec::new();
+        GzDecoder::new(fs::File::open(path)?).read_to_end(&mut content)?;
+        Ok(content)
+    } else {
+        fs::read(path)
+    }
+}
+
+/// Write a raw copy compressed with gzip, to be read back with [`read_raw_copy`].
+fn write_compressed_raw_copy(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let mut encoder = GzEncoder::new(fs::File::create(path)?, Compression::default());
+    encoder.write_all(content)?;
+    encoder.finish()?;
+    Ok(())
+}
 
-    if text.trim().is_empty() {
-        return Err(anyhow::anyhow!("No text extracted from PDF"));
+/// The size of the raw copy at `path`, if there is one with the given Dropbox content hash.
+fn verified_raw_copy(path: &Path, content_hash: &FileHash) -> Option<u64> {
+    if content_hash.0.is_empty() {
+        return None;
     }
+    let content = read_raw_copy(path).ok()?;
+    (dropbox_content_hash(&content) == *content_hash).then_some(content.len() as u64)
+}
+
+/// The text extracted from a file in Dropbox, by its id or path, as the pipeline would
+/// extract it, e.g. to see why a paper was misclassified. With `max_pages`, that many pages
+/// of a PDF are read instead of the usual.
+pub async fn dropbox_file_text(
+    dropbox: &dyn DropboxClient,
+    id: &DropboxId,
+    max_pages: Option<usize>,
+) -> Result<String> {
+    let content = dropbox
+        .download_file(id)
+        .await
+        .with_context(|| format!("Failed to download {}", id.0))?;
+    file_text(&content, max_pages).with_context(|| format!("Failed to extract text from {}", id.0))
+}
+
+/// The text extracted from file content, see [`dropbox_file_text`].
+pub fn file_text(content: &[u8], max_pages: Option<usize>) -> Result<String> {
+    match max_pages {
+        Some(max_pages) => extract_text_pages(content, max_pages),
+        None => extract_text(content),
+    }
+}
+
+/// Extract the text of a local file and ask the LLM about it, without touching Dropbox or
+/// the database, e.g. to triage papers or try out rules.
+pub async fn analyze_local_file(
+    path: &std::path::Path,
+    llm: &dyn LlmClient,
+    rules: &Rules,
+) -> Result<Analysis> {
+    let content =
+        fs::read(path).with_context(|| format!("Failed to read {}", path.to_string_lossy()))?;
+    let text = extract_text(&content)
+        .with_context(|| format!("Failed to extract text from {}", path.to_string_lossy()))?;
+    let (mut metadata, rules) = llm.query_llm(&text, rules).await?;
+    metadata.extraction_quality = Some(extraction_quality(&text));
+    metadata.extracted_text_hash = Some(text_hash(&text));
+    metadata.doi = extract_doi(&text);
+    metadata.arxiv_id = extract_arxiv_id(&text);
+    Ok(Analysis { metadata, rules })
+}
+
+/// Below this [`extraction_quality`], the text of a PDF is likely too poor to classify, and
+/// the PDF may need OCR.
+pub const LOW_EXTRACTION_QUALITY: f32 = 0.5;
+
+/// Below this many letters, digits and symbols, extracted text counts as incomplete.
+const FULL_EXTRACTION_LENGTH: usize = 500;
 
-    Ok(text)
+/// A heuristic score from 0 to 1 for how usable extracted text is: the share of letters
+/// among the non-whitespace characters, scaled down for texts too short to be a paper's
+/// first page, e.g. ligature soup or only a few characters.
+pub fn extraction_quality(text: &str) -> f32 {
+    let (letters, total) = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .fold((0usize, 0usize), |(letters, total), c| {
+            (letters + usize::from(c.is_alphabetic()), total + 1)
+        });
+    if total == 0 {
+        return 0.0;
+    }
+    let letter_ratio = letters as f32 / total as f32;
+    let length_factor = (total as f32 / FULL_EXTRACTION_LENGTH as f32).min(1.0);
+    letter_ratio * length_factor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extraction_quality_separates_garbage_from_text() {
+        let clean = "We present a gradual type system for a dynamically typed language, \
+                     prove it sound, and evaluate it on a corpus of real programs. "
+            .repeat(5);
+        let symbols =
+            "\u{fb01}\u{fb02} ## @@ 1 2 3 \u{2202} %% && ** (( )) || -- ++ == ".repeat(20);
+
+        assert!(extraction_quality(&clean) > 0.8);
+        assert!(extraction_quality(&symbols) < LOW_EXTRACTION_QUALITY);
+        assert!(extraction_quality("abc") < LOW_EXTRACTION_QUALITY);
+        assert_eq!(extraction_quality(""), 0.0);
+    }
+
+    #[test]
+    fn test_metadata_problem_flags_implausible_titles_and_authors() {
+        let good = ArticleMetadata {
+            title: String::from("Gradual Typing for Functional Languages"),
+            authors: vec![String::from("Jeremy Siek")],
+            ..Default::default()
+        };
+        assert_eq!(metadata_problem(&good), None);
+        assert_eq!(metadata_problem(&ArticleMetadata::not_extracted()), None);
+        assert!(
+            metadata_problem(&ArticleMetadata::default())
+                .unwrap()
+                .contains("title is empty")
+        );
+
+        let long = ArticleMetadata {
+            title: "x".repeat(MAX_TITLE_CHARS + 1),
+            ..good.clone()
+        };
+        assert!(metadata_problem(&long).unwrap().contains("more than 300"));
+        let untitled = ArticleMetadata {
+            title: String::from("  "),
+            ..good.clone()
+        };
+        assert!(
+            metadata_problem(&untitled)
+                .unwrap()
+                .contains("title is empty")
+        );
+        let anonymous = ArticleMetadata {
+            authors: vec![],
+            ..good
+        };
+        assert!(metadata_problem(&anonymous).unwrap().contains("No authors"));
+    }
+
+    #[test]
+    fn test_guard_rules_rejects_unknown_and_outside_targets() {
+        let rule = |name: &str, path: &str| Rule {
+            name: name.to_string(),
+            description: String::new(),
+            path: RemotePath::from(path),
+            ..Default::default()
+        };
+        let loaded = Rules::from(vec![rule("PL", "/sorted/pl"), rule("Old", "/elsewhere")]);
+
+        let (accepted, rejected) = guard_rules(
+            vec![
+                rule("PL", "/sorted/pl"),
+                rule("PL", "/sorted/pl-edited"),
+                rule("Old", "/elsewhere"),
+                rule("Made up", "/sorted/x"),
+            ],
+            &loaded,
+            &ArticleMetadata::default(),
+            &[String::from("/Sorted")],
+        );
+
+        assert_eq!(accepted, vec![rule("PL", "/sorted/pl")]);
+        assert_eq!(rejected.len(), 3);
+    }
+
+    #[test]
+    fn test_guard_rules_accepts_targets_under_any_prefix() {
+        let rule = |name: &str, path: &str| Rule {
+            name: name.to_string(),
+            path: RemotePath::from(path),
+            ..Default::default()
+        };
+        let loaded = Rules::from(vec![
+            rule("Research", "/research/pl"),
+            rule("Shared", "/shared/ai"),
+            rule("Other", "/other/x"),
+        ]);
+
+        let (accepted, rejected) = guard_rules(
+            loaded.0.clone(),
+            &loaded,
+            &ArticleMetadata::default(),
+            &[String::from("/research"), String::from("/shared")],
+        );
+
+        assert_eq!(
+            accepted,
+            vec![
+                rule("Research", "/research/pl"),
+                rule("Shared", "/shared/ai")
+            ]
+        );
+        assert_eq!(rejected.len(), 1);
+    }
+
+    #[test]
+    fn test_compressed_raw_copy_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = b"%PDF-1.4 Gradual Typing ".repeat(100);
+        let path = dir
+            .path()
+            .join("hash")
+            .with_extension(raw_copy_file_extension("pdf", true));
+        assert_eq!(path.file_name().unwrap(), "hash.pdf.gz");
+
+        write_compressed_raw_copy(&path, &content).unwrap();
+        assert!(fs::metadata(&path).unwrap().len() < content.len() as u64);
+        assert_eq!(read_raw_copy(&path).unwrap(), content);
+        assert_eq!(
+            verified_raw_copy(&path, &dropbox_content_hash(&content)),
+            Some(content.len() as u64)
+        );
+        assert_eq!(
+            verified_raw_copy(&path, &FileHash("other".to_string())),
+            None
+        );
+    }
+
+    #[test]
+    fn test_result_order_holds_back_later_results() {
+        let id = |name: &str| DropboxId(name.to_string());
+        let mut order = ResultOrder::new([id("a"), id("b"), id("c")]);
+
+        assert!(order.release(&id("c"), "c".to_string()).is_empty());
+        assert_eq!(
+            order.release(&id("a"), "a".to_string()),
+            vec![(0, "a".to_string())]
+        );
+        assert_eq!(
+            order.release(&id("b"), "b".to_string()),
+            vec![(1, "b".to_string()), (2, "c".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_overall_progress_style_template_compiles() {
+        let style = overall_progress_style().unwrap();
+        let pb = ProgressBar::with_draw_target(Some(10), ProgressDrawTarget::hidden());
+        pb.set_style(style);
+        pb.inc(3);
+        pb.finish();
+    }
+
+    #[test]
+    fn test_format_throughput() {
+        assert_eq!(
+            format_throughput(6, Duration::from_secs(120)),
+            "3.0 files/min"
+        );
+        assert_eq!(format_throughput(0, Duration::ZERO), "- files/min");
+    }
 }
@@ -0,0 +1,62 @@
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// Spaces calls out evenly to at most a given number per minute, shared between workers.
+/// It is a token bucket holding a single token, so calls beyond the rate wait for their turn
+/// instead of going out in a burst that trips the provider's per-minute limit.
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    /// When the next call may start
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn per_minute(calls: u32) -> Self {
+        RateLimiter {
+            interval: Duration::from_secs(60) / calls.max(1),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait until it is this call's turn.
+    pub async fn acquire(&self) {
+        let start = {
+            let mut next = self.next.lock().await;
+            let start = (*next).max(Instant::now());
+            *next = start + self.interval;
+            start
+        };
+        tokio::time::sleep_until(start).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_calls_start_at_most_once_per_second_at_60_rpm() {
+        let limiter = Arc::new(RateLimiter::per_minute(60));
+        let started = Instant::now();
+        let calls = (0..10).map(|_| {
+            let limiter = Arc::clone(&limiter);
+            tokio::spawn(async move {
+                limiter.acquire().await;
+                Instant::now()
+            })
+        });
+        let mut starts = Vec::new();
+        for call in calls.collect::<Vec<_>>() {
+            starts.push(call.await.unwrap());
+        }
+        starts.sort();
+
+        assert!(starts[9] - started >= Duration::from_secs(9));
+        for pair in starts.windows(2) {
+            assert!(pair[1] - pair[0] >= Duration::from_secs(1));
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::Result;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::time::Duration;
+
+/// Delay before the first retry. It doubles for every further retry.
+pub const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+
+/// Run `f` up to `attempts` times, backing off between attempts, for as long as it fails
+/// with a connection-level error (see [`is_connection_error`]). Any other error, e.g. one
+/// reported by the API, is returned at once.
+pub async fn retry_async<T, F, Fut>(attempts: u32, mut f: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut delay = RETRY_BASE_DELAY;
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < attempts && is_connection_error(&e) => {
+                tracing::warn!(
+                    "Attempt {} of {} failed, retrying in {:?}: {:#}",
+                    attempt,
+                    attempts,
+                    delay,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Whether an error is a failure to reach the other side at all, such as a DNS failure,
+/// a refused or reset connection or a time-out, as opposed to an error response.
+pub fn is_connection_error(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return e.is_connect() || e.is_timeout();
+        }
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return matches!(
+                e.kind(),
+                ErrorKind::ConnectionRefused
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::NotConnected
+                    | ErrorKind::TimedOut
+                    | ErrorKind::HostUnreachable
+                    | ErrorKind::NetworkUnreachable
+            );
+        }
+        false
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_connection_errors_are_connection_errors() {
+        let reset = anyhow::Error::from(std::io::Error::from(ErrorKind::ConnectionReset))
+            .context("Failed to list folder");
+        assert!(is_connection_error(&reset));
+        assert!(!is_connection_error(&anyhow::anyhow!(
+            "Dropbox API error: path/not_found"
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_api_errors_are_not_retried() {
+        let mut calls = 0;
+        let result: Result<()> = retry_async(3, || {
+            calls += 1;
+            async { Err(anyhow::anyhow!("Dropbox API error: path/not_found")) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+}
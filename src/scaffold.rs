@@ -0,0 +1,121 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the sample rules file written by `init`, to pass with `--rules`.
+pub const SAMPLE_RULES_FILE: &str = "rules.yaml";
+
+/// Name of the sample file with the environment variables to set.
+pub const SAMPLE_ENV_FILE: &str = ".env";
+
+/// A few example categories to start a rules file from.
+const SAMPLE_RULES: &str = "\
+# The categories papers are filed into. Use this file with --rules <path to this file>.
+#
+# Each rule has a unique name, a description for the LLM to match papers against, and
+# the Dropbox folder to file matching papers into, under an allowed upload prefix
+# (--allowed-upload-prefix, /sorted by default). Folders may use the {{year}} and
+# {{first_author}} placeholders.
+#
+# Optionally, give `examples` of papers that belong in a category and
+# `negative_keywords` for topics that do not, to tell similar categories apart.
+
+- name: AI
+  description: Neural networks, deep learning, large language models and reinforcement learning
+  path: /sorted/ai
+  examples: [Attention is all you need]
+
+- name: Programming Languages
+  description: Type systems, compilers, parsers and language design
+  path: /sorted/programming-languages
+  negative_keywords: [agile processes, requirements engineering]
+
+- name: Databases
+  description: Query processing, storage engines and transactions
+  path: /sorted/databases/{{year}}
+";
+
+/// The environment variables to set, with placeholder values.
+const SAMPLE_ENV: &str = "\
+# Secrets for sci-librarian. Fill in the values, and keep this file private.
+# It is read from the directory sci-librarian is run in, so copy or move it there.
+
+# Access token of a Dropbox app with read and write access to the files
+DROPBOX_TOKEN=
+
+# API key for Mistral AI, which classifies and summarizes the papers
+MISTRAL_API_KEY=
+";
+
+/// The files written by [`scaffold_work_directory`], and those left as they were.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scaffold {
+    pub written: Vec<PathBuf>,
+    /// Files that already existed, which are only overwritten with `force`
+    pub kept: Vec<PathBuf>,
+}
+
+/// Set up a work directory for a new library: create it with its `raw` directory for
+/// downloads, and write a sample rules file and a sample `.env` file to fill in. Existing
+/// files are kept, unless `force` is given.
+pub fn scaffold_work_directory(work_dir: &Path, force: bool) -> Result<Scaffold> {
+    fs::create_dir_all(work_dir.join("raw")).with_context(|| {
+        format!(
+            "Failed to create work directory {}",
+            work_dir.to_string_lossy()
+        )
+    })?;
+    let mut scaffold = Scaffold::default();
+    for (name, content) in [
+        (SAMPLE_RULES_FILE, SAMPLE_RULES),
+        (SAMPLE_ENV_FILE, SAMPLE_ENV),
+    ] {
+        let path = work_dir.join(name);
+        if path.exists() && !force {
+            scaffold.kept.push(path);
+            continue;
+        }
+        fs::write(&path, content)
+            .with_context(|| format!("Failed to write {}", path.to_string_lossy()))?;
+        scaffold.written.push(path);
+    }
+    Ok(scaffold)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Rules;
+
+    #[test]
+    fn test_scaffold_work_directory_writes_samples_once() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let work_dir = temp_dir.path().join("working");
+        let rules_path = work_dir.join(SAMPLE_RULES_FILE);
+        let env_path = work_dir.join(SAMPLE_ENV_FILE);
+
+        let scaffold = scaffold_work_directory(&work_dir, false).unwrap();
+        assert_eq!(scaffold.written, vec![rules_path.clone(), env_path.clone()]);
+        assert!(scaffold.kept.is_empty());
+        assert!(work_dir.join("raw").is_dir());
+        let rules = Rules::load(&rules_path).unwrap();
+        assert_eq!(rules.0.len(), 3);
+        rules.validate_targets(&[String::from("/sorted")]).unwrap();
+        assert!(
+            fs::read_to_string(&env_path)
+                .unwrap()
+                .contains("DROPBOX_TOKEN=")
+        );
+
+        // Edited files are left alone, unless forced
+        fs::write(&rules_path, "[]").unwrap();
+        let scaffold = scaffold_work_directory(&work_dir, false).unwrap();
+        assert!(scaffold.written.is_empty());
+        assert_eq!(scaffold.kept, vec![rules_path.clone(), env_path.clone()]);
+        assert_eq!(fs::read_to_string(&rules_path).unwrap(), "[]");
+
+        let scaffold = scaffold_work_directory(&work_dir, true).unwrap();
+        assert_eq!(scaffold.written.len(), 2);
+        assert_eq!(fs::read_to_string(&rules_path).unwrap(), SAMPLE_RULES);
+    }
+}
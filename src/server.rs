@@ -0,0 +1,136 @@
+use crate::clients::{DropboxClient, LlmClient};
+use crate::models::{DropboxId, FileRecord, Rules, WorkDirectory};
+use crate::pipeline::{Pipeline, PipelineOptions, sync_inbox};
+use crate::storage::{ListFilter, Storage};
+use anyhow::{Context, Result};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Everything the HTTP API needs to browse the library and run syncs and batches.
+#[derive(Clone)]
+pub struct AppState {
+    pub storage: Arc<Storage>,
+    pub dropbox: Arc<dyn DropboxClient>,
+    pub llm: Arc<dyn LlmClient>,
+    pub work_dir: WorkDirectory,
+    pub rules: Arc<Rules>,
+    pub inboxes: Vec<String>,
+    /// Whether to sync the subfolders of the inboxes too, see [`sync_inbox`]
+    pub inbox_recursive: bool,
+    /// See [`sync_inbox`]
+    pub skip_suffixes: Vec<String>,
+    pub options: PipelineOptions,
+    pub batch_size: i64,
+    pub jobs: usize,
+    /// Held while a sync or batch runs, so only one runs against the database at a time
+    pub run_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+/// The routes of the HTTP API:
+///
+/// - `GET /files`: all file records
+/// - `GET /files/{id}`: one file record by Dropbox id
+/// - `POST /sync`: sync the inboxes
+/// - `POST /process`: process a batch of pending files
+///
+/// A sync or batch requested while another is running is refused with 409 Conflict.
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/files", get(list_files))
+        .route("/files/{id}", get(get_file))
+        .route("/sync", post(sync))
+        .route("/process", post(process))
+        .with_state(state)
+}
+
+/// Serve the HTTP API on the given port of localhost until the process is stopped.
+pub async fn serve(state: AppState, port: u16) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
+        .await
+        .with_context(|| format!("Failed to listen on port {}", port))?;
+    tracing::info!("Serving on http://{}", listener.local_addr()?);
+    axum::serve(listener, router(state)).await?;
+    Ok(())
+}
+
+/// An error from a handler, reported as a 500 with the error message.
+struct ApiError(anyhow::Error);
+
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        ApiError(error)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        tracing::error!("Request failed: {:#}", self.0);
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", self.0)).into_response()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct SyncResponse {
+    synced: usize,
+}
+
+async fn list_files(State(state): State<AppState>) -> Result<Json<Vec<FileRecord>>, ApiError> {
+    Ok(Json(
+        state
+            .storage
+            .list_files(&ListFilter::default(), None, 0)
+            .await?,
+    ))
+}
+
+async fn get_file(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Response, ApiError> {
+    Ok(match state.storage.get_file(&DropboxId(id)).await? {
+        Some(record) => Json(record).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    })
+}
+
+async fn sync(State(state): State<AppState>) -> Result<Response, ApiError> {
+    let Ok(_running) = state.run_lock.try_lock() else {
+        return Ok(StatusCode::CONFLICT.into_response());
+    };
+    let mut synced = 0;
+    for inbox in &state.inboxes {
+        synced += sync_inbox(
+            &state.storage,
+            state.dropbox.as_ref(),
+            inbox,
+            state.inbox_recursive,
+            &state.skip_suffixes,
+            &state.options.allowed_upload_prefixes,
+        )
+        .await?;
+    }
+    Ok(Json(SyncResponse { synced }).into_response())
+}
+
+async fn process(State(state): State<AppState>) -> Result<StatusCode, ApiError> {
+    let Ok(_running) = state.run_lock.try_lock() else {
+        return Ok(StatusCode::CONFLICT);
+    };
+    Pipeline::new(
+        state.storage.clone(),
+        state.dropbox.clone(),
+        state.llm.clone(),
+        state.work_dir.clone(),
+        state.rules.clone(),
+    )
+    .with_options(state.options.clone())
+    .with_plain_output()
+    .run_batch(state.batch_size, state.jobs)
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,282 @@
+use crate::clients::DropboxClient;
+use crate::models::{ArticleMetadata, FileRecord, FileStatus, OneLineSummary, RemotePath};
+use crate::storage::Storage;
+use crate::targets::{SidecarPlacement, sidecar_file_path};
+use anyhow::Result;
+use serde_json::json;
+
+/// The format of the sidecars uploaded next to filed PDFs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SidecarFormat {
+    /// A Markdown page for reading, `<file>.md`
+    #[default]
+    Markdown,
+    /// A schema.org `ScholarlyArticle` in JSON-LD for publishing, e.g. on a static site,
+    /// `<file>.jsonld`
+    JsonLd,
+}
+
+impl SidecarFormat {
+    pub fn render(self, meta: &ArticleMetadata) -> String {
+        match self {
+            SidecarFormat::Markdown => render_sidecar(meta),
+            SidecarFormat::JsonLd => render_json_ld(meta),
+        }
+    }
+
+    /// The extension of sidecars in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            SidecarFormat::Markdown => "md",
+            SidecarFormat::JsonLd => "jsonld",
+        }
+    }
+
+    /// The path of the sidecar in this format for a PDF filed at `target`.
+    pub fn path(self, target: &RemotePath, placement: SidecarPlacement) -> RemotePath {
+        sidecar_file_path(target, self.extension(), placement)
+    }
+}
+
+/// Render the Markdown sidecar uploaded next to a filed PDF.
+pub fn render_sidecar(meta: &ArticleMetadata) -> String {
+    let mut sidecar = format!(
+        "# {}\n\n## Authors\n{}\n\n## Summary\n{}",
+        meta.title,
+        meta.authors.join(", "),
+        meta.summary.0,
+    );
+    if !meta.key_findings.is_empty() {
+        let bullets: Vec<String> = meta
+            .key_findings
+            .iter()
+            .map(|finding| format!("- {}", finding))
+            .collect();
+        sidecar.push_str(&format!("\n\n## Key Findings\n{}", bullets.join("\n")));
+    }
+    sidecar.push_str(&format!("\n\n## Abstract\n{}", meta.abstract_text));
+    let links = source_links(meta);
+    if !links.is_empty() {
+        sidecar.push_str(&format!("\n\n## Links\n{}", links.join("\n")));
+    }
+    if !meta.tags.is_empty() {
+        sidecar.push_str(&format!("\n\n## Tags\n{}", meta.tags.join(", ")));
+    }
+    sidecar
+}
+
+/// Render a schema.org `ScholarlyArticle` describing a paper, as JSON-LD. Fields without a
+/// value are left out.
+pub fn render_json_ld(meta: &ArticleMetadata) -> String {
+    let mut article = json!({
+        "@context": "https://schema.org",
+        "@type": "ScholarlyArticle",
+        "headline": meta.title,
+        "author": meta
+            .authors
+            .iter()
+            .map(|name| json!({"@type": "Person", "name": name}))
+            .collect::<Vec<_>>(),
+    });
+    let optional = [
+        (
+            "abstract",
+            (!meta.abstract_text.is_empty()).then(|| json!(meta.abstract_text)),
+        ),
+        (
+            "description",
+            (!meta.summary.0.is_empty()).then(|| json!(meta.summary.0)),
+        ),
+        (
+            "datePublished",
+            meta.year.map(|year| json!(year.to_string())),
+        ),
+        (
+            "keywords",
+            (!meta.tags.is_empty()).then(|| json!(meta.tags)),
+        ),
+        (
+            "identifier",
+            meta.doi
+                .as_ref()
+                .map(|doi| json!({"@type": "PropertyValue", "propertyID": "DOI", "value": doi})),
+        ),
+    ];
+    for (key, value) in optional {
+        if let Some(value) = value {
+            article[key] = value;
+        }
+    }
+    let links = source_urls(meta);
+    if !links.is_empty() {
+        article["sameAs"] = json!(links);
+    }
+    serde_json::to_string_pretty(&article).expect("JSON values always serialize")
+}
+
+/// The pages of the paper online: its DOI and arXiv pages.
+fn source_urls(meta: &ArticleMetadata) -> Vec<String> {
+    let doi = meta
+        .doi
+        .iter()
+        .map(|doi| format!("https://doi.org/{}", doi));
+    let arxiv = meta
+        .arxiv_id
+        .iter()
+        .map(|id| format!("https://arxiv.org/abs/{}", id));
+    doi.chain(arxiv).collect()
+}
+
+/// Links back to the paper online, as Markdown list items: its DOI and arXiv pages.
+fn source_links(meta: &ArticleMetadata) -> Vec<String> {
+    let doi = meta
+        .doi
+        .iter()
+        .map(|doi| format!("- DOI: <https://doi.org/{}>", doi));
+    let arxiv = meta
+        .arxiv_id
+        .iter()
+        .map(|id| format!("- arXiv: <https://arxiv.org/abs/{}>", id));
+    doi.chain(arxiv).collect()
+}
+
+/// The path of the Markdown sidecar next to a PDF filed at `target`.
+pub fn sidecar_path(target: &RemotePath) -> RemotePath {
+    SidecarFormat::Markdown.path(target, SidecarPlacement::Alongside)
+}
+
+/// The metadata stored for a file, as far as it is needed for its sidecar.
+pub(crate) fn stored_metadata(file: &FileRecord) -> ArticleMetadata {
+    ArticleMetadata {
+        title: file.title.clone().unwrap_or_default(),
+        authors: file
+            .authors
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default(),
+        summary: OneLineSummary(file.summary.clone().unwrap_or_default()),
+        key_findings: file.key_finding_list(),
+        abstract_text: file.abstract_text.clone().unwrap_or_default(),
+        tags: file.tag_list(),
+        doi: file.doi.clone(),
+        arxiv_id: file.arxiv_id.clone(),
+        ..Default::default()
+    }
+}
+
+/// Render and upload the sidecars of all processed files from their stored metadata, without
+/// uploading the PDFs again. Returns the number of sidecars written.
+pub async fn regenerate_sidecars(
+    storage: &Storage,
+    dropbox: &dyn DropboxClient,
+    format: SidecarFormat,
+    placement: SidecarPlacement,
+) -> Result<usize> {
+    let mut count = 0;
+    for file in storage.get_files_with_status(FileStatus::Processed).await? {
+        let sidecar = format.render(&stored_metadata(&file));
+        for target in file.target_paths() {
+            dropbox
+                .upload_file(
+                    &format.path(&target, placement),
+                    sidecar.clone().into_bytes(),
+                )
+                .await?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_sidecar() {
+        let meta = ArticleMetadata {
+            title: "Gradual Types".to_string(),
+            authors: vec!["Jane Roe".to_string(), "John Doe".to_string()],
+            summary: OneLineSummary("Types, gradually.".to_string()),
+            abstract_text: "We present gradual types.".to_string(),
+            tags: vec!["types".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            render_sidecar(&meta),
+            "# Gradual Types\n\n## Authors\nJane Roe, John Doe\n\n## Summary\nTypes, gradually.\n\n\
+             ## Abstract\nWe present gradual types.\n\n## Tags\ntypes"
+        );
+    }
+
+    #[test]
+    fn test_render_sidecar_section_order() {
+        let meta = ArticleMetadata {
+            title: "Gradual Types".to_string(),
+            authors: vec!["Jane Roe".to_string()],
+            summary: OneLineSummary("Types, gradually.".to_string()),
+            key_findings: vec!["Sound".to_string()],
+            abstract_text: "We present gradual types.".to_string(),
+            doi: Some("10.1145/3290355".to_string()),
+            tags: vec!["types".to_string()],
+            ..Default::default()
+        };
+
+        let headings: Vec<String> = render_sidecar(&meta)
+            .lines()
+            .filter(|line| line.starts_with('#'))
+            .map(String::from)
+            .collect();
+
+        assert_eq!(
+            headings,
+            vec![
+                "# Gradual Types",
+                "## Authors",
+                "## Summary",
+                "## Key Findings",
+                "## Abstract",
+                "## Links",
+                "## Tags"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_json_ld() {
+        let meta = ArticleMetadata {
+            title: "The \"Gradual\" Guarantee".to_string(),
+            authors: vec!["Jane Roe".to_string()],
+            abstract_text: "We present gradual types.\nThey are \\ sound.".to_string(),
+            doi: Some("10.1145/3290355".to_string()),
+            ..Default::default()
+        };
+
+        let json_ld: serde_json::Value = serde_json::from_str(&render_json_ld(&meta)).unwrap();
+
+        assert_eq!(json_ld["@type"], "ScholarlyArticle");
+        assert_eq!(json_ld["@context"], "https://schema.org");
+        assert_eq!(json_ld["headline"], "The \"Gradual\" Guarantee");
+        assert_eq!(json_ld["author"][0]["name"], "Jane Roe");
+        assert_eq!(json_ld["abstract"], meta.abstract_text);
+        assert_eq!(json_ld["identifier"]["value"], "10.1145/3290355");
+        assert_eq!(json_ld["sameAs"][0], "https://doi.org/10.1145/3290355");
+        assert!(json_ld.get("keywords").is_none());
+    }
+
+    #[test]
+    fn test_render_sidecar_links_to_doi_and_arxiv() {
+        let meta = ArticleMetadata {
+            title: "Gradual Types".to_string(),
+            abstract_text: "We present gradual types.".to_string(),
+            doi: Some("10.1145/3290355".to_string()),
+            arxiv_id: Some("2101.00001".to_string()),
+            ..Default::default()
+        };
+        assert!(render_sidecar(&meta).ends_with(
+            "## Abstract\nWe present gradual types.\n\n## Links\n\
+             - DOI: <https://doi.org/10.1145/3290355>\n\
+             - arXiv: <https://arxiv.org/abs/2101.00001>"
+        ));
+    }
+}
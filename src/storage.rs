@@ -1,39 +1,274 @@
-use crate::models::{ArticleMetadata, DropboxId, FileHash, FileRecord, FileStatus};
+use crate::MigrationStatus;
+use crate::models::{
+    ArticleMetadata, DropboxId, ExtractedTextHash, FileHash, FileRecord, FileStatus, RemotePath,
+    Rule, Rules, RulesSnapshot, RunId, SkipReason, TokenUsage,
+};
 use anyhow::Result;
-use chrono::Utc;
-use sqlx::SqlitePool;
+use chrono::{DateTime, Utc};
+use futures::future;
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use sqlx::query::Query;
+use sqlx::sqlite::SqliteArguments;
+use sqlx::{Sqlite, SqlitePool};
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+
+/// Columns of a [`FileRecord`], for use as `SELECT {FILE_RECORD_COLUMNS} FROM files ...`.
+const FILE_RECORD_COLUMNS: &str = r#"
+    dropbox_id,
+    file_name,
+    content_hash,
+    status,
+    title,
+    authors,
+    summary,
+    abstract_text,
+    target_path,
+    pending_target_path,
+    tags,
+    key_findings,
+    prompt_tokens,
+    completion_tokens,
+    size,
+    server_modified,
+    review_candidates,
+    run_id,
+    source_folder,
+    extraction_quality,
+    extracted_text_hash,
+    doi,
+    arxiv_id,
+    skip_reason,
+    last_error,
+    updated_at,
+    processed_at,
+    started_at
+"#;
+
+/// The condition for files that are filed at their target paths. Files still uploading, or
+/// whose uploads failed, are not, whatever targets they were meant for.
+const FILED: &str = "status IN ('PROCESSED', 'ARCHIVED')";
+
+/// Filed files with a target path in a folder, bound as `?1` (`%<folder>/%`). The exact
+/// folder is checked on each record.
+static FILES_IN_FOLDER_QUERY: LazyLock<String> = LazyLock::new(|| {
+    format!(
+        r#"
+        SELECT {FILE_RECORD_COLUMNS}
+        FROM files
+        WHERE target_path LIKE ?1 AND {FILED}
+        ORDER BY title ASC
+        "#
+    )
+});
+
+/// Marks a file skipped, see [`Storage::mark_skipped`], bound as `?1` (skipped), `?2`
+/// (reason), `?3` (time) and `?4` (id).
+const MARK_SKIPPED: &str = r#"
+    UPDATE files
+    SET status = ?1, skip_reason = ?2, updated_at = ?3
+    WHERE dropbox_id = ?4
+"#;
+
+/// Finds the file another is a duplicate of, see [`Storage::find_duplicate`], bound as `?1`
+/// (content hash) and `?2` (id of the other file).
+static FIND_DUPLICATE: LazyLock<String> = LazyLock::new(|| {
+    format!(
+        r#"
+        SELECT dropbox_id
+        FROM files
+        WHERE content_hash = ?1 AND dropbox_id != ?2 AND {FILED}
+        ORDER BY rowid ASC
+        LIMIT 1
+        "#
+    )
+});
+
+/// Sets the metadata of a file, bound by [`bind_metadata`] as `?1` to `?12`.
+const SET_METADATA: &str = r#"
+    title = ?1,
+    authors = ?2,
+    summary = ?3,
+    abstract_text = ?4,
+    tags = ?5,
+    extraction_quality = ?6,
+    doi = ?7,
+    arxiv_id = ?8,
+    key_findings = ?9,
+    prompt_tokens = ?10,
+    completion_tokens = ?11,
+    extracted_text_hash = ?12
+"#;
+
+/// Bind the metadata of a query setting [`SET_METADATA`].
+fn bind_metadata<'q>(
+    query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    meta: ArticleMetadata,
+) -> Result<Query<'q, Sqlite, SqliteArguments<'q>>> {
+    let token_usage = meta.token_usage;
+    Ok(query
+        .bind(meta.title)
+        .bind(serde_json::to_string(&meta.authors)?)
+        .bind(meta.summary.0)
+        .bind(meta.abstract_text)
+        .bind(serde_json::to_string(&meta.tags)?)
+        .bind(meta.extraction_quality)
+        .bind(meta.doi)
+        .bind(meta.arxiv_id)
+        .bind(serde_json::to_string(&meta.key_findings)?)
+        .bind(token_usage.map(|usage| usage.prompt_tokens as i64))
+        .bind(token_usage.map(|usage| usage.completion_tokens as i64))
+        .bind(meta.extracted_text_hash))
+}
+
+/// Which files [`Storage::list_files`] lists. The default lists all files.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ListFilter<'a> {
+    /// Only files with this tag
+    pub tag: Option<&'a str>,
+    /// Only files whose text extracted worse than this
+    pub below_extraction_quality: Option<f32>,
+    /// Only files whose extracted text changed since they were processed in this run
+    pub text_changed_since: Option<&'a RunId>,
+}
+
+/// The condition for a [`ListFilter`], bound as `?1` (tag), `?2` (quality) and `?3` (run).
+const LIST_FILTER: &str = r#"
+    (?1 IS NULL
+        OR EXISTS (SELECT 1 FROM json_each(files.tags) WHERE lower(value) = lower(?1)))
+    AND (?2 IS NULL OR files.extraction_quality < ?2)
+    AND (?3 IS NULL
+        OR EXISTS (SELECT 1 FROM extracted_text_hashes h
+                   WHERE h.dropbox_id = files.dropbox_id AND h.run_id = ?3
+                     AND h.text_hash IS NOT files.extracted_text_hash))
+"#;
+
+/// The order [`Storage::get_pending_files_in_order`] gets pending files in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PendingOrder {
+    /// Most recently synced first
+    #[default]
+    Synced,
+    /// Largest first
+    Size,
+    /// Most recently changed in Dropbox first
+    Date,
+}
+
+impl PendingOrder {
+    fn order_by(self) -> &'static str {
+        match self {
+            PendingOrder::Synced => "updated_at DESC, dropbox_id ASC",
+            PendingOrder::Size => "size DESC NULLS LAST, dropbox_id ASC",
+            PendingOrder::Date => "server_modified DESC NULLS LAST, dropbox_id ASC",
+        }
+    }
+}
+
+/// What [`Storage::upsert_inbox_file`] does with a known file whose content has changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OnContentChange {
+    /// Process the file again, whatever became of it before
+    #[default]
+    Reprocess,
+    /// Process the file again, unless it was archived or skipped, which is taken as final
+    KeepTerminal,
+}
 
 pub struct Storage {
     pool: SqlitePool,
+    on_content_change: OnContentChange,
 }
 
 impl Storage {
     pub fn new(pool: SqlitePool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            on_content_change: OnContentChange::default(),
+        }
     }
 
+    pub fn with_on_content_change(mut self, on_content_change: OnContentChange) -> Self {
+        self.on_content_change = on_content_change;
+        self
+    }
+
+    /// Record the metadata of a file filed at the given targets, now.
     pub async fn update_metadata(
         &self,
         id: &DropboxId,
         meta: ArticleMetadata,
+        target_paths: &[RemotePath],
         status: FileStatus,
     ) -> Result<()> {
-        let authors_json = serde_json::to_string(&meta.authors)?;
+        let sql = format!(
+            r#"
+            UPDATE files
+            SET {SET_METADATA},
+                status = ?13,
+                target_path = ?14,
+                pending_target_path = NULL,
+                updated_at = ?15,
+                processed_at = ?15
+            WHERE dropbox_id = ?16
+            "#
+        );
+        bind_metadata(sqlx::query(&sql), meta)?
+            .bind(status)
+            .bind(serde_json::to_string(target_paths)?)
+            .bind(Utc::now())
+            .bind(&id.0)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Record the metadata of a file about to be uploaded to the given targets, as
+    /// [`FileStatus::Uploading`]. The targets only become its target paths once the uploads
+    /// are done, see [`Storage::settle_upload`], so until then it is not listed as filed there.
+    pub async fn record_upload_intent(
+        &self,
+        id: &DropboxId,
+        meta: ArticleMetadata,
+        target_paths: &[RemotePath],
+    ) -> Result<()> {
+        let sql = format!(
+            r#"
+            UPDATE files
+            SET {SET_METADATA},
+                status = ?13,
+                pending_target_path = ?14,
+                updated_at = ?15
+            WHERE dropbox_id = ?16
+            "#
+        );
+        bind_metadata(sqlx::query(&sql), meta)?
+            .bind(FileStatus::Uploading)
+            .bind(serde_json::to_string(target_paths)?)
+            .bind(Utc::now())
+            .bind(&id.0)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Settle a file left [`FileStatus::Uploading`]: processed, filed at the targets it was
+    /// being uploaded to, if `uploaded`, and pending again otherwise.
+    pub async fn settle_upload(&self, id: &DropboxId, uploaded: bool) -> Result<()> {
         sqlx::query(
             r#"
-            UPDATE files 
-            SET status = ?1, 
-                title = ?2, 
-                authors = ?3, 
-                summary = ?4, 
-                updated_at = ?5 
-            WHERE dropbox_id = ?6
+            UPDATE files
+            SET status = CASE WHEN ?1 THEN ?2 ELSE ?3 END,
+                target_path = CASE WHEN ?1 THEN pending_target_path ELSE target_path END,
+                processed_at = CASE WHEN ?1 THEN ?4 ELSE processed_at END,
+                pending_target_path = NULL,
+                updated_at = ?4
+            WHERE dropbox_id = ?5
             "#,
         )
-        .bind(status)
-        .bind(meta.title)
-        .bind(authors_json)
-        .bind(meta.summary.0)
+        .bind(uploaded)
+        .bind(FileStatus::Processed)
+        .bind(FileStatus::Pending)
         .bind(Utc::now())
         .bind(&id.0)
         .execute(&self.pool)
@@ -47,18 +282,45 @@ impl Storage {
         file_name: &str,
         hash: &FileHash,
     ) -> Result<()> {
+        self.upsert_inbox_file(id, file_name, hash, None).await
+    }
+
+    /// Like [`Storage::upsert_file`], also recording the inbox folder the file was found in.
+    ///
+    /// Dropbox gives a file moved between folders a new id, so a paper already recorded may
+    /// turn up again under another id. A new or changed file with the content of a filed
+    /// file is skipped as a duplicate of it (see [`Storage::find_duplicate`]) instead of
+    /// being processed again. Recording the file and skipping it is one transaction.
+    ///
+    /// A known file whose content has changed is pending again, unless it was archived or
+    /// skipped and the storage keeps those, see [`OnContentChange`].
+    pub async fn upsert_inbox_file(
+        &self,
+        id: &DropboxId,
+        file_name: &str,
+        hash: &FileHash,
+        source_folder: Option<&str>,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
         sqlx::query(
             r#"
-            INSERT INTO files (dropbox_id, file_name, content_hash, status, updated_at)
-            VALUES (?1, ?2, ?3, ?4, ?5)
+            INSERT INTO files (dropbox_id, file_name, content_hash, status, updated_at, source_folder)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
             ON CONFLICT(dropbox_id) DO UPDATE SET
                 file_name = excluded.file_name,
                 content_hash = excluded.content_hash,
                 status = CASE 
-                    WHEN files.content_hash != excluded.content_hash THEN ?4
+                    WHEN files.content_hash != excluded.content_hash
+                        AND NOT (?7 AND files.status IN ('ARCHIVED', 'SKIPPED')) THEN ?4
                     ELSE files.status
                 END,
-                updated_at = excluded.updated_at
+                skip_reason = CASE
+                    WHEN files.content_hash != excluded.content_hash
+                        AND NOT (?7 AND files.status IN ('ARCHIVED', 'SKIPPED')) THEN NULL
+                    ELSE files.skip_reason
+                END,
+                updated_at = excluded.updated_at,
+                source_folder = COALESCE(excluded.source_folder, files.source_folder)
             "#,
         )
         .bind(&id.0)
@@ -66,37 +328,79 @@ impl Storage {
         .bind(&hash.0)
         .bind(FileStatus::Pending)
         .bind(Utc::now())
-        .execute(&self.pool)
+        .bind(source_folder)
+        .bind(self.on_content_change == OnContentChange::KeepTerminal)
+        .execute(&mut *tx)
         .await?;
+
+        // Fake and some listed entries have no content hash, which says nothing
+        if !hash.0.is_empty() {
+            let status: Option<FileStatus> =
+                sqlx::query_scalar("SELECT status FROM files WHERE dropbox_id = ?1")
+                    .bind(&id.0)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+            let original = match status {
+                Some(FileStatus::Pending) => sqlx::query_scalar::<_, String>(&FIND_DUPLICATE)
+                    .bind(&hash.0)
+                    .bind(&id.0)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .map(DropboxId),
+                _ => None,
+            };
+            if let Some(original) = original {
+                let reason = SkipReason::Duplicate { of: original };
+                sqlx::query(MARK_SKIPPED)
+                    .bind(FileStatus::Skipped)
+                    .bind(reason.to_string())
+                    .bind(Utc::now())
+                    .bind(&id.0)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        tx.commit().await?;
         Ok(())
     }
 
     pub async fn get_pending_files(&self, limit: i64) -> Result<Vec<FileRecord>> {
-        let records = sqlx::query_as::<_, FileRecord>(
-            r#"
-            SELECT 
-                dropbox_id,
-                file_name,
-                content_hash,
-                status,
-                title,
-                authors,
-                summary,
-                target_path,
-                last_error,
-                updated_at
+        self.get_pending_files_in_order(limit, PendingOrder::default())
+            .await
+    }
+
+    /// Like [`Storage::get_pending_files`], in the given order.
+    pub async fn get_pending_files_in_order(
+        &self,
+        limit: i64,
+        order: PendingOrder,
+    ) -> Result<Vec<FileRecord>> {
+        let records = sqlx::query_as::<_, FileRecord>(&format!(
+            r#"
+            SELECT {FILE_RECORD_COLUMNS}
             FROM files
             WHERE status = 'PENDING'
-            ORDER BY updated_at DESC
+            ORDER BY {}
             LIMIT ?1
             "#,
-        )
+            order.order_by()
+        ))
         .bind(limit)
         .fetch_all(&self.pool)
         .await?;
         Ok(records)
     }
 
+    pub async fn get_file(&self, id: &DropboxId) -> Result<Option<FileRecord>> {
+        let record = sqlx::query_as::<_, FileRecord>(&format!(
+            "SELECT {FILE_RECORD_COLUMNS} FROM files WHERE dropbox_id = ?1"
+        ))
+        .bind(&id.0)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(record)
+    }
+
     pub async fn update_status(&self, id: &DropboxId, status: FileStatus) -> Result<()> {
         sqlx::query("UPDATE files SET status = ?1, updated_at = ?2 WHERE dropbox_id = ?3")
             .bind(status)
@@ -107,28 +411,650 @@ impl Storage {
         Ok(())
     }
 
-    pub async fn get_files_in_folder(&self, folder: &str) -> Result<Vec<FileRecord>> {
-        let records = sqlx::query_as::<_, FileRecord>(
-            r#"
-            SELECT 
-                dropbox_id,
-                file_name,
-                content_hash,
-                status,
-                title,
-                authors,
-                summary,
-                target_path,
-                last_error,
-                updated_at
+    /// The size of the database in bytes, i.e. its pages in use and free.
+    pub async fn database_size(&self) -> Result<u64> {
+        let page_count: i64 = sqlx::query_scalar("PRAGMA page_count")
+            .fetch_one(&self.pool)
+            .await?;
+        let page_size: i64 = sqlx::query_scalar("PRAGMA page_size")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok((page_count * page_size) as u64)
+    }
+
+    /// Every migration of the database and whether it has been applied.
+    pub async fn migration_status(&self) -> Result<Vec<MigrationStatus>> {
+        crate::migration_status(&self.pool).await
+    }
+
+    /// Apply the pending migrations, see [`crate::apply_migrations`].
+    pub async fn apply_migrations(&self) -> Result<()> {
+        crate::apply_migrations(&self.pool).await
+    }
+
+    /// Rebuild the database to reclaim the space of deleted and rewritten rows, and let
+    /// SQLite update its query planner statistics.
+    pub async fn vacuum(&self) -> Result<()> {
+        sqlx::query("VACUUM").execute(&self.pool).await?;
+        sqlx::query("PRAGMA optimize").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    /// Record the rules in effect for a run.
+    pub async fn snapshot_rules(&self, rules: &Rules, run_id: &RunId) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for (position, rule) in rules.0.iter().enumerate() {
+            sqlx::query(
+                r#"
+                INSERT OR REPLACE INTO rules (
+                    run_id, position, name, description, path, examples, negative_keywords,
+                    keywords, parent
+                )
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                "#,
+            )
+            .bind(run_id)
+            .bind(position as i64)
+            .bind(&rule.name)
+            .bind(&rule.description)
+            .bind(&rule.path)
+            .bind(serde_json::to_string(&rule.examples)?)
+            .bind(serde_json::to_string(&rule.negative_keywords)?)
+            .bind(serde_json::to_string(&rule.keywords)?)
+            .bind(&rule.parent)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Get the rules that were in effect for a run, in their original order.
+    pub async fn rules_for_run(&self, run_id: &RunId) -> Result<Rules> {
+        let rows = sqlx::query_as::<
+            _,
+            (
+                String,
+                String,
+                RemotePath,
+                Option<String>,
+                Option<String>,
+                Option<String>,
+                Option<String>,
+            ),
+        >(
+            r#"
+            SELECT name, description, path, examples, negative_keywords, keywords, parent
+            FROM rules
+            WHERE run_id = ?1
+            ORDER BY position ASC
+            "#,
+        )
+        .bind(run_id)
+        .fetch_all(&self.pool)
+        .await?;
+        let json_list = |json: Option<String>| {
+            json.as_deref()
+                .and_then(|json| serde_json::from_str(json).ok())
+                .unwrap_or_default()
+        };
+        Ok(Rules(
+            rows.into_iter()
+                .map(
+                    |(name, description, path, examples, negative_keywords, keywords, parent)| {
+                        Rule {
+                            name,
+                            description,
+                            path,
+                            examples: json_list(examples),
+                            negative_keywords: json_list(negative_keywords),
+                            keywords: json_list(keywords),
+                            parent,
+                        }
+                    },
+                )
+                .collect(),
+        ))
+    }
+
+    /// Read the rules recorded for every run, ordered by run, e.g. for a backup.
+    pub async fn export_rules_snapshots(&self) -> Result<Vec<RulesSnapshot>> {
+        let run_ids =
+            sqlx::query_scalar::<_, RunId>("SELECT DISTINCT run_id FROM rules ORDER BY run_id ASC")
+                .fetch_all(&self.pool)
+                .await?;
+        let mut snapshots = Vec::with_capacity(run_ids.len());
+        for run_id in run_ids {
+            let rules = self.rules_for_run(&run_id).await?;
+            snapshots.push(RulesSnapshot { run_id, rules });
+        }
+        Ok(snapshots)
+    }
+
+    /// Restore the rules recorded for runs, e.g. from a backup. Existing rules of the same
+    /// runs are replaced.
+    pub async fn import_rules_snapshots(&self, snapshots: &[RulesSnapshot]) -> Result<()> {
+        for snapshot in snapshots {
+            sqlx::query("DELETE FROM rules WHERE run_id = ?1")
+                .bind(&snapshot.run_id)
+                .execute(&self.pool)
+                .await?;
+            self.snapshot_rules(&snapshot.rules, &snapshot.run_id)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Read the text hashes recorded for every file and run, e.g. for a backup.
+    pub async fn export_extracted_text_hashes(&self) -> Result<Vec<ExtractedTextHash>> {
+        let hashes = sqlx::query_as::<_, ExtractedTextHash>(
+            r#"
+            SELECT dropbox_id, run_id, text_hash
+            FROM extracted_text_hashes
+            ORDER BY dropbox_id ASC, run_id ASC
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(hashes)
+    }
+
+    /// Restore the text hashes recorded for files and runs, e.g. from a backup. Existing
+    /// hashes of the same file and run are overwritten.
+    pub async fn import_extracted_text_hashes(&self, hashes: &[ExtractedTextHash]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for hash in hashes {
+            sqlx::query(
+                r#"
+                INSERT OR REPLACE INTO extracted_text_hashes (dropbox_id, run_id, text_hash)
+                VALUES (?1, ?2, ?3)
+                "#,
+            )
+            .bind(&hash.dropbox_id.0)
+            .bind(&hash.run_id)
+            .bind(&hash.text_hash)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Record the run that processed a file, and the hash of the text extracted from it in
+    /// that run, to tell later runs whose text changed (see [`ListFilter::text_changed_since`]).
+    pub async fn set_run_id(&self, id: &DropboxId, run_id: &RunId) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE files SET run_id = ?1 WHERE dropbox_id = ?2")
+            .bind(run_id)
+            .bind(&id.0)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO extracted_text_hashes (dropbox_id, run_id, text_hash)
+            SELECT dropbox_id, ?1, extracted_text_hash
             FROM files
-            WHERE target_path LIKE ?1
-            ORDER BY title ASC
+            WHERE dropbox_id = ?2 AND extracted_text_hash IS NOT NULL
+            "#,
+        )
+        .bind(run_id)
+        .bind(&id.0)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Record the size and modification time Dropbox listed for a file.
+    pub async fn set_listing_details(
+        &self,
+        id: &DropboxId,
+        size: u64,
+        server_modified: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        sqlx::query("UPDATE files SET size = ?1, server_modified = ?2 WHERE dropbox_id = ?3")
+            .bind(size as i64)
+            .bind(server_modified)
+            .bind(&id.0)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Mark a pending file as handed to a worker. Returns whether it was still pending, and so
+    /// is now claimed by the caller rather than by another batch.
+    pub async fn mark_in_progress(&self, id: &DropboxId) -> Result<bool> {
+        let now = Utc::now();
+        let result = sqlx::query(
+            r#"
+            UPDATE files SET status = ?1, started_at = ?2, updated_at = ?2
+            WHERE dropbox_id = ?3 AND status = ?4
             "#,
         )
-        .bind(format!("%{}%", folder)) // Simple match for now
+        .bind(FileStatus::InProgress)
+        .bind(now)
+        .bind(&id.0)
+        .bind(FileStatus::Pending)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Reset files that were handed to a worker before `started_before`, but never finished,
+    /// e.g. because the run was killed, back to pending. Returns the number of files reset.
+    pub async fn reclaim_in_progress(&self, started_before: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE files
+            SET status = ?1, updated_at = ?2
+            WHERE status = ?3 AND (started_at IS NULL OR started_at < ?4)
+            "#,
+        )
+        .bind(FileStatus::Pending)
+        .bind(Utc::now())
+        .bind(FileStatus::InProgress)
+        .bind(started_before)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Record the metadata of a file that needs review, along with the names of the rules
+    /// it may be filed under and why it needs review. It is not filed anywhere, so it has
+    /// no targets and is not stamped as processed.
+    pub async fn mark_needs_review(
+        &self,
+        id: &DropboxId,
+        meta: ArticleMetadata,
+        candidates: &[String],
+        reason: &str,
+    ) -> Result<()> {
+        let sql = format!(
+            r#"
+            UPDATE files
+            SET {SET_METADATA},
+                status = ?13,
+                target_path = '[]',
+                pending_target_path = NULL,
+                review_candidates = ?14,
+                last_error = ?15,
+                updated_at = ?16
+            WHERE dropbox_id = ?17
+            "#
+        );
+        bind_metadata(sqlx::query(&sql), meta)?
+            .bind(FileStatus::NeedsReview)
+            .bind(serde_json::to_string(candidates)?)
+            .bind(reason)
+            .bind(Utc::now())
+            .bind(&id.0)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Get the files with the given status, ordered by title.
+    pub async fn get_files_with_status(&self, status: FileStatus) -> Result<Vec<FileRecord>> {
+        let records = sqlx::query_as::<_, FileRecord>(&format!(
+            "SELECT {FILE_RECORD_COLUMNS} FROM files WHERE status = ?1 ORDER BY title ASC"
+        ))
+        .bind(status)
         .fetch_all(&self.pool)
         .await?;
         Ok(records)
     }
+
+    /// The number of files with each status, for the statuses any file has.
+    pub async fn count_by_status(&self) -> Result<Vec<(FileStatus, u64)>> {
+        let counts = sqlx::query_as::<_, (FileStatus, i64)>(
+            "SELECT status, COUNT(*) FROM files GROUP BY status ORDER BY status",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(counts
+            .into_iter()
+            .map(|(status, count)| (status, count as u64))
+            .collect())
+    }
+
+    /// Mark a file as skipped, recording why as its skip reason and last error.
+    pub async fn mark_skipped(&self, id: &DropboxId, reason: &str) -> Result<()> {
+        sqlx::query(MARK_SKIPPED)
+            .bind(FileStatus::Skipped)
+            .bind(reason)
+            .bind(Utc::now())
+            .bind(&id.0)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Mark a file processed again without changes as processed, keeping its metadata and
+    /// setting its update time back to `updated_at`, when it last changed. The hash of the
+    /// extracted text is recorded, as the text may have changed without changing the metadata,
+    /// and so are the tokens the LLM used to find that out.
+    pub async fn mark_unchanged(
+        &self,
+        id: &DropboxId,
+        updated_at: DateTime<Utc>,
+        text_hash: Option<&str>,
+        token_usage: Option<TokenUsage>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE files
+            SET status = ?1,
+                updated_at = ?2,
+                extracted_text_hash = COALESCE(?3, extracted_text_hash),
+                prompt_tokens = COALESCE(?4, prompt_tokens),
+                completion_tokens = COALESCE(?5, completion_tokens)
+            WHERE dropbox_id = ?6
+            "#,
+        )
+        .bind(FileStatus::Processed)
+        .bind(updated_at)
+        .bind(text_hash)
+        .bind(token_usage.map(|usage| usage.prompt_tokens as i64))
+        .bind(token_usage.map(|usage| usage.completion_tokens as i64))
+        .bind(&id.0)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Mark a file as failed, recording the error as its last error. Its targets are
+    /// cleared, as the failure may have come part way through uploading to them.
+    pub async fn mark_error(&self, id: &DropboxId, error: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE files
+            SET status = ?1,
+                last_error = ?2,
+                updated_at = ?3,
+                target_path = NULL,
+                pending_target_path = NULL,
+                processed_at = NULL
+            WHERE dropbox_id = ?4
+            "#,
+        )
+        .bind(FileStatus::Error)
+        .bind(error)
+        .bind(Utc::now())
+        .bind(&id.0)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The at most `limit` files that failed most recently, most recent first.
+    pub async fn recent_errors(&self, limit: i64) -> Result<Vec<FileRecord>> {
+        let records = sqlx::query_as::<_, FileRecord>(&format!(
+            r#"
+            SELECT {FILE_RECORD_COLUMNS}
+            FROM files
+            WHERE status = ?1
+            ORDER BY updated_at DESC, dropbox_id ASC
+            LIMIT ?2
+            "#
+        ))
+        .bind(FileStatus::Error)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(records)
+    }
+
+    /// The number of skipped files of each kind of [`SkipReason`], by kind.
+    pub async fn count_skipped_by_reason(&self) -> Result<Vec<(String, u64)>> {
+        let reasons = sqlx::query_scalar::<_, Option<String>>(
+            "SELECT skip_reason FROM files WHERE status = ?1",
+        )
+        .bind(FileStatus::Skipped)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut counts = BTreeMap::new();
+        for reason in &reasons {
+            let kind = SkipReason::kind_of(reason.as_deref().unwrap_or("unknown"));
+            *counts.entry(kind.to_string()).or_insert(0) += 1;
+        }
+        Ok(counts.into_iter().collect())
+    }
+
+    /// Another file with the given content that was filed, i.e. processed or archived. A
+    /// copy of a file that failed or is still waiting is not a duplicate, as the copy may
+    /// well get filed. The file recorded first is preferred.
+    pub async fn find_duplicate(
+        &self,
+        id: &DropboxId,
+        hash: &FileHash,
+    ) -> Result<Option<DropboxId>> {
+        let duplicate = sqlx::query_scalar::<_, String>(&FIND_DUPLICATE)
+            .bind(&hash.0)
+            .bind(&id.0)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(duplicate.map(DropboxId))
+    }
+
+    /// Get the files with a target directly in the given folder.
+    pub async fn get_files_in_folder(&self, folder: &str) -> Result<Vec<FileRecord>> {
+        self.stream_files_in_folder(folder).try_collect().await
+    }
+
+    /// Like [`Storage::get_files_in_folder`], but one file at a time, for folders too large
+    /// to hold all their records in memory.
+    pub fn stream_files_in_folder<'a>(
+        &'a self,
+        folder: &'a str,
+    ) -> BoxStream<'a, Result<FileRecord>> {
+        sqlx::query_as::<_, FileRecord>(&FILES_IN_FOLDER_QUERY)
+            .bind(format!("%{}/%", folder))
+            .fetch(&self.pool)
+            .map_err(anyhow::Error::from)
+            .try_filter(move |record| future::ready(record.target_in_folder(folder).is_some()))
+            .boxed()
+    }
+
+    /// Get the `limit` filed files with a target directly in the given folder that were
+    /// processed last, most recently processed first.
+    pub async fn get_recent_files_in_folder(
+        &self,
+        folder: &str,
+        limit: usize,
+    ) -> Result<Vec<FileRecord>> {
+        sqlx::query_as::<_, FileRecord>(&format!(
+            r#"
+            SELECT {FILE_RECORD_COLUMNS}
+            FROM files
+            WHERE target_path LIKE ?1 AND processed_at IS NOT NULL AND {FILED}
+            ORDER BY processed_at DESC, dropbox_id ASC
+            "#
+        ))
+        .bind(format!("%{}/%", folder))
+        .fetch(&self.pool)
+        .map_err(anyhow::Error::from)
+        .try_filter(|record| future::ready(record.target_in_folder(folder).is_some()))
+        .take(limit)
+        .try_collect()
+        .await
+    }
+
+    /// Get the distinct folders that files have been filed into. Folders differing only by
+    /// case are the same folder in Dropbox, so only the first spelling seen is returned.
+    pub async fn get_target_folders(&self) -> Result<Vec<String>> {
+        let target_paths: Vec<String> = sqlx::query_scalar(&format!(
+            "SELECT DISTINCT target_path FROM files WHERE target_path IS NOT NULL AND {FILED}"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+        let mut folders = BTreeMap::new();
+        for folder in target_paths
+            .iter()
+            .flat_map(|json| serde_json::from_str::<Vec<RemotePath>>(json).unwrap_or_default())
+            .filter_map(|path| path.parent())
+        {
+            folders.entry(folder.comparison_key()).or_insert(folder.0);
+        }
+        Ok(folders.into_values().collect())
+    }
+
+    /// Get the processed files, most recently processed first, optionally only those
+    /// processed at or after `since`.
+    pub async fn get_processed_since(
+        &self,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<Vec<FileRecord>> {
+        let records = sqlx::query_as::<_, FileRecord>(&format!(
+            r#"
+            SELECT {FILE_RECORD_COLUMNS}
+            FROM files
+            WHERE processed_at IS NOT NULL
+              AND {FILED}
+              AND (?1 IS NULL OR processed_at >= ?1)
+            ORDER BY processed_at DESC
+            "#
+        ))
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(records)
+    }
+
+    /// List all files, optionally only those with the given tag (compared case-insensitively).
+    /// Ordered by title, then id, so pages of the listing never overlap.
+    /// Without a limit, all files from the offset are listed.
+    pub async fn list_files(
+        &self,
+        filter: &ListFilter<'_>,
+        limit: Option<u32>,
+        offset: u32,
+    ) -> Result<Vec<FileRecord>> {
+        let records = sqlx::query_as::<_, FileRecord>(&format!(
+            r#"
+            SELECT {FILE_RECORD_COLUMNS}
+            FROM files
+            WHERE {LIST_FILTER}
+            ORDER BY title ASC, dropbox_id ASC
+            LIMIT ?4 OFFSET ?5
+            "#
+        ))
+        .bind(filter.tag)
+        .bind(filter.below_extraction_quality)
+        .bind(filter.text_changed_since)
+        .bind(limit.map(i64::from).unwrap_or(-1))
+        .bind(i64::from(offset))
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(records)
+    }
+
+    /// Count the files [`Storage::list_files`] would list without a limit.
+    pub async fn count_files(&self, filter: &ListFilter<'_>) -> Result<u64> {
+        let count: i64 =
+            sqlx::query_scalar(&format!("SELECT COUNT(*) FROM files WHERE {LIST_FILTER}"))
+                .bind(filter.tag)
+                .bind(filter.below_extraction_quality)
+                .bind(filter.text_changed_since)
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(count as u64)
+    }
+
+    /// Read all file records, e.g. for a backup.
+    pub async fn export_all(&self) -> Result<Vec<FileRecord>> {
+        let records = sqlx::query_as::<_, FileRecord>(&format!(
+            r#"
+            SELECT {FILE_RECORD_COLUMNS}
+            FROM files
+            ORDER BY dropbox_id ASC
+            "#
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(records)
+    }
+
+    /// Restore file records, e.g. from a backup. Existing records with the same Dropbox id
+    /// are overwritten. Returns the number of records restored.
+    pub async fn import_all(&self, records: &[FileRecord]) -> Result<usize> {
+        let mut tx = self.pool.begin().await?;
+        for record in records {
+            sqlx::query(
+                r#"
+                INSERT INTO files (
+                    dropbox_id, file_name, content_hash, status, title, authors,
+                    summary, abstract_text, target_path, last_error, updated_at,
+                    processed_at, started_at, tags, review_candidates, run_id, source_folder,
+                    extraction_quality, doi, arxiv_id, skip_reason, key_findings,
+                    prompt_tokens, completion_tokens, size, server_modified, extracted_text_hash,
+                    pending_target_path
+                )
+                VALUES (
+                    ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18,
+                    ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28
+                )
+                ON CONFLICT(dropbox_id) DO UPDATE SET
+                    file_name = excluded.file_name,
+                    content_hash = excluded.content_hash,
+                    status = excluded.status,
+                    title = excluded.title,
+                    authors = excluded.authors,
+                    summary = excluded.summary,
+                    abstract_text = excluded.abstract_text,
+                    target_path = excluded.target_path,
+                    last_error = excluded.last_error,
+                    updated_at = excluded.updated_at,
+                    processed_at = excluded.processed_at,
+                    started_at = excluded.started_at,
+                    tags = excluded.tags,
+                    review_candidates = excluded.review_candidates,
+                    run_id = excluded.run_id,
+                    source_folder = excluded.source_folder,
+                    extraction_quality = excluded.extraction_quality,
+                    doi = excluded.doi,
+                    arxiv_id = excluded.arxiv_id,
+                    skip_reason = excluded.skip_reason,
+                    key_findings = excluded.key_findings,
+                    prompt_tokens = excluded.prompt_tokens,
+                    completion_tokens = excluded.completion_tokens,
+                    size = excluded.size,
+                    server_modified = excluded.server_modified,
+                    extracted_text_hash = excluded.extracted_text_hash,
+                    pending_target_path = excluded.pending_target_path
+                "#,
+            )
+            .bind(&record.dropbox_id.0)
+            .bind(&record.file_name)
+            .bind(&record.content_hash.0)
+            .bind(&record.status)
+            .bind(&record.title)
+            .bind(&record.authors)
+            .bind(&record.summary)
+            .bind(&record.abstract_text)
+            .bind(&record.target_path)
+            .bind(&record.last_error)
+            .bind(record.updated_at)
+            .bind(record.processed_at)
+            .bind(record.started_at)
+            .bind(&record.tags)
+            .bind(&record.review_candidates)
+            .bind(&record.run_id)
+            .bind(&record.source_folder)
+            .bind(record.extraction_quality)
+            .bind(&record.doi)
+            .bind(&record.arxiv_id)
+            .bind(&record.skip_reason)
+            .bind(&record.key_findings)
+            .bind(record.prompt_tokens)
+            .bind(record.completion_tokens)
+            .bind(record.size)
+            .bind(record.server_modified)
+            .bind(&record.extracted_text_hash)
+            .bind(&record.pending_target_path)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(records.len())
+    }
 }
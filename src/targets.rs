@@ -0,0 +1,300 @@
+use crate::models::{ArticleMetadata, RemotePath};
+use std::collections::HashSet;
+
+/// Segment used in place of a placeholder that cannot be resolved from the metadata.
+pub const UNKNOWN_SEGMENT: &str = "_unknown";
+
+/// Resolve the placeholders in a rule target to a concrete folder for one paper.
+///
+/// Supported placeholders are `{{year}}` and `{{first_author}}` (the surname of the first
+/// author). Placeholders that cannot be resolved are replaced by [`UNKNOWN_SEGMENT`].
+/// Targets without placeholders are returned unchanged, apart from any trailing slash.
+pub fn resolve_target_folder(target: &RemotePath, meta: &ArticleMetadata) -> RemotePath {
+    let mut resolved = String::with_capacity(target.0.len());
+    let mut rest = target.0.as_str();
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        resolved.push_str(&rest[..start]);
+        let placeholder = rest[start + 2..start + end].trim();
+        let value = resolve_placeholder(placeholder, meta)
+            .map(|v| sanitize_segment(&v))
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| UNKNOWN_SEGMENT.to_string());
+        resolved.push_str(&value);
+        rest = &rest[start + end + 2..];
+    }
+    resolved.push_str(rest);
+
+    let trimmed = resolved.trim_end_matches('/');
+    RemotePath(if trimmed.is_empty() {
+        resolved
+    } else {
+        trimmed.to_string()
+    })
+}
+
+/// The part of a rule target before its first placeholder, i.e. the folder that exists
+/// independently of any paper's metadata.
+pub fn static_prefix(target: &RemotePath) -> RemotePath {
+    let prefix = match target.0.find("{{") {
+        Some(start) => &target.0[..start],
+        None => target.0.as_str(),
+    };
+    RemotePath(prefix.trim_end_matches('/').to_string())
+}
+
+/// Extensions of files that a rule target may mistakenly end with, instead of a folder.
+const FILE_EXTENSIONS: &[&str] = &["pdf", "ps", "djvu", "epub", "html", "txt", "md", "zip"];
+
+/// Guess the extension of a file from its first bytes, for the formats papers come in.
+pub fn sniff_extension(content: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"%PDF-", "pdf"),
+        (b"%!PS", "ps"),
+        (b"AT&TFORM", "djvu"),
+        (b"PK\x03\x04", "zip"),
+        (b"\x1f\x8b", "gz"),
+        (b"\x89PNG", "png"),
+        (b"\xff\xd8\xff", "jpg"),
+    ];
+    if crate::extract::is_epub(content) {
+        return Some("epub");
+    }
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| content.starts_with(signature))
+        .map(|(_, extension)| *extension)
+}
+
+/// The name a file is uploaded under: its original name made safe as a path segment, or
+/// `fallback_stem` if it has none. The extension sniffed from the content is added if the
+/// name has no extension of its own.
+pub fn remote_file_name(original: Option<&str>, fallback_stem: &str, content: &[u8]) -> String {
+    let name = original
+        .map(|name| {
+            sanitize_segment(name)
+                .chars()
+                .filter(|c| !c.is_control())
+                .collect::<String>()
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| fallback_stem.to_string());
+    match (extension(&name), sniff_extension(content)) {
+        (None, Some(sniffed)) => format!("{}.{}", name, sniffed),
+        _ => name,
+    }
+}
+
+/// The path to upload a file to in a resolved rule target folder. A target that ends in a
+/// file name, e.g. `/sorted/ai/paper.pdf`, is taken to mean the folder it is in.
+pub fn target_file_path(folder: &RemotePath, file_name: &str) -> RemotePath {
+    let is_file = folder
+        .file_name()
+        .and_then(extension)
+        .is_some_and(|ext| FILE_EXTENSIONS.contains(&ext.to_lowercase().as_str()));
+    match folder.parent() {
+        Some(parent) if is_file => parent.join(file_name),
+        _ => folder.join(file_name),
+    }
+}
+
+/// The extension of a file name, if it has one.
+pub fn extension(name: &str) -> Option<&str> {
+    match name.rsplit_once('.') {
+        Some((stem, ext))
+            if !stem.is_empty() && !ext.is_empty() && ext.chars().all(|c| c.is_alphanumeric()) =>
+        {
+            Some(ext)
+        }
+        _ => None,
+    }
+}
+
+/// Where sidecars are uploaded relative to the PDFs they describe.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SidecarPlacement {
+    /// Next to the PDF, `<folder>/<file>.md`
+    #[default]
+    Alongside,
+    /// In a subfolder of the PDF's folder, `<folder>/_meta/<file>.md`
+    Subfolder,
+}
+
+/// The subfolder sidecars are uploaded to with [`SidecarPlacement::Subfolder`].
+pub const SIDECAR_SUBFOLDER: &str = "_meta";
+
+/// The path of a sidecar with the given extension for a PDF filed at `target`.
+pub fn sidecar_file_path(
+    target: &RemotePath,
+    extension: &str,
+    placement: SidecarPlacement,
+) -> RemotePath {
+    match (placement, target.parent(), target.file_name()) {
+        (SidecarPlacement::Subfolder, Some(folder), Some(name)) => folder
+            .join(SIDECAR_SUBFOLDER)
+            .join(&format!("{}.{}", name, extension)),
+        _ => RemotePath(format!("{}.{}", target.0, extension)),
+    }
+}
+
+/// Remove targets that refer to the same Dropbox location, keeping the first spelling.
+/// Dropbox paths are case-insensitive, so `/out/AI/paper.pdf` and `/out/ai/paper.pdf` collide.
+pub fn dedup_targets(targets: Vec<RemotePath>) -> Vec<RemotePath> {
+    let mut seen = HashSet::new();
+    targets
+        .into_iter()
+        .filter(|target| seen.insert(target.comparison_key()))
+        .collect()
+}
+
+fn resolve_placeholder(placeholder: &str, meta: &ArticleMetadata) -> Option<String> {
+    match placeholder {
+        "year" => meta.year.map(|y| y.to_string()),
+        "first_author" => meta
+            .authors
+            .first()
+            .and_then(|name| name.split_whitespace().last())
+            .map(String::from),
+        _ => {
+            tracing::warn!(
+                "Unknown placeholder in rule target: {{{{{}}}}}",
+                placeholder
+            );
+            None
+        }
+    }
+}
+
+/// Make a metadata value safe to use as a single path segment.
+fn sanitize_segment(value: &str) -> String {
+    value
+        .trim()
+        .replace(['/', '\\', ':'], "-")
+        .trim_matches('.')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(year: Option<i32>, authors: &[&str]) -> ArticleMetadata {
+        ArticleMetadata {
+            title: "A Paper".to_string(),
+            authors: authors.iter().map(|s| s.to_string()).collect(),
+            year,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_resolve_target_folder_plain_target_unchanged() {
+        let target = RemotePath::from("/out/ai");
+        assert_eq!(
+            resolve_target_folder(&target, &meta(Some(2021), &["John Doe"])),
+            RemotePath::from("/out/ai")
+        );
+    }
+
+    #[test]
+    fn test_resolve_target_folder_year_and_first_author() {
+        let target = RemotePath::from("/out/ai/{{year}}/{{first_author}}/");
+        assert_eq!(
+            resolve_target_folder(&target, &meta(Some(2021), &["John Doe", "Jane Roe"])),
+            RemotePath::from("/out/ai/2021/Doe")
+        );
+    }
+
+    #[test]
+    fn test_resolve_target_folder_missing_values_fall_back_to_unknown() {
+        let target = RemotePath::from("/out/ai/{{year}}/{{first_author}}");
+        assert_eq!(
+            resolve_target_folder(&target, &meta(None, &[])),
+            RemotePath::from("/out/ai/_unknown/_unknown")
+        );
+    }
+
+    #[test]
+    fn test_dedup_targets_is_case_insensitive() {
+        let targets = dedup_targets(vec![
+            RemotePath::from("/out/AI/paper.pdf"),
+            RemotePath::from("/out/ai/paper.pdf"),
+            RemotePath::from("/out/pl/paper.pdf"),
+        ]);
+        assert_eq!(
+            targets,
+            vec![
+                RemotePath::from("/out/AI/paper.pdf"),
+                RemotePath::from("/out/pl/paper.pdf")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_static_prefix() {
+        assert_eq!(
+            static_prefix(&RemotePath::from("/out/ai/{{year}}/")),
+            RemotePath::from("/out/ai")
+        );
+        assert_eq!(
+            static_prefix(&RemotePath::from("/out/ai")),
+            RemotePath::from("/out/ai")
+        );
+    }
+
+    #[test]
+    fn test_target_file_path_in_folder_target() {
+        assert_eq!(
+            target_file_path(&RemotePath::from("/out/ai"), "paper.pdf"),
+            RemotePath::from("/out/ai/paper.pdf")
+        );
+        assert_eq!(
+            target_file_path(&RemotePath::from("/out/ai/v1.2/"), "paper.pdf"),
+            RemotePath::from("/out/ai/v1.2/paper.pdf")
+        );
+    }
+
+    #[test]
+    fn test_target_file_path_target_including_file_name() {
+        assert_eq!(
+            target_file_path(&RemotePath::from("/out/ai/old-name.PDF"), "paper.pdf"),
+            RemotePath::from("/out/ai/paper.pdf")
+        );
+    }
+
+    #[test]
+    fn test_sidecar_file_path_per_placement() {
+        let target = RemotePath::from("/sorted/ai/paper.pdf");
+        assert_eq!(
+            sidecar_file_path(&target, "md", SidecarPlacement::Alongside),
+            RemotePath::from("/sorted/ai/paper.pdf.md")
+        );
+        assert_eq!(
+            sidecar_file_path(&target, "jsonld", SidecarPlacement::Subfolder),
+            RemotePath::from("/sorted/ai/_meta/paper.pdf.jsonld")
+        );
+    }
+
+    #[test]
+    fn test_remote_file_name_preserves_or_sniffs_extension() {
+        let pdf = b"%PDF-1.7 ...";
+        let postscript = b"%!PS-Adobe-3.0";
+        assert_eq!(
+            remote_file_name(Some("paper.pdf"), "id_1", pdf),
+            "paper.pdf"
+        );
+        assert_eq!(
+            remote_file_name(Some("notes.ps"), "id_1", postscript),
+            "notes.ps"
+        );
+        assert_eq!(remote_file_name(Some("paper"), "id_1", pdf), "paper.pdf");
+        assert_eq!(remote_file_name(None, "id_1", postscript), "id_1.ps");
+        assert_eq!(remote_file_name(None, "id_1", b"unknown"), "id_1");
+        assert_eq!(
+            remote_file_name(Some("a/b: c.pdf"), "id_1", pdf),
+            "a-b- c.pdf"
+        );
+    }
+}
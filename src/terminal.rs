@@ -0,0 +1,88 @@
+use indicatif::MultiProgress;
+use std::io::{IsTerminal, Write};
+use std::sync::OnceLock;
+
+/// Whether output should be colored: not when asked not to with `--no-color` or by setting
+/// `NO_COLOR` to anything non-empty (see <https://no-color.org>), nor when stdout is not a
+/// terminal, e.g. when piped to a file.
+pub fn should_colorize(no_color_flag: bool, no_color_env: Option<&str>, is_tty: bool) -> bool {
+    let no_color_env = no_color_env.is_some_and(|value| !value.is_empty());
+    !no_color_flag && !no_color_env && is_tty
+}
+
+/// Turn colored output on or off for the whole process, based on the `--no-color` flag,
+/// the environment and whether stdout is a terminal. Returns whether colors are enabled.
+pub fn configure_colors(no_color_flag: bool) -> bool {
+    let colorize = should_colorize(
+        no_color_flag,
+        std::env::var("NO_COLOR").ok().as_deref(),
+        std::io::stdout().is_terminal(),
+    );
+    colored::control::set_override(colorize);
+    colorize
+}
+
+/// Whether progress bars can be drawn: on a terminal that can redraw lines, i.e. not one with
+/// `TERM=dumb`, such as an editor's shell buffer.
+pub fn should_draw_progress(is_tty: bool, term: Option<&str>) -> bool {
+    is_tty && term != Some("dumb")
+}
+
+/// Whether progress bars can be drawn, i.e. stderr is a terminal that can redraw lines.
+pub fn can_draw_progress() -> bool {
+    should_draw_progress(
+        std::io::stderr().is_terminal(),
+        std::env::var("TERM").ok().as_deref(),
+    )
+}
+
+/// The progress bars of the whole process. Lines printed while they are drawn must go through
+/// it, e.g. with [`MultiProgress::suspend`] or [`ProgressAwareStderr`], not to be drawn over.
+pub fn progress() -> MultiProgress {
+    static PROGRESS: OnceLock<MultiProgress> = OnceLock::new();
+    PROGRESS.get_or_init(MultiProgress::new).clone()
+}
+
+/// Standard error, hiding the progress bars of [`progress`] while writing to it, e.g. for log
+/// lines.
+pub struct ProgressAwareStderr;
+
+impl Write for ProgressAwareStderr {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        progress().suspend(|| std::io::stderr().write(buf))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use colored::Colorize;
+
+    #[test]
+    fn test_should_colorize() {
+        assert!(should_colorize(false, None, true));
+        assert!(should_colorize(false, Some(""), true));
+        assert!(!should_colorize(true, None, true));
+        assert!(!should_colorize(false, Some("1"), true));
+        assert!(!should_colorize(false, None, false));
+    }
+
+    #[test]
+    fn test_should_draw_progress() {
+        assert!(should_draw_progress(true, None));
+        assert!(should_draw_progress(true, Some("xterm-256color")));
+        assert!(!should_draw_progress(true, Some("dumb")));
+        assert!(!should_draw_progress(false, Some("xterm-256color")));
+    }
+
+    #[test]
+    fn test_no_color_produces_plain_strings() {
+        colored::control::set_override(should_colorize(false, Some("1"), true));
+        assert_eq!("Sync complete".green().bold().to_string(), "Sync complete");
+        colored::control::unset_override();
+    }
+}
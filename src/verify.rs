@@ -0,0 +1,60 @@
+use crate::clients::DropboxClient;
+use crate::models::{DropboxId, FileStatus, RemotePath};
+use crate::sidecar::SidecarFormat;
+use crate::storage::Storage;
+use crate::targets::SidecarPlacement;
+use anyhow::Result;
+
+/// What was found checking the filed files in Dropbox against the database.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// The number of records checked
+    pub checked: usize,
+    /// The PDFs and sidecars recorded as filed that are not in Dropbox
+    pub missing: Vec<RemotePath>,
+    /// The records none of whose PDFs are left in Dropbox
+    pub orphaned: Vec<DropboxId>,
+    /// The records reset to pending to be filed again, with `fix`
+    pub reset: Vec<DropboxId>,
+}
+
+/// Check that the PDFs and sidecars of the processed and archived files are still where the
+/// database says they were filed, e.g. after files were moved or deleted by hand in Dropbox.
+/// With `fix`, the records with a PDF missing are reset to pending, to be processed again.
+/// Missing sidecars alone are only reported, as `sidecars` writes them again.
+pub async fn verify_library(
+    storage: &Storage,
+    dropbox: &dyn DropboxClient,
+    format: SidecarFormat,
+    placement: SidecarPlacement,
+    fix: bool,
+) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    for status in [FileStatus::Processed, FileStatus::Archived] {
+        for file in storage.get_files_with_status(status).await? {
+            report.checked += 1;
+            let targets = file.target_paths();
+            let mut missing_pdfs = 0;
+            for target in &targets {
+                if dropbox.get_metadata(target).await?.is_none() {
+                    missing_pdfs += 1;
+                    report.missing.push(target.clone());
+                }
+                let sidecar = format.path(target, placement);
+                if dropbox.get_metadata(&sidecar).await?.is_none() {
+                    report.missing.push(sidecar);
+                }
+            }
+            if missing_pdfs > 0 && missing_pdfs == targets.len() {
+                report.orphaned.push(file.dropbox_id.clone());
+            }
+            if fix && missing_pdfs > 0 {
+                storage
+                    .update_status(&file.dropbox_id, FileStatus::Pending)
+                    .await?;
+                report.reset.push(file.dropbox_id);
+            }
+        }
+    }
+    Ok(report)
+}
@@ -0,0 +1,91 @@
+use crate::clients::DropboxClient;
+use crate::pipeline::{BatchSummary, Pipeline, sync_inbox};
+use crate::storage::Storage;
+use anyhow::Result;
+use std::future::Future;
+use std::time::Duration;
+
+/// What to sync and process on each cycle of [`watch`], and how often.
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    pub inboxes: Vec<String>,
+    /// Whether to sync the subfolders of the inboxes too, see [`sync_inbox`]
+    pub inbox_recursive: bool,
+    /// See [`sync_inbox`]
+    pub skip_suffixes: Vec<String>,
+    /// Where papers are filed, which is not synced as part of an inbox, see [`sync_inbox`]
+    pub allowed_upload_prefixes: Vec<String>,
+    /// Time to wait after a cycle before starting the next
+    pub interval: Duration,
+    pub batch_size: i64,
+    pub jobs: usize,
+}
+
+/// What one cycle of [`watch`] synced and processed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CycleSummary {
+    pub synced: usize,
+    pub batch: BatchSummary,
+}
+
+/// Sync the inboxes and process a batch, then wait `interval` and do it again, until
+/// `shutdown` completes. A cycle that is running when `shutdown` completes is finished
+/// first, so files are not left half processed. A failed cycle does not stop watching, as
+/// e.g. a network outage may be over by the next cycle. `on_cycle` is called with the
+/// number and outcome of every cycle. Returns the number of cycles run.
+pub async fn watch(
+    pipeline: &Pipeline,
+    storage: &Storage,
+    dropbox: &dyn DropboxClient,
+    options: &WatchOptions,
+    shutdown: impl Future<Output = ()>,
+    mut on_cycle: impl FnMut(usize, &Result<CycleSummary>),
+) -> Result<usize> {
+    tokio::pin!(shutdown);
+    let mut stopping = false;
+    let mut cycles = 0;
+    while !stopping {
+        cycles += 1;
+        let cycle = run_cycle(pipeline, storage, dropbox, options);
+        tokio::pin!(cycle);
+        let summary = tokio::select! {
+            summary = &mut cycle => summary,
+            () = &mut shutdown => {
+                tracing::info!("Stopping after the current cycle");
+                stopping = true;
+                cycle.await
+            }
+        };
+        on_cycle(cycles, &summary);
+
+        if !stopping {
+            tokio::select! {
+                () = tokio::time::sleep(options.interval) => {}
+                () = &mut shutdown => stopping = true,
+            }
+        }
+    }
+    Ok(cycles)
+}
+
+async fn run_cycle(
+    pipeline: &Pipeline,
+    storage: &Storage,
+    dropbox: &dyn DropboxClient,
+    options: &WatchOptions,
+) -> Result<CycleSummary> {
+    let mut synced = 0;
+    for inbox in &options.inboxes {
+        synced += sync_inbox(
+            storage,
+            dropbox,
+            inbox,
+            options.inbox_recursive,
+            &options.skip_suffixes,
+            &options.allowed_upload_prefixes,
+        )
+        .await?;
+    }
+    let batch = pipeline.run_batch(options.batch_size, options.jobs).await?;
+    Ok(CycleSummary { synced, batch })
+}
@@ -1,52 +1,200 @@
-use lopdf::{Document, dictionary};
-use sci_librarian::clients::{DropboxClient, DropboxEntry, FakeDropboxClient, FakeMistralClient};
+use async_trait::async_trait;
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use sci_librarian::classifier::HybridClassifier;
+use sci_librarian::clients::{
+    DropboxClient, DropboxEntry, FakeDropboxClient, FakeMistralClient, LlmClient,
+    dropbox_content_hash,
+};
+use sci_librarian::config::DEFAULT_SKIPPED_SUFFIXES;
+use sci_librarian::extract::{TextExtractor, extract_text, text_hash};
+use sci_librarian::feed::render_rss;
+use sci_librarian::indexing::{IndexLinks, IndexOptions, generate_all_indexes, generate_index};
+use sci_librarian::local::LocalFsClient;
 use sci_librarian::models::Rules;
 use sci_librarian::models::{
-    ArticleMetadata, DropboxId, FileHash, OneLineSummary, RemotePath, Rule, WorkDirectory,
+    ArticleMetadata, DropboxId, FileHash, FileRecord, OneLineSummary, RemotePath, Rule, RunId,
+    TokenUsage, WorkDirectory,
+};
+use sci_librarian::models::{DatabaseDump, FileStatus};
+use sci_librarian::pipeline::{
+    BatchSummary, LOW_EXTRACTION_QUALITY, MAX_WORKER_BARS, Pipeline, PipelineOptions,
+    ProcessingStage, ProgressEvent, TooManyCategories, analyze_local_file, dropbox_file_text,
+    sync_inbox,
 };
-use sci_librarian::pipeline::Pipeline;
-use sci_librarian::setup_db;
-use sci_librarian::storage::Storage;
+use sci_librarian::retry::retry_async;
+use sci_librarian::sidecar::{SidecarFormat, regenerate_sidecars};
+use sci_librarian::storage::{ListFilter, OnContentChange, PendingOrder, Storage};
+use sci_librarian::targets::SidecarPlacement;
+use sci_librarian::test_support::{pdf_bytes, scanned_pdf_bytes};
+use sci_librarian::verify::verify_library;
+use sci_librarian::watch::{WatchOptions, watch};
+use sci_librarian::{
+    MigrationStatus, apply_migrations, migration_status, open_db, setup_db, sqlite_url,
+};
+use sqlx::Row;
 
 use std::fs;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
-fn create_pdf(content: &str) -> Document {
-    let mut doc = lopdf::Document::with_version("1.4");
-    let pages_id = doc.new_object_id();
-    let font_id = doc.add_object(dictionary! {
-        "Type" => "Font",
-        "Subtype" => "Type1",
-        "BaseFont" => "Helvetica",
-    });
-    let resources_id = doc.add_object(dictionary! {
-        "Font" => dictionary! {
-            "F1" => font_id,
-        },
-    });
-    let content_bytes: &[u8] = content.as_bytes();
-    let content_id = doc.add_object(lopdf::Stream::new(dictionary! {}, content_bytes.to_vec()));
-    let page_id = doc.add_object(dictionary! {
-        "Type" => "Page",
-        "Parent" => pages_id,
-        "Contents" => content_id,
-        "Resources" => resources_id,
-        "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
-    });
-    let pages = dictionary! {
-        "Type" => "Pages",
-        "Kids" => vec![page_id.into()],
-        "Count" => 1,
-    };
-    doc.objects
-        .insert(pages_id, lopdf::Object::Dictionary(pages));
-    let catalog_id = doc.add_object(dictionary! {
-        "Type" => "Catalog",
-        "Pages" => pages_id,
-    });
-    doc.trailer.set("Root", catalog_id);
-    doc
+/// Metadata as the LLM might answer it for a paper, plausible enough to be filed.
+fn paper_metadata(title: &str) -> ArticleMetadata {
+    ArticleMetadata {
+        title: title.to_string(),
+        authors: vec!["Ada Lovelace".to_string()],
+        ..Default::default()
+    }
+}
+
+async fn setup_work_dir_and_storage(temp_dir: &tempfile::TempDir) -> (WorkDirectory, Arc<Storage>) {
+    let work_dir = WorkDirectory(temp_dir.path().to_path_buf());
+    fs::create_dir_all(work_dir.0.join("raw")).unwrap();
+    let db_path = work_dir.0.join("state.db");
+    let db_url = sqlite_url(&db_path);
+    let pool = setup_db(&db_url).await.unwrap();
+    (work_dir, Arc::new(Storage::new(pool)))
+}
+
+async fn sync_all(storage: &Storage, dropbox: &FakeDropboxClient, inbox: &str) {
+    for entry in dropbox.list_folder(inbox).await.unwrap() {
+        storage
+            .upsert_file(&entry.id, &entry.name, &entry.content_hash)
+            .await
+            .unwrap();
+    }
+}
+
+/// The rule most tests file their papers with, into `/out/pl`.
+fn pl_rule() -> Rule {
+    Rule {
+        name: String::from("Programming Languages"),
+        description: String::from("Compilers and type systems"),
+        path: RemotePath::from("/out/pl"),
+        ..Default::default()
+    }
+}
+
+/// Put `/0_inbox/<name>.pdf` in the inbox, a PDF reading `word`, with the id `id:<name>` and
+/// the content hash `hash-<name>`.
+async fn add_pdf(dropbox: &mut FakeDropboxClient, name: &str, word: &str) -> DropboxId {
+    let id = DropboxId(format!("id:{}", name));
+    dropbox
+        .add_entry(
+            DropboxEntry {
+                id: id.clone(),
+                name: format!("{}.pdf", name),
+                path: RemotePath(format!("/0_inbox/{}.pdf", name)),
+                content_hash: FileHash(format!("hash-{}", name)),
+                size: 0,
+                server_modified: None,
+            },
+            pdf_bytes(&[word]),
+        )
+        .await;
+    id
+}
+
+/// Sync the inbox and process it in one batch of up to 10 files with one worker.
+async fn run_pipeline(
+    work_dir: &WorkDirectory,
+    storage: &Arc<Storage>,
+    dropbox: &Arc<FakeDropboxClient>,
+    llm: Arc<dyn LlmClient>,
+    rules: Vec<Rule>,
+    options: PipelineOptions,
+) -> BatchSummary {
+    sync_all(storage, dropbox, "/0_inbox").await;
+    Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        llm,
+        work_dir.clone(),
+        Arc::new(Rules::from(rules)),
+    )
+    .with_options(options)
+    .run_batch(10, 1)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_migration_status_of_fresh_database() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("state.db");
+    let db_url = sqlite_url(&db_path);
+    let migration_files = fs::read_dir("migrations").unwrap().count();
+
+    let pool = open_db(&db_url).await.unwrap();
+    let fresh = migration_status(&pool).await.unwrap();
+    assert_eq!(fresh.len(), migration_files);
+    assert!(fresh.iter().all(|migration| !migration.applied));
+    assert!(
+        fresh
+            .windows(2)
+            .all(|pair| pair[0].version < pair[1].version)
+    );
+
+    apply_migrations(&pool).await.unwrap();
+    let migrated = migration_status(&pool).await.unwrap();
+    assert!(migrated.iter().all(|migration| migration.applied));
+    assert_eq!(
+        migrated
+            .iter()
+            .map(MigrationStatus::name)
+            .collect::<Vec<_>>(),
+        fresh.iter().map(MigrationStatus::name).collect::<Vec<_>>()
+    );
+    assert!(
+        migrated
+            .iter()
+            .any(|migration| migration.name() == "20261016101200_add_key_findings")
+    );
+}
+
+#[tokio::test]
+async fn test_dump_text_of_dropbox_file() {
+    let mut dropbox = FakeDropboxClient::new();
+    let id = add_pdf(&mut dropbox, "dump", "Publisher Specific Layout").await;
+
+    let text = dropbox_file_text(&dropbox, &id, None).await.unwrap();
+    assert!(text.contains("Publisher Specific Layout"));
+    let first_page = dropbox_file_text(&dropbox, &id, Some(1)).await.unwrap();
+    assert_eq!(first_page, text);
+
+    let missing = dropbox_file_text(&dropbox, &DropboxId("id:missing".to_string()), None)
+        .await
+        .unwrap_err();
+    assert!(format!("{:#}", missing).contains("id:missing"));
 }
+
+#[test]
+fn test_only_rules_leaves_out_other_rules() {
+    let rules = Rules::from(
+        ["AI", "Programming Languages", "Databases"]
+            .into_iter()
+            .map(|name| Rule {
+                name: name.to_string(),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>(),
+    );
+    let names =
+        |rules: &Rules| -> Vec<String> { rules.0.iter().map(|rule| rule.name.clone()).collect() };
+
+    let only_ai = rules.select(&["AI".to_string()], &[]).unwrap();
+    assert_eq!(names(&only_ai), vec!["AI"]);
+
+    let without_databases = rules.select(&[], &["databases".to_string()]).unwrap();
+    assert_eq!(
+        names(&without_databases),
+        vec!["AI", "Programming Languages"]
+    );
+
+    let error = rules.select(&["Biology".to_string()], &[]).unwrap_err();
+    assert!(error.to_string().contains("Unknown rules Biology"));
+}
+
 #[tokio::test]
 async fn test_full_scenario() {
     // 1. Setup
@@ -55,17 +203,13 @@ async fn test_full_scenario() {
     fs::create_dir_all(work_dir.0.join("raw")).unwrap();
 
     let db_path = work_dir.0.join("state.db");
-    let db_url = format!("sqlite:///{}", db_path.to_string_lossy().replace('\\', "/"));
+    let db_url = sqlite_url(&db_path);
     let pool = setup_db(&db_url).await.unwrap();
     let storage = Arc::new(Storage::new(pool));
     let mut dropbox = FakeDropboxClient::new();
     let llm = FakeMistralClient::new();
 
-    // Create a valid PDF using lopdf
-    let mut doc = create_pdf("BT /F1 12 Tf 100 700 Td (Quantum Computing) Tj ET");
-
-    let mut paper_content = Vec::new();
-    doc.save_to(&mut paper_content).unwrap();
+    let paper_content = pdf_bytes(&["Quantum Computing"]);
 
     let paper_id = DropboxId("id:123".to_string());
     let paper_path = RemotePath("/0_inbox/paper.pdf".to_string());
@@ -78,6 +222,8 @@ async fn test_full_scenario() {
                 name: "paper.pdf".to_string(),
                 path: paper_path.clone(),
                 content_hash: paper_hash.clone(),
+                size: 0,
+                server_modified: None,
             },
             paper_content.clone(),
         )
@@ -88,11 +234,13 @@ async fn test_full_scenario() {
         authors: vec!["John Doe".to_string()],
         summary: OneLineSummary("A beginner's guide to quantum computing.".to_string()),
         abstract_text: "This paper explains quantum computing in simple terms.".to_string(),
+        ..Default::default()
     };
     let matching_rules = vec![Rule {
         name: String::from("Quantum Computing"),
         description: String::from("Everything about Quantum Computing"),
         path: RemotePath::from("/Research/Quantum_Computing"),
+        ..Default::default()
     }];
     llm.set_response("Quantum", meta.clone(), matching_rules.clone())
         .await;
@@ -100,12 +248,14 @@ async fn test_full_scenario() {
     let dropbox = Arc::new(dropbox);
     let llm = Arc::new(llm);
     let rules = Arc::new(Rules::from(vec![
+        matching_rules[0].clone(),
         Rule {
             name: String::from("AI"),
             description: String::from(
                 "Neural Networks, Deep Learning, Large Language Models (LLMs), Reinforcement Learning and other large-scale text, image and video processing tasks using function approximators",
             ),
             path: RemotePath::from("/out/ai"),
+            ..Default::default()
         },
         Rule {
             name: String::from("Programming Languages"),
@@ -113,6 +263,7 @@ async fn test_full_scenario() {
                 "Programming language theory, parsers, compilers, partial evaluation, type systems etc.",
             ),
             path: RemotePath::from("/out/programming-languages"),
+            ..Default::default()
         },
     ]));
     let pipeline = Pipeline::new(
@@ -159,3 +310,3663 @@ async fn test_full_scenario() {
         sidecar.contains("## Abstract\nThis paper explains quantum computing in simple terms.")
     );
 }
+
+#[tokio::test]
+async fn test_templated_rule_target_files_by_year() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let llm = FakeMistralClient::new();
+
+    add_pdf(&mut dropbox, "paper", "Attention").await;
+
+    let ai_rule = Rule {
+        name: String::from("AI"),
+        description: String::from("Artificial intelligence"),
+        path: RemotePath::from("/out/ai/{{year}}/"),
+        ..Default::default()
+    };
+    llm.set_response(
+        "Attention",
+        ArticleMetadata {
+            title: "Attention Revisited".to_string(),
+            authors: vec!["Jane Roe".to_string()],
+            year: Some(2021),
+            ..Default::default()
+        },
+        vec![ai_rule.clone()],
+    )
+    .await;
+
+    let dropbox = Arc::new(dropbox);
+    run_pipeline(
+        &work_dir,
+        &storage,
+        &dropbox,
+        Arc::new(llm),
+        vec![ai_rule],
+        PipelineOptions::default(),
+    )
+    .await;
+
+    let files = dropbox.files.lock().await;
+    assert!(files.contains_key("/out/ai/2021/paper.pdf"));
+    assert!(files.contains_key("/out/ai/2021/paper.pdf.md"));
+}
+
+#[tokio::test]
+async fn test_generate_all_indexes_writes_one_readme_per_folder() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (_work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let dropbox = FakeDropboxClient::new();
+
+    let filed = [
+        ("id:1", vec!["/out/ai/one.pdf"]),
+        ("id:2", vec!["/out/ai/two.pdf", "/out/pl/two.pdf"]),
+        ("id:3", vec!["/out/ai/2021/three.pdf"]),
+        ("id:4", vec![]),
+    ];
+    for (id, targets) in &filed {
+        let id = DropboxId(id.to_string());
+        storage
+            .upsert_file(&id, "paper.pdf", &FileHash(format!("hash-{}", id.0)))
+            .await
+            .unwrap();
+        let targets: Vec<RemotePath> = targets.iter().map(|t| RemotePath::from(*t)).collect();
+        storage
+            .update_metadata(
+                &id,
+                ArticleMetadata::default(),
+                &targets,
+                FileStatus::Processed,
+            )
+            .await
+            .unwrap();
+    }
+
+    let written = generate_all_indexes(&storage, &dropbox, &IndexOptions::default(), 2)
+        .await
+        .unwrap();
+
+    assert_eq!(written.len(), 3);
+    let files = dropbox.files.lock().await;
+    let mut readmes: Vec<&String> = files.keys().filter(|k| k.ends_with("README.md")).collect();
+    readmes.sort();
+    assert_eq!(
+        readmes,
+        vec![
+            "/out/ai/2021/README.md",
+            "/out/ai/README.md",
+            "/out/pl/README.md"
+        ]
+    );
+    let ai_index = String::from_utf8(files["/out/ai/README.md"].clone()).unwrap();
+    assert_eq!(ai_index.lines().count(), 4);
+    assert!(ai_index.contains("(one.pdf)"));
+    assert!(ai_index.contains("(two.pdf)"));
+}
+
+#[tokio::test]
+async fn test_progress_events_for_two_file_batch() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let llm = FakeMistralClient::new();
+
+    let good_id = add_pdf(&mut dropbox, "good", "Compilers").await;
+    let bad_id = DropboxId("id:bad".to_string());
+    dropbox
+        .add_entry(
+            DropboxEntry {
+                id: bad_id.clone(),
+                name: "bad.pdf".to_string(),
+                path: RemotePath("/0_inbox/bad.pdf".to_string()),
+                content_hash: FileHash("hash-bad".to_string()),
+                size: 0,
+                server_modified: None,
+            },
+            // A broken PDF, so extraction fails
+            b"%PDF-1.4 truncated".to_vec(),
+        )
+        .await;
+    llm.set_response("Compilers", paper_metadata("Compilers"), vec![pl_rule()])
+        .await;
+
+    let dropbox = Arc::new(dropbox);
+    sync_all(&storage, &dropbox, "/0_inbox").await;
+    let (events_tx, mut events_rx) = tokio::sync::mpsc::channel(100);
+    let pipeline = Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        Arc::new(llm),
+        work_dir,
+        Arc::new(Rules::from(vec![pl_rule()])),
+    )
+    .with_progress_events(events_tx);
+    let summary = pipeline.run_batch(10, 1).await.unwrap();
+    drop(pipeline);
+    assert_eq!(summary.processed, 1);
+    assert_eq!(summary.failed.len(), 1);
+
+    let mut events = Vec::new();
+    while let Some(event) = events_rx.recv().await {
+        events.push(event);
+    }
+    let events_for = |id: &DropboxId| -> Vec<ProgressEvent> {
+        events
+            .iter()
+            .filter(|e| match e {
+                ProgressEvent::Started { id: i }
+                | ProgressEvent::Progress { id: i, .. }
+                | ProgressEvent::Completed { id: i, .. }
+                | ProgressEvent::Failed { id: i, .. }
+                | ProgressEvent::Skipped { id: i, .. }
+                | ProgressEvent::NeedsReview { id: i, .. } => i == id,
+            })
+            .cloned()
+            .collect()
+    };
+    let progress = |id: &DropboxId, stage| ProgressEvent::Progress {
+        id: id.clone(),
+        stage,
+    };
+
+    assert_eq!(events.len(), 10);
+    assert_eq!(
+        events_for(&good_id),
+        vec![
+            ProgressEvent::Started {
+                id: good_id.clone()
+            },
+            progress(&good_id, ProcessingStage::Download),
+            progress(&good_id, ProcessingStage::Extract),
+            progress(&good_id, ProcessingStage::Analyze),
+            progress(&good_id, ProcessingStage::Upload),
+            ProgressEvent::Completed {
+                id: good_id.clone(),
+                target_paths: vec![RemotePath::from("/out/pl/good.pdf")],
+            },
+        ]
+    );
+    let bad_events = events_for(&bad_id);
+    assert_eq!(
+        bad_events[..3],
+        [
+            ProgressEvent::Started { id: bad_id.clone() },
+            progress(&bad_id, ProcessingStage::Download),
+            progress(&bad_id, ProcessingStage::Extract),
+        ]
+    );
+    assert!(matches!(bad_events[3], ProgressEvent::Failed { .. }));
+}
+
+#[tokio::test]
+async fn test_dump_and_import_round_trip() {
+    let source_dir = tempfile::tempdir().unwrap();
+    let (_, source) = setup_work_dir_and_storage(&source_dir).await;
+    for (id, status) in [
+        ("id:pending", FileStatus::Pending),
+        ("id:processed", FileStatus::Processed),
+        ("id:error", FileStatus::Error),
+    ] {
+        let id = DropboxId(id.to_string());
+        source
+            .upsert_file(&id, "paper.pdf", &FileHash(format!("hash-{}", id.0)))
+            .await
+            .unwrap();
+        if status == FileStatus::Processed {
+            let meta = ArticleMetadata {
+                title: "A \"quoted\" title".to_string(),
+                authors: vec!["John Doe".to_string()],
+                extracted_text_hash: Some("text-v1".to_string()),
+                ..Default::default()
+            };
+            source
+                .update_metadata(&id, meta, &[RemotePath::from("/out/ai/paper.pdf")], status)
+                .await
+                .unwrap();
+        } else {
+            source.update_status(&id, status).await.unwrap();
+        }
+    }
+    let processed = DropboxId("id:processed".to_string());
+    let run_id = RunId("20261016T101500.000Z".to_string());
+    source
+        .snapshot_rules(&Rules(vec![pl_rule()]), &run_id)
+        .await
+        .unwrap();
+    source.set_run_id(&processed, &run_id).await.unwrap();
+    // The text extracts differently since that run
+    let meta = ArticleMetadata {
+        title: "A \"quoted\" title".to_string(),
+        extracted_text_hash: Some("text-v2".to_string()),
+        ..Default::default()
+    };
+    source
+        .update_metadata(
+            &processed,
+            meta,
+            &[RemotePath::from("/out/ai/paper.pdf")],
+            FileStatus::Processed,
+        )
+        .await
+        .unwrap();
+
+    let dump = DatabaseDump {
+        files: source.export_all().await.unwrap(),
+        rules: source.export_rules_snapshots().await.unwrap(),
+        extracted_text_hashes: source.export_extracted_text_hashes().await.unwrap(),
+    };
+    let json = serde_json::to_string(&dump).unwrap();
+
+    let target_dir = tempfile::tempdir().unwrap();
+    let (_, target) = setup_work_dir_and_storage(&target_dir).await;
+    let restored: DatabaseDump = serde_json::from_str(&json).unwrap();
+    let count = target.import_all(&restored.files).await.unwrap();
+    target
+        .import_rules_snapshots(&restored.rules)
+        .await
+        .unwrap();
+    target
+        .import_extracted_text_hashes(&restored.extracted_text_hashes)
+        .await
+        .unwrap();
+
+    assert_eq!(count, 3);
+    assert_eq!(target.export_all().await.unwrap(), dump.files);
+    assert_eq!(dump.rules.len(), 1);
+    assert_eq!(target.export_rules_snapshots().await.unwrap(), dump.rules);
+    assert_eq!(
+        target.rules_for_run(&run_id).await.unwrap(),
+        Rules(vec![pl_rule()])
+    );
+    assert_eq!(dump.extracted_text_hashes.len(), 1);
+    assert_eq!(
+        target.export_extracted_text_hashes().await.unwrap(),
+        dump.extracted_text_hashes
+    );
+    let changed = ListFilter {
+        text_changed_since: Some(&run_id),
+        ..Default::default()
+    };
+    assert_eq!(target.count_files(&changed).await.unwrap(), 1);
+}
+
+#[test]
+fn test_dump_without_rules_or_text_hashes_still_loads() {
+    let dump: DatabaseDump = serde_json::from_str(r#"{"files": []}"#).unwrap();
+    assert!(dump.rules.is_empty());
+    assert!(dump.extracted_text_hashes.is_empty());
+}
+
+/// An LLM client that fails its first `failures` calls and then delegates to a fake.
+struct FlakyLlmClient {
+    failures: usize,
+    calls: AtomicUsize,
+    inner: FakeMistralClient,
+}
+
+#[async_trait]
+impl LlmClient for FlakyLlmClient {
+    async fn query_llm(
+        &self,
+        text: &str,
+        rules: &Rules,
+    ) -> anyhow::Result<(ArticleMetadata, Vec<Rule>)> {
+        if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+            return Err(anyhow::anyhow!("connection reset"));
+        }
+        self.inner.query_llm(text, rules).await
+    }
+}
+
+#[tokio::test]
+async fn test_transient_failure_is_retried_once() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    add_pdf(&mut dropbox, "flaky", "Compilers").await;
+    let inner = FakeMistralClient::new();
+    inner
+        .set_response("Compilers", paper_metadata("Compilers"), vec![pl_rule()])
+        .await;
+    let llm = Arc::new(FlakyLlmClient {
+        failures: 1,
+        calls: AtomicUsize::new(0),
+        inner,
+    });
+
+    let dropbox = Arc::new(dropbox);
+    sync_all(&storage, &dropbox, "/0_inbox").await;
+    let pipeline = Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        llm.clone(),
+        work_dir,
+        Arc::new(Rules::from(vec![pl_rule()])),
+    )
+    .with_options(PipelineOptions {
+        max_file_retries: 1,
+        ..Default::default()
+    });
+    pipeline.run_batch(10, 2).await.unwrap();
+
+    assert_eq!(llm.calls.load(Ordering::SeqCst), 2);
+    let records = storage.export_all().await.unwrap();
+    assert_eq!(records[0].status, FileStatus::Processed);
+    assert!(dropbox.files.lock().await.contains_key("/out/pl/flaky.pdf"));
+}
+
+#[tokio::test]
+async fn test_fake_llm_failures_drive_retries() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    for (name, text) in [("flaky", "Compilers"), ("broken", "Garbage")] {
+        add_pdf(&mut dropbox, name, text).await;
+    }
+    let llm = FakeMistralClient::new();
+    llm.set_response("Compilers", paper_metadata("Compilers"), vec![pl_rule()])
+        .await;
+    llm.set_fail_n_times("Compilers", 1).await;
+    llm.set_error("Garbage", "Model overloaded").await;
+
+    let dropbox = Arc::new(dropbox);
+    let summary = run_pipeline(
+        &work_dir,
+        &storage,
+        &dropbox,
+        Arc::new(llm),
+        vec![pl_rule()],
+        PipelineOptions {
+            max_file_retries: 1,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert_eq!(summary.processed, 1);
+    let flaky = storage
+        .get_file(&DropboxId("id:flaky".to_string()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(flaky.status, FileStatus::Processed);
+    assert_eq!(summary.failed.len(), 1);
+    assert_eq!(summary.failed[0].0, DropboxId("id:broken".to_string()));
+    assert!(summary.failed[0].1.contains("Model overloaded"));
+    let errors = storage.recent_errors(10).await.unwrap();
+    assert_eq!(errors.len(), 1);
+    assert!(
+        errors[0]
+            .last_error
+            .as_deref()
+            .unwrap()
+            .contains("Model overloaded")
+    );
+}
+
+#[tokio::test]
+async fn test_recent_errors_lists_most_recent_first() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (_, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    for (i, id) in ["id:old", "id:ok", "id:middle", "id:new"]
+        .iter()
+        .enumerate()
+    {
+        let id = DropboxId(id.to_string());
+        storage
+            .upsert_file(&id, "paper.pdf", &FileHash(format!("hash-{}", i)))
+            .await
+            .unwrap();
+        if id.0 != "id:ok" {
+            storage
+                .mark_error(&id, &format!("Failure {}", i))
+                .await
+                .unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    let errors = storage.recent_errors(2).await.unwrap();
+    let ids: Vec<_> = errors.iter().map(|r| r.dropbox_id.0.as_str()).collect();
+    assert_eq!(ids, vec!["id:new", "id:middle"]);
+    assert_eq!(errors[0].last_error.as_deref(), Some("Failure 3"));
+    assert_eq!(storage.recent_errors(10).await.unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn test_abstract_survives_processing_and_appears_in_index() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let llm = FakeMistralClient::new();
+    let id = add_pdf(&mut dropbox, "types", "Types").await;
+    let abstract_text = "We present a type system | with gradual guarantees.";
+    llm.set_response(
+        "Types",
+        ArticleMetadata {
+            title: "Gradual Types".to_string(),
+            authors: vec!["Jeremy Siek".to_string()],
+            abstract_text: abstract_text.to_string(),
+            ..Default::default()
+        },
+        vec![pl_rule()],
+    )
+    .await;
+
+    let dropbox = Arc::new(dropbox);
+    run_pipeline(
+        &work_dir,
+        &storage,
+        &dropbox,
+        Arc::new(llm),
+        vec![pl_rule()],
+        PipelineOptions::default(),
+    )
+    .await;
+
+    let record = storage.get_file(&id).await.unwrap().unwrap();
+    assert_eq!(record.abstract_text.as_deref(), Some(abstract_text));
+
+    let options = IndexOptions {
+        include_abstract: true,
+        ..Default::default()
+    };
+    generate_index(&storage, &*dropbox, "/out/pl", &options)
+        .await
+        .unwrap();
+    let index = String::from_utf8(dropbox.files.lock().await["/out/pl/README.md"].clone()).unwrap();
+    assert!(index.starts_with("| Title | Authors | Summary | Abstract |"));
+    assert!(index.contains("| We present a type system \\| with gradual guarantees. |"));
+}
+
+#[tokio::test]
+async fn test_oversized_file_is_skipped() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let id = add_pdf(&mut dropbox, "huge", "Huge").await;
+    let size = pdf_bytes(&["Huge"]).len();
+    let llm = Arc::new(FlakyLlmClient {
+        failures: 0,
+        calls: AtomicUsize::new(0),
+        inner: FakeMistralClient::new(),
+    });
+
+    let dropbox = Arc::new(dropbox);
+    run_pipeline(
+        &work_dir,
+        &storage,
+        &dropbox,
+        llm.clone(),
+        vec![],
+        PipelineOptions {
+            max_pdf_bytes: (size - 1) as u64,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let record = storage.get_file(&id).await.unwrap().unwrap();
+    assert_eq!(record.status, FileStatus::Skipped);
+    assert!(
+        record
+            .skip_reason
+            .unwrap()
+            .contains("more than the maximum")
+    );
+    assert_eq!(record.last_error, None);
+    assert_eq!(llm.calls.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn test_feed_from_seeded_database() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (_, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    for (id, title) in [
+        ("id:one", "Types & <Effects>"),
+        ("id:two", "Plain Title"),
+        ("id:pending", "Not Yet"),
+    ] {
+        let id = DropboxId(id.to_string());
+        storage
+            .upsert_file(&id, "paper.pdf", &FileHash(format!("hash-{}", id.0)))
+            .await
+            .unwrap();
+        if id.0 == "id:pending" {
+            continue;
+        }
+        let meta = ArticleMetadata {
+            title: title.to_string(),
+            summary: OneLineSummary("Summary with \"quotes\"".to_string()),
+            ..Default::default()
+        };
+        storage
+            .update_metadata(
+                &id,
+                meta,
+                &[RemotePath::from("/out/pl/paper.pdf")],
+                FileStatus::Processed,
+            )
+            .await
+            .unwrap();
+    }
+
+    let files = storage.get_processed_since(None).await.unwrap();
+    let xml = render_rss(&files, "https://www.dropbox.com/home");
+    let doc = roxmltree::Document::parse(&xml).unwrap();
+    let items: Vec<roxmltree::Node> = doc
+        .descendants()
+        .filter(|n| n.has_tag_name("item"))
+        .collect();
+    assert_eq!(items.len(), 2);
+    let text_of = |item: &roxmltree::Node, tag: &str| {
+        item.children()
+            .find(|n| n.has_tag_name(tag))
+            .and_then(|n| n.text())
+            .map(String::from)
+    };
+    let titles: Vec<String> = items.iter().filter_map(|i| text_of(i, "title")).collect();
+    assert!(titles.contains(&"Types & <Effects>".to_string()));
+    assert_eq!(
+        text_of(&items[0], "description").as_deref(),
+        Some("Summary with \"quotes\"")
+    );
+    assert_eq!(
+        text_of(&items[0], "link").as_deref(),
+        Some("https://www.dropbox.com/home/out/pl/paper.pdf")
+    );
+
+    let future = chrono::Utc::now() + chrono::Duration::days(1);
+    assert!(
+        storage
+            .get_processed_since(Some(future))
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+/// An LLM client that answers slowly about papers mentioning "Slow", so they finish last.
+struct SlowLlmClient;
+
+#[async_trait]
+impl LlmClient for SlowLlmClient {
+    async fn query_llm(
+        &self,
+        text: &str,
+        _rules: &Rules,
+    ) -> anyhow::Result<(ArticleMetadata, Vec<Rule>)> {
+        if text.contains("Slow") {
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        }
+        Ok((paper_metadata("A Paper"), vec![]))
+    }
+}
+
+#[tokio::test]
+async fn test_ordered_results_print_one_line_per_file_in_queue_order() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    for i in 0..5 {
+        let snippet = if i % 2 == 0 { "Slow" } else { "Fast" };
+        add_pdf(&mut dropbox, &format!("paper{i}"), snippet).await;
+    }
+    let dropbox = Arc::new(dropbox);
+    sync_all(&storage, &dropbox, "/0_inbox").await;
+    let queued: Vec<DropboxId> = storage
+        .get_pending_files(10)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|file| file.dropbox_id)
+        .collect();
+
+    let (lines_tx, mut lines_rx) = tokio::sync::mpsc::unbounded_channel();
+    Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        Arc::new(SlowLlmClient),
+        work_dir,
+        Arc::new(Rules::from(vec![])),
+    )
+    .with_options(PipelineOptions {
+        ordered_results: true,
+        ..Default::default()
+    })
+    .with_plain_output_to(lines_tx)
+    .run_batch(10, 3)
+    .await
+    .unwrap();
+
+    let mut lines = Vec::new();
+    while let Ok(line) = lines_rx.try_recv() {
+        lines.push(line);
+    }
+    assert_eq!(lines.len(), queued.len());
+    for (position, (line, id)) in lines.iter().zip(&queued).enumerate() {
+        assert!(
+            line.starts_with(&format!("[{}/5]", position + 1)) && line.contains(&id.0),
+            "line {} is not about {}: {}",
+            position,
+            id.0,
+            line
+        );
+    }
+}
+
+/// An LLM client that counts how many queries are in flight at once.
+#[derive(Default)]
+struct CountingLlmClient {
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+#[async_trait]
+impl LlmClient for CountingLlmClient {
+    async fn query_llm(
+        &self,
+        _text: &str,
+        _rules: &Rules,
+    ) -> anyhow::Result<(ArticleMetadata, Vec<Rule>)> {
+        let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(now, Ordering::SeqCst);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        Ok((paper_metadata("A Paper"), vec![]))
+    }
+}
+
+#[tokio::test]
+async fn test_llm_jobs_limits_concurrent_llm_queries() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    for i in 0..6 {
+        add_pdf(&mut dropbox, &format!("paper{i}"), "Paper").await;
+    }
+    let llm = Arc::new(CountingLlmClient::default());
+
+    let dropbox = Arc::new(dropbox);
+    sync_all(&storage, &dropbox, "/0_inbox").await;
+    Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        llm.clone(),
+        work_dir,
+        Arc::new(Rules::from(vec![])),
+    )
+    .with_options(PipelineOptions {
+        llm_jobs: Some(2),
+        ..Default::default()
+    })
+    .run_batch(10, 6)
+    .await
+    .unwrap();
+
+    assert_eq!(llm.max_in_flight.load(Ordering::SeqCst), 2);
+    let records = storage.export_all().await.unwrap();
+    assert!(records.iter().all(|r| r.status == FileStatus::Processed));
+}
+
+#[tokio::test]
+async fn test_max_inflight_uploads_limits_concurrent_uploads() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut inner = FakeDropboxClient::new();
+    let llm = FakeMistralClient::new();
+    let rules = vec![
+        Rule {
+            name: String::from("AI"),
+            description: String::from("Artificial intelligence"),
+            path: RemotePath::from("/out/ai"),
+            ..Default::default()
+        },
+        pl_rule(),
+    ];
+    for i in 0..8 {
+        add_pdf(&mut inner, &format!("paper{i}"), &format!("Paper{i}")).await;
+        llm.set_response(
+            &format!("Paper{}", i),
+            ArticleMetadata {
+                title: format!("Paper {}", i),
+                authors: vec!["Ada Lovelace".to_string()],
+                ..Default::default()
+            },
+            rules.clone(),
+        )
+        .await;
+    }
+    let dropbox =
+        Arc::new(CountingDropboxClient::new(inner).with_upload_delay(Duration::from_millis(20)));
+    sync_all(&storage, &dropbox.inner, "/0_inbox").await;
+
+    let summary = Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        Arc::new(llm),
+        work_dir,
+        Arc::new(Rules::from(rules)),
+    )
+    .with_options(PipelineOptions {
+        max_inflight_uploads: 2,
+        ..Default::default()
+    })
+    .run_batch(10, 8)
+    .await
+    .unwrap();
+
+    assert_eq!(summary.processed, 8);
+    // Eight workers, each uploading two PDFs and two sidecars, but two uploads at a time
+    assert_eq!(dropbox.max_uploads_in_flight.load(Ordering::SeqCst), 2);
+    let uploaded = dropbox
+        .inner
+        .files
+        .lock()
+        .await
+        .keys()
+        .filter(|path| path.starts_with("/out"))
+        .count();
+    assert_eq!(uploaded, 8 * 4);
+}
+
+#[tokio::test]
+async fn test_batch_with_more_workers_than_progress_bars_completes() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    for i in 0..24 {
+        add_pdf(&mut dropbox, &format!("paper{i}"), "Paper").await;
+    }
+    let llm = Arc::new(CountingLlmClient::default());
+
+    let dropbox = Arc::new(dropbox);
+    sync_all(&storage, &dropbox, "/0_inbox").await;
+    let summary = Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        llm.clone(),
+        work_dir,
+        Arc::new(Rules::from(vec![])),
+    )
+    .run_batch(24, 16)
+    .await
+    .unwrap();
+
+    assert_eq!(summary.processed, 24);
+    assert!(llm.max_in_flight.load(Ordering::SeqCst) > MAX_WORKER_BARS);
+}
+
+#[tokio::test]
+async fn test_stale_in_progress_file_is_reclaimed() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let id = add_pdf(&mut dropbox, "stuck", "Stuck").await;
+    let dropbox = Arc::new(dropbox);
+    sync_all(&storage, &dropbox, "/0_inbox").await;
+    // A run that was killed after handing the file to a worker
+    assert!(storage.mark_in_progress(&id).await.unwrap());
+    // Which no other batch can claim again
+    assert!(!storage.mark_in_progress(&id).await.unwrap());
+
+    let pipeline = |options| {
+        Pipeline::new(
+            storage.clone(),
+            dropbox.clone(),
+            Arc::new(FakeMistralClient::new()),
+            work_dir.clone(),
+            Arc::new(Rules::from(vec![])),
+        )
+        .with_options(options)
+    };
+
+    // Recently started, so it may still be running elsewhere
+    pipeline(PipelineOptions::default())
+        .run_batch(10, 1)
+        .await
+        .unwrap();
+    let record = storage.get_file(&id).await.unwrap().unwrap();
+    assert_eq!(record.status, FileStatus::InProgress);
+
+    pipeline(PipelineOptions {
+        reclaim_after: std::time::Duration::ZERO,
+        ..Default::default()
+    })
+    .run_batch(10, 1)
+    .await
+    .unwrap();
+    let record = storage.get_file(&id).await.unwrap().unwrap();
+    assert_eq!(record.status, FileStatus::Processed);
+}
+
+#[tokio::test]
+async fn test_key_findings_are_rendered_and_persisted() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let llm = FakeMistralClient::new();
+    let id = add_pdf(&mut dropbox, "findings", "Findings").await;
+    let key_findings = vec![
+        "Gradual types are sound".to_string(),
+        "Casts cost 5% at run time".to_string(),
+    ];
+    llm.set_response(
+        "Findings",
+        ArticleMetadata {
+            title: "Gradual Findings".to_string(),
+            authors: vec!["Jeremy Siek".to_string()],
+            key_findings: key_findings.clone(),
+            ..Default::default()
+        },
+        vec![pl_rule()],
+    )
+    .await;
+
+    let dropbox = Arc::new(dropbox);
+    run_pipeline(
+        &work_dir,
+        &storage,
+        &dropbox,
+        Arc::new(llm),
+        vec![pl_rule()],
+        PipelineOptions::default(),
+    )
+    .await;
+
+    let record = storage.get_file(&id).await.unwrap().unwrap();
+    assert_eq!(record.key_finding_list(), key_findings);
+    let sidecar =
+        String::from_utf8(dropbox.files.lock().await["/out/pl/findings.pdf.md"].clone()).unwrap();
+    assert!(sidecar.contains(
+        "## Key Findings\n- Gradual types are sound\n- Casts cost 5% at run time\n\n## Abstract"
+    ));
+}
+
+#[tokio::test]
+async fn test_token_usage_is_persisted_and_totalled() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let llm = FakeMistralClient::new();
+    for (name, snippet, prompt_tokens) in [("one", "First", 1000), ("two", "Second", 500)] {
+        add_pdf(&mut dropbox, name, snippet).await;
+        llm.set_response(
+            snippet,
+            ArticleMetadata {
+                title: format!("{snippet} Paper"),
+                token_usage: Some(TokenUsage {
+                    prompt_tokens,
+                    completion_tokens: 100,
+                }),
+                ..Default::default()
+            },
+            vec![pl_rule()],
+        )
+        .await;
+    }
+
+    let dropbox = Arc::new(dropbox);
+    sync_all(&storage, &dropbox, "/0_inbox").await;
+    let summary = Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        Arc::new(llm),
+        work_dir,
+        Arc::new(Rules::from(vec![pl_rule()])),
+    )
+    .run_batch(10, 2)
+    .await
+    .unwrap();
+
+    assert_eq!(
+        summary.token_usage,
+        TokenUsage {
+            prompt_tokens: 1500,
+            completion_tokens: 200,
+        }
+    );
+    assert!((summary.token_usage.estimated_cost(0.002) - 0.0034).abs() < 1e-9);
+    let record = storage
+        .get_file(&DropboxId("id:one".to_string()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.prompt_tokens, Some(1000));
+    assert_eq!(record.completion_tokens, Some(100));
+}
+
+#[tokio::test]
+async fn test_tags_round_trip_and_filter() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let llm = FakeMistralClient::new();
+    for (name, snippet, tags) in [
+        ("survey", "Survey", vec!["survey", "types"]),
+        ("dataset", "Dataset", vec!["dataset"]),
+    ] {
+        add_pdf(&mut dropbox, name, snippet).await;
+        llm.set_response(
+            snippet,
+            ArticleMetadata {
+                title: format!("A {}", snippet),
+                authors: vec!["Jeremy Siek".to_string()],
+                tags: tags.into_iter().map(String::from).collect(),
+                ..Default::default()
+            },
+            vec![pl_rule()],
+        )
+        .await;
+    }
+
+    let dropbox = Arc::new(dropbox);
+    sync_all(&storage, &dropbox, "/0_inbox").await;
+    Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        Arc::new(llm),
+        work_dir,
+        Arc::new(Rules::from(vec![pl_rule()])),
+    )
+    .run_batch(10, 2)
+    .await
+    .unwrap();
+
+    let record = storage
+        .get_file(&DropboxId("id:survey".to_string()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.tag_list(), vec!["survey", "types"]);
+    let sidecar =
+        String::from_utf8(dropbox.files.lock().await["/out/pl/survey.pdf.md"].clone()).unwrap();
+    assert!(sidecar.ends_with("## Tags\nsurvey, types"));
+
+    let surveys = storage
+        .list_files(
+            &ListFilter {
+                tag: Some("Survey"),
+                ..Default::default()
+            },
+            None,
+            0,
+        )
+        .await
+        .unwrap();
+    assert_eq!(surveys.len(), 1);
+    assert_eq!(surveys[0].dropbox_id.0, "id:survey");
+    assert_eq!(
+        storage
+            .list_files(&ListFilter::default(), None, 0)
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+    assert!(
+        storage
+            .list_files(
+                &ListFilter {
+                    tag: Some("missing"),
+                    ..Default::default()
+                },
+                None,
+                0
+            )
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    // The one-word test PDFs are far too short to count as good extractions
+    assert!(record.extraction_quality.unwrap() < LOW_EXTRACTION_QUALITY);
+    let low_quality = ListFilter {
+        below_extraction_quality: Some(LOW_EXTRACTION_QUALITY),
+        ..Default::default()
+    };
+    assert_eq!(storage.count_files(&low_quality).await.unwrap(), 2);
+
+    generate_index(&storage, &*dropbox, "/out/pl", &IndexOptions::default())
+        .await
+        .unwrap();
+    let index = String::from_utf8(dropbox.files.lock().await["/out/pl/README.md"].clone()).unwrap();
+    assert!(index.starts_with("| Title | Authors | Summary | Tags |"));
+    assert!(index.contains("| survey, types |"));
+}
+
+#[tokio::test]
+async fn test_low_confidence_result_needs_review() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let llm = FakeMistralClient::new();
+    let id = add_pdf(&mut dropbox, "unsure", "Unsure").await;
+    let rules = vec![
+        Rule {
+            name: String::from("AI"),
+            description: String::from("Artificial intelligence"),
+            path: RemotePath::from("/out/ai"),
+            ..Default::default()
+        },
+        pl_rule(),
+    ];
+    llm.set_response(
+        "Unsure",
+        ArticleMetadata {
+            title: "Neural Compilers".to_string(),
+            authors: vec!["Jeremy Siek".to_string()],
+            confidence: Some(0.2),
+            ..Default::default()
+        },
+        rules.clone(),
+    )
+    .await;
+
+    let dropbox = Arc::new(dropbox);
+    run_pipeline(
+        &work_dir,
+        &storage,
+        &dropbox,
+        Arc::new(llm),
+        rules,
+        PipelineOptions::default(),
+    )
+    .await;
+
+    let record = storage.get_file(&id).await.unwrap().unwrap();
+    assert_eq!(record.status, FileStatus::NeedsReview);
+    assert_eq!(record.title.as_deref(), Some("Neural Compilers"));
+    assert_eq!(
+        record.review_candidate_list(),
+        vec!["AI", "Programming Languages"]
+    );
+    assert!(record.last_error.unwrap().contains("Confidence 0.20"));
+    assert_eq!(record.processed_at, None);
+    assert!(storage.get_processed_since(None).await.unwrap().is_empty());
+    assert!(
+        !dropbox
+            .files
+            .lock()
+            .await
+            .keys()
+            .any(|path| path.starts_with("/out"))
+    );
+    let waiting = storage
+        .get_files_with_status(FileStatus::NeedsReview)
+        .await
+        .unwrap();
+    assert_eq!(waiting.len(), 1);
+}
+
+#[tokio::test]
+async fn test_rule_tree_files_under_the_most_specific_match() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let llm = FakeMistralClient::new();
+    let cs = Rule {
+        name: String::from("CS"),
+        description: String::from("Computer science"),
+        path: RemotePath::from("/out/cs"),
+        ..Default::default()
+    };
+    let ai = Rule {
+        name: String::from("AI"),
+        description: String::from("Machine learning"),
+        path: RemotePath::from("/out/cs/ai"),
+        parent: Some(String::from("CS")),
+        ..Default::default()
+    };
+    let rules = Rules::from(vec![cs.clone(), ai.clone()]);
+    rules.validate_tree().unwrap();
+    for (name, word, matched) in [
+        ("transformers", "Transformers", vec![cs.clone(), ai.clone()]),
+        ("compilers", "Compilers", vec![cs.clone()]),
+    ] {
+        add_pdf(&mut dropbox, name, word).await;
+        llm.set_response(
+            word,
+            ArticleMetadata {
+                title: word.to_string(),
+                authors: vec!["Ada Lovelace".to_string()],
+                ..Default::default()
+            },
+            matched,
+        )
+        .await;
+    }
+
+    let dropbox = Arc::new(dropbox);
+    sync_all(&storage, &dropbox, "/0_inbox").await;
+    let summary = Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        Arc::new(llm),
+        work_dir,
+        Arc::new(rules.clone()),
+    )
+    .run_batch(10, 1)
+    .await
+    .unwrap();
+    assert_eq!(summary.processed, 2);
+
+    // The leaf matched along with its parent, so the paper is filed under the leaf only
+    let leaf = storage
+        .get_file(&DropboxId("id:transformers".to_string()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        leaf.target_paths(),
+        vec![RemotePath::from("/out/cs/ai/transformers.pdf")]
+    );
+    // Only the parent matched, so the paper is filed under the parent
+    let parent = storage
+        .get_file(&DropboxId("id:compilers".to_string()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        parent.target_paths(),
+        vec![RemotePath::from("/out/cs/compilers.pdf")]
+    );
+    let files = dropbox.files.lock().await;
+    assert!(!files.contains_key("/out/cs/transformers.pdf"));
+
+    // The run's rules are recorded with their parents
+    assert_eq!(
+        storage.rules_for_run(&leaf.run_id.unwrap()).await.unwrap(),
+        rules
+    );
+}
+
+#[tokio::test]
+async fn test_max_categories_files_under_top_match_or_reviews() {
+    let rules = vec![
+        Rule {
+            name: String::from("AI"),
+            description: String::from("Artificial intelligence"),
+            path: RemotePath::from("/out/ai"),
+            ..Default::default()
+        },
+        pl_rule(),
+        Rule {
+            name: String::from("Databases"),
+            description: String::from("Query processing"),
+            path: RemotePath::from("/out/db"),
+            ..Default::default()
+        },
+    ];
+    for too_many_categories in [TooManyCategories::Top, TooManyCategories::Review] {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+        let mut dropbox = FakeDropboxClient::new();
+        let llm = FakeMistralClient::new();
+        let id = add_pdf(&mut dropbox, "broad", "Everything").await;
+        // Best match first, as the LLM lists them
+        let matched = vec![rules[1].clone(), rules[0].clone(), rules[2].clone()];
+        llm.set_response(
+            "Everything",
+            ArticleMetadata {
+                title: "Learned Query Compilers".to_string(),
+                authors: vec!["Jeremy Siek".to_string()],
+                ..Default::default()
+            },
+            matched,
+        )
+        .await;
+
+        let dropbox = Arc::new(dropbox);
+        let summary = run_pipeline(
+            &work_dir,
+            &storage,
+            &dropbox,
+            Arc::new(llm),
+            rules.clone(),
+            PipelineOptions {
+                max_categories: Some(1),
+                too_many_categories,
+                ..Default::default()
+            },
+        )
+        .await;
+
+        let record = storage.get_file(&id).await.unwrap().unwrap();
+        let filed: Vec<String> = dropbox
+            .files
+            .lock()
+            .await
+            .keys()
+            .filter(|path| path.starts_with("/out") && path.ends_with(".pdf"))
+            .cloned()
+            .collect();
+        match too_many_categories {
+            TooManyCategories::Top => {
+                assert_eq!(summary.processed, 1);
+                assert_eq!(filed, vec!["/out/pl/broad.pdf"]);
+                assert_eq!(
+                    record.target_paths(),
+                    vec![RemotePath::from("/out/pl/broad.pdf")]
+                );
+            }
+            TooManyCategories::Review => {
+                assert_eq!(summary.needs_review, 1);
+                assert!(filed.is_empty());
+                assert_eq!(record.status, FileStatus::NeedsReview);
+                assert!(
+                    record
+                        .last_error
+                        .unwrap()
+                        .contains("Matched 3 categories, more than 1")
+                );
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_implausible_metadata_needs_review() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let llm = FakeMistralClient::new();
+    let long_title = "Gradual typing ".repeat(30);
+    let cases = [
+        (
+            "long",
+            ArticleMetadata {
+                title: long_title.clone(),
+                authors: vec!["Jeremy Siek".to_string()],
+                ..Default::default()
+            },
+            "more than 300",
+        ),
+        (
+            "anonymous",
+            ArticleMetadata {
+                title: "Gradual Typing".to_string(),
+                ..Default::default()
+            },
+            "No authors",
+        ),
+        ("empty", ArticleMetadata::default(), "title is empty"),
+    ];
+    for (name, meta, _) in &cases {
+        add_pdf(&mut dropbox, name, name).await;
+        llm.set_response(name, meta.clone(), vec![pl_rule()]).await;
+    }
+
+    let dropbox = Arc::new(dropbox);
+    run_pipeline(
+        &work_dir,
+        &storage,
+        &dropbox,
+        Arc::new(llm),
+        vec![pl_rule()],
+        PipelineOptions::default(),
+    )
+    .await;
+
+    for (name, _, reason) in &cases {
+        let record = storage
+            .get_file(&DropboxId(format!("id:{}", name)))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.status, FileStatus::NeedsReview, "{}", name);
+        assert!(record.last_error.unwrap().contains(reason), "{}", name);
+    }
+    assert!(
+        !dropbox
+            .files
+            .lock()
+            .await
+            .keys()
+            .any(|path| path.starts_with("/out"))
+    );
+}
+
+#[tokio::test]
+async fn test_regenerate_sidecars_uploads_only_sidecars() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let llm = FakeMistralClient::new();
+    add_pdf(&mut dropbox, "sidecar", "Sidecar arXiv:2101.00001v2").await;
+    let rules = vec![
+        Rule {
+            name: String::from("AI"),
+            description: String::from("Artificial intelligence"),
+            path: RemotePath::from("/out/ai"),
+            ..Default::default()
+        },
+        pl_rule(),
+    ];
+    llm.set_response(
+        "Sidecar",
+        ArticleMetadata {
+            title: "Neural Compilers".to_string(),
+            authors: vec!["Jane Roe".to_string()],
+            ..Default::default()
+        },
+        rules.clone(),
+    )
+    .await;
+
+    let dropbox = Arc::new(dropbox);
+    run_pipeline(
+        &work_dir,
+        &storage,
+        &dropbox,
+        Arc::new(llm),
+        rules,
+        PipelineOptions::default(),
+    )
+    .await;
+    let sidecar_before = dropbox.files.lock().await["/out/ai/sidecar.pdf.md"].clone();
+    // The arXiv id found in the text is stored, so the regenerated sidecar still links to it
+    assert!(
+        String::from_utf8_lossy(&sidecar_before)
+            .contains("- arXiv: <https://arxiv.org/abs/2101.00001>")
+    );
+    dropbox.uploads.lock().await.clear();
+
+    let count = regenerate_sidecars(
+        &storage,
+        &*dropbox,
+        SidecarFormat::Markdown,
+        SidecarPlacement::Alongside,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(count, 2);
+    let mut uploads: Vec<String> = dropbox
+        .uploads
+        .lock()
+        .await
+        .iter()
+        .map(|(path, _)| path.0.clone())
+        .collect();
+    uploads.sort();
+    assert_eq!(
+        uploads,
+        vec!["/out/ai/sidecar.pdf.md", "/out/pl/sidecar.pdf.md"]
+    );
+    assert_eq!(
+        dropbox.files.lock().await["/out/ai/sidecar.pdf.md"],
+        sidecar_before
+    );
+}
+
+#[tokio::test]
+async fn test_sidecars_in_subfolder_keep_index_links_to_pdfs() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let llm = FakeMistralClient::new();
+    add_pdf(&mut dropbox, "paper", "Gradual").await;
+    llm.set_response(
+        "Gradual",
+        ArticleMetadata {
+            title: "Gradual Typing".to_string(),
+            authors: vec!["Jeremy Siek".to_string()],
+            ..Default::default()
+        },
+        vec![pl_rule()],
+    )
+    .await;
+
+    let dropbox = Arc::new(dropbox);
+    run_pipeline(
+        &work_dir,
+        &storage,
+        &dropbox,
+        Arc::new(llm),
+        vec![pl_rule()],
+        PipelineOptions {
+            sidecar_placement: SidecarPlacement::Subfolder,
+            ..Default::default()
+        },
+    )
+    .await;
+    generate_all_indexes(&storage, &*dropbox, &IndexOptions::default(), 1)
+        .await
+        .unwrap();
+
+    let files = dropbox.files.lock().await;
+    assert!(files.contains_key("/out/pl/paper.pdf"));
+    assert!(files.contains_key("/out/pl/_meta/paper.pdf.md"));
+    assert!(!files.contains_key("/out/pl/paper.pdf.md"));
+    assert!(!files.contains_key("/out/pl/_meta/README.md"));
+    let index = String::from_utf8_lossy(&files["/out/pl/README.md"]);
+    assert!(index.contains("](paper.pdf)"), "{}", index);
+}
+
+#[tokio::test]
+async fn test_only_changed_uploads_sidecars_of_changed_metadata_only() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let llm = Arc::new(FakeMistralClient::new());
+    let id = add_pdf(&mut dropbox, "paper", "Gradual").await;
+    let metadata = |title: &str| ArticleMetadata {
+        title: title.to_string(),
+        authors: vec!["Jeremy Siek".to_string()],
+        token_usage: Some(TokenUsage {
+            prompt_tokens: 1000,
+            completion_tokens: 200,
+        }),
+        ..Default::default()
+    };
+    llm.set_response("Gradual", metadata("Gradual Typing"), vec![pl_rule()])
+        .await;
+
+    let dropbox = Arc::new(dropbox);
+    sync_all(&storage, &dropbox, "/0_inbox").await;
+    let pipeline = Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        llm.clone(),
+        work_dir,
+        Arc::new(Rules::from(vec![pl_rule()])),
+    )
+    .with_options(PipelineOptions {
+        only_changed: true,
+        ..Default::default()
+    });
+    let first = pipeline.run_batch(10, 1).await.unwrap();
+    assert_eq!((first.processed, first.unchanged), (1, 0));
+    assert_eq!(dropbox.uploads.lock().await.len(), 2);
+
+    // Processing the file again with the same answer uploads nothing
+    dropbox.uploads.lock().await.clear();
+    storage
+        .update_status(&id, FileStatus::Pending)
+        .await
+        .unwrap();
+    let filed = storage.get_file(&id).await.unwrap().unwrap();
+    // The tokens used to find that out still count
+    let same_again = ArticleMetadata {
+        token_usage: Some(TokenUsage {
+            prompt_tokens: 500,
+            completion_tokens: 100,
+        }),
+        ..metadata("Gradual Typing")
+    };
+    llm.set_response("Gradual", same_again, vec![pl_rule()])
+        .await;
+    let same = pipeline.run_batch(10, 1).await.unwrap();
+    assert_eq!((same.processed, same.unchanged), (1, 1));
+    assert_eq!(same.token_usage.total(), 600);
+    assert!(dropbox.uploads.lock().await.is_empty());
+    let record = storage.get_file(&id).await.unwrap().unwrap();
+    assert_eq!(record.status, FileStatus::Processed);
+    assert_eq!(
+        (record.prompt_tokens, record.completion_tokens),
+        (Some(500), Some(100))
+    );
+    assert_eq!(record.updated_at, filed.updated_at);
+    assert_eq!(record.processed_at, filed.processed_at);
+
+    // A better title only changes the sidecar
+    llm.set_response(
+        "Gradual",
+        metadata("Gradual Typing for Functional Languages"),
+        vec![pl_rule()],
+    )
+    .await;
+    storage
+        .update_status(&id, FileStatus::Pending)
+        .await
+        .unwrap();
+    let changed = pipeline.run_batch(10, 1).await.unwrap();
+    assert_eq!((changed.processed, changed.unchanged), (1, 0));
+    let uploads = dropbox.uploads.lock().await;
+    assert_eq!(uploads.len(), 1);
+    assert_eq!(uploads[0].0, RemotePath::from("/out/pl/paper.pdf.md"));
+    let record = storage.get_file(&id).await.unwrap().unwrap();
+    assert_eq!(
+        record.title.as_deref(),
+        Some("Gradual Typing for Functional Languages")
+    );
+    assert!(record.updated_at > filed.updated_at);
+    assert!(record.processed_at > filed.processed_at);
+}
+
+/// Reads every file as the same text, as an OCR engine might read a scanned paper.
+struct OcrExtractor;
+
+impl TextExtractor for OcrExtractor {
+    fn extract(&self, _content: &[u8]) -> anyhow::Result<String> {
+        Ok(String::from("Gradual typing, as read by OCR"))
+    }
+}
+
+#[tokio::test]
+async fn test_extracted_text_hash_changes_with_the_extractor_only() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let llm = Arc::new(FakeMistralClient::new());
+    let id = add_pdf(&mut dropbox, "scan", "Gradual").await;
+    let dropbox = Arc::new(dropbox);
+    sync_all(&storage, &dropbox, "/0_inbox").await;
+    let rules = Arc::new(Rules::from(vec![pl_rule()]));
+    let pipeline = |extractor: Option<Arc<dyn TextExtractor>>| {
+        let pipeline = Pipeline::new(
+            storage.clone(),
+            dropbox.clone(),
+            llm.clone(),
+            work_dir.clone(),
+            rules.clone(),
+        );
+        match extractor {
+            Some(extractor) => pipeline.with_extractor(extractor),
+            None => pipeline,
+        }
+    };
+    let process = |extractor: Option<Arc<dyn TextExtractor>>| {
+        let pipeline = pipeline(extractor);
+        let storage = storage.clone();
+        let id = id.clone();
+        async move {
+            storage
+                .update_status(&id, FileStatus::Pending)
+                .await
+                .unwrap();
+            assert_eq!(pipeline.run_batch(10, 1).await.unwrap().processed, 1);
+            storage.get_file(&id).await.unwrap().unwrap()
+        }
+    };
+
+    let first = process(None).await;
+    let first_hash = first.extracted_text_hash.clone().unwrap();
+    assert_eq!(
+        first_hash,
+        text_hash(&extract_text(&pdf_bytes(&["Gradual"])).unwrap())
+    );
+    let first_run = first.run_id.unwrap();
+    let changed_since_first = ListFilter {
+        text_changed_since: Some(&first_run),
+        ..Default::default()
+    };
+
+    // The same extractor reads the same text
+    let second = process(None).await;
+    assert_eq!(
+        second.extracted_text_hash.as_deref(),
+        Some(first_hash.as_str())
+    );
+    assert_ne!(second.run_id.as_ref(), Some(&first_run));
+    assert_eq!(storage.count_files(&changed_since_first).await.unwrap(), 0);
+
+    // Another extractor reads other text
+    let ocr = process(Some(Arc::new(OcrExtractor))).await;
+    let ocr_hash = ocr.extracted_text_hash.unwrap();
+    assert_ne!(ocr_hash, first_hash);
+    assert_eq!(ocr_hash, text_hash("Gradual typing, as read by OCR"));
+    let changed = storage
+        .list_files(&changed_since_first, None, 0)
+        .await
+        .unwrap();
+    assert_eq!(changed.len(), 1);
+    assert_eq!(changed[0].dropbox_id, id);
+    let changed_since_ocr = ListFilter {
+        text_changed_since: ocr.run_id.as_ref(),
+        ..Default::default()
+    };
+    assert_eq!(storage.count_files(&changed_since_ocr).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_verify_reports_and_resets_papers_missing_from_dropbox() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let llm = FakeMistralClient::new();
+    for (name, word) in [("kept", "Gradual"), ("moved", "Effects")] {
+        add_pdf(&mut dropbox, name, word).await;
+    }
+    for word in ["Gradual", "Effects"] {
+        llm.set_response(
+            word,
+            ArticleMetadata {
+                title: format!("{} Typing", word),
+                authors: vec!["Jeremy Siek".to_string()],
+                ..Default::default()
+            },
+            vec![pl_rule()],
+        )
+        .await;
+    }
+
+    let dropbox = Arc::new(dropbox);
+    run_pipeline(
+        &work_dir,
+        &storage,
+        &dropbox,
+        Arc::new(llm),
+        vec![pl_rule()],
+        PipelineOptions::default(),
+    )
+    .await;
+    // The PDF was moved elsewhere by hand, leaving its sidecar behind
+    dropbox.files.lock().await.remove("/out/pl/moved.pdf");
+
+    let report = verify_library(
+        &storage,
+        &*dropbox,
+        SidecarFormat::Markdown,
+        SidecarPlacement::Alongside,
+        false,
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.checked, 2);
+    assert_eq!(report.missing, vec![RemotePath::from("/out/pl/moved.pdf")]);
+    assert_eq!(report.orphaned, vec![DropboxId("id:moved".to_string())]);
+    assert!(report.reset.is_empty());
+    let moved = DropboxId("id:moved".to_string());
+    let record = storage.get_file(&moved).await.unwrap().unwrap();
+    assert_eq!(record.status, FileStatus::Processed);
+
+    let report = verify_library(
+        &storage,
+        &*dropbox,
+        SidecarFormat::Markdown,
+        SidecarPlacement::Alongside,
+        true,
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.reset, vec![moved.clone()]);
+    let record = storage.get_file(&moved).await.unwrap().unwrap();
+    assert_eq!(record.status, FileStatus::Pending);
+    let kept = storage
+        .get_file(&DropboxId("id:kept".to_string()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(kept.status, FileStatus::Processed);
+}
+
+#[tokio::test]
+async fn test_rename_from_metadata_uses_slug() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let llm = FakeMistralClient::new();
+    dropbox
+        .add_entry(
+            DropboxEntry {
+                id: DropboxId("id:messy".to_string()),
+                name: "1706.03762v7 (1).pdf".to_string(),
+                path: RemotePath("/0_inbox/1706.03762v7 (1).pdf".to_string()),
+                content_hash: FileHash("hash-messy".to_string()),
+                size: 0,
+                server_modified: None,
+            },
+            pdf_bytes(&["Attention"]),
+        )
+        .await;
+    let ai_rule = Rule {
+        name: String::from("AI"),
+        description: String::from("Artificial intelligence"),
+        path: RemotePath::from("/out/ai"),
+        ..Default::default()
+    };
+    llm.set_response(
+        "Attention",
+        ArticleMetadata {
+            title: "Attention Is All You Need".to_string(),
+            authors: vec!["Ashish Vaswani".to_string()],
+            year: Some(2017),
+            ..Default::default()
+        },
+        vec![ai_rule.clone()],
+    )
+    .await;
+
+    let dropbox = Arc::new(dropbox);
+    run_pipeline(
+        &work_dir,
+        &storage,
+        &dropbox,
+        Arc::new(llm),
+        vec![ai_rule],
+        PipelineOptions {
+            rename_from_metadata: true,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let files = dropbox.files.lock().await;
+    assert!(files.contains_key("/out/ai/vaswani-2017-attention-is-all-you-need.pdf"));
+    assert!(files.contains_key("/out/ai/vaswani-2017-attention-is-all-you-need.pdf.md"));
+}
+
+#[tokio::test]
+async fn test_run_rules_snapshot_matches_rules_used() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let id = add_pdf(&mut dropbox, "snapshot", "Snapshot").await;
+    let rules = Rules::from(vec![
+        pl_rule(),
+        Rule {
+            name: String::from("AI"),
+            description: String::from("Artificial intelligence"),
+            path: RemotePath::from("/out/ai/{{year}}"),
+            ..Default::default()
+        },
+    ]);
+
+    let dropbox = Arc::new(dropbox);
+    sync_all(&storage, &dropbox, "/0_inbox").await;
+    Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        Arc::new(FakeMistralClient::new()),
+        work_dir,
+        Arc::new(rules.clone()),
+    )
+    .run_batch(10, 1)
+    .await
+    .unwrap();
+
+    let record = storage.get_file(&id).await.unwrap().unwrap();
+    let run_id = record.run_id.expect("processed file has a run id");
+    assert_eq!(storage.rules_for_run(&run_id).await.unwrap(), rules);
+    assert!(
+        storage
+            .rules_for_run(&RunId("unknown".to_string()))
+            .await
+            .unwrap()
+            .0
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn test_list_files_pages_do_not_overlap() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (_work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    for n in [3, 1, 5, 2, 4] {
+        let id = DropboxId(format!("id:{}", n));
+        storage
+            .upsert_file(&id, &format!("{}.pdf", n), &FileHash(format!("hash-{}", n)))
+            .await
+            .unwrap();
+        let meta = ArticleMetadata {
+            title: format!("Paper {}", n),
+            ..Default::default()
+        };
+        storage
+            .update_metadata(&id, meta, &[], FileStatus::Processed)
+            .await
+            .unwrap();
+    }
+
+    let page = storage
+        .list_files(&ListFilter::default(), Some(2), 2)
+        .await
+        .unwrap();
+
+    let titles = page
+        .iter()
+        .map(|file| file.title.clone().unwrap())
+        .collect::<Vec<String>>();
+    assert_eq!(titles, vec!["Paper 3", "Paper 4"]);
+    assert_eq!(
+        storage.count_files(&ListFilter::default()).await.unwrap(),
+        5
+    );
+    assert_eq!(
+        storage
+            .list_files(&ListFilter::default(), Some(2), 4)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_index_since_only_updates_recent_papers() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (_work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let dropbox = FakeDropboxClient::new();
+    let file_paper = |name: &'static str| {
+        let storage = storage.clone();
+        async move {
+            let id = DropboxId(format!("id:{}", name));
+            storage
+                .upsert_file(&id, &format!("{}.pdf", name), &FileHash(name.to_string()))
+                .await
+                .unwrap();
+            let meta = ArticleMetadata {
+                title: format!("Paper {}", name),
+                ..Default::default()
+            };
+            let target = RemotePath(format!("/out/pl/{}.pdf", name));
+            storage
+                .update_metadata(&id, meta, &[target], FileStatus::Processed)
+                .await
+                .unwrap();
+        }
+    };
+    let readme = |dropbox: &FakeDropboxClient| {
+        let files = dropbox.files.clone();
+        async move { String::from_utf8(files.lock().await["/out/pl/README.md"].clone()).unwrap() }
+    };
+
+    file_paper("old").await;
+    generate_index(&storage, &dropbox, "/out/pl", &IndexOptions::default())
+        .await
+        .unwrap();
+    let since = chrono::Utc::now();
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    file_paper("new").await;
+    let options = IndexOptions {
+        since: Some(since),
+        ..Default::default()
+    };
+
+    // Merged into the existing index, which keeps the older paper
+    generate_index(&storage, &dropbox, "/out/pl", &options)
+        .await
+        .unwrap();
+    let merged = readme(&dropbox).await;
+    assert!(merged.contains("[Paper old](old.pdf)"));
+    assert_eq!(merged.matches("[Paper new](new.pdf)").count(), 1);
+
+    // Without an existing index, only the recent paper appears
+    dropbox.files.lock().await.remove("/out/pl/README.md");
+    generate_index(&storage, &dropbox, "/out/pl", &options)
+        .await
+        .unwrap();
+    let recent = readme(&dropbox).await;
+    assert!(!recent.contains("Paper old"));
+    assert!(recent.contains("[Paper new](new.pdf)"));
+}
+
+#[tokio::test]
+async fn test_index_links_to_shared_links() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (_work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let dropbox = CountingDropboxClient::new(FakeDropboxClient::new());
+    let id = DropboxId("id:shared".to_string());
+    storage
+        .upsert_file(&id, "shared.pdf", &FileHash("shared".to_string()))
+        .await
+        .unwrap();
+    let meta = ArticleMetadata {
+        title: "Shared Paper".to_string(),
+        ..Default::default()
+    };
+    storage
+        .update_metadata(
+            &id,
+            meta,
+            &[RemotePath::from("/out/pl/shared.pdf")],
+            FileStatus::Processed,
+        )
+        .await
+        .unwrap();
+    let options = IndexOptions {
+        links: IndexLinks::Shared,
+        recently_added: 1,
+        ..Default::default()
+    };
+
+    generate_index(&storage, &dropbox, "/out/pl", &options)
+        .await
+        .unwrap();
+
+    let readme =
+        String::from_utf8(dropbox.inner.files.lock().await["/out/pl/README.md"].clone()).unwrap();
+    assert!(readme.contains("- [Shared Paper](https://dropbox.example/s/out/pl/shared.pdf?dl=0)"));
+    assert!(readme.contains("| [Shared Paper](https://dropbox.example/s/out/pl/shared.pdf?dl=0)"));
+    // The recently added line reuses the link created for the row
+    assert_eq!(dropbox.shared_links.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_index_summary_line_counts_rows_and_top_author() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (_work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let dropbox = FakeDropboxClient::new();
+    let file_paper = |n: usize| {
+        let storage = storage.clone();
+        async move {
+            let id = DropboxId(format!("id:{}", n));
+            storage
+                .upsert_file(&id, "paper.pdf", &FileHash(format!("hash-{}", n)))
+                .await
+                .unwrap();
+            let mut authors = vec!["Ada Lovelace".to_string()];
+            if n.is_multiple_of(2) {
+                authors.push("Jeremy Siek".to_string());
+            }
+            let meta = ArticleMetadata {
+                title: format!("Paper {}", n),
+                authors,
+                ..Default::default()
+            };
+            storage
+                .update_metadata(
+                    &id,
+                    meta,
+                    &[RemotePath(format!("/out/pl/{}.pdf", n))],
+                    FileStatus::Processed,
+                )
+                .await
+                .unwrap();
+        }
+    };
+    for n in 0..7 {
+        file_paper(n).await;
+    }
+    let readme = |dropbox: &FakeDropboxClient| {
+        let files = dropbox.files.try_lock().unwrap();
+        String::from_utf8(files["/out/pl/README.md"].clone()).unwrap()
+    };
+    let table_rows = |index: &str| index.lines().filter(|line| line.starts_with("| [")).count();
+    let options = IndexOptions {
+        summary_line: true,
+        ..Default::default()
+    };
+
+    generate_index(&storage, &dropbox, "/out/pl", &options)
+        .await
+        .unwrap();
+
+    let index = readme(&dropbox);
+    assert_eq!(
+        index.lines().next(),
+        Some("7 papers, most by Ada Lovelace (7)")
+    );
+    assert_eq!(table_rows(&index), 7);
+
+    // Updating the index counts the rows it keeps as well as the new ones
+    let since = chrono::Utc::now();
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    file_paper(7).await;
+    generate_index(
+        &storage,
+        &dropbox,
+        "/out/pl",
+        &IndexOptions {
+            since: Some(since),
+            ..options
+        },
+    )
+    .await
+    .unwrap();
+
+    let index = readme(&dropbox);
+    assert_eq!(
+        index.lines().next(),
+        Some("8 papers, most by Ada Lovelace (8)")
+    );
+    assert_eq!(table_rows(&index), 8);
+}
+
+#[tokio::test]
+async fn test_index_lists_recently_added_papers_above_the_table() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (_work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let dropbox = FakeDropboxClient::new();
+    for n in 0..7 {
+        let id = DropboxId(format!("id:{}", n));
+        storage
+            .upsert_file(&id, "paper.pdf", &FileHash(format!("hash-{}", n)))
+            .await
+            .unwrap();
+        let meta = ArticleMetadata {
+            // Titles in the opposite order of processing, so the table order differs
+            title: format!("Paper {}", 9 - n),
+            authors: vec!["Ada Lovelace".to_string()],
+            ..Default::default()
+        };
+        storage
+            .update_metadata(
+                &id,
+                meta,
+                &[RemotePath(format!("/out/pl/{}.pdf", n))],
+                FileStatus::Processed,
+            )
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    }
+    // Papers in subfolders are not in this folder's index
+    storage
+        .upsert_file(
+            &DropboxId("id:sub".to_string()),
+            "sub.pdf",
+            &FileHash("hash-sub".to_string()),
+        )
+        .await
+        .unwrap();
+    storage
+        .update_metadata(
+            &DropboxId("id:sub".to_string()),
+            ArticleMetadata {
+                title: String::from("Elsewhere"),
+                ..Default::default()
+            },
+            &[RemotePath::from("/out/pl/sub/sub.pdf")],
+            FileStatus::Processed,
+        )
+        .await
+        .unwrap();
+
+    generate_index(
+        &storage,
+        &dropbox,
+        "/out/pl",
+        &IndexOptions {
+            recently_added: 5,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let index = String::from_utf8(dropbox.files.lock().await["/out/pl/README.md"].clone()).unwrap();
+    let today = chrono::Utc::now().format("%Y-%m-%d");
+    let (recent, table) = index.split_once("## All papers").unwrap();
+    let recent: Vec<&str> = recent
+        .lines()
+        .filter(|line| line.starts_with("- "))
+        .collect();
+    assert_eq!(
+        recent,
+        [(6, 3), (5, 4), (4, 5), (3, 6), (2, 7)]
+            .map(|(n, title)| format!("- [Paper {}]({}.pdf) by Ada Lovelace, {}", title, n, today))
+    );
+    assert!(index.starts_with("## Recently added\n\n- [Paper 3](6.pdf)"));
+    assert!(!index.contains("Elsewhere"));
+    // The table still lists every paper, by title
+    assert_eq!(
+        table.lines().filter(|line| line.starts_with("| [")).count(),
+        7
+    );
+    assert!(table.contains("| Title | Authors | Summary |"));
+
+    // Indexes are written without the section unless asked for
+    generate_index(&storage, &dropbox, "/out/pl", &IndexOptions::default())
+        .await
+        .unwrap();
+    let index = String::from_utf8(dropbox.files.lock().await["/out/pl/README.md"].clone()).unwrap();
+    assert!(index.starts_with("| Title | Authors | Summary |"));
+}
+
+#[tokio::test]
+async fn test_sync_skips_generated_files() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (_work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    for (id, name) in [("id:readme", "README.md"), ("id:paper", "paper.pdf")] {
+        dropbox
+            .add_entry(
+                DropboxEntry {
+                    id: DropboxId(id.to_string()),
+                    name: name.to_string(),
+                    path: RemotePath(format!("/0_inbox/{}", name)),
+                    content_hash: FileHash(format!("hash-{}", id)),
+                    size: 0,
+                    server_modified: None,
+                },
+                vec![],
+            )
+            .await;
+    }
+    let skip_suffixes = DEFAULT_SKIPPED_SUFFIXES
+        .iter()
+        .map(|suffix| suffix.to_string())
+        .collect::<Vec<String>>();
+
+    let count = sync_inbox(&storage, &dropbox, "/0_inbox", false, &skip_suffixes, &[])
+        .await
+        .unwrap();
+
+    assert_eq!(count, 2);
+    let pending = storage.get_pending_files(10).await.unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].dropbox_id.0, "id:paper");
+    let readme = storage
+        .get_file(&DropboxId("id:readme".to_string()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(readme.status, FileStatus::Skipped);
+}
+
+#[tokio::test]
+async fn test_sync_several_inboxes_records_source_folder() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (_work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    for (id, path) in [
+        ("id:mine", "/0_inbox/mine.pdf"),
+        ("id:shared", "/shared/incoming/shared.pdf"),
+        ("id:elsewhere", "/elsewhere/other.pdf"),
+    ] {
+        dropbox
+            .add_entry(
+                DropboxEntry {
+                    id: DropboxId(id.to_string()),
+                    name: path.rsplit('/').next().unwrap().to_string(),
+                    path: RemotePath::from(path),
+                    content_hash: FileHash(format!("hash-{}", id)),
+                    size: 0,
+                    server_modified: None,
+                },
+                vec![],
+            )
+            .await;
+    }
+
+    for inbox in ["/0_inbox", "/shared/incoming"] {
+        sync_inbox(&storage, &dropbox, inbox, false, &[], &[])
+            .await
+            .unwrap();
+    }
+
+    let mut queued = storage
+        .get_pending_files(10)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|file| (file.dropbox_id.0, file.source_folder.unwrap()))
+        .collect::<Vec<(String, String)>>();
+    queued.sort();
+    assert_eq!(
+        queued,
+        vec![
+            ("id:mine".to_string(), "/0_inbox".to_string()),
+            ("id:shared".to_string(), "/shared/incoming".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_recursive_sync_queues_files_in_subfolders() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (_work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    for (id, path) in [
+        ("id:top", "/0_inbox/top.pdf"),
+        ("id:arxiv", "/0_inbox/arxiv/nested.pdf"),
+        ("id:deep", "/0_inbox/journals/acm/deep.pdf"),
+    ] {
+        dropbox
+            .add_entry(
+                DropboxEntry {
+                    id: DropboxId(id.to_string()),
+                    name: path.rsplit('/').next().unwrap().to_string(),
+                    path: RemotePath::from(path),
+                    content_hash: FileHash(format!("hash-{}", id)),
+                    size: 0,
+                    server_modified: None,
+                },
+                vec![],
+            )
+            .await;
+    }
+    let queued = |storage: Arc<Storage>| async move {
+        let mut ids = storage
+            .get_pending_files(10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|file| file.dropbox_id.0)
+            .collect::<Vec<String>>();
+        ids.sort();
+        ids
+    };
+
+    let count = sync_inbox(&storage, &dropbox, "/0_inbox", false, &[], &[])
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+    assert_eq!(queued(storage.clone()).await, vec!["id:top"]);
+
+    let count = sync_inbox(&storage, &dropbox, "/0_inbox", true, &[], &[])
+        .await
+        .unwrap();
+    assert_eq!(count, 3);
+    assert_eq!(
+        queued(storage.clone()).await,
+        vec!["id:arxiv", "id:deep", "id:top"]
+    );
+}
+
+#[tokio::test]
+async fn test_recursive_sync_of_root_inbox_leaves_out_the_library() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (_work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    for (id, path) in [
+        ("id:new", "/new.pdf"),
+        ("id:nested", "/incoming/nested.pdf"),
+        ("id:filed", "/sorted/pl/filed.pdf"),
+        ("id:sibling", "/sorted-old/kept.pdf"),
+    ] {
+        dropbox
+            .add_entry(
+                DropboxEntry {
+                    id: DropboxId(id.to_string()),
+                    name: path.rsplit('/').next().unwrap().to_string(),
+                    path: RemotePath::from(path),
+                    content_hash: FileHash(format!("hash-{}", id)),
+                    size: 0,
+                    server_modified: None,
+                },
+                vec![],
+            )
+            .await;
+    }
+
+    let count = sync_inbox(
+        &storage,
+        &dropbox,
+        "",
+        true,
+        &[],
+        &[String::from("/Sorted")],
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(count, 3);
+    let mut queued: Vec<String> = storage
+        .get_pending_files(10)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|file| file.dropbox_id.0)
+        .collect();
+    queued.sort();
+    assert_eq!(queued, vec!["id:nested", "id:new", "id:sibling"]);
+}
+
+#[tokio::test]
+async fn test_analyze_local_file() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path().join("local.pdf");
+    fs::write(&path, pdf_bytes(&["Gradual Typing"])).unwrap();
+    let llm = FakeMistralClient::new();
+    llm.set_response(
+        "Gradual Typing",
+        ArticleMetadata {
+            title: String::from("Gradual Typing for Everyone"),
+            ..Default::default()
+        },
+        vec![pl_rule()],
+    )
+    .await;
+
+    let analysis = analyze_local_file(&path, &llm, &Rules::from(vec![pl_rule()]))
+        .await
+        .unwrap();
+
+    assert_eq!(analysis.metadata.title, "Gradual Typing for Everyone");
+    assert_eq!(analysis.rules, vec![pl_rule()]);
+    let json = serde_json::to_value(&analysis).unwrap();
+    assert_eq!(json["rules"][0]["path"], "/out/pl");
+}
+
+#[tokio::test]
+async fn test_target_outside_upload_prefix_is_not_uploaded() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let id = add_pdf(&mut dropbox, "outside", "Escape").await;
+    let edited_rule = Rule {
+        name: String::from("Escape"),
+        description: String::from("A rule edited to point outside the library"),
+        path: RemotePath::from("/private/escape"),
+        ..Default::default()
+    };
+    let llm = FakeMistralClient::new();
+    llm.set_response(
+        "Escape",
+        ArticleMetadata {
+            title: String::from("Escape"),
+            ..Default::default()
+        },
+        vec![edited_rule.clone()],
+    )
+    .await;
+
+    let dropbox = Arc::new(dropbox);
+    run_pipeline(
+        &work_dir,
+        &storage,
+        &dropbox,
+        Arc::new(llm),
+        vec![edited_rule],
+        PipelineOptions {
+            allowed_upload_prefixes: vec![String::from("/out")],
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert!(dropbox.uploads.lock().await.is_empty());
+    let record = storage.get_file(&id).await.unwrap().unwrap();
+    assert_eq!(record.status, FileStatus::NeedsReview);
+    assert!(
+        record
+            .last_error
+            .unwrap()
+            .contains("outside the allowed upload prefix")
+    );
+}
+
+/// A Dropbox client whose listings fail with a connection error `failures` times, and which
+/// otherwise delegates to a fake.
+struct FlakyDropboxClient {
+    failures: usize,
+    calls: AtomicUsize,
+    inner: FakeDropboxClient,
+}
+
+#[async_trait]
+impl DropboxClient for FlakyDropboxClient {
+    async fn list_folder(&self, path: &str) -> anyhow::Result<Vec<DropboxEntry>> {
+        if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+            return Err(anyhow::Error::from(std::io::Error::from(
+                std::io::ErrorKind::ConnectionReset,
+            )));
+        }
+        self.inner.list_folder(path).await
+    }
+    async fn list_folder_recursive(&self, path: &str) -> anyhow::Result<Vec<DropboxEntry>> {
+        self.inner.list_folder_recursive(path).await
+    }
+    async fn get_metadata(&self, path: &RemotePath) -> anyhow::Result<Option<DropboxEntry>> {
+        self.inner.get_metadata(path).await
+    }
+    async fn download_file(&self, id: &DropboxId) -> anyhow::Result<Vec<u8>> {
+        self.inner.download_file(id).await
+    }
+    async fn upload_file(&self, path: &RemotePath, content: Vec<u8>) -> anyhow::Result<()> {
+        self.inner.upload_file(path, content).await
+    }
+    async fn folder_exists(&self, path: &str) -> anyhow::Result<bool> {
+        self.inner.folder_exists(path).await
+    }
+    async fn create_folder(&self, path: &str) -> anyhow::Result<()> {
+        self.inner.create_folder(path).await
+    }
+    async fn create_folder_if_not_exists(&self, path: &str) -> anyhow::Result<()> {
+        self.inner.create_folder_if_not_exists(path).await
+    }
+    async fn move_file(&self, from: &RemotePath, to: &RemotePath) -> anyhow::Result<()> {
+        self.inner.move_file(from, to).await
+    }
+    async fn delete_file(&self, path: &RemotePath) -> anyhow::Result<()> {
+        self.inner.delete_file(path).await
+    }
+    async fn create_shared_link(&self, path: &RemotePath) -> anyhow::Result<String> {
+        self.inner.create_shared_link(path).await
+    }
+}
+
+#[tokio::test]
+async fn test_sync_retries_connection_errors() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (_work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut inner = FakeDropboxClient::new();
+    add_pdf(&mut inner, "blip", "Blip").await;
+    let dropbox = FlakyDropboxClient {
+        failures: 2,
+        calls: AtomicUsize::new(0),
+        inner,
+    };
+
+    let count = retry_async(3, || {
+        sync_inbox(&storage, &dropbox, "/0_inbox", false, &[], &[])
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(count, 1);
+    assert_eq!(dropbox.calls.load(Ordering::SeqCst), 3);
+    assert_eq!(storage.get_pending_files(10).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_vacuum_populated_database() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (_work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    for n in 0..200 {
+        let id = DropboxId(format!("id:{}", n));
+        storage
+            .upsert_file(&id, &format!("{}.pdf", n), &FileHash(format!("hash-{}", n)))
+            .await
+            .unwrap();
+        let meta = ArticleMetadata {
+            abstract_text: "An abstract that takes up some space. ".repeat(20),
+            ..Default::default()
+        };
+        storage
+            .update_metadata(&id, meta, &[], FileStatus::Processed)
+            .await
+            .unwrap();
+    }
+    // Rewrite every row, leaving free pages behind
+    for n in 0..200 {
+        let id = DropboxId(format!("id:{}", n));
+        storage
+            .update_metadata(&id, ArticleMetadata::default(), &[], FileStatus::Processed)
+            .await
+            .unwrap();
+    }
+    let before = storage.database_size().await.unwrap();
+
+    storage.vacuum().await.unwrap();
+
+    assert!(storage.database_size().await.unwrap() <= before);
+    assert_eq!(
+        storage.count_files(&ListFilter::default()).await.unwrap(),
+        200
+    );
+}
+
+#[tokio::test]
+async fn test_index_of_large_folder() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (_work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let records: Vec<FileRecord> = (0..5000)
+        .map(|n| FileRecord {
+            dropbox_id: DropboxId(format!("id:{}", n)),
+            file_name: Some(format!("{}.pdf", n)),
+            content_hash: FileHash(format!("hash-{}", n)),
+            status: FileStatus::Archived,
+            title: Some(format!("Paper {}", n)),
+            authors: Some(serde_json::to_string(&[format!("Author {}", n % 100)]).unwrap()),
+            summary: Some("A summary".to_string()),
+            target_path: Some(serde_json::to_string(&[format!("/out/big/{}.pdf", n)]).unwrap()),
+            updated_at: chrono::Utc::now(),
+            processed_at: Some(chrono::Utc::now()),
+            ..Default::default()
+        })
+        .collect();
+    storage.import_all(&records).await.unwrap();
+    let dropbox = FakeDropboxClient::new();
+    let options = IndexOptions {
+        by_author: true,
+        ..Default::default()
+    };
+
+    generate_index(&storage, &dropbox, "/out/big", &options)
+        .await
+        .unwrap();
+
+    let readme = dropbox
+        .download_file(&DropboxId("/out/big/README.md".to_string()))
+        .await
+        .unwrap();
+    let readme = String::from_utf8(readme).unwrap();
+    // Two header lines, then one row per paper
+    assert_eq!(readme.lines().count(), 5002);
+    let authors = dropbox
+        .download_file(&DropboxId("/out/big/AUTHORS.md".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(String::from_utf8(authors).unwrap().lines().count(), 102);
+}
+
+#[tokio::test]
+async fn test_duplicates_are_found_by_hash_index_in_large_library() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let records: Vec<FileRecord> = (0..5000)
+        .map(|n| FileRecord {
+            dropbox_id: DropboxId(format!("id:{}", n)),
+            file_name: Some(format!("{}.pdf", n)),
+            // Every file but the last is a copy of one of a hundred others
+            content_hash: FileHash(format!("hash-{}", if n == 4999 { n } else { n % 100 })),
+            status: FileStatus::Processed,
+            updated_at: chrono::Utc::now(),
+            ..Default::default()
+        })
+        .collect();
+    storage.import_all(&records).await.unwrap();
+
+    let id = |n: usize| DropboxId(format!("id:{}", n));
+    assert_eq!(
+        storage
+            .find_duplicate(&id(4250), &FileHash("hash-50".to_string()))
+            .await
+            .unwrap(),
+        Some(id(50))
+    );
+    assert_eq!(
+        storage
+            .find_duplicate(&id(4999), &FileHash("hash-4999".to_string()))
+            .await
+            .unwrap(),
+        None
+    );
+    let pool = open_db(&sqlite_url(&work_dir.0.join("state.db")))
+        .await
+        .unwrap();
+    let plan = sqlx::query(
+        "EXPLAIN QUERY PLAN SELECT dropbox_id FROM files WHERE content_hash = 'hash-50'",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    let plan: Vec<String> = plan.iter().map(|step| step.get("detail")).collect();
+    assert!(
+        plan.iter()
+            .any(|step| step.contains("idx_files_content_hash")),
+        "{:?}",
+        plan
+    );
+}
+
+#[tokio::test]
+async fn test_crash_after_upload_does_not_upload_again() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    for name in ["uploaded", "interrupted"] {
+        add_pdf(&mut dropbox, name, "Compilers").await;
+    }
+    let llm = FakeMistralClient::new();
+    llm.set_response("Compilers", paper_metadata("Compilers"), vec![pl_rule()])
+        .await;
+    let dropbox = Arc::new(dropbox);
+    sync_all(&storage, &dropbox, "/0_inbox").await;
+
+    // A run that crashed after recording both files as uploading, with only the first
+    // file's uploads done
+    for name in ["uploaded", "interrupted"] {
+        let meta = ArticleMetadata {
+            title: format!("The {} paper", name),
+            ..Default::default()
+        };
+        storage
+            .record_upload_intent(
+                &DropboxId(format!("id:{}", name)),
+                meta,
+                &[RemotePath(format!("/out/pl/{}.pdf", name))],
+            )
+            .await
+            .unwrap();
+    }
+    dropbox
+        .upload_file(&RemotePath::from("/out/pl/uploaded.pdf"), vec![1])
+        .await
+        .unwrap();
+    dropbox
+        .upload_file(&RemotePath::from("/out/pl/uploaded.pdf.md"), vec![1])
+        .await
+        .unwrap();
+    dropbox.uploads.lock().await.clear();
+
+    Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        Arc::new(llm),
+        work_dir,
+        Arc::new(Rules::from(vec![pl_rule()])),
+    )
+    .run_batch(10, 1)
+    .await
+    .unwrap();
+
+    let uploaded = storage
+        .get_file(&DropboxId("id:uploaded".to_string()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(uploaded.status, FileStatus::Processed);
+    assert_eq!(uploaded.title.as_deref(), Some("The uploaded paper"));
+    let interrupted = storage
+        .get_file(&DropboxId("id:interrupted".to_string()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(interrupted.status, FileStatus::Processed);
+    let uploads: Vec<String> = dropbox
+        .uploads
+        .lock()
+        .await
+        .iter()
+        .map(|(path, _)| path.0.clone())
+        .collect();
+    assert_eq!(
+        uploads,
+        vec!["/out/pl/interrupted.pdf", "/out/pl/interrupted.pdf.md"]
+    );
+}
+
+#[tokio::test]
+async fn test_failed_upload_is_not_listed_as_filed() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    // Dropbox rejects the upload to the target the pipeline accepts
+    let mut dropbox = FakeDropboxClient::new().with_allowed_upload_prefix("/elsewhere");
+    let id = add_pdf(&mut dropbox, "rejected", "Compilers").await;
+    let llm = FakeMistralClient::new();
+    llm.set_response(
+        "Compilers",
+        ArticleMetadata {
+            title: String::from("A Compiler"),
+            authors: vec![String::from("Ada")],
+            ..Default::default()
+        },
+        vec![pl_rule()],
+    )
+    .await;
+    let dropbox = Arc::new(dropbox);
+    run_pipeline(
+        &work_dir,
+        &storage,
+        &dropbox,
+        Arc::new(llm),
+        vec![pl_rule()],
+        PipelineOptions::default(),
+    )
+    .await;
+
+    let record = storage.get_file(&id).await.unwrap().unwrap();
+    assert_eq!(record.status, FileStatus::Error);
+    assert!(record.target_paths().is_empty());
+    assert!(record.pending_target_paths().is_empty());
+    assert_eq!(record.processed_at, None);
+    assert!(
+        storage
+            .get_files_in_folder("/out/pl")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(storage.get_target_folders().await.unwrap().is_empty());
+    assert!(storage.get_processed_since(None).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_watch_processes_file_added_after_first_cycle() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let dropbox = FakeDropboxClient::new();
+    let (entries, files) = (dropbox.entries.clone(), dropbox.files.clone());
+    let dropbox = Arc::new(dropbox);
+    let llm = FakeMistralClient::new();
+    llm.set_response("Compilers", paper_metadata("Compilers"), vec![pl_rule()])
+        .await;
+    let pipeline = Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        Arc::new(llm),
+        work_dir,
+        Arc::new(Rules::from(vec![pl_rule()])),
+    )
+    .with_plain_output();
+    let options = WatchOptions {
+        inboxes: vec![String::from("/0_inbox")],
+        inbox_recursive: false,
+        skip_suffixes: vec![],
+        allowed_upload_prefixes: vec![],
+        interval: Duration::from_millis(20),
+        batch_size: 10,
+        jobs: 1,
+    };
+
+    let (cycles_tx, mut cycles_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let watching = watch(
+        &pipeline,
+        &storage,
+        dropbox.as_ref(),
+        &options,
+        async {
+            let _ = stop_rx.await;
+        },
+        |cycle, summary| {
+            let summary = summary.as_ref().unwrap();
+            cycles_tx
+                .send((cycle, summary.synced, summary.batch.processed))
+                .unwrap();
+        },
+    );
+    let driver = async {
+        assert_eq!(cycles_rx.recv().await, Some((1, 0, 0)));
+        // A new paper lands in the inbox while watching
+        let id = DropboxId("id:new".to_string());
+        entries.lock().await.push(DropboxEntry {
+            id: id.clone(),
+            name: "new.pdf".to_string(),
+            path: RemotePath("/0_inbox/new.pdf".to_string()),
+            content_hash: FileHash("hash-new".to_string()),
+            size: 0,
+            server_modified: None,
+        });
+        files
+            .lock()
+            .await
+            .insert(id.0.clone(), pdf_bytes(&["Compilers"]));
+        let second = cycles_rx.recv().await;
+        stop_tx.send(()).unwrap();
+        second
+    };
+
+    let (cycles, second) = tokio::time::timeout(Duration::from_secs(10), async {
+        tokio::join!(watching, driver)
+    })
+    .await
+    .expect("watching stops when asked to");
+
+    assert_eq!(cycles.unwrap(), 2);
+    assert_eq!(second, Some((2, 1, 1)));
+    let record = storage
+        .get_file(&DropboxId("id:new".to_string()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.status, FileStatus::Processed);
+    assert!(dropbox.files.lock().await.contains_key("/out/pl/new.pdf"));
+}
+
+#[tokio::test]
+async fn test_hybrid_classifier_files_keyword_match_without_llm() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    add_pdf(&mut dropbox, "keyword", "A verified compiler for C").await;
+    // Any query to the LLM fails the file
+    let llm = FakeMistralClient::new();
+    llm.set_error("", "The LLM must not be asked").await;
+    let pl_rule = Rule {
+        name: String::from("Programming Languages"),
+        description: String::from("Compilers and type systems"),
+        path: RemotePath::from("/out/pl"),
+        keywords: vec![String::from("compiler")],
+        ..Default::default()
+    };
+    let ai_rule = Rule {
+        name: String::from("AI"),
+        description: String::from("Artificial intelligence"),
+        path: RemotePath::from("/out/ai"),
+        keywords: vec![String::from("neural")],
+        ..Default::default()
+    };
+
+    let dropbox = Arc::new(dropbox);
+    let summary = run_pipeline(
+        &work_dir,
+        &storage,
+        &dropbox,
+        Arc::new(HybridClassifier::new(Arc::new(llm))),
+        vec![pl_rule, ai_rule],
+        PipelineOptions::default(),
+    )
+    .await;
+
+    assert_eq!(summary.processed, 1);
+    assert!(summary.failed.is_empty());
+    let record = storage
+        .get_file(&DropboxId("id:keyword".to_string()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.status, FileStatus::Processed);
+    assert_eq!(
+        record.target_paths(),
+        vec![RemotePath::from("/out/pl/keyword.pdf")]
+    );
+}
+
+#[tokio::test]
+async fn test_skip_reasons_are_recorded() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let paper = pdf_bytes(&["Compilers"]);
+    for (id, name, hash, content) in [
+        ("id:original", "paper.pdf", "hash-paper", paper),
+        (
+            "id:image",
+            "scan.jpg",
+            "hash-image",
+            b"\xff\xd8\xff\xe0".to_vec(),
+        ),
+    ] {
+        dropbox
+            .add_entry(
+                DropboxEntry {
+                    id: DropboxId(id.to_string()),
+                    name: name.to_string(),
+                    path: RemotePath(format!("/0_inbox/{}", name)),
+                    content_hash: FileHash(hash.to_string()),
+                    size: 0,
+                    server_modified: None,
+                },
+                content,
+            )
+            .await;
+    }
+    let llm = Arc::new(FakeMistralClient::new());
+
+    let dropbox = Arc::new(dropbox);
+    sync_inbox(&storage, dropbox.as_ref(), "/0_inbox", false, &[], &[])
+        .await
+        .unwrap();
+    let summary = Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        llm,
+        work_dir,
+        Arc::new(Rules::from(vec![])),
+    )
+    .run_batch(10, 1)
+    .await
+    .unwrap();
+    // A copy of the filed paper turns up later
+    storage
+        .upsert_file(
+            &DropboxId("id:copy".to_string()),
+            "paper (1).pdf",
+            &FileHash("hash-paper".to_string()),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(summary.skipped, 1);
+    let skip_reason = |id: &str| {
+        let storage = storage.clone();
+        let id = DropboxId(id.to_string());
+        async move { storage.get_file(&id).await.unwrap().unwrap().skip_reason }
+    };
+    assert_eq!(
+        skip_reason("id:copy").await.as_deref(),
+        Some("duplicate of id:original")
+    );
+    assert_eq!(skip_reason("id:image").await.as_deref(), Some("not a PDF"));
+    assert_eq!(skip_reason("id:original").await, None);
+    for id in ["id:copy", "id:image"] {
+        let record = storage
+            .get_file(&DropboxId(id.to_string()))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.last_error, None, "{}", id);
+    }
+    assert_eq!(
+        storage.count_skipped_by_reason().await.unwrap(),
+        vec![("duplicate".to_string(), 1), ("not a PDF".to_string(), 1)]
+    );
+}
+
+#[tokio::test]
+async fn test_scanned_pdf_is_skipped_for_ocr() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    let id = DropboxId("id:scan".to_string());
+    dropbox
+        .add_entry(
+            DropboxEntry {
+                id: id.clone(),
+                name: "scan.pdf".to_string(),
+                path: RemotePath::from("/0_inbox/scan.pdf"),
+                content_hash: FileHash("hash-scan".to_string()),
+                size: 0,
+                server_modified: None,
+            },
+            scanned_pdf_bytes(),
+        )
+        .await;
+
+    let dropbox = Arc::new(dropbox);
+    let summary = run_pipeline(
+        &work_dir,
+        &storage,
+        &dropbox,
+        Arc::new(FakeMistralClient::new()),
+        vec![],
+        PipelineOptions::default(),
+    )
+    .await;
+
+    assert_eq!(summary.skipped, 1);
+    assert!(summary.failed.is_empty());
+    let record = storage.get_file(&id).await.unwrap().unwrap();
+    assert_eq!(record.status, FileStatus::Skipped);
+    assert_eq!(
+        record.skip_reason.as_deref(),
+        Some("scanned (no text layer)")
+    );
+    assert_eq!(
+        storage.count_skipped_by_reason().await.unwrap(),
+        vec![("scanned".to_string(), 1)]
+    );
+}
+
+#[tokio::test]
+async fn test_local_backend_files_paper_into_local_tree() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let root = temp_dir.path().join("library");
+    fs::create_dir_all(root.join("0_inbox")).unwrap();
+    fs::write(
+        root.join("0_inbox/paper.pdf"),
+        pdf_bytes(&["Gradual Typing"]),
+    )
+    .unwrap();
+    let dropbox: Arc<dyn DropboxClient> =
+        Arc::new(LocalFsClient::new(root.clone(), vec![String::from("/out")]));
+    let pl_rule = Rule {
+        name: String::from("Programming Languages"),
+        path: RemotePath::from("/out/pl"),
+        ..Default::default()
+    };
+    let llm = FakeMistralClient::new();
+    llm.set_response(
+        "Gradual Typing",
+        ArticleMetadata {
+            title: "Gradual Typing".to_string(),
+            authors: vec!["Jeremy Siek".to_string()],
+            ..Default::default()
+        },
+        vec![pl_rule.clone()],
+    )
+    .await;
+
+    let synced = sync_inbox(&storage, dropbox.as_ref(), "/0_inbox", false, &[], &[])
+        .await
+        .unwrap();
+    let summary = Pipeline::new(
+        storage.clone(),
+        dropbox,
+        Arc::new(llm),
+        work_dir,
+        Arc::new(Rules::from(vec![pl_rule])),
+    )
+    .run_batch(10, 1)
+    .await
+    .unwrap();
+
+    assert_eq!(synced, 1);
+    assert_eq!(summary.processed, 1, "{:?}", summary.failed);
+    assert_eq!(
+        summary.target_paths,
+        vec![RemotePath::from("/out/pl/paper.pdf")]
+    );
+    assert!(root.join("out/pl/paper.pdf").is_file());
+    assert!(root.join("out/pl/paper.pdf.md").is_file());
+    let record = storage
+        .get_file(&DropboxId("/0_inbox/paper.pdf".to_string()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.status, FileStatus::Processed);
+}
+
+#[tokio::test]
+async fn test_raw_copies_are_named_by_sniffed_format() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    for (id, name, content) in [
+        ("id:paper", "paper.pdf", pdf_bytes(&["Compilers"])),
+        ("id:notes", "notes.pdf", b"Notes on compilers".to_vec()),
+        (
+            "id:junk",
+            "junk.pdf",
+            b"\xff\xd8\xff\xe0\x00\x10JFIF".to_vec(),
+        ),
+    ] {
+        dropbox
+            .add_entry(
+                DropboxEntry {
+                    id: DropboxId(id.to_string()),
+                    name: name.to_string(),
+                    path: RemotePath(format!("/0_inbox/{}", name)),
+                    content_hash: FileHash(format!("hash-{}", id.trim_start_matches("id:"))),
+                    size: 0,
+                    server_modified: None,
+                },
+                content,
+            )
+            .await;
+    }
+
+    let dropbox = Arc::new(dropbox);
+    sync_inbox(&storage, dropbox.as_ref(), "/0_inbox", false, &[], &[])
+        .await
+        .unwrap();
+    Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        Arc::new(FakeMistralClient::new()),
+        work_dir.clone(),
+        Arc::new(Rules::from(vec![])),
+    )
+    .run_batch(10, 1)
+    .await
+    .unwrap();
+
+    let junk = storage
+        .get_file(&DropboxId("id:junk".to_string()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(junk.skip_reason.as_deref(), Some("not a PDF"));
+    let mut raw_files = fs::read_dir(work_dir.0.join("raw"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect::<Vec<String>>();
+    raw_files.sort();
+    assert_eq!(raw_files, vec!["hash-notes.txt", "hash-paper.pdf"]);
+}
+
+#[tokio::test]
+async fn test_changed_content_of_terminal_files_per_policy() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    for (on_content_change, expected) in [
+        (OnContentChange::Reprocess, FileStatus::Pending),
+        (OnContentChange::KeepTerminal, FileStatus::Archived),
+    ] {
+        let db_path = temp_dir.path().join(format!("{:?}.db", on_content_change));
+        let db_url = sqlite_url(&db_path);
+        let storage = Storage::new(setup_db(&db_url).await.unwrap())
+            .with_on_content_change(on_content_change);
+        let archived = DropboxId("id:archived".to_string());
+        let skipped = DropboxId("id:skipped".to_string());
+        for id in [&archived, &skipped] {
+            storage
+                .upsert_file(id, "paper.pdf", &FileHash(format!("old-{}", id.0)))
+                .await
+                .unwrap();
+        }
+        storage
+            .update_status(&archived, FileStatus::Archived)
+            .await
+            .unwrap();
+        storage.mark_skipped(&skipped, "not a PDF").await.unwrap();
+
+        for id in [&archived, &skipped] {
+            storage
+                .upsert_file(id, "paper.pdf", &FileHash(format!("new-{}", id.0)))
+                .await
+                .unwrap();
+        }
+
+        let archived = storage.get_file(&archived).await.unwrap().unwrap();
+        assert_eq!(archived.status, expected, "{:?}", on_content_change);
+        assert_eq!(archived.content_hash.0, "new-id:archived");
+        let skipped = storage.get_file(&skipped).await.unwrap().unwrap();
+        match on_content_change {
+            OnContentChange::Reprocess => {
+                assert_eq!(skipped.status, FileStatus::Pending);
+                assert_eq!(skipped.skip_reason, None);
+            }
+            OnContentChange::KeepTerminal => {
+                assert_eq!(skipped.status, FileStatus::Skipped);
+                assert_eq!(skipped.skip_reason.as_deref(), Some("not a PDF"));
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_moved_copy_of_processed_file_is_skipped() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (_work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let original = DropboxId("id:before-move".to_string());
+    let moved = DropboxId("id:after-move".to_string());
+    let hash = FileHash("hash-moved".to_string());
+    storage
+        .upsert_file(&original, "paper.pdf", &hash)
+        .await
+        .unwrap();
+    storage
+        .update_metadata(
+            &original,
+            ArticleMetadata::default(),
+            &[RemotePath::from("/out/pl/paper.pdf")],
+            FileStatus::Processed,
+        )
+        .await
+        .unwrap();
+
+    storage
+        .upsert_file(&moved, "paper.pdf", &hash)
+        .await
+        .unwrap();
+    // Seeing the original again does not make it a duplicate of its copy
+    storage
+        .upsert_file(&original, "paper.pdf", &hash)
+        .await
+        .unwrap();
+
+    let copy = storage.get_file(&moved).await.unwrap().unwrap();
+    assert_eq!(copy.status, FileStatus::Skipped);
+    assert_eq!(
+        copy.skip_reason.as_deref(),
+        Some("duplicate of id:before-move")
+    );
+    let original = storage.get_file(&original).await.unwrap().unwrap();
+    assert_eq!(original.status, FileStatus::Processed);
+    assert!(storage.get_pending_files(10).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_copy_of_unfiled_file_is_not_skipped() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (_work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let hash = FileHash("hash-copied".to_string());
+    for (original, status) in [
+        ("id:failed", FileStatus::Error),
+        ("id:waiting", FileStatus::Pending),
+    ] {
+        let original = DropboxId(original.to_string());
+        storage
+            .upsert_file(&original, "paper.pdf", &hash)
+            .await
+            .unwrap();
+        storage.update_status(&original, status).await.unwrap();
+    }
+
+    let copy = DropboxId("id:copy".to_string());
+    storage
+        .upsert_file(&copy, "paper.pdf", &hash)
+        .await
+        .unwrap();
+
+    let copy = storage.get_file(&copy).await.unwrap().unwrap();
+    assert_eq!(copy.status, FileStatus::Pending);
+    assert_eq!(copy.skip_reason, None);
+}
+
+/// Counts the downloads, the uploads in flight at once and the shared links made through
+/// it. Downloads and uploads each take at least their delay, and with `pages` the listing
+/// comes a page at a time, failing at a page of `None`, as an interrupted listing.
+struct CountingDropboxClient {
+    downloads: AtomicUsize,
+    download_delay: Duration,
+    uploads_in_flight: AtomicUsize,
+    max_uploads_in_flight: AtomicUsize,
+    upload_delay: Duration,
+    shared_links: AtomicUsize,
+    pages: Option<Vec<Option<Vec<DropboxEntry>>>>,
+    inner: FakeDropboxClient,
+}
+
+impl CountingDropboxClient {
+    fn new(inner: FakeDropboxClient) -> Self {
+        Self {
+            downloads: AtomicUsize::new(0),
+            download_delay: Duration::ZERO,
+            uploads_in_flight: AtomicUsize::new(0),
+            max_uploads_in_flight: AtomicUsize::new(0),
+            upload_delay: Duration::ZERO,
+            shared_links: AtomicUsize::new(0),
+            pages: None,
+            inner,
+        }
+    }
+
+    fn with_download_delay(mut self, download_delay: Duration) -> Self {
+        self.download_delay = download_delay;
+        self
+    }
+
+    fn with_upload_delay(mut self, upload_delay: Duration) -> Self {
+        self.upload_delay = upload_delay;
+        self
+    }
+
+    fn with_pages(mut self, pages: Vec<Option<Vec<DropboxEntry>>>) -> Self {
+        self.pages = Some(pages);
+        self
+    }
+}
+
+#[async_trait]
+impl DropboxClient for CountingDropboxClient {
+    async fn list_folder(&self, path: &str) -> anyhow::Result<Vec<DropboxEntry>> {
+        match self.pages {
+            Some(_) => self.list_folder_stream(path, false).try_concat().await,
+            None => self.inner.list_folder(path).await,
+        }
+    }
+    async fn list_folder_recursive(&self, path: &str) -> anyhow::Result<Vec<DropboxEntry>> {
+        match self.pages {
+            Some(_) => self.list_folder_stream(path, true).try_concat().await,
+            None => self.inner.list_folder_recursive(path).await,
+        }
+    }
+    fn list_folder_stream<'a>(
+        &'a self,
+        path: &'a str,
+        recursive: bool,
+    ) -> BoxStream<'a, anyhow::Result<Vec<DropboxEntry>>> {
+        match &self.pages {
+            Some(pages) => Box::pin(
+                futures::stream::iter(pages.clone())
+                    .map(|page| page.ok_or_else(|| anyhow::anyhow!("The connection was reset"))),
+            ),
+            None => self.inner.list_folder_stream(path, recursive),
+        }
+    }
+    async fn get_metadata(&self, path: &RemotePath) -> anyhow::Result<Option<DropboxEntry>> {
+        self.inner.get_metadata(path).await
+    }
+    async fn download_file(&self, id: &DropboxId) -> anyhow::Result<Vec<u8>> {
+        self.downloads.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(self.download_delay).await;
+        self.inner.download_file(id).await
+    }
+    async fn upload_file(&self, path: &RemotePath, content: Vec<u8>) -> anyhow::Result<()> {
+        let now = self.uploads_in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_uploads_in_flight.fetch_max(now, Ordering::SeqCst);
+        tokio::time::sleep(self.upload_delay).await;
+        self.uploads_in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.inner.upload_file(path, content).await
+    }
+    async fn folder_exists(&self, path: &str) -> anyhow::Result<bool> {
+        self.inner.folder_exists(path).await
+    }
+    async fn create_folder(&self, path: &str) -> anyhow::Result<()> {
+        self.inner.create_folder(path).await
+    }
+    async fn create_folder_if_not_exists(&self, path: &str) -> anyhow::Result<()> {
+        self.inner.create_folder_if_not_exists(path).await
+    }
+    async fn move_file(&self, from: &RemotePath, to: &RemotePath) -> anyhow::Result<()> {
+        self.inner.move_file(from, to).await
+    }
+    async fn delete_file(&self, path: &RemotePath) -> anyhow::Result<()> {
+        self.inner.delete_file(path).await
+    }
+    async fn create_shared_link(&self, path: &RemotePath) -> anyhow::Result<String> {
+        self.shared_links.fetch_add(1, Ordering::SeqCst);
+        self.inner.create_shared_link(path).await
+    }
+}
+
+#[tokio::test]
+async fn test_sync_records_each_page_of_the_listing_as_it_arrives() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (_, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let entry = |name: &str| DropboxEntry {
+        id: DropboxId(format!("id:{}", name)),
+        name: format!("{}.pdf", name),
+        path: RemotePath(format!("/0_inbox/{}.pdf", name)),
+        content_hash: FileHash(format!("hash-{}", name)),
+        size: 0,
+        server_modified: None,
+    };
+    let dropbox = CountingDropboxClient::new(FakeDropboxClient::new()).with_pages(vec![
+        Some(vec![entry("a"), entry("b")]),
+        Some(vec![entry("c")]),
+        None,
+    ]);
+
+    let result = sync_inbox(&storage, &dropbox, "/0_inbox", false, &[], &[]).await;
+
+    // The pages listed before the listing failed were recorded all the same
+    assert!(result.is_err());
+    let pending = storage
+        .get_files_with_status(FileStatus::Pending)
+        .await
+        .unwrap();
+    assert_eq!(pending.len(), 3);
+}
+
+#[tokio::test]
+async fn test_stage_timings_record_slow_download() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut inner = FakeDropboxClient::new();
+    add_pdf(&mut inner, "slow", "Slow").await;
+    let dropbox =
+        Arc::new(CountingDropboxClient::new(inner).with_download_delay(Duration::from_millis(200)));
+    sync_inbox(&storage, dropbox.as_ref(), "/0_inbox", false, &[], &[])
+        .await
+        .unwrap();
+
+    let summary = Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        Arc::new(FakeMistralClient::new()),
+        work_dir,
+        Arc::new(Rules::from(vec![])),
+    )
+    .run_batch(10, 1)
+    .await
+    .unwrap();
+
+    assert_eq!(summary.processed, 1);
+    let timings = summary.average_timings().unwrap();
+    assert!(
+        timings.download >= Duration::from_millis(200),
+        "{}",
+        timings
+    );
+    assert!(timings.extract < timings.download, "{}", timings);
+}
+
+#[tokio::test]
+async fn test_rerun_reuses_matching_raw_copy() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut inner = FakeDropboxClient::new();
+    let id = DropboxId("id:cached".to_string());
+    let content = pdf_bytes(&["Compilers"]);
+    inner
+        .add_entry(
+            DropboxEntry {
+                id: id.clone(),
+                name: "cached.pdf".to_string(),
+                path: RemotePath("/0_inbox/cached.pdf".to_string()),
+                content_hash: dropbox_content_hash(&content),
+                size: 0,
+                server_modified: None,
+            },
+            content,
+        )
+        .await;
+    let dropbox = Arc::new(CountingDropboxClient::new(inner));
+    sync_inbox(&storage, dropbox.as_ref(), "/0_inbox", false, &[], &[])
+        .await
+        .unwrap();
+    let pipeline = Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        Arc::new(FakeMistralClient::new()),
+        work_dir,
+        Arc::new(Rules::from(vec![])),
+    );
+
+    pipeline.run_batch(10, 1).await.unwrap();
+    assert_eq!(dropbox.downloads.load(Ordering::SeqCst), 1);
+
+    // Queue the file again, as after an interrupted run
+    storage
+        .update_status(&id, FileStatus::Pending)
+        .await
+        .unwrap();
+    assert!(storage.mark_in_progress(&id).await.unwrap());
+    storage
+        .reclaim_in_progress(chrono::Utc::now() + chrono::Duration::seconds(1))
+        .await
+        .unwrap();
+    let summary = pipeline.run_batch(10, 1).await.unwrap();
+
+    assert!(summary.failed.is_empty());
+    assert_eq!(summary.processed + summary.needs_review, 1);
+    assert_eq!(dropbox.downloads.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_rerun_reuses_compressed_raw_copy() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut inner = FakeDropboxClient::new();
+    let id = DropboxId("id:packed".to_string());
+    let content = pdf_bytes(&["Compilers"]);
+    let content_hash = dropbox_content_hash(&content);
+    inner
+        .add_entry(
+            DropboxEntry {
+                id: id.clone(),
+                name: "packed.pdf".to_string(),
+                path: RemotePath("/0_inbox/packed.pdf".to_string()),
+                content_hash: content_hash.clone(),
+                size: 0,
+                server_modified: None,
+            },
+            content,
+        )
+        .await;
+    let dropbox = Arc::new(CountingDropboxClient::new(inner));
+    sync_inbox(&storage, dropbox.as_ref(), "/0_inbox", false, &[], &[])
+        .await
+        .unwrap();
+    let raw_dir = work_dir.0.join("raw");
+    let pipeline = Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        Arc::new(FakeMistralClient::new()),
+        work_dir,
+        Arc::new(Rules::from(vec![])),
+    )
+    .with_options(PipelineOptions {
+        compress_raw: true,
+        ..Default::default()
+    });
+
+    pipeline.run_batch(10, 1).await.unwrap();
+    let kept: Vec<String> = std::fs::read_dir(&raw_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    assert_eq!(kept, vec![format!("{}.pdf.gz", content_hash.0)]);
+
+    // Queue the file again, as after an interrupted run
+    storage
+        .update_status(&id, FileStatus::Pending)
+        .await
+        .unwrap();
+    assert!(storage.mark_in_progress(&id).await.unwrap());
+    storage
+        .reclaim_in_progress(chrono::Utc::now() + chrono::Duration::seconds(1))
+        .await
+        .unwrap();
+    let summary = pipeline.run_batch(10, 1).await.unwrap();
+
+    assert!(summary.failed.is_empty());
+    assert_eq!(summary.processed + summary.needs_review, 1);
+    assert_eq!(dropbox.downloads.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_listed_size_and_date_order_and_guard_the_batch() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut inner = FakeDropboxClient::new();
+    let day = |day: u32| {
+        chrono::NaiveDate::from_ymd_opt(2026, 1, day)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+            .and_utc()
+    };
+    for (id, size, modified) in [
+        ("id:small-new", 1_000, day(3)),
+        ("id:huge-old", 500_000_000, day(1)),
+        ("id:medium-mid", 50_000, day(2)),
+    ] {
+        inner
+            .add_entry(
+                DropboxEntry {
+                    id: DropboxId(id.to_string()),
+                    name: format!("{}.pdf", id.trim_start_matches("id:")),
+                    path: RemotePath(format!("/0_inbox/{}.pdf", id.trim_start_matches("id:"))),
+                    content_hash: FileHash(format!("hash-{}", id)),
+                    size,
+                    server_modified: Some(modified),
+                },
+                pdf_bytes(&["Compilers"]),
+            )
+            .await;
+    }
+    let dropbox = Arc::new(CountingDropboxClient::new(inner));
+    sync_inbox(&storage, dropbox.as_ref(), "/0_inbox", false, &[], &[])
+        .await
+        .unwrap();
+
+    let ids = |files: Vec<FileRecord>| {
+        files
+            .into_iter()
+            .map(|file| file.dropbox_id.0)
+            .collect::<Vec<String>>()
+    };
+    assert_eq!(
+        ids(storage
+            .get_pending_files_in_order(10, PendingOrder::Size)
+            .await
+            .unwrap()),
+        vec!["id:huge-old", "id:medium-mid", "id:small-new"]
+    );
+    assert_eq!(
+        ids(storage
+            .get_pending_files_in_order(10, PendingOrder::Date)
+            .await
+            .unwrap()),
+        vec!["id:small-new", "id:medium-mid", "id:huge-old"]
+    );
+    let record = storage
+        .get_file(&DropboxId("id:huge-old".to_string()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.size, Some(500_000_000));
+    assert_eq!(record.server_modified, Some(day(1)));
+
+    let summary = Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        Arc::new(FakeMistralClient::new()),
+        work_dir,
+        Arc::new(Rules::from(vec![])),
+    )
+    .run_batch(10, 1)
+    .await
+    .unwrap();
+
+    assert_eq!(summary.skipped, 1);
+    assert_eq!(dropbox.downloads.load(Ordering::SeqCst), 2);
+    let huge = storage
+        .get_file(&DropboxId("id:huge-old".to_string()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(huge.skip_reason.unwrap().starts_with("too large"));
+}
+
+#[tokio::test]
+async fn test_batch_larger_than_job_queue_does_not_deadlock() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut dropbox = FakeDropboxClient::new();
+    for n in 0..40 {
+        add_pdf(&mut dropbox, &format!("paper{n}"), &format!("Paper {n}")).await;
+    }
+    // Some files are retried, putting jobs back into the full queue
+    let llm = FakeMistralClient::new();
+    for n in [3, 17, 29] {
+        llm.set_fail_n_times(&format!("Paper {}\n", n), 1).await;
+    }
+
+    let dropbox = Arc::new(dropbox);
+    sync_all(&storage, &dropbox, "/0_inbox").await;
+    let pipeline = Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        Arc::new(llm),
+        work_dir,
+        Arc::new(Rules::from(vec![])),
+    )
+    .with_options(PipelineOptions {
+        job_queue_capacity: 1,
+        ..Default::default()
+    });
+    let summary = tokio::time::timeout(Duration::from_secs(30), pipeline.run_batch(40, 2))
+        .await
+        .expect("the batch finishes");
+
+    let summary = summary.unwrap();
+    assert!(summary.failed.is_empty());
+    assert_eq!(summary.processed + summary.needs_review, 40);
+}
+
+#[tokio::test]
+async fn test_workers_wait_for_slow_database_writes() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let (work_dir, storage) = setup_work_dir_and_storage(&temp_dir).await;
+    let mut inner = FakeDropboxClient::new();
+    let files = 12;
+    for n in 0..files {
+        // Not papers, so the workers skip them without waiting for the database
+        inner
+            .add_entry(
+                DropboxEntry {
+                    id: DropboxId(format!("id:{}", n)),
+                    name: format!("{}.bin", n),
+                    path: RemotePath(format!("/0_inbox/{}.bin", n)),
+                    content_hash: FileHash(format!("hash-{}", n)),
+                    size: 0,
+                    server_modified: None,
+                },
+                vec![0, 159, 146, 150, n as u8],
+            )
+            .await;
+    }
+    let dropbox =
+        Arc::new(CountingDropboxClient::new(inner).with_download_delay(Duration::from_millis(20)));
+    sync_inbox(&storage, dropbox.as_ref(), "/0_inbox", false, &[], &[])
+        .await
+        .unwrap();
+    let (workers, capacity) = (2, 2);
+    let pipeline = Pipeline::new(
+        storage.clone(),
+        dropbox.clone(),
+        Arc::new(FakeMistralClient::new()),
+        work_dir.clone(),
+        Arc::new(Rules::from(vec![])),
+    )
+    .with_options(PipelineOptions {
+        result_queue_capacity: capacity,
+        ..Default::default()
+    });
+    let batch = tokio::spawn(async move { pipeline.run_batch(files as i64, workers).await });
+
+    // Once the workers have started, hold a write lock on the database to stall the collector
+    while dropbox.downloads.load(Ordering::SeqCst) == 0 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    let locker = open_db(&sqlite_url(&work_dir.0.join("state.db")))
+        .await
+        .unwrap();
+    let mut lock = locker.acquire().await.unwrap();
+    sqlx::query("BEGIN EXCLUSIVE")
+        .execute(&mut *lock)
+        .await
+        .unwrap();
+    let recorded: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM files WHERE status = 'SKIPPED'")
+        .fetch_one(&mut *lock)
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let downloads = dropbox.downloads.load(Ordering::SeqCst);
+    sqlx::query("ROLLBACK").execute(&mut *lock).await.unwrap();
+
+    // The result being recorded, a full queue, and one waiting result per worker
+    let bound = recorded as usize + 1 + capacity + workers;
+    assert!(
+        downloads <= bound,
+        "{} downloads, bound {}",
+        downloads,
+        bound
+    );
+    assert!(downloads < files);
+    let summary = batch.await.unwrap().unwrap();
+    assert_eq!(summary.skipped, files);
+}
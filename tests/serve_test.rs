@@ -0,0 +1,161 @@
+#![cfg(feature = "serve")]
+
+use sci_librarian::clients::{DropboxEntry, FakeDropboxClient, FakeMistralClient};
+use sci_librarian::models::{
+    ArticleMetadata, DropboxId, FileHash, FileRecord, FileStatus, RemotePath, Rule, Rules,
+    WorkDirectory,
+};
+use sci_librarian::pipeline::PipelineOptions;
+use sci_librarian::server::{AppState, router};
+use sci_librarian::setup_db;
+use sci_librarian::storage::Storage;
+use sci_librarian::test_support::pdf_bytes;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Serve the API for the state on a free port, returning its base URL.
+async fn serve(state: AppState) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, router(state)).await });
+    base
+}
+
+#[tokio::test]
+async fn test_get_files_lists_seeded_files() {
+    let storage = Arc::new(Storage::new(setup_db("sqlite::memory:").await.unwrap()));
+    let id = DropboxId("id:paper".to_string());
+    storage
+        .upsert_file(&id, "paper.pdf", &FileHash("hash".to_string()))
+        .await
+        .unwrap();
+    let meta = ArticleMetadata {
+        title: "A Paper".to_string(),
+        ..Default::default()
+    };
+    storage
+        .update_metadata(
+            &id,
+            meta,
+            &[RemotePath::from("/out/paper.pdf")],
+            FileStatus::Processed,
+        )
+        .await
+        .unwrap();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = AppState {
+        storage,
+        dropbox: Arc::new(FakeDropboxClient::new()),
+        llm: Arc::new(FakeMistralClient::new()),
+        work_dir: WorkDirectory(temp_dir.path().to_path_buf()),
+        rules: Arc::new(Rules(vec![])),
+        inboxes: vec!["/0_inbox".to_string()],
+        inbox_recursive: false,
+        skip_suffixes: vec![],
+        options: PipelineOptions::default(),
+        batch_size: 10,
+        jobs: 1,
+        run_lock: Arc::default(),
+    };
+    let base = serve(state).await;
+
+    let files: Vec<FileRecord> = reqwest::get(format!("{}/files", base))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].title.as_deref(), Some("A Paper"));
+    assert_eq!(
+        files[0].target_paths(),
+        vec![RemotePath::from("/out/paper.pdf")]
+    );
+
+    let missing = reqwest::get(format!("{}/files/id:missing", base))
+        .await
+        .unwrap();
+    assert_eq!(missing.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_concurrent_process_requests_upload_each_file_once() {
+    let storage = Arc::new(Storage::new(setup_db("sqlite::memory:").await.unwrap()));
+    let mut dropbox = FakeDropboxClient::new();
+    let llm = FakeMistralClient::new();
+    let rule = Rule {
+        name: "Programming Languages".to_string(),
+        description: "Compilers and type systems".to_string(),
+        path: RemotePath::from("/out/pl"),
+        ..Default::default()
+    };
+    for i in 0..4 {
+        let word = format!("Paper{}", i);
+        let entry = DropboxEntry {
+            id: DropboxId(format!("id:{}", i)),
+            name: format!("paper{}.pdf", i),
+            path: RemotePath(format!("/0_inbox/paper{}.pdf", i)),
+            content_hash: FileHash(format!("hash-{}", i)),
+            size: 0,
+            server_modified: None,
+        };
+        storage
+            .upsert_file(&entry.id, &entry.name, &entry.content_hash)
+            .await
+            .unwrap();
+        dropbox.add_entry(entry, pdf_bytes(&[&word])).await;
+        let meta = ArticleMetadata {
+            title: word.clone(),
+            authors: vec!["Ada Lovelace".to_string()],
+            ..Default::default()
+        };
+        llm.set_response(&word, meta, vec![rule.clone()]).await;
+    }
+    let uploads = dropbox.uploads.clone();
+    let temp_dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(temp_dir.path().join("raw")).unwrap();
+    let state = AppState {
+        storage: storage.clone(),
+        dropbox: Arc::new(dropbox),
+        llm: Arc::new(llm),
+        work_dir: WorkDirectory(temp_dir.path().to_path_buf()),
+        rules: Arc::new(Rules(vec![rule])),
+        inboxes: vec!["/0_inbox".to_string()],
+        inbox_recursive: false,
+        skip_suffixes: vec![],
+        options: PipelineOptions::default(),
+        batch_size: 10,
+        jobs: 2,
+        run_lock: Arc::default(),
+    };
+    let base = serve(state).await;
+
+    let client = reqwest::Client::new();
+    let process = || client.post(format!("{}/process", base)).send();
+    let (first, second) = tokio::join!(process(), process());
+
+    for response in [first.unwrap(), second.unwrap()] {
+        assert!(
+            [
+                reqwest::StatusCode::NO_CONTENT,
+                reqwest::StatusCode::CONFLICT
+            ]
+            .contains(&response.status()),
+            "{}",
+            response.status()
+        );
+    }
+    let mut upload_counts: HashMap<String, usize> = HashMap::new();
+    for (path, _) in uploads.lock().await.iter() {
+        *upload_counts.entry(path.0.clone()).or_default() += 1;
+    }
+    for i in 0..4 {
+        let path = format!("/out/pl/paper{}.pdf", i);
+        assert_eq!(upload_counts.get(&path), Some(&1), "{}", path);
+    }
+    let processed = storage
+        .get_files_with_status(FileStatus::Processed)
+        .await
+        .unwrap();
+    assert_eq!(processed.len(), 4);
+}